@@ -0,0 +1,33 @@
+/* C ABI of libdprintx: profile routing for editor plugins. */
+#ifndef DPRINTX_H
+#define DPRINTX_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct DprintxRouter DprintxRouter;
+
+/* Load a dprintx config (NULL: the default location). Returns NULL on
+ * failure and, if error is not NULL, stores a message to free with
+ * dprintx_string_free. */
+DprintxRouter *dprintx_router_new(const char *config_path, char **error);
+
+/* Resolve the profile for path. content_head (NULL: read the file) is the
+ * start of the buffer, checked for pin comments and match_content.
+ * Returns a JSON object to free with dprintx_string_free:
+ *   {"resolution":"config","profile":"default","config":"/abs/dprint.json"}
+ *   {"resolution":"ignore"}
+ *   {"resolution":"none"}
+ *   {"error":"..."} */
+char *dprintx_resolve_profile(const DprintxRouter *router, const char *path,
+                              const char *content_head);
+
+void dprintx_router_free(DprintxRouter *router);
+void dprintx_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DPRINTX_H */
@@ -0,0 +1,6 @@
+//! Scriptable stand-in for dprint in integration tests; see
+//! [`dprintx::testing`].
+
+fn main() {
+    dprintx::testing::fake_dprint_main()
+}
@@ -0,0 +1,186 @@
+//! Opt-in cache of formatted output (`format_cache`): what dprint made of
+//! some content, so unchanged files are not formatted again, in this run or
+//! a later one. An entry is keyed by the file's path and content, the
+//! effective config with the local configs it extends, and the dprint
+//! version; entries live in `~/.cache/dprintx/format`.
+
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::config::DprintxConfig;
+use crate::fsutil;
+use crate::warnings;
+
+/// How deep local `extends` chains are followed into the key.
+const MAX_EXTENDS_DEPTH: usize = 8;
+
+/// The formatted-output cache of one dprint binary.
+#[derive(Debug)]
+pub struct FormatCache {
+    dir: PathBuf,
+    dprint_bin: PathBuf,
+    /// `dprint --version`, asked on first use. None disables the cache:
+    /// without it, an upgrade could serve stale output.
+    version: OnceLock<Option<String>>,
+}
+
+/// Where an entry is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey(String);
+
+impl FormatCache {
+    /// The cache for `dprint_bin`, if `format_cache` is on.
+    pub fn open(config: &DprintxConfig, dprint_bin: &Path) -> Option<Self> {
+        if !config.format_cache {
+            return None;
+        }
+        let dir = dirs::cache_dir()?.join("dprintx").join("format");
+        Some(Self::in_dir(dir, dprint_bin))
+    }
+
+    pub(crate) fn in_dir(dir: PathBuf, dprint_bin: &Path) -> Self {
+        Self {
+            dir,
+            dprint_bin: dprint_bin.to_path_buf(),
+            version: OnceLock::new(),
+        }
+    }
+
+    /// The same cache for another dprint binary.
+    pub fn for_dprint(self, dprint_bin: &Path) -> Self {
+        Self::in_dir(self.dir, dprint_bin)
+    }
+
+    /// Key of `text`, the content of `file`, formatted under
+    /// `effective_config`. None if dprint reports no version.
+    pub fn key(&self, file: &Path, text: &[u8], effective_config: &Path) -> Option<CacheKey> {
+        let version = self
+            .version
+            .get_or_init(|| dprint_version(&self.dprint_bin));
+        let version = version.as_deref()?;
+        let file = std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf());
+        let mut config = Vec::new();
+        config_fingerprint(effective_config, MAX_EXTENDS_DEPTH, &mut config);
+        Some(CacheKey(digest(&[
+            version.as_bytes(),
+            file.as_os_str().as_encoded_bytes(),
+            &config,
+            text,
+        ])))
+    }
+
+    /// The formatted output stored under `key`.
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        std::fs::read(self.path(key)).ok()
+    }
+
+    /// Store `formatted` under `key`. Failing to is only a warning.
+    pub fn put(&self, key: &CacheKey, formatted: &[u8]) {
+        let path = self.path(key);
+        let stored = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| fsutil::write_atomic(&path, formatted));
+        if let Err(e) = stored {
+            warnings::warn("format-cache", format!("caching formatted output: {e:#}"));
+        }
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        let (shard, rest) = key.0.split_at(2);
+        self.dir.join(shard).join(rest)
+    }
+}
+
+fn dprint_version(dprint_bin: &Path) -> Option<String> {
+    let output = Command::new(dprint_bin).arg("--version").output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !version.is_empty()).then_some(version)
+}
+
+/// Append `path`'s content, and that of the local configs it extends, to
+/// `out`. Remote `extends` are covered by their URL, which is in the content.
+fn config_fingerprint(path: &Path, depth: usize, out: &mut Vec<u8>) {
+    let content = std::fs::read(path).unwrap_or_default();
+    out.extend_from_slice(&(content.len() as u64).to_le_bytes());
+    out.extend_from_slice(&content);
+    if depth == 0 {
+        return;
+    }
+    let Ok(value) = crate::jsonc::from_str::<serde_json::Value>(&String::from_utf8_lossy(&content))
+    else {
+        return;
+    };
+    let extends = match value.get("extends") {
+        Some(serde_json::Value::String(one)) => vec![one.as_str()],
+        Some(serde_json::Value::Array(many)) => many.iter().filter_map(|e| e.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for extended in extends.into_iter().filter(|e| !e.contains("://")) {
+        config_fingerprint(
+            &dir.join(crate::config::expand_tilde(extended)),
+            depth - 1,
+            out,
+        );
+    }
+}
+
+/// 128-bit hex digest of `parts`, from two differently salted hashes.
+fn digest(parts: &[&[u8]]) -> String {
+    let half = |salt: u8| {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        salt.hash(&mut hasher);
+        for part in parts {
+            part.hash(&mut hasher);
+        }
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", half(0), half(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_cache_key() {
+        let dir = std::env::temp_dir().join(format!("dprintx-test-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("profiles")).unwrap();
+        let profile = dir.join("profiles/base.json");
+        std::fs::write(&profile, r#"{ "lineWidth": 80 }"#).unwrap();
+        let merged = dir.join("merged.json");
+        std::fs::write(&merged, r#"{ "extends": "profiles/base.json" }"#).unwrap();
+
+        // No dprint, no version, no key.
+        let cache = FormatCache::in_dir(dir.join("cache"), &dir.join("no-dprint"));
+        assert_eq!(cache.key(Path::new("/x/a.ts"), b"let a", &merged), None);
+
+        let cache = FormatCache::in_dir(dir.join("cache"), Path::new("uname"));
+        let key = cache.key(Path::new("/x/a.ts"), b"let a", &merged).unwrap();
+        assert_eq!(cache.get(&key), None);
+        cache.put(&key, b"let a;\n");
+        assert_eq!(cache.get(&key).as_deref(), Some(&b"let a;\n"[..]));
+
+        // Content, path and the extended profile are all part of the key.
+        assert_ne!(
+            cache.key(Path::new("/x/a.ts"), b"let b", &merged),
+            Some(key.clone())
+        );
+        assert_ne!(
+            cache.key(Path::new("/x/b.ts"), b"let a", &merged),
+            Some(key.clone())
+        );
+        std::fs::write(&profile, r#"{ "lineWidth": 100 }"#).unwrap();
+        assert_ne!(
+            cache.key(Path::new("/x/a.ts"), b"let a", &merged),
+            Some(key)
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,144 +1,318 @@
+use std::ffi::{OsStr, OsString};
+
+use clap::{CommandFactory, Parser, Subcommand, ValueHint};
+
 /// Parsed CLI result.
-#[derive(Debug)]
+#[derive(Debug, Parser)]
+#[command(
+    name = "dprintx",
+    about = "Multi-profile wrapper for dprint",
+    disable_help_subcommand = true
+)]
 pub struct Cli {
-    /// Override config path.
+    /// Override config path (~/.config/dprint/dprintx.jsonc).
+    #[arg(long, global = true, value_name = "PATH", value_hint = ValueHint::FilePath)]
     pub config: Option<String>,
+    /// Print warnings as JSON lines on stderr.
+    #[arg(long, global = true)]
+    pub events: bool,
     /// Parsed command.
+    #[command(subcommand)]
     pub command: CliCommand,
 }
 
-#[derive(Debug)]
+// File arguments and `--stdin` names are kept as given (`OsString`): paths
+// need not be UTF-8.
+#[derive(Debug, Subcommand)]
 pub enum CliCommand {
     /// Format files.
     Fmt {
-        stdin: Option<String>,
-        files: Vec<String>,
+        /// Format stdin as if it were this file.
+        #[arg(
+            long,
+            visible_alias = "assume-filename",
+            value_name = "PATH",
+            value_hint = ValueHint::FilePath
+        )]
+        stdin: Option<OsString>,
+        /// Files and directories to format (default: all of each profile's files).
+        #[arg(value_hint = ValueHint::AnyPath)]
+        files: Vec<OsString>,
+        /// Expand directory arguments in dprintx, following symlinked dirs.
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Exit 0 when no file matched.
+        #[arg(long)]
+        allow_no_files: bool,
+        /// Re-check files after formatting them.
+        #[arg(long)]
+        verify: bool,
+        /// Delegate to a running `dprintx daemon` if one is listening.
+        #[arg(long, visible_alias = "daemon")]
+        attach: bool,
+        /// Format the files staged in git and stage the result.
+        #[arg(long)]
+        staged: bool,
+        /// Format with the profile configs alone, ignoring local dprint configs.
+        #[arg(long)]
+        no_merge: bool,
+        /// Extra match rule for this run, ahead of the config's own.
+        #[arg(long = "match", value_name = "GLOB=PROFILE")]
+        extra_match: Vec<String>,
+        /// Snapshot configs, files and dprint commands into this directory.
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        save_repro: Option<String>,
     },
     /// Check if files are formatted.
-    Check { files: Vec<String> },
-    /// Show resolved config for a file.
-    Config { file: Option<String> },
-    /// List files that would be formatted.
+    Check {
+        /// Check stdin as if it were this file.
+        #[arg(
+            long,
+            visible_alias = "assume-filename",
+            value_name = "PATH",
+            value_hint = ValueHint::FilePath
+        )]
+        stdin: Option<OsString>,
+        /// Files and directories to check (default: all of each profile's files).
+        #[arg(value_hint = ValueHint::AnyPath)]
+        files: Vec<OsString>,
+        /// Expand directory arguments in dprintx, following symlinked dirs.
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Exit 0 when no file matched.
+        #[arg(long)]
+        allow_no_files: bool,
+        /// Delegate to a running `dprintx daemon` if one is listening.
+        #[arg(long, visible_alias = "daemon")]
+        attach: bool,
+        /// Print how many files each match rule decided.
+        #[arg(long)]
+        rule_stats: bool,
+        /// Check the files staged in git.
+        #[arg(long)]
+        staged: bool,
+        /// Check with the profile configs alone, ignoring local dprint configs.
+        #[arg(long)]
+        no_merge: bool,
+        /// Check the files changed relative to this git ref.
+        #[arg(long, value_name = "REF")]
+        since: Option<String>,
+        /// Report format instead of dprint's own output.
+        #[arg(long, value_parser = ["json"], conflicts_with = "stdin")]
+        format: Option<String>,
+        /// Extra match rule for this run, ahead of the config's own.
+        #[arg(long = "match", value_name = "GLOB=PROFILE")]
+        extra_match: Vec<String>,
+        /// Snapshot configs, files and dprint commands into this directory.
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        save_repro: Option<String>,
+    },
+    /// Show resolved profiles and match rules, or the config used for a file.
+    Config {
+        /// File to show the resolved dprint config for.
+        #[arg(value_hint = ValueHint::FilePath)]
+        file: Option<OsString>,
+        /// List profiles and rules as tables with their descriptions.
+        #[arg(long, conflicts_with = "file")]
+        describe: bool,
+        #[command(subcommand)]
+        command: Option<ConfigCommand>,
+    },
+    /// List files that would be formatted (merged from all profiles).
     OutputFilePaths,
-    /// Start LSP server.
-    Lsp,
-    /// Generate shell completions (patched with dprintx extras).
-    Completions { shell: String },
+    /// Start the LSP proxy: one `dprint lsp` per profile, routed by file URI.
+    Lsp {
+        /// Periodically dump per-backend metrics to this file.
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        metrics: Option<String>,
+        /// Route every document to this dprint config.
+        #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+        force_config: Option<String>,
+        /// Extra match rule for this session, ahead of the config's own.
+        #[arg(long, value_name = "GLOB=PROFILE")]
+        extra_match: Vec<String>,
+        /// Backend response timeout in milliseconds (overrides `lsp_timeout_ms`).
+        #[arg(long = "lsp-timeout", value_name = "MS")]
+        timeout: Option<String>,
+        /// Log routing records to this file (overrides `lsp_log`).
+        #[arg(long = "lsp-log", value_name = "FILE", value_hint = ValueHint::FilePath)]
+        log: Option<String>,
+        /// Serve one client on this localhost TCP port instead of stdio.
+        #[arg(long)]
+        port: Option<u16>,
+        /// Format with the profile configs alone, ignoring local dprint configs.
+        #[arg(long)]
+        no_merge: bool,
+    },
+    /// Serve `fmt --attach` / `check --attach` over a unix socket.
+    Daemon {
+        /// Socket path (default: $DPRINTX_SOCKET, else dprintx/daemon.sock in
+        /// the runtime or cache dir).
+        #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+        socket: Option<String>,
+    },
+    /// Serve `POST /fmt` over HTTP.
+    Serve {
+        /// Address to listen on, e.g. 127.0.0.1:7657.
+        #[arg(long, value_name = "ADDR:PORT")]
+        listen: String,
+    },
+    /// Add a named profile to dprintx.jsonc.
+    AddProfile {
+        name: String,
+        /// The profile's dprint config.
+        #[arg(value_name = "CONFIG_PATH", value_hint = ValueHint::FilePath)]
+        path: String,
+    },
+    /// Add a match rule to dprintx.jsonc (inserted before a trailing "**").
+    AddRule {
+        glob: String,
+        profile: String,
+        /// Insert the rule before this existing glob.
+        #[arg(long, value_name = "GLOB")]
+        before: Option<String>,
+        /// Skip the confirmation prompt after the match preview.
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Mark a directory trusted for local config merging; list them
+    /// without an argument.
+    Trust {
+        #[arg(value_hint = ValueHint::DirPath)]
+        dir: Option<String>,
+    },
+    /// Check the dprint binary and profile configs.
+    Doctor {
+        /// Offer to point a missing `dprint` at the discovered one.
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Compare dprintx's output with plain dprint's on a sample of files.
+    Parity {
+        /// How many files to compare.
+        #[arg(long, value_name = "N")]
+        sample: Option<String>,
+        #[arg(value_hint = ValueHint::AnyPath)]
+        paths: Vec<OsString>,
+    },
+    /// Generate shell completions for dprint and dprintx's own commands.
+    Completions {
+        /// Shell to generate for; `--install` detects it when absent.
+        shell: Option<String>,
+        /// Write the script where the shell loads completions from instead
+        /// of printing it.
+        #[arg(long)]
+        install: bool,
+    },
+    /// Dynamic completion data for the generated scripts:
+    /// `profiles` (names from the live config) or `recent` (used paths).
+    #[command(name = "__complete", hide = true)]
+    Complete { kind: String },
     /// Passthrough to real dprint (unknown command or --help etc).
-    Passthrough { args: Vec<String> },
+    #[command(skip)]
+    Passthrough { args: Vec<OsString> },
+}
+
+/// `dprintx config` subcommands.
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Show how the resolved settings of two profiles differ.
+    Diff {
+        #[arg(value_name = "PROFILE_A")]
+        a: String,
+        #[arg(value_name = "PROFILE_B")]
+        b: String,
+    },
 }
 
 impl Cli {
-    /// Parse CLI from env args.
+    /// Parse CLI from env args, exiting with usage on malformed ones.
     /// Known commands are parsed by us; everything else is passthrough.
     pub fn parse() -> Self {
-        let args: Vec<String> = std::env::args().skip(1).collect();
+        let args: Vec<OsString> = std::env::args_os().skip(1).collect();
         Self::parse_from(&args)
     }
 
-    fn parse_from(args: &[String]) -> Self {
+    /// Parse `args`, without the program name, like [`Cli::parse`].
+    pub fn parse_from(args: &[OsString]) -> Self {
+        Self::try_parse_from(args).unwrap_or_else(|e| e.exit())
+    }
+
+    pub(crate) fn try_parse_from(args: &[OsString]) -> Result<Self, clap::Error> {
+        let (config, events, rest) = Self::split_globals(args);
+
+        // No subcommand, `--help`, `--version` and dprint's own commands
+        // (`license`, `init`, ...) go to dprint as they are.
+        let ours = rest
+            .first()
+            .and_then(|cmd| cmd.to_str())
+            .is_some_and(|cmd| {
+                Self::command()
+                    .get_subcommands()
+                    .any(|sub| sub.get_name() == cmd)
+            });
+        if !ours {
+            return Ok(Self {
+                config,
+                events,
+                command: CliCommand::Passthrough { args: rest },
+            });
+        }
+
+        let argv =
+            std::iter::once(OsStr::new("dprintx")).chain(args.iter().map(OsString::as_os_str));
+        <Self as Parser>::try_parse_from(argv)
+    }
+
+    /// Take `--config <path>` and `--events` from anywhere in `args`, for
+    /// the passthrough: dprint does not know them. A `--config` without a
+    /// value is left for dprint to reject.
+    fn split_globals(args: &[OsString]) -> (Option<String>, bool, Vec<OsString>) {
         let mut config: Option<String> = None;
-        let mut rest: Vec<String> = Vec::new();
+        let mut events = false;
+        let mut rest: Vec<OsString> = Vec::new();
 
-        // Extract --config <path> from anywhere in args.
         let mut i = 0;
         while i < args.len() {
             if args[i] == "--config" {
                 if i + 1 < args.len() {
-                    config = Some(args[i + 1].clone());
+                    config = Some(lossy(&args[i + 1]));
                     i += 2;
                     continue;
                 }
-            } else if let Some(val) = args[i].strip_prefix("--config=") {
+            } else if let Some(val) = flag(&args[i]).strip_prefix("--config=") {
                 config = Some(val.to_string());
                 i += 1;
                 continue;
+            } else if args[i] == "--events" {
+                events = true;
+                i += 1;
+                continue;
             }
             rest.push(args[i].clone());
             i += 1;
         }
-
-        // No subcommand or help flags → passthrough.
-        if rest.is_empty() {
-            return Self {
-                config,
-                command: CliCommand::Passthrough { args: rest },
-            };
-        }
-
-        let subcmd = rest[0].as_str();
-        let sub_args = &rest[1..];
-
-        let command = match subcmd {
-            "fmt" => Self::parse_fmt(sub_args),
-            "check" => Self::parse_check(sub_args),
-            "config" => CliCommand::Config {
-                file: sub_args.first().cloned(),
-            },
-            "output-file-paths" => CliCommand::OutputFilePaths,
-            "lsp" => CliCommand::Lsp,
-            "completions" => CliCommand::Completions {
-                shell: sub_args.first().cloned().unwrap_or_else(|| "zsh".into()),
-            },
-            // Everything else: --help, -h, --version, -V, license, completions, etc.
-            _ => CliCommand::Passthrough { args: rest },
-        };
-
-        Self { config, command }
-    }
-
-    fn parse_fmt(args: &[String]) -> CliCommand {
-        let mut stdin: Option<String> = None;
-        let mut files: Vec<String> = Vec::new();
-
-        let mut i = 0;
-        while i < args.len() {
-            match args[i].as_str() {
-                "--stdin" => {
-                    if i + 1 < args.len() {
-                        stdin = Some(args[i + 1].clone());
-                        i += 2;
-                        continue;
-                    }
-                }
-                // Pass through help to real dprint.
-                "-h" | "--help" => {
-                    let mut passthrough = vec!["fmt".to_string()];
-                    passthrough.extend_from_slice(args);
-                    return CliCommand::Passthrough { args: passthrough };
-                }
-                other => files.push(other.to_string()),
-            }
-            i += 1;
-        }
-
-        CliCommand::Fmt { stdin, files }
+        (config, events, rest)
     }
+}
 
-    fn parse_check(args: &[String]) -> CliCommand {
-        let mut files: Vec<String> = Vec::new();
-
-        for arg in args {
-            match arg.as_str() {
-                "-h" | "--help" => {
-                    let mut passthrough = vec!["check".to_string()];
-                    passthrough.extend_from_slice(args);
-                    return CliCommand::Passthrough { args: passthrough };
-                }
-                other => files.push(other.to_string()),
-            }
-        }
+/// `arg` as a flag to match on: "" if it is not UTF-8, which only a path
+/// argument can be.
+fn flag(arg: &OsStr) -> &str {
+    arg.to_str().unwrap_or_default()
+}
 
-        CliCommand::Check { files }
-    }
+/// A flag value or non-file argument as a string.
+fn lossy(arg: &OsString) -> String {
+    arg.to_string_lossy().into_owned()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn args(s: &str) -> Vec<String> {
-        s.split_whitespace().map(String::from).collect()
+    fn args(s: &str) -> Vec<OsString> {
+        s.split_whitespace().map(OsString::from).collect()
     }
 
     #[test]
@@ -153,7 +327,7 @@ mod tests {
     #[test]
     fn test_fmt_files() {
         let cli = Cli::parse_from(&args("fmt a.go b.go"));
-        if let CliCommand::Fmt { stdin, files } = &cli.command {
+        if let CliCommand::Fmt { stdin, files, .. } = &cli.command {
             assert!(stdin.is_none());
             assert_eq!(files, &["a.go", "b.go"]);
         } else {
@@ -164,7 +338,7 @@ mod tests {
     #[test]
     fn test_check_files() {
         let cli = Cli::parse_from(&args("check a.yaml"));
-        if let CliCommand::Check { files } = &cli.command {
+        if let CliCommand::Check { files, .. } = &cli.command {
             assert_eq!(files, &["a.yaml"]);
         } else {
             panic!("expected Check");
@@ -189,6 +363,191 @@ mod tests {
         assert!(matches!(cli.command, CliCommand::Passthrough { .. }));
     }
 
+    #[test]
+    fn test_follow_symlinks() {
+        let cli = Cli::parse_from(&args("check --follow-symlinks src"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { follow_symlinks: true, ref files, .. } if files == &["src"]
+        ));
+    }
+
+    #[test]
+    fn test_allow_no_files() {
+        let cli = Cli::parse_from(&args("fmt --allow-no-files"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Fmt { allow_no_files: true, ref files, .. } if files.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_fmt_verify() {
+        let cli = Cli::parse_from(&args("fmt --verify a.ts"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Fmt { verify: true, ref files, .. } if files == &["a.ts"]
+        ));
+    }
+
+    #[test]
+    fn test_daemon_and_attach() {
+        let cli = Cli::parse_from(&args("daemon --socket /tmp/d.sock"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Daemon { socket: Some(ref s) } if s == "/tmp/d.sock"
+        ));
+        let cli = Cli::parse_from(&args("daemon"));
+        assert!(matches!(cli.command, CliCommand::Daemon { socket: None }));
+        let cli = Cli::parse_from(&args("check --attach a.ts"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { attach: true, ref files, .. } if files == &["a.ts"]
+        ));
+        let cli = Cli::parse_from(&args("fmt --daemon a.ts"));
+        assert!(matches!(cli.command, CliCommand::Fmt { attach: true, .. }));
+        let cli = Cli::parse_from(&args("serve --listen=127.0.0.1:8080"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Serve { ref listen } if listen == "127.0.0.1:8080"
+        ));
+        assert!(Cli::try_parse_from(&args("serve")).is_err());
+    }
+
+    #[test]
+    fn test_no_merge() {
+        let cli = Cli::parse_from(&args("fmt --no-merge a.ts"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Fmt { no_merge: true, .. }
+        ));
+        let cli = Cli::parse_from(&args("check --no-merge"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { no_merge: true, .. }
+        ));
+        let cli = Cli::parse_from(&args("lsp --no-merge"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Lsp { no_merge: true, .. }
+        ));
+        let cli = Cli::parse_from(&args("fmt"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Fmt {
+                no_merge: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_staged() {
+        let cli = Cli::parse_from(&args("fmt --staged"));
+        assert!(matches!(cli.command, CliCommand::Fmt { staged: true, .. }));
+        let cli = Cli::parse_from(&args("check --staged"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { staged: true, .. }
+        ));
+    }
+
+    #[test]
+    fn test_match_rules() {
+        let cli = Cli::parse_from(&args("fmt --match **/*.md=docs a.md --match=a/**=b"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Fmt { ref extra_match, ref files, .. }
+                if extra_match == &["**/*.md=docs", "a/**=b"] && files == &["a.md"]
+        ));
+        let cli = Cli::parse_from(&args("check --match src/**=strict"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { ref extra_match, .. } if extra_match == &["src/**=strict"]
+        ));
+    }
+
+    #[test]
+    fn test_since() {
+        let cli = Cli::parse_from(&args("check --since origin/main"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { since: Some(ref r), ref files, .. } if r == "origin/main" && files.is_empty()
+        ));
+        let cli = Cli::parse_from(&args("check --since=HEAD~3 a.ts"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { since: Some(ref r), ref files, .. } if r == "HEAD~3" && files == &["a.ts"]
+        ));
+    }
+
+    #[test]
+    fn test_check_format() {
+        let cli = Cli::parse_from(&args("check --format json src/"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { format: Some(ref f), ref files, .. } if f == "json" && files == &["src/"]
+        ));
+        let cli = Cli::parse_from(&args("check --format=json"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { format: Some(ref f), .. } if f == "json"
+        ));
+    }
+
+    #[test]
+    fn test_save_repro() {
+        let cli = Cli::parse_from(&args("check --save-repro /tmp/r a.ts"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { save_repro: Some(ref d), ref files, .. } if d == "/tmp/r" && files == &["a.ts"]
+        ));
+        let cli = Cli::parse_from(&args("fmt --save-repro=/tmp/r a.ts"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Fmt { save_repro: Some(ref d), ref files, .. } if d == "/tmp/r" && files == &["a.ts"]
+        ));
+    }
+
+    #[test]
+    fn test_check_rule_stats() {
+        let cli = Cli::parse_from(&args("check --rule-stats"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { rule_stats: true, ref files, .. } if files.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_config_diff() {
+        let cli = Cli::parse_from(&args("config diff default strict"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Config {
+                command: Some(ConfigCommand::Diff { ref a, ref b }),
+                ..
+            } if a == "default" && b == "strict"
+        ));
+        let cli = Cli::parse_from(&args("config src/diff.rs"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Config {
+                file: Some(_),
+                describe: false,
+                command: None
+            }
+        ));
+        let cli = Cli::parse_from(&args("config --describe"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Config {
+                file: None,
+                describe: true,
+                command: None
+            }
+        ));
+    }
+
     #[test]
     fn test_config_extracted() {
         let cli = Cli::parse_from(&args("--config /tmp/test.jsonc fmt a.go"));
@@ -196,6 +555,31 @@ mod tests {
         assert!(matches!(cli.command, CliCommand::Fmt { .. }));
     }
 
+    #[test]
+    fn test_assume_filename_alias() {
+        let cli = Cli::parse_from(&args("fmt --assume-filename src/a.ts"));
+        assert!(
+            matches!(cli.command, CliCommand::Fmt { stdin: Some(ref f), .. } if f == "src/a.ts")
+        );
+
+        let cli = Cli::parse_from(&args("check --assume-filename=src/a.ts"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Check { stdin: Some(ref f), ref files, .. } if f == "src/a.ts" && files.is_empty()
+        ));
+
+        let cli = Cli::parse_from(&args("check --stdin b.go"));
+        assert!(matches!(cli.command, CliCommand::Check { stdin: Some(ref f), .. } if f == "b.go"));
+    }
+
+    #[test]
+    fn test_events_extracted() {
+        let cli = Cli::parse_from(&args("check --events a.go"));
+        assert!(cli.events);
+        assert!(matches!(cli.command, CliCommand::Check { ref files, .. } if files == &["a.go"]));
+        assert!(!Cli::parse_from(&args("check")).events);
+    }
+
     #[test]
     fn test_config_equals() {
         let cli = Cli::parse_from(&args("--config=/tmp/test.jsonc check"));
@@ -210,8 +594,171 @@ mod tests {
     }
 
     #[test]
-    fn test_fmt_help_passthrough() {
-        let cli = Cli::parse_from(&args("fmt --help"));
-        assert!(matches!(cli.command, CliCommand::Passthrough { .. }));
+    fn test_lsp_metrics() {
+        let cli = Cli::parse_from(&args(
+            "lsp --metrics /tmp/m.json --lsp-timeout=8000 --lsp-log /tmp/lsp.log --port 7658",
+        ));
+        if let CliCommand::Lsp {
+            metrics,
+            timeout,
+            log,
+            port,
+            ..
+        } = &cli.command
+        {
+            assert_eq!(metrics.as_deref(), Some("/tmp/m.json"));
+            assert_eq!(timeout.as_deref(), Some("8000"));
+            assert_eq!(log.as_deref(), Some("/tmp/lsp.log"));
+            assert_eq!(*port, Some(7658));
+        } else {
+            panic!("expected Lsp");
+        }
+    }
+
+    #[test]
+    fn test_lsp_routing_overrides() {
+        let cli = Cli::parse_from(&args(
+            "lsp --force-config ./dprint.json --extra-match **/*.md=docs --extra-match=a/**=b",
+        ));
+        if let CliCommand::Lsp {
+            force_config,
+            extra_match,
+            ..
+        } = &cli.command
+        {
+            assert_eq!(force_config.as_deref(), Some("./dprint.json"));
+            assert_eq!(extra_match, &["**/*.md=docs", "a/**=b"]);
+        } else {
+            panic!("expected Lsp");
+        }
+    }
+
+    #[test]
+    fn test_add_rule_before() {
+        let cli = Cli::parse_from(&args("add-rule **/*.md docs --before **"));
+        if let CliCommand::AddRule {
+            glob,
+            profile,
+            before,
+            yes,
+        } = &cli.command
+        {
+            assert_eq!(glob, "**/*.md");
+            assert_eq!(profile, "docs");
+            assert_eq!(before.as_deref(), Some("**"));
+            assert!(!yes);
+        } else {
+            panic!("expected AddRule");
+        }
+    }
+
+    #[test]
+    fn test_add_rule_yes() {
+        let cli = Cli::parse_from(&args("add-rule --yes src/** strict"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::AddRule { yes: true, ref glob, .. } if glob == "src/**"
+        ));
+    }
+
+    #[test]
+    fn test_add_profile() {
+        let cli = Cli::parse_from(&args("add-profile strict ./strict.jsonc"));
+        if let CliCommand::AddProfile { name, path } = &cli.command {
+            assert_eq!(name, "strict");
+            assert_eq!(path, "./strict.jsonc");
+        } else {
+            panic!("expected AddProfile");
+        }
+    }
+
+    #[test]
+    fn test_trust() {
+        let cli = Cli::parse_from(&args("trust ~/src/work"));
+        assert!(matches!(cli.command, CliCommand::Trust { dir: Some(ref d) } if d == "~/src/work"));
+        let cli = Cli::parse_from(&args("trust"));
+        assert!(matches!(cli.command, CliCommand::Trust { dir: None }));
+    }
+
+    #[test]
+    fn test_doctor() {
+        let cli = Cli::parse_from(&args("doctor"));
+        assert!(matches!(cli.command, CliCommand::Doctor { fix: false }));
+        let cli = Cli::parse_from(&args("doctor --fix"));
+        assert!(matches!(cli.command, CliCommand::Doctor { fix: true }));
+    }
+
+    #[test]
+    fn test_parity() {
+        let cli = Cli::parse_from(&args("parity --sample 20 src docs/a.md"));
+        if let CliCommand::Parity { sample, paths } = &cli.command {
+            assert_eq!(sample.as_deref(), Some("20"));
+            assert_eq!(paths, &["src", "docs/a.md"]);
+        } else {
+            panic!("expected Parity");
+        }
+        let cli = Cli::parse_from(&args("parity --sample=5"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Parity { sample: Some(ref n), ref paths } if n == "5" && paths.is_empty()
+        ));
+    }
+
+    #[test]
+    fn test_completions() {
+        let cli = Cli::parse_from(&args("completions fish"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Completions { shell: Some(ref s), install: false } if s == "fish"
+        ));
+        let cli = Cli::parse_from(&args("completions --install"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Completions {
+                shell: None,
+                install: true
+            }
+        ));
+        let cli = Cli::parse_from(&args("completions --install bash"));
+        assert!(matches!(
+            cli.command,
+            CliCommand::Completions { shell: Some(ref s), install: true } if s == "bash"
+        ));
+    }
+
+    #[test]
+    fn test_subcommand_help() {
+        for command in ["fmt", "check", "config", "lsp"] {
+            let err = Cli::try_parse_from(&args(&format!("{command} --help"))).unwrap_err();
+            assert_eq!(err.kind(), clap::error::ErrorKind::DisplayHelp);
+            assert!(
+                err.to_string()
+                    .contains(&format!("Usage: dprintx {command}"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_malformed_flags() {
+        let err = Cli::try_parse_from(&args("fmt a.ts --config")).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidValue);
+        let err = Cli::try_parse_from(&args("check --bogus")).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::UnknownArgument);
+        let err = Cli::try_parse_from(&args("check --format xml")).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidValue);
+        let err = Cli::try_parse_from(&args("add-profile strict")).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+        let err = Cli::try_parse_from(&args("lsp --port x")).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn test_passthrough_keeps_globals() {
+        let cli = Cli::parse_from(&args("license --config /c.jsonc --events"));
+        assert_eq!(cli.config.as_deref(), Some("/c.jsonc"));
+        assert!(cli.events);
+        assert!(
+            matches!(cli.command, CliCommand::Passthrough { ref args } if args == &["license"])
+        );
     }
 }
@@ -0,0 +1,614 @@
+//! Shell completion for dprintx's own commands, generated from their clap
+//! definitions in [`crate::cli`] and appended to dprint's completion script.
+//! Past the command name, dprint's commands that dprintx passes through keep
+//! dprint's completion. Profile names and recently inspected paths come from
+//! the hidden `dprintx __complete` command at completion time.
+
+use std::fmt::Write;
+
+use clap::{Arg, ArgAction, Command, CommandFactory, ValueHint};
+
+use crate::cli::Cli;
+
+/// dprint commands that dprintx passes through, offered next to its own.
+const DPRINT_COMMANDS: &[(&str, &str)] = &[
+    (
+        "init",
+        "Initializes a configuration file in the current directory",
+    ),
+    ("clear-cache", "Deletes the plugin cache directory"),
+    (
+        "output-resolved-config",
+        "Prints the resolved configuration for the plugins",
+    ),
+    (
+        "output-format-times",
+        "Prints the amount of time it takes to format each file",
+    ),
+    ("upgrade", "Upgrades the dprint executable"),
+    ("license", "Outputs the software license"),
+];
+
+/// What a flag value or positional argument completes to.
+#[derive(Debug, PartialEq)]
+enum Value {
+    Any,
+    Files,
+    Dirs,
+    Profiles,
+    /// Recently inspected paths, then files (`config <FILE>`).
+    Recent,
+    Choices(Vec<String>),
+}
+
+#[derive(Debug)]
+struct Flag {
+    /// `--long`, visible aliases and `-s`.
+    names: Vec<String>,
+    help: String,
+    /// The value's name in help, e.g. `PATH`.
+    value_name: String,
+    value: Option<Value>,
+    repeat: bool,
+}
+
+/// A command and what its arguments complete to.
+#[derive(Debug)]
+struct Spec {
+    /// Command names from the top: empty for `dprintx` itself.
+    path: Vec<String>,
+    about: String,
+    flags: Vec<Flag>,
+    positionals: Vec<(String, Value)>,
+    /// The last positional takes any number of values.
+    repeat_last: bool,
+    subcommands: Vec<Spec>,
+}
+
+/// Completion code for dprintx's commands in `shell`; empty for shells
+/// other than zsh, bash and fish.
+pub fn script(shell: &str) -> String {
+    let mut cli = Cli::command();
+    // Propagates `--config` and `--events` to the subcommands.
+    cli.build();
+    let root = spec(&cli, Vec::new());
+    match shell {
+        "zsh" => zsh(&root),
+        "bash" => bash(&root),
+        "fish" => fish(&root),
+        _ => String::new(),
+    }
+}
+
+fn spec(cmd: &Command, path: Vec<String>) -> Spec {
+    let visible = |arg: &&Arg| !arg.is_hide_set();
+    let flags = cmd
+        .get_arguments()
+        .filter(visible)
+        .filter(|arg| !arg.is_positional())
+        .map(|arg| {
+            let long = arg.get_long_and_visible_aliases().unwrap_or_default();
+            let short = arg.get_short_and_visible_aliases().unwrap_or_default();
+            Flag {
+                names: long
+                    .iter()
+                    .map(|l| format!("--{l}"))
+                    .chain(short.iter().map(|s| format!("-{s}")))
+                    .collect(),
+                help: help(arg),
+                value_name: value_name(arg),
+                value: arg.get_action().takes_values().then(|| value(arg, false)),
+                repeat: matches!(arg.get_action(), ArgAction::Append),
+            }
+        })
+        .collect();
+    let positionals: Vec<&Arg> = cmd.get_positionals().filter(visible).collect();
+    // `config <FILE>` records the paths it is asked about.
+    let recent = path == ["config"];
+    Spec {
+        about: cmd.get_about().map(|a| a.to_string()).unwrap_or_default(),
+        flags,
+        repeat_last: positionals
+            .last()
+            .is_some_and(|arg| matches!(arg.get_action(), ArgAction::Append)),
+        positionals: positionals
+            .iter()
+            .map(|arg| (value_name(arg), value(arg, recent)))
+            .collect(),
+        subcommands: cmd
+            .get_subcommands()
+            .filter(|sub| !sub.is_hide_set())
+            .map(|sub| {
+                let mut path = path.clone();
+                path.push(sub.get_name().to_string());
+                spec(sub, path)
+            })
+            .collect(),
+        path,
+    }
+}
+
+fn value(arg: &Arg, recent: bool) -> Value {
+    match arg.get_value_hint() {
+        ValueHint::FilePath if recent => Value::Recent,
+        ValueHint::AnyPath | ValueHint::FilePath | ValueHint::ExecutablePath => Value::Files,
+        ValueHint::DirPath => Value::Dirs,
+        _ => {
+            let choices: Vec<String> = arg
+                .get_possible_values()
+                .iter()
+                .filter(|v| !v.is_hide_set())
+                .map(|v| v.get_name().to_string())
+                .collect();
+            if !choices.is_empty() {
+                Value::Choices(choices)
+            } else if value_name(arg).starts_with("PROFILE") {
+                Value::Profiles
+            } else {
+                Value::Any
+            }
+        }
+    }
+}
+
+fn value_name(arg: &Arg) -> String {
+    match arg.get_value_names() {
+        Some([name, ..]) => name.to_string(),
+        _ => arg.get_id().as_str().to_uppercase(),
+    }
+}
+
+/// The first line of `arg`'s help.
+fn help(arg: &Arg) -> String {
+    let help = arg.get_help().map(|h| h.to_string()).unwrap_or_default();
+    help.lines().next().unwrap_or_default().to_string()
+}
+
+/// The shell function completing `spec`.
+fn function_name(spec: &Spec) -> String {
+    if spec.path.is_empty() {
+        "_dprintx".to_string()
+    } else {
+        format!("_dprintx_{}", spec.path.join("_").replace('-', "_"))
+    }
+}
+
+/// `spec` and everything under it, parents first.
+fn all(spec: &Spec) -> Vec<&Spec> {
+    let mut specs = vec![spec];
+    specs.extend(spec.subcommands.iter().flat_map(all));
+    specs
+}
+
+/// Subcommand names and descriptions; for `dprintx` itself, dprint's follow.
+fn commands(spec: &Spec) -> Vec<(&str, &str)> {
+    let mut commands: Vec<(&str, &str)> = spec
+        .subcommands
+        .iter()
+        .map(|sub| {
+            (
+                sub.path.last().map_or("", String::as_str),
+                sub.about.as_str(),
+            )
+        })
+        .collect();
+    if spec.path.is_empty() {
+        commands.extend_from_slice(DPRINT_COMMANDS);
+    }
+    commands
+}
+
+/// The flags of `spec` that take a value, for skipping it.
+fn value_flags(spec: &Spec) -> Vec<&str> {
+    spec.flags
+        .iter()
+        .filter(|flag| flag.value.is_some())
+        .flat_map(|flag| flag.names.iter().map(String::as_str))
+        .collect()
+}
+
+/// `s` as a single-quoted POSIX shell word.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn zsh(root: &Spec) -> String {
+    let mut out = String::from(
+        r#"
+_dprintx_profiles() {
+  local -a profiles
+  profiles=(${(f)"$(dprintx __complete profiles 2>/dev/null)"})
+  _describe 'profile' profiles
+}
+
+_dprintx_recent() {
+  local -a recent
+  recent=(${(f)"$(dprintx __complete recent 2>/dev/null)"})
+  _alternative 'recent:recent path:compadd -a recent' 'files:file:_files'
+}
+"#,
+    );
+    for spec in all(root) {
+        out.push_str(&zsh_function(spec));
+    }
+    out.push_str("\ncompdef _dprintx dprintx dprint\n");
+    out
+}
+
+/// `words[1]` is the command of `spec`; its subcommand gets the words from
+/// its own name on.
+fn zsh_function(spec: &Spec) -> String {
+    let mut out = format!("\n{}() {{\n", function_name(spec));
+    if !spec.subcommands.is_empty() {
+        out.push_str("  local i\n  for ((i = 2; i < CURRENT; i++)); do\n    case ${words[i]} in\n");
+        let value_flags = value_flags(spec);
+        if !value_flags.is_empty() {
+            let _ = writeln!(out, "      ({}) (( i++ )) ;;", value_flags.join("|"));
+        }
+        out.push_str("      (-*) ;;\n");
+        for sub in &spec.subcommands {
+            let _ = writeln!(
+                out,
+                "      ({}) words=(\"${{(@)words[i,-1]}}\"); (( CURRENT -= i - 1 )); {}; return ;;",
+                sub.path.last().unwrap(),
+                function_name(sub)
+            );
+        }
+        if spec.path.is_empty() {
+            out.push_str("      (*) _dprint \"$@\"; return ;;\n");
+        } else {
+            out.push_str("      (*) break ;;\n");
+        }
+        out.push_str("    esac\n  done\n");
+        out.push_str("  if (( i == CURRENT )) && [[ $PREFIX != -* ]]; then\n");
+        let items: Vec<String> = commands(spec)
+            .iter()
+            .map(|(name, about)| sh_quote(&format!("{name}:{about}")))
+            .collect();
+        let _ = writeln!(
+            out,
+            "    local -a commands\n    commands=({})",
+            items.join(" ")
+        );
+        let what = spec.path.last().map_or("dprintx", String::as_str);
+        let _ = writeln!(
+            out,
+            "    _describe -t commands '{what} command' commands\n  fi"
+        );
+    }
+    out.push_str("  _arguments -s");
+    for flag in &spec.flags {
+        for name in &flag.names {
+            let help = flag.help.replace('[', r"\[").replace(']', r"\]");
+            let spec = match &flag.value {
+                None => format!("{name}[{help}]"),
+                Some(value) => format!("{name}=[{help}]:{}", zsh_value(&flag.value_name, value)),
+            };
+            let repeat = if flag.repeat { "*" } else { "" };
+            let _ = write!(out, " \\\n    {}", sh_quote(&format!("{repeat}{spec}")));
+        }
+    }
+    for (i, (name, value)) in spec.positionals.iter().enumerate() {
+        let repeat = if spec.repeat_last && i + 1 == spec.positionals.len() {
+            "*"
+        } else {
+            ""
+        };
+        let _ = write!(
+            out,
+            " \\\n    {}",
+            sh_quote(&format!("{repeat}:{}", zsh_value(name, value)))
+        );
+    }
+    out.push_str("\n}\n");
+    out
+}
+
+/// `<message>:<action>` of an `_arguments` spec.
+fn zsh_value(name: &str, value: &Value) -> String {
+    let action = match value {
+        Value::Any => " ".to_string(),
+        Value::Files => "_files".to_string(),
+        Value::Dirs => "_files -/".to_string(),
+        Value::Profiles => "_dprintx_profiles".to_string(),
+        Value::Recent => "_dprintx_recent".to_string(),
+        Value::Choices(choices) => format!("({})", choices.join(" ")),
+    };
+    format!("{}:{action}", name.replace(':', r"\:"))
+}
+
+fn bash(root: &Spec) -> String {
+    let mut out = String::new();
+    for spec in all(root) {
+        out.push_str(&bash_function(spec));
+    }
+    out.push_str("\ncomplete -F _dprintx -o bashdefault -o default dprintx dprint\n");
+    out
+}
+
+/// `$1` is the index of the command of `spec` in `COMP_WORDS` (none for
+/// `dprintx` itself).
+fn bash_function(spec: &Spec) -> String {
+    let mut out = format!(
+        "\n{}() {{\n  local start=${{1:-0}} cur=${{COMP_WORDS[COMP_CWORD]}} prev=${{COMP_WORDS[COMP_CWORD-1]}}\n",
+        function_name(spec)
+    );
+    let value_flags = value_flags(spec);
+
+    // Count the positionals before the word being completed, dispatching to
+    // a subcommand in first position.
+    out.push_str("  local i n=0\n  for ((i = start + 1; i < COMP_CWORD; i++)); do\n    case ${COMP_WORDS[i]} in\n");
+    if !value_flags.is_empty() {
+        let _ = writeln!(out, "      {}) ((i++)) ;;", value_flags.join("|"));
+    }
+    out.push_str("      -*) ;;\n");
+    if !spec.subcommands.is_empty() {
+        out.push_str("      *)\n        if ((n == 0)); then\n          case ${COMP_WORDS[i]} in\n");
+        for sub in &spec.subcommands {
+            let _ = writeln!(
+                out,
+                "            {}) {} $i; return ;;",
+                sub.path.last().unwrap(),
+                function_name(sub)
+            );
+        }
+        if spec.path.is_empty() {
+            out.push_str("            *) _dprint \"$@\"; return ;;\n");
+        }
+        out.push_str("          esac\n        fi\n        ((n++)) ;;\n");
+    } else {
+        out.push_str("      *) ((n++)) ;;\n");
+    }
+    out.push_str("    esac\n  done\n");
+
+    // The word being completed is for this command.
+    if !value_flags.is_empty() {
+        out.push_str("  case $prev in\n");
+        for flag in &spec.flags {
+            if let Some(value) = &flag.value {
+                let _ = writeln!(
+                    out,
+                    "    {}) {}; return ;;",
+                    flag.names.join("|"),
+                    bash_value(value)
+                );
+            }
+        }
+        out.push_str("  esac\n");
+    }
+    let names: Vec<&str> = spec
+        .flags
+        .iter()
+        .flat_map(|flag| flag.names.iter().map(String::as_str))
+        .collect();
+    let _ = writeln!(
+        out,
+        "  if [[ $cur == -* ]]; then\n    COMPREPLY=($(compgen -W {} -- \"$cur\"))\n    return\n  fi",
+        sh_quote(&names.join(" "))
+    );
+    out.push_str("  case $n in\n");
+    for (i, (_, value)) in spec.positionals.iter().enumerate() {
+        let last = i + 1 == spec.positionals.len();
+        let pattern = if last && spec.repeat_last {
+            "*".to_string()
+        } else {
+            i.to_string()
+        };
+        let action = bash_value(value);
+        if i == 0 && !spec.subcommands.is_empty() {
+            let _ = writeln!(
+                out,
+                "    {pattern}) {action}; COMPREPLY+=($(compgen -W {} -- \"$cur\")) ;;",
+                sh_quote(&command_names(spec))
+            );
+        } else {
+            let _ = writeln!(out, "    {pattern}) {action} ;;");
+        }
+    }
+    if spec.positionals.is_empty() && !spec.subcommands.is_empty() {
+        let _ = writeln!(
+            out,
+            "    0) COMPREPLY=($(compgen -W {} -- \"$cur\")) ;;",
+            sh_quote(&command_names(spec))
+        );
+    }
+    out.push_str("  esac\n}\n");
+    out
+}
+
+fn command_names(spec: &Spec) -> String {
+    let names: Vec<&str> = commands(spec).iter().map(|(name, _)| *name).collect();
+    names.join(" ")
+}
+
+/// Sets `COMPREPLY` for `cur`.
+fn bash_value(value: &Value) -> String {
+    let words = |words: &str| format!("COMPREPLY=($(compgen -W {words} -- \"$cur\"))");
+    match value {
+        Value::Any => "COMPREPLY=()".to_string(),
+        Value::Files => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+        Value::Dirs => "COMPREPLY=($(compgen -d -- \"$cur\"))".to_string(),
+        Value::Profiles => words("\"$(dprintx __complete profiles 2>/dev/null)\""),
+        Value::Recent => {
+            words("\"$(dprintx __complete recent 2>/dev/null)\"")
+                + "; COMPREPLY+=($(compgen -f -- \"$cur\"))"
+        }
+        Value::Choices(choices) => words(&sh_quote(&choices.join(" "))),
+    }
+}
+
+fn fish(root: &Spec) -> String {
+    let mut out = String::from("\nfor cmd in dprintx dprint\n");
+    for spec in all(root) {
+        fish_lines(spec, &mut out);
+    }
+    out.push_str("end\n");
+    out
+}
+
+/// `s` as a fish single-quoted string.
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// `complete` lines for `spec`, which apply once its command is on the
+/// line and none of its subcommands is.
+fn fish_lines(spec: &Spec, out: &mut String) {
+    let mut condition = match spec.path.last() {
+        None => "__fish_use_subcommand".to_string(),
+        Some(name) => format!("__fish_seen_subcommand_from {name}"),
+    };
+    if !spec.path.is_empty() && !spec.subcommands.is_empty() {
+        let _ = write!(
+            condition,
+            "; and not __fish_seen_subcommand_from {}",
+            command_names(spec)
+        );
+    }
+    let complete = format!("  complete -c $cmd -n {}", fish_quote(&condition));
+    for (name, about) in commands(spec) {
+        let _ = writeln!(out, "{complete} -f -a {name} -d {}", fish_quote(about));
+    }
+    for flag in &spec.flags {
+        let mut line = complete.clone();
+        for name in &flag.names {
+            match name.strip_prefix("--") {
+                Some(long) => {
+                    let _ = write!(line, " -l {long}");
+                }
+                None => {
+                    let _ = write!(line, " -s {}", name.trim_start_matches('-'));
+                }
+            }
+        }
+        if let Some(value) = &flag.value {
+            line.push_str(&fish_value(value, true));
+        }
+        let _ = writeln!(out, "{line} -d {}", fish_quote(&flag.help));
+    }
+    for (_, value) in &spec.positionals {
+        let value = fish_value(value, false);
+        if !value.is_empty() {
+            let _ = writeln!(out, "{complete}{value}");
+        }
+    }
+    if spec.positionals.is_empty() && spec.subcommands.is_empty() {
+        let _ = writeln!(out, "{complete} -f");
+    }
+}
+
+/// Options of a `complete` line for `value`, of a flag or a positional
+/// (which completes files unless told otherwise).
+fn fish_value(value: &Value, flag: bool) -> String {
+    let args = |args: &str| fish_quote(args);
+    match (value, flag) {
+        (Value::Any, true) => " -x".to_string(),
+        (Value::Files, true) => " -r -F".to_string(),
+        (Value::Any | Value::Files, false) => String::new(),
+        (Value::Dirs, _) => format!(" -x -a {}", args("(__fish_complete_directories)")),
+        (Value::Profiles, _) => {
+            format!(
+                " -x -a {}",
+                args("(dprintx __complete profiles 2>/dev/null)")
+            )
+        }
+        (Value::Recent, _) => format!(" -a {}", args("(dprintx __complete recent 2>/dev/null)")),
+        (Value::Choices(choices), _) => format!(" -x -a {}", args(&choices.join(" "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'a>(spec: &'a Spec, path: &[&str]) -> &'a Spec {
+        all(spec).into_iter().find(|s| s.path == path).unwrap()
+    }
+
+    #[test]
+    fn test_spec() {
+        let mut cli = Cli::command();
+        cli.build();
+        let root = spec(&cli, Vec::new());
+        assert!(root.flags.iter().any(|f| f.names == ["--config"]));
+        assert!(!root.subcommands.iter().any(|s| s.path == ["__complete"]));
+
+        let fmt = find(&root, &["fmt"]);
+        let stdin = fmt.flags.iter().find(|f| f.names[0] == "--stdin").unwrap();
+        assert_eq!(stdin.names, ["--stdin", "--assume-filename"]);
+        assert_eq!(stdin.value, Some(Value::Files));
+        let rule = fmt.flags.iter().find(|f| f.names[0] == "--match").unwrap();
+        assert!(rule.repeat);
+        assert_eq!(rule.value, Some(Value::Any));
+        assert!(fmt.repeat_last);
+        assert!(fmt.flags.iter().any(|f| f.names == ["--config"]));
+
+        let check = find(&root, &["check"]);
+        let format = check
+            .flags
+            .iter()
+            .find(|f| f.names[0] == "--format")
+            .unwrap();
+        assert_eq!(format.value, Some(Value::Choices(vec!["json".into()])));
+
+        let config = find(&root, &["config"]);
+        assert_eq!(config.positionals, [("FILE".to_string(), Value::Recent)]);
+        let diff = find(&root, &["config", "diff"]);
+        assert_eq!(diff.positionals[0].1, Value::Profiles);
+        assert_eq!(diff.positionals[1].1, Value::Profiles);
+        let add_rule = find(&root, &["add-rule"]);
+        assert_eq!(add_rule.positionals[0].1, Value::Any);
+        assert_eq!(add_rule.positionals[1].1, Value::Profiles);
+        assert_eq!(find(&root, &["trust"]).positionals[0].1, Value::Dirs);
+        let yes = add_rule
+            .flags
+            .iter()
+            .find(|f| f.names[0] == "--yes")
+            .unwrap();
+        assert_eq!(yes.names, ["--yes", "-y"]);
+    }
+
+    #[test]
+    fn test_scripts() {
+        let zsh = script("zsh");
+        assert!(zsh.contains("\n_dprintx_config_diff() {\n"));
+        assert!(zsh.contains("'--stdin=[Format stdin as if it were this file]:PATH:_files'"));
+        assert!(zsh.contains("':PROFILE_A:_dprintx_profiles'"));
+        assert!(zsh.contains("(*) _dprint \"$@\"; return ;;"));
+        assert!(zsh.contains("'license:Outputs the software license'"));
+
+        let bash = script("bash");
+        assert!(bash.contains(
+            "--stdin|--assume-filename) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;"
+        ));
+        assert!(bash.contains("diff) _dprintx_config_diff $i; return ;;"));
+
+        let fish = script("fish");
+        assert!(fish.contains(
+            "complete -c $cmd -n '__fish_seen_subcommand_from diff' -x -a '(dprintx __complete profiles 2>/dev/null)'"
+        ));
+        assert!(fish.contains(
+            "-n '__fish_seen_subcommand_from config; and not __fish_seen_subcommand_from diff' -l describe"
+        ));
+
+        assert_eq!(script("powershell"), "");
+    }
+
+    #[test]
+    fn test_bash_syntax() {
+        let Ok(mut bash) = std::process::Command::new("bash")
+            .args(["-n"])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+        else {
+            return;
+        };
+        use std::io::Write;
+        bash.stdin
+            .take()
+            .unwrap()
+            .write_all(script("bash").as_bytes())
+            .unwrap();
+        assert!(bash.wait().unwrap().success());
+    }
+}
@@ -1,9 +1,18 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use regex::{RegexSet, RegexSetBuilder};
 use serde::Deserialize;
 use serde_json::Map;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::error::DprintxError;
+use crate::fsutil;
+use crate::jsonc;
+use crate::remote::RemoteExtends;
+use crate::trust;
+use crate::warnings;
+
+/// Profile name given to `dprintx lsp --force-config`.
+const FORCED_PROFILE: &str = "forced";
 
 /// Result of resolving a profile name.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -14,8 +23,131 @@ pub enum ProfileResolution {
     Ignore,
 }
 
-/// Counter for generating unique temp file names within a process.
-static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// How the LSP proxy sets `rootUri` when initializing a backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LspRoot {
+    /// Directory of the profile (or merged) config the backend serves.
+    #[default]
+    ConfigDir,
+    /// The client's own workspace root, passed through unchanged.
+    Client,
+    /// Directory of the document that caused the backend to spawn.
+    FileDir,
+}
+
+/// Format of `check` diffs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiffFormat {
+    /// `diff -u`: changed lines as `-`/`+` pairs, amid context lines.
+    #[default]
+    Unified,
+    /// `diff -c`: old and new version of each hunk one after the other.
+    Context,
+}
+
+/// How `check` renders the diffs of one profile, from
+/// [`DprintxConfig::diff_settings`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffSettings {
+    pub pager: Option<String>,
+    pub format: DiffFormat,
+    /// Context lines around each change.
+    pub context: u64,
+}
+
+/// What to do when a project's local dprint config cannot be read or parsed
+/// while building a merged config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LocalConfigErrors {
+    /// Abort the command.
+    #[default]
+    Error,
+    /// Warn and format with the profile config alone.
+    UseProfile,
+    /// Warn and leave the file unformatted.
+    Skip,
+}
+
+/// Settings for building merged configs, taken from [`DprintxConfig::merge_options`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MergeOptions<'a> {
+    /// What to do with unreadable/unparsable local configs.
+    pub on_error: LocalConfigErrors,
+    /// Vendor remote `extends` URLs, if configured.
+    pub remote_extends: Option<&'a RemoteExtends>,
+    /// Plugins the local config may bring in (the profile's `local_plugins`).
+    pub local_plugins: LocalPlugins<'a>,
+    /// Directories whose local configs may be merged; None: all of them.
+    pub trusted_dirs: Option<&'a [PathBuf]>,
+    /// Merge nothing: `merge_local` is off.
+    pub profile_only: bool,
+}
+
+/// Which of a local config's `plugins` survive merging, from the profile
+/// option `local_plugins`: a list of URL globs or `"profile"`.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum LocalPlugins<'a> {
+    /// Keep them all.
+    #[default]
+    Any,
+    /// Keep those matching one of these URL globs.
+    Allow(&'a [serde_json::Value]),
+    /// Drop them, so the profile's plugins apply.
+    Profile,
+}
+
+impl LocalPlugins<'_> {
+    /// Filter `config["plugins"]`, warning about each dropped plugin. An
+    /// emptied list is removed so the profile's plugins apply.
+    fn apply(self, config: &mut serde_json::Value, local_config_path: &Path) {
+        let globs = match self {
+            LocalPlugins::Any => return,
+            LocalPlugins::Allow(globs) => globs,
+            LocalPlugins::Profile => &[],
+        };
+        let Some(serde_json::Value::Array(plugins)) = config.get_mut("plugins") else {
+            return;
+        };
+        let allowed: Vec<globset::GlobMatcher> = globs
+            .iter()
+            .filter_map(|g| globset::Glob::new(g.as_str()?).ok())
+            .map(|g| g.compile_matcher())
+            .collect();
+        plugins.retain(|plugin| {
+            let url = plugin.as_str().unwrap_or_default();
+            let keep = allowed.iter().any(|g| g.is_match(url));
+            if !keep && matches!(self, LocalPlugins::Allow(_)) {
+                warnings::warn(
+                    "local-plugins",
+                    format!(
+                        "{}: plugin {plugin} is not in local_plugins; dropped",
+                        local_config_path.display()
+                    ),
+                );
+            }
+            keep
+        });
+        if plugins.is_empty()
+            && let Some(obj) = config.as_object_mut()
+        {
+            obj.remove("plugins");
+        }
+    }
+}
+
+/// Outcome of merging a file's local dprint config with its profile config.
+pub enum Merge {
+    /// Local config with the profile injected into `extends`.
+    Merged(TempConfig),
+    /// No local config (or it is the profile itself, or it was unusable
+    /// under `use-profile`): use the profile config directly.
+    Profile,
+    /// Local config unusable under `skip`: leave the file alone.
+    Skip,
+}
 
 /// RAII guard for a temporary merged config file. Deletes the file on drop.
 pub struct TempConfig {
@@ -55,7 +187,7 @@ impl Drop for TempConfig {
 ///   },
 /// }
 /// ```
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct DprintxConfig {
     /// Directory containing the dprintx.jsonc config file.
     /// Used to resolve relative paths in profile configs.
@@ -66,20 +198,48 @@ pub struct DprintxConfig {
     /// Path to real dprint binary.
     pub dprint: String,
 
-    /// Named profiles: name → config path (string) or null (ignore).
+    /// Named profiles: name → config path (string), null (ignore), or an
+    /// object `{ "config": path, "max_file_size": bytes, "severity": "warning" }`
+    /// for per-profile options (and a `description`, and the `diff_*`
+    /// settings).
     pub profiles: Map<String, serde_json::Value>,
 
-    /// Ordered match rules: glob pattern → profile name.
+    /// Ordered match rules: glob pattern → profile name, or an object
+    /// `{ "profile": name, "description": text }` (see [`rule_profile`]).
     /// Uses serde_json::Map with preserve_order for first-match semantics.
     #[serde(rename = "match")]
     pub match_rules: Map<String, serde_json::Value>,
 
+    /// Globs of files never formatted, checked before pins and any match
+    /// rule, so common exclusions need no null profile placed ahead of the
+    /// catch-all rules. Default: none.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+
     /// Ordered content match rules: regex pattern → profile name.
     /// Applied after path match. Scans entire file in line-aligned blocks.
     /// First match wins and overrides the path-matched profile.
     #[serde(default)]
     pub match_content: Option<Map<String, serde_json::Value>>,
 
+    /// Comment markers accepted before a `dprintx-profile: NAME` pin on a
+    /// file's first line. Unset: common markers (`//`, `#`, `/*`, `<!--`, ...);
+    /// empty list: pinning disabled.
+    #[serde(default)]
+    pub pin_markers: Option<Vec<String>>,
+
+    /// Comment markers accepted before a `dprintx-disable-file` comment,
+    /// by file extension (`"sql": ["--"]`). Extensions not listed use the
+    /// pin markers.
+    #[serde(default)]
+    pub disable_markers: std::collections::HashMap<String, Vec<String>>,
+
+    /// LSP languageId → profile name, consulted by the LSP proxy before path
+    /// rules for documents whose languageId disagrees with their extension
+    /// (e.g. `yaml.ansible`, templated files).
+    #[serde(default)]
+    pub match_language: Option<Map<String, serde_json::Value>>,
+
     /// Optional diff pager command for `dprint check` (e.g. "delta -s").
     /// When set, check produces unified diff output:
     /// - stdout is TTY → pipe through pager
@@ -87,6 +247,28 @@ pub struct DprintxConfig {
     #[serde(default)]
     pub diff_pager: Option<String>,
 
+    /// Format of `check` diffs: "unified" (default) or "context".
+    #[serde(default)]
+    pub diff_format: DiffFormat,
+
+    /// Context lines around each change in `check` diffs. Default: 3.
+    #[serde(default)]
+    pub diff_context: Option<u64>,
+
+    /// Optional Prometheus textfile-collector output (e.g.
+    /// "/var/lib/node_exporter/textfile/dprintx.prom"). The CLI runner adds
+    /// per-profile counts/durations after each run; the LSP proxy refreshes
+    /// its per-backend series periodically.
+    #[serde(default)]
+    pub metrics_textfile: Option<String>,
+
+    /// Shell command run after `fmt`/`check` (not `--stdin`), with the
+    /// outcome in its environment: `DPRINTX_COMMAND`, `DPRINTX_EXIT_CODE`,
+    /// `DPRINTX_FILES`, `DPRINTX_FILES_CHANGED` (fmt), `DPRINTX_FAILURES`
+    /// and `DPRINTX_DURATION_MS`. Default: none.
+    #[serde(default)]
+    pub post_run: Option<String>,
+
     /// Rewrite file URIs in LSP based on editor's languageId.
     /// When true, the proxy appends the correct file extension to URIs
     /// forwarded to dprint, so files without extensions (or with wrong ones)
@@ -94,45 +276,458 @@ pub struct DprintxConfig {
     /// Default: false (transparent passthrough).
     #[serde(default)]
     pub lsp_rewrite_uris: bool,
+
+    /// LSP methods for which the proxy answers unserved requests (backend
+    /// timeout, no matching profile) with a JSON-RPC `RequestFailed` error
+    /// instead of a null result. `"*"` matches every method.
+    /// Default: empty (null results, silent).
+    #[serde(default)]
+    pub lsp_error_methods: Vec<String>,
+
+    /// Milliseconds the LSP proxy waits for a backend response before
+    /// answering the request itself (null or an error). `dprintx lsp
+    /// --lsp-timeout` overrides it. Default: 5000.
+    #[serde(default)]
+    pub lsp_timeout_ms: Option<u64>,
+
+    /// File the LSP proxy appends a JSON line to for every routed message
+    /// and every answered request (see `dprintx lsp --lsp-log`, which
+    /// overrides it). Default: none.
+    #[serde(default)]
+    pub lsp_log: Option<String>,
+
+    /// Upper bound on live LSP backends. When reached, the least recently
+    /// used merged-config backend is shut down before spawning another.
+    /// Profile backends are never evicted. Default: unbounded.
+    #[serde(default)]
+    pub lsp_max_backends: Option<usize>,
+
+    /// Largest LSP message body (bytes) the proxy reads. Larger messages
+    /// are skipped and answered with an error. Default: 64 MiB.
+    #[serde(default)]
+    pub lsp_max_message_size: Option<usize>,
+
+    /// rootUri policy for backend `initialize`: "config-dir" (default),
+    /// "client" (keep the client's workspace root, e.g. for plugins with
+    /// relative paths) or "file-dir" (the triggering document's directory).
+    #[serde(default)]
+    pub lsp_root: LspRoot,
+
+    /// Check documents on open and save in LSP mode and publish a
+    /// diagnostic, with a code action to format, when one is not formatted
+    /// per its profile. Default: false.
+    #[serde(default)]
+    pub lsp_diagnostics: bool,
+
+    /// Format LSP formatting requests with the editor's `tabSize` and
+    /// `insertSpaces` (as dprint's `indentWidth` and `useTabs`) over the
+    /// effective config. Default: false (the profile decides).
+    #[serde(default)]
+    pub lsp_honor_editor_options: bool,
+
+    /// Profile formatting LSP documents without a file path (`untitled:`
+    /// buffers, virtual documents) whose languageId no `match_language`
+    /// rule maps. Default: none (they are left alone).
+    #[serde(default)]
+    pub lsp_untitled_profile: Option<String>,
+
+    /// Files larger than this (bytes) are skipped with a warning by
+    /// fmt/check. Profiles may override it with their own `max_file_size`.
+    #[serde(default)]
+    pub max_file_size: Option<u64>,
+
+    /// Exit 0 when fmt/check find no file matching any profile, instead of
+    /// failing like dprint does (exit code 14). `--allow-no-files` sets it
+    /// for one run. Default: false.
+    #[serde(default)]
+    pub allow_no_files: bool,
+
+    /// Extra directories that `fmt`/`check` without file arguments walk
+    /// recursively besides what each profile's dprint config enumerates.
+    /// Their files are routed by the match rules like explicit arguments.
+    /// Relative paths resolve against the config directory. Default: none.
+    #[serde(default)]
+    pub roots: Vec<String>,
+
+    /// Match files in a linked git worktree against their path in the
+    /// primary worktree, so root-anchored globs like `~/src/repo/**` cover
+    /// every checkout. Default: false.
+    #[serde(default)]
+    pub match_primary_worktree: bool,
+
+    /// Policy for local dprint configs that fail to parse during merging:
+    /// "error" (default, abort), "use-profile" or "skip" (both warn).
+    #[serde(default)]
+    pub local_config_errors: LocalConfigErrors,
+
+    /// Vendor remote `extends` URLs of merged configs (download with
+    /// timeout/size limits, cache) so dprint never fetches them itself.
+    /// Unset: remote extends are left to dprint.
+    #[serde(default)]
+    pub remote_extends: Option<RemoteExtends>,
+
+    /// Merge local dprint configs only in directories marked with
+    /// `dprintx trust`; elsewhere the profile config is used, with a
+    /// warning. Default: false (every local config is merged).
+    #[serde(default)]
+    pub require_trust: bool,
+
+    /// Merge local dprint configs into the matched profile config at all.
+    /// `--no-merge` turns it off for one run, to format with the pure
+    /// profile configs. Default: true.
+    #[serde(default = "merge_local_default")]
+    pub merge_local: bool,
+
+    /// Trusted directories, read at load time when `require_trust` is set.
+    #[serde(skip)]
+    pub trusted_dirs: Vec<PathBuf>,
+
+    /// dprint binary used because `dprint` does not exist, found by
+    /// [`discover_dprint`] at load time.
+    #[serde(skip)]
+    pub dprint_discovered: Option<PathBuf>,
+
+    /// Format stdin content and `check` diffs through long-lived
+    /// `dprint editor-service` processes (one per config) instead of one
+    /// `dprint fmt --stdin` per file. Falls back to `fmt --stdin` whenever a
+    /// backend fails. Default: false.
+    #[serde(default)]
+    pub editor_service: bool,
+
+    /// Keep formatted output of stdin content, `check` diffs and LSP
+    /// formatting in a cache keyed by content, effective config and dprint
+    /// version, so unchanged content is not formatted again. Default: false.
+    #[serde(default)]
+    pub format_cache: bool,
+}
+
+fn merge_local_default() -> bool {
+    true
 }
 
 impl DprintxConfig {
-    /// Try to load config from the default location (~/.config/dprint/dprintx.jsonc).
+    /// Try to load config from the default location (see [`Self::default_path`]).
     /// Returns Ok(None) if the file doesn't exist.
     /// Returns Err if the file exists but is invalid.
     pub fn try_load_default() -> Result<Option<Self>> {
-        let config_dir = dirs::config_dir().context("cannot determine config directory")?;
-        let path = config_dir.join("dprint").join("dprintx.jsonc");
+        let path = Self::default_path()?;
         if !path.exists() {
             return Ok(None);
         }
         Self::load(&path).map(Some)
     }
 
-    /// Load config from a specific path.
-    pub fn load(path: &Path) -> Result<Self> {
-        let content = std::fs::read_to_string(path)
-            .with_context(|| format!("cannot read config: {}", path.display()))?;
+    /// Default config location: the first existing file among
+    /// [`Self::default_candidates`], or ~/.config/dprint/dprintx.jsonc
+    /// (platform config dir) if none exists yet.
+    pub fn default_path() -> Result<PathBuf> {
+        let candidates = Self::default_candidates();
+        if let Some(found) = candidates.iter().find(|p| p.is_file()) {
+            return Ok(found.clone());
+        }
+        let config_dir = dirs::config_dir().context("cannot determine config directory")?;
+        Ok(config_dir.join("dprint").join("dprintx.jsonc"))
+    }
+
+    /// Config locations in precedence order:
+    ///
+    /// 1. `$XDG_CONFIG_HOME/dprintx/config.jsonc`
+    /// 2. `<config dir>/dprint/dprintx.jsonc` (`~/.config` on Linux)
+    /// 3. `~/.config/dprintx/config.jsonc` and `~/.config/dprint/dprintx.jsonc`
+    ///    — the host locations when `XDG_CONFIG_HOME` is redirected by a
+    ///    sandbox (flatpak sets it to `~/.var/app/<id>/config`).
+    pub fn default_candidates() -> Vec<PathBuf> {
+        let xdg = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from);
+        config_candidates(xdg, dirs::config_dir(), dirs::home_dir())
+    }
 
-        // Strip JSONC comments (// and /* */) before parsing.
-        let json = strip_jsonc_comments(&content);
+    /// Kill-switch flag file: ~/.config/dprint/dprintx.disable.
+    pub fn disable_flag_path() -> Result<PathBuf> {
+        let config_dir = dirs::config_dir().context("cannot determine config directory")?;
+        Ok(config_dir.join("dprint").join("dprintx.disable"))
+    }
 
-        let mut config: DprintxConfig =
-            serde_json::from_str(&json).with_context(|| "invalid dprintx.jsonc format")?;
+    /// True if dprintx is disabled via `DPRINTX_DISABLE` or the flag file,
+    /// in which case every command passes straight through to dprint.
+    pub fn is_disabled() -> bool {
+        let env = std::env::var("DPRINTX_DISABLE").ok();
+        let flag = Self::disable_flag_path().is_ok_and(|p| p.exists());
+        disable_requested(env.as_deref(), flag)
+    }
+
+    /// Load config from a specific path.
+    ///
+    /// Syntax and validation errors point at the offending line and column
+    /// with a snippet of the file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| DprintxError::Config {
+            path: path.to_path_buf(),
+            message: format!("cannot read config: {e}"),
+            at: None,
+        })?;
+
+        let mut config: Self = jsonc::from_str(&content).map_err(|e| {
+            located_error(
+                path,
+                &content,
+                &format!("invalid dprintx.jsonc format: {}", e.message),
+                Some((e.line, e.column)),
+            )
+        })?;
+        if let Err((message, section, key)) = config.validate() {
+            let at = jsonc::find_key(&content, section, &key);
+            return Err(located_error(path, &content, &message, at).into());
+        }
 
         // Store the config directory for resolving relative paths.
         config.config_dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        if config.require_trust {
+            config.trusted_dirs = trust::load();
+        }
+        // A stale `dprint` (say, after switching install methods) should not
+        // break every command.
+        let configured = config.configured_dprint_path();
+        if !configured.is_file()
+            && let Some(found) = discover_dprint()
+        {
+            warnings::warn(
+                "dprint",
+                format!(
+                    "{} not found, using {} (`dprintx doctor --fix` updates the config)",
+                    configured.display(),
+                    found.display()
+                ),
+            );
+            config.dprint_discovered = Some(found);
+        }
 
         Ok(config)
     }
 
-    /// Resolve dprint binary path (expand ~ and relative paths).
+    /// Parse config from JSONC text. `config_dir` is left empty.
+    pub fn parse(content: &str) -> Result<Self> {
+        jsonc::from_str(content).with_context(|| "invalid dprintx.jsonc format")
+    }
+
+    /// Apply `dprintx lsp` routing overrides. `force_config` routes every
+    /// document to one dprint config (as profile `forced`; ignore globs,
+    /// content and language rules, pins and disable comments are dropped).
+    /// `extra_match` rules (`<glob>=<profile>`) go ahead of the configured
+    /// ones.
+    pub fn apply_lsp_overrides(
+        &mut self,
+        force_config: Option<&Path>,
+        extra_match: &[String],
+    ) -> Result<()> {
+        if let Some(path) = force_config {
+            if !extra_match.is_empty() {
+                bail!("--force-config and --extra-match cannot be combined");
+            }
+            let path = std::path::absolute(path)
+                .with_context(|| format!("resolving {}", path.display()))?;
+            self.profiles.insert(
+                FORCED_PROFILE.to_string(),
+                serde_json::Value::String(path.display().to_string()),
+            );
+            self.match_rules = Map::new();
+            self.match_rules.insert(
+                "**".to_string(),
+                serde_json::Value::String(FORCED_PROFILE.to_string()),
+            );
+            self.ignore = Vec::new();
+            self.match_content = None;
+            self.match_language = None;
+            self.pin_markers = Some(Vec::new());
+            self.disable_markers.clear();
+            self.lsp_untitled_profile = Some(FORCED_PROFILE.to_string());
+            return Ok(());
+        }
+        self.prepend_match_rules("--extra-match", extra_match)
+    }
+
+    /// Put `<glob>=<profile>` rules from the command line (`flag`, for
+    /// errors) ahead of the config's match block for this run. A glob
+    /// already in the config takes the new profile and moves to the front.
+    pub fn prepend_match_rules(&mut self, flag: &str, extra_match: &[String]) -> Result<()> {
+        let mut rules = Map::new();
+        for rule in extra_match {
+            let Some((glob, profile)) = rule.rsplit_once('=') else {
+                bail!("{flag} {rule:?}: expected <glob>=<profile>");
+            };
+            if !self.profiles.contains_key(profile) {
+                bail!("{flag} {rule:?}: profile '{profile}' is not defined in profiles");
+            }
+            rules.insert(
+                glob.to_string(),
+                serde_json::Value::String(profile.to_string()),
+            );
+        }
+        for (glob, profile) in std::mem::take(&mut self.match_rules) {
+            rules.entry(glob).or_insert(profile);
+        }
+        self.match_rules = rules;
+        Ok(())
+    }
+
+    /// Check that rules name defined profiles and that globs and regexes
+    /// compile. Errors carry the section and key to point at.
+    fn validate(&self) -> std::result::Result<(), (String, &'static str, String)> {
+        for pattern in &self.ignore {
+            if let Err(e) = globset::Glob::new(&fsutil::glob_pattern(&expand_tilde(pattern))) {
+                return Err((
+                    format!("ignore: invalid pattern {pattern:?}: {e}"),
+                    "ignore",
+                    pattern.clone(),
+                ));
+            }
+        }
+        let sections = [
+            ("match", Some(&self.match_rules)),
+            ("match_content", self.match_content.as_ref()),
+            ("match_language", self.match_language.as_ref()),
+        ];
+        for (section, rules) in sections {
+            for (key, value) in rules.into_iter().flatten() {
+                let fail = |message: String| (message, section, key.clone());
+                let Some(profile) = rule_profile(value) else {
+                    return Err(fail(format!(
+                        "{section}: {key:?} must map to a profile name or {{\"profile\": ...}}, got {value}"
+                    )));
+                };
+                if !description_valid(value) {
+                    return Err(fail(format!(
+                        "{section}: {key:?} description must be a string"
+                    )));
+                }
+                if !self.profiles.contains_key(profile) {
+                    return Err(fail(format!(
+                        "{section}: {key:?} uses profile '{profile}', which is not defined in profiles"
+                    )));
+                }
+                let compiled = match section {
+                    "match" => globset::Glob::new(&fsutil::glob_pattern(&expand_tilde(key)))
+                        .map(drop)
+                        .map_err(|e| e.to_string()),
+                    "match_content" => regex::Regex::new(key).map(drop).map_err(|e| e.to_string()),
+                    _ => Ok(()),
+                };
+                if let Err(e) = compiled {
+                    return Err(fail(format!("{section}: invalid pattern {key:?}: {e}")));
+                }
+            }
+        }
+        for (name, value) in &self.profiles {
+            if self.resolve_profile(name).is_none() {
+                return Err((
+                    format!(
+                        "profiles: '{name}' must be a config path, null or {{\"config\": ...}}, got {value}"
+                    ),
+                    "profiles",
+                    name.clone(),
+                ));
+            }
+            if !description_valid(value) {
+                return Err((
+                    format!("profiles: '{name}' description must be a string"),
+                    "profiles",
+                    name.clone(),
+                ));
+            }
+            if let Some(severity) = value.get("severity")
+                && severity != "error"
+                && severity != "warning"
+            {
+                return Err((
+                    format!(
+                        "profiles: '{name}' severity must be \"error\" or \"warning\", got {severity}"
+                    ),
+                    "profiles",
+                    name.clone(),
+                ));
+            }
+            if let Some(pager) = value.get("diff_pager")
+                && !pager.is_string()
+            {
+                return Err((
+                    format!("profiles: '{name}' diff_pager must be a command, got {pager}"),
+                    "profiles",
+                    name.clone(),
+                ));
+            }
+            if let Some(format) = value.get("diff_format")
+                && serde_json::from_value::<DiffFormat>(format.clone()).is_err()
+            {
+                return Err((
+                    format!(
+                        "profiles: '{name}' diff_format must be \"unified\" or \"context\", got {format}"
+                    ),
+                    "profiles",
+                    name.clone(),
+                ));
+            }
+            if let Some(context) = value.get("diff_context")
+                && !context.is_u64()
+            {
+                return Err((
+                    format!("profiles: '{name}' diff_context must be a line count, got {context}"),
+                    "profiles",
+                    name.clone(),
+                ));
+            }
+            if let Some(plugins) = value.get("local_plugins") {
+                let valid = match plugins {
+                    serde_json::Value::String(s) => s == "profile",
+                    serde_json::Value::Array(globs) => globs
+                        .iter()
+                        .all(|g| g.as_str().is_some_and(|g| globset::Glob::new(g).is_ok())),
+                    _ => false,
+                };
+                if !valid {
+                    return Err((
+                        format!(
+                            "profiles: '{name}' local_plugins must be \"profile\" or a list of URL globs, got {plugins}"
+                        ),
+                        "profiles",
+                        name.clone(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// dprint binary to run: `dprint` (with ~ and relative paths
+    /// resolved), or the one discovered when that does not exist.
     pub fn dprint_path(&self) -> PathBuf {
+        self.dprint_discovered
+            .clone()
+            .unwrap_or_else(|| self.configured_dprint_path())
+    }
+
+    /// `dprint` as configured, with ~ and relative paths resolved.
+    pub fn configured_dprint_path(&self) -> PathBuf {
         self.resolve_path(&self.dprint)
     }
 
+    /// Resolve the Prometheus textfile path, if configured.
+    pub fn metrics_textfile_path(&self) -> Option<PathBuf> {
+        self.metrics_textfile
+            .as_deref()
+            .map(|p| self.resolve_path(p))
+    }
+
+    /// Resolve the LSP log path, if configured.
+    pub fn lsp_log_path(&self) -> Option<PathBuf> {
+        self.lsp_log.as_deref().map(|p| self.resolve_path(p))
+    }
+
+    /// Resolved `roots` directories.
+    pub fn root_paths(&self) -> Vec<PathBuf> {
+        self.roots.iter().map(|r| self.resolve_path(r)).collect()
+    }
+
     /// Resolve a path string: expand ~ and resolve relative paths against config_dir.
-    fn resolve_path(&self, path: &str) -> PathBuf {
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
         let expanded = expand_tilde(path);
         if expanded.is_relative() {
             self.config_dir.join(expanded)
@@ -155,17 +750,121 @@ impl DprintxConfig {
                 Some(ProfileResolution::Config(self.resolve_path(s)))
             }
             Some(serde_json::Value::Null) => Some(ProfileResolution::Ignore),
+            Some(serde_json::Value::Object(options)) => match options.get("config") {
+                Some(serde_json::Value::String(s)) => {
+                    Some(ProfileResolution::Config(self.resolve_path(s)))
+                }
+                Some(serde_json::Value::Null) => Some(ProfileResolution::Ignore),
+                _ => None,
+            },
             _ => None,
         }
     }
 
+    /// Options for [`merge_local_config`] with the profile `profile_config`.
+    pub fn merge_options(&self, profile_config: &Path) -> MergeOptions<'_> {
+        let local_plugins = match self.profile_option(profile_config, "local_plugins") {
+            Some(serde_json::Value::Array(globs)) => LocalPlugins::Allow(globs),
+            Some(serde_json::Value::String(s)) if s == "profile" => LocalPlugins::Profile,
+            _ => LocalPlugins::Any,
+        };
+        MergeOptions {
+            on_error: self.local_config_errors,
+            remote_extends: self.remote_extends.as_ref(),
+            local_plugins,
+            trusted_dirs: self.require_trust.then_some(self.trusted_dirs.as_slice()),
+            profile_only: !self.merge_local,
+        }
+    }
+
+    /// Size limit for files formatted with `config_path`: the profile's own
+    /// `max_file_size` if set, otherwise the global one.
+    pub fn max_file_size_for(&self, config_path: &Path) -> Option<u64> {
+        self.profile_option(config_path, "max_file_size")
+            .and_then(|v| v.as_u64())
+            .or(self.max_file_size)
+    }
+
+    /// Whether unformatted files of profile `name` are only reported by
+    /// `check`: its `severity` is `"warning"` (the default is `"error"`).
+    pub fn warns_only(&self, name: &str) -> bool {
+        self.profiles
+            .get(name)
+            .and_then(|value| value.get("severity"))
+            .is_some_and(|severity| severity == "warning")
+    }
+
+    /// Whether `check` prints diffs: `diff_pager` is set, globally or for
+    /// some profile.
+    pub fn diffs_enabled(&self) -> bool {
+        self.diff_pager.is_some()
+            || self
+                .profiles
+                .values()
+                .any(|value| value.get("diff_pager").is_some())
+    }
+
+    /// Diff settings of profile `name`: its own `diff_pager`, `diff_format`
+    /// and `diff_context` where given, the global ones otherwise.
+    pub fn diff_settings(&self, name: &str) -> DiffSettings {
+        let option = |key: &str| self.profiles.get(name).and_then(|value| value.get(key));
+        DiffSettings {
+            pager: option("diff_pager")
+                .and_then(|p| p.as_str())
+                .map(str::to_string)
+                .or_else(|| self.diff_pager.clone()),
+            format: option("diff_format")
+                .and_then(|f| serde_json::from_value(f.clone()).ok())
+                .unwrap_or(self.diff_format),
+            context: option("diff_context")
+                .and_then(|c| c.as_u64())
+                .or(self.diff_context)
+                .unwrap_or(3),
+        }
+    }
+
+    /// Option `key` of the profile resolving to `config_path`, if it is
+    /// given in object form.
+    fn profile_option(&self, config_path: &Path, key: &str) -> Option<&serde_json::Value> {
+        self.profiles
+            .iter()
+            .find(|(name, _)| {
+                matches!(self.resolve_profile(name), Some(ProfileResolution::Config(p)) if p == config_path)
+            })
+            .and_then(|(_, value)| value.get(key))
+    }
+
+    /// Human-readable label for a resolved profile config path:
+    /// the profile name if one maps to it, otherwise the path itself.
+    pub fn profile_label(&self, config_path: &Path) -> String {
+        self.profiles
+            .keys()
+            .find(|name| {
+                matches!(self.resolve_profile(name), Some(ProfileResolution::Config(p)) if p == config_path)
+            })
+            .cloned()
+            .unwrap_or_else(|| config_path.display().to_string())
+    }
+
+    /// Whether unserved LSP requests for `method` should get a JSON-RPC error.
+    pub fn lsp_error_for(&self, method: &str) -> bool {
+        self.lsp_error_methods
+            .iter()
+            .any(|m| m == "*" || m == method)
+    }
+
     /// Get ordered match rules as (glob_pattern, profile_name) pairs.
     pub fn match_rules_iter(&self) -> impl Iterator<Item = (&str, &str)> {
         self.match_rules.iter().filter_map(|(pattern, value)| {
-            value.as_str().map(|profile| (pattern.as_str(), profile))
+            rule_profile(value).map(|profile| (pattern.as_str(), profile))
         })
     }
 
+    /// Description of profile `name`, given in object form.
+    pub fn profile_description(&self, name: &str) -> Option<&str> {
+        self.profiles.get(name).and_then(description)
+    }
+
     /// Get ordered content match rules as (regex_pattern, profile_name) pairs.
     /// Returns empty iterator if match_content is not configured.
     pub fn match_content_rules_iter(&self) -> impl Iterator<Item = (&str, &str)> {
@@ -173,10 +872,24 @@ impl DprintxConfig {
             .iter()
             .flat_map(|m| m.iter())
             .filter_map(|(pattern, value)| {
-                value.as_str().map(|profile| (pattern.as_str(), profile))
+                rule_profile(value).map(|profile| (pattern.as_str(), profile))
             })
     }
 
+    /// Get language match rules as (language_id, profile_name) pairs.
+    /// Returns empty iterator if match_language is not configured.
+    pub fn match_language_rules_iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.match_language
+            .iter()
+            .flat_map(|m| m.iter())
+            .filter_map(|(lang, value)| rule_profile(value).map(|profile| (lang.as_str(), profile)))
+    }
+
+    /// Profile name mapped to an LSP languageId via match_language, if any.
+    pub fn language_profile(&self, language_id: &str) -> Option<&str> {
+        rule_profile(self.match_language.as_ref()?.get(language_id)?)
+    }
+
     /// Compile content match patterns into a RegexSet for efficient matching.
     /// Returns None if no match_content rules are configured.
     pub fn compile_content_patterns(&self) -> Result<Option<ContentMatcher>> {
@@ -191,7 +904,10 @@ impl DprintxConfig {
         let regex_set = RegexSetBuilder::new(&patterns)
             .multi_line(true)
             .build()
-            .context("invalid regex in match_content")?;
+            .map_err(|e| DprintxError::Match {
+                rule: "match_content".to_string(),
+                message: format!("invalid regex in match_content: {e}"),
+            })?;
 
         Ok(Some(ContentMatcher {
             regex_set,
@@ -213,14 +929,27 @@ pub struct ContentMatcher {
 impl ContentMatcher {
     /// Match file content against compiled patterns.
     /// Returns the profile name of the first matching pattern, or None.
+    #[cfg(test)]
     pub fn match_content(&self, content: &str) -> Option<&str> {
+        self.match_index(content)
+            .map(|idx| self.profiles[idx].as_str())
+    }
+
+    /// Index of the first pattern matching `content`, in config order.
+    pub fn match_index(&self, content: &str) -> Option<usize> {
         // RegexSet::matches returns all matches; we want first-match semantics
         // based on config order, so take the minimum index.
-        self.regex_set
-            .matches(content)
-            .iter()
-            .next()
-            .map(|idx| self.profiles[idx].as_str())
+        self.regex_set.matches(content).iter().next()
+    }
+
+    /// Number of content rules.
+    pub fn rule_count(&self) -> usize {
+        self.profiles.len()
+    }
+
+    /// Pattern and profile name of the rule at `idx`.
+    pub fn rule(&self, idx: usize) -> (&str, &str) {
+        (&self.regex_set.patterns()[idx], &self.profiles[idx])
     }
 }
 
@@ -242,17 +971,36 @@ pub fn find_local_config(start_dir: &Path) -> Option<PathBuf> {
     }
 }
 
+/// An error about `path`, followed by its location and a snippet of
+/// `content` when known.
+fn located_error(
+    path: &Path,
+    content: &str,
+    message: &str,
+    at: Option<(usize, usize)>,
+) -> DprintxError {
+    DprintxError::Config {
+        path: path.to_path_buf(),
+        message: message.to_string(),
+        at: at.map(|(line, column)| (line, column, jsonc::snippet(content, line, column))),
+    }
+}
+
 /// Read a local dprint config file as a JSON Value.
 /// Handles both .json and .jsonc (strips comments and trailing commas).
 pub fn read_local_config(path: &Path) -> Result<serde_json::Value> {
     let content =
         std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
 
-    // Strip JSONC comments if needed (safe to run on plain JSON too).
-    let json = strip_jsonc_comments(&content);
-
-    serde_json::from_str(&json)
-        .with_context(|| format!("parsing local dprint config: {}", path.display()))
+    let value = jsonc::from_str(&content).map_err(|e| {
+        located_error(
+            path,
+            &content,
+            &format!("parsing local dprint config: {}", e.message),
+            Some((e.line, e.column)),
+        )
+    })?;
+    Ok(value)
 }
 
 /// Inject a profile config path into the `extends` field of a local dprint config.
@@ -295,38 +1043,106 @@ pub fn inject_extends(config: &mut serde_json::Value, profile_config_path: &Path
 /// Build a merged config for a file: find local dprint config, inject profile
 /// extends, write to a unique temp file.
 ///
-/// Returns None if no local config is found (caller should use profile config directly).
-/// Returns a `TempConfig` guard that auto-deletes the file on drop.
+/// Returns `Merge::Profile` if no local config is found (caller should use the
+/// profile config directly), or a `TempConfig` guard that auto-deletes the
+/// file on drop. `options.on_error` decides what happens when the local config
+/// cannot be read or parsed; with `options.remote_extends` remote URLs in the
+/// local config's `extends` are replaced by vendored copies, and
+/// `options.local_plugins` filters its `plugins`. Under
+/// `options.trusted_dirs` local configs outside trusted directories are
+/// ignored, and with `options.profile_only` all of them are.
 ///
 /// The temp file is written to `$XDG_RUNTIME_DIR/dprintx/` (per-user, secure).
 /// Falls back to `$TMPDIR/dprintx/` if unavailable.
-pub fn build_merged_config(
+pub fn merge_local_config(
     file_dir: &Path,
     profile_config_path: &Path,
-) -> Result<Option<TempConfig>> {
+    options: MergeOptions<'_>,
+) -> Result<Merge> {
+    if options.profile_only {
+        return Ok(Merge::Profile);
+    }
     let local_config_path = match find_local_config(file_dir) {
         Some(p) => p,
-        None => return Ok(None),
+        None => return Ok(Merge::Profile),
     };
 
     // If the local config IS the profile config, skip merging.
     if local_config_path == profile_config_path {
-        return Ok(None);
+        return Ok(Merge::Profile);
+    }
+
+    if let Some(trusted) = options.trusted_dirs {
+        let local_dir = local_config_path.parent().unwrap_or(file_dir);
+        if !trust::is_trusted(local_dir, trusted) {
+            warnings::warn(
+                "untrusted-config",
+                format!(
+                    "{}: directory not trusted (dprintx trust {}); using profile config",
+                    local_config_path.display(),
+                    local_dir.display()
+                ),
+            );
+            return Ok(Merge::Profile);
+        }
     }
 
-    let mut local_config = read_local_config(&local_config_path)?;
+    let mut local_config = match read_local_config(&local_config_path) {
+        Ok(value) => value,
+        Err(e) => match options.on_error {
+            LocalConfigErrors::Error => return Err(e),
+            LocalConfigErrors::UseProfile => {
+                warnings::warn("local-config", format!("{e:#}; using profile config"));
+                return Ok(Merge::Profile);
+            }
+            LocalConfigErrors::Skip => {
+                warnings::warn("local-config", format!("{e:#}; skipping its files"));
+                return Ok(Merge::Skip);
+            }
+        },
+    };
+    if let Some(remote) = options.remote_extends {
+        remote.vendor_extends(&mut local_config);
+    }
+    options
+        .local_plugins
+        .apply(&mut local_config, &local_config_path);
     inject_extends(&mut local_config, profile_config_path);
 
     // Write to a per-user runtime dir with a unique name.
     let cache_dir = merged_config_dir()?;
-    let seq = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
-    let pid = std::process::id();
-    let temp_path = cache_dir.join(format!("merged-{pid}-{seq}.json"));
-
     let json = serde_json::to_string_pretty(&local_config).context("serializing merged config")?;
-    std::fs::write(&temp_path, json)
-        .with_context(|| format!("writing merged config to {}", temp_path.display()))?;
+    let temp_path = fsutil::create_unique(&cache_dir, "merged", ".json", json.as_bytes())
+        .context("writing merged config")?;
+
+    Ok(Merge::Merged(TempConfig { path: temp_path }))
+}
+
+/// Write a config extending `config_path` with the editor's formatting
+/// `options` (LSP `FormattingOptions`) on top: `tabSize` as `indentWidth`,
+/// `insertSpaces` as the inverse of `useTabs`, the global options every
+/// plugin falls back to. None if `options` has neither.
+pub fn overlay_editor_options(
+    config_path: &Path,
+    options: &serde_json::Value,
+) -> Result<Option<TempConfig>> {
+    let mut overlay = Map::new();
+    if let Some(tab_size) = options.get("tabSize").and_then(|t| t.as_u64()) {
+        overlay.insert("indentWidth".to_string(), tab_size.into());
+    }
+    if let Some(insert_spaces) = options.get("insertSpaces").and_then(|i| i.as_bool()) {
+        overlay.insert("useTabs".to_string(), (!insert_spaces).into());
+    }
+    if overlay.is_empty() {
+        return Ok(None);
+    }
+    let mut config = serde_json::Value::Object(overlay);
+    inject_extends(&mut config, config_path);
 
+    let cache_dir = merged_config_dir()?;
+    let json = serde_json::to_string_pretty(&config).context("serializing editor options")?;
+    let temp_path = fsutil::create_unique(&cache_dir, "editor", ".json", json.as_bytes())
+        .context("writing editor options config")?;
     Ok(Some(TempConfig { path: temp_path }))
 }
 
@@ -345,149 +1161,132 @@ fn merged_config_dir() -> Result<PathBuf> {
     Ok(dir)
 }
 
-/// Expand ~ to home directory in a path string.
-pub fn expand_tilde(path: &str) -> PathBuf {
-    if let Some(rest) = path.strip_prefix("~/")
-        && let Some(home) = dirs::home_dir()
-    {
-        return home.join(rest);
+/// Profile name of a `match`, `match_content` or `match_language` rule:
+/// the plain string form, or `profile` of the object form.
+pub fn rule_profile(value: &serde_json::Value) -> Option<&str> {
+    match value {
+        serde_json::Value::Object(rule) => rule.get("profile")?.as_str(),
+        _ => value.as_str(),
     }
-    PathBuf::from(path)
 }
 
-/// Strip JSONC-style comments from a string.
-/// Handles // line comments and /* */ block comments.
-/// Does not strip inside strings.
-fn strip_jsonc_comments(input: &str) -> String {
-    let mut result = String::with_capacity(input.len());
-    let chars: Vec<char> = input.chars().collect();
-    let len = chars.len();
-    let mut i = 0;
-    let mut in_string = false;
-
-    while i < len {
-        if in_string {
-            result.push(chars[i]);
-            if chars[i] == '\\' && i + 1 < len {
-                i += 1;
-                result.push(chars[i]);
-            } else if chars[i] == '"' {
-                in_string = false;
-            }
-            i += 1;
-            continue;
-        }
-
-        if chars[i] == '"' {
-            in_string = true;
-            result.push(chars[i]);
-            i += 1;
-            continue;
-        }
+/// `description` of a rule or profile in object form.
+pub fn description(value: &serde_json::Value) -> Option<&str> {
+    value.get("description")?.as_str()
+}
 
-        // Line comment
-        if chars[i] == '/' && i + 1 < len && chars[i + 1] == '/' {
-            // Skip until end of line
-            i += 2;
-            while i < len && chars[i] != '\n' {
-                i += 1;
-            }
-            continue;
-        }
+/// Whether `value` has no `description` or a string one.
+fn description_valid(value: &serde_json::Value) -> bool {
+    value.get("description").is_none_or(|d| d.is_string())
+}
 
-        // Block comment
-        if chars[i] == '/' && i + 1 < len && chars[i + 1] == '*' {
-            i += 2;
-            while i + 1 < len && !(chars[i] == '*' && chars[i + 1] == '/') {
-                i += 1;
-            }
-            if i + 1 < len {
-                i += 2; // skip */
-            }
-            continue;
-        }
+/// The first existing dprint binary among [`dprint_candidates`] that is not
+/// dprintx itself (symlinked as `dprint` for transparent use).
+pub fn discover_dprint() -> Option<PathBuf> {
+    let own = std::env::current_exe().ok().map(fsutil::canonical);
+    dprint_candidates(
+        std::env::var_os("PATH"),
+        std::env::var_os("DPRINT_INSTALL").map(PathBuf::from),
+        std::env::var_os("CARGO_HOME").map(PathBuf::from),
+        dirs::home_dir(),
+    )
+    .into_iter()
+    .find(|p| p.is_file() && Some(fsutil::canonical(p)) != own)
+}
 
-        result.push(chars[i]);
-        i += 1;
+/// Where dprint may be installed, in discovery order: `PATH`, the install
+/// script's `$DPRINT_INSTALL` (default ~/.dprint), cargo's bin directory
+/// and the Homebrew prefixes, which editors started outside a login shell
+/// may miss in `PATH`.
+fn dprint_candidates(
+    path: Option<std::ffi::OsString>,
+    dprint_install: Option<PathBuf>,
+    cargo_home: Option<PathBuf>,
+    home: Option<PathBuf>,
+) -> Vec<PathBuf> {
+    let mut candidates: Vec<PathBuf> = path
+        .iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| dir.join("dprint"))
+        .collect();
+    if let Some(dir) = dprint_install.or_else(|| home.as_ref().map(|h| h.join(".dprint"))) {
+        candidates.push(dir.join("bin").join("dprint"));
     }
-
-    // Strip trailing commas before } and ] (JSONC allows them, JSON doesn't).
-    strip_trailing_commas(&result)
+    if let Some(dir) = cargo_home.or_else(|| home.as_ref().map(|h| h.join(".cargo"))) {
+        candidates.push(dir.join("bin").join("dprint"));
+    }
+    candidates.push(PathBuf::from("/opt/homebrew/bin/dprint"));
+    candidates.push(PathBuf::from("/usr/local/bin/dprint"));
+    let mut seen = std::collections::HashSet::new();
+    candidates.retain(|p| seen.insert(p.clone()));
+    candidates
 }
 
-/// Remove trailing commas before } and ].
-fn strip_trailing_commas(input: &str) -> String {
-    let mut result = String::with_capacity(input.len());
-    let chars: Vec<char> = input.chars().collect();
-    let len = chars.len();
-    let mut i = 0;
-    let mut in_string = false;
-
-    while i < len {
-        if in_string {
-            result.push(chars[i]);
-            if chars[i] == '\\' && i + 1 < len {
-                i += 1;
-                result.push(chars[i]);
-            } else if chars[i] == '"' {
-                in_string = false;
-            }
-            i += 1;
-            continue;
-        }
-
-        if chars[i] == '"' {
-            in_string = true;
-            result.push(chars[i]);
-            i += 1;
-            continue;
-        }
+/// `path` with the home directory written as `~`, for writing to the config.
+pub fn contract_tilde(path: &Path) -> String {
+    match dirs::home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
 
-        if chars[i] == ',' {
-            // Look ahead for } or ] (skipping whitespace)
-            let mut j = i + 1;
-            while j < len && chars[j].is_whitespace() {
-                j += 1;
-            }
-            if j < len && (chars[j] == '}' || chars[j] == ']') {
-                // Skip the trailing comma
-                i += 1;
-                continue;
-            }
-        }
+/// Expand ~ to home directory in a path string.
+pub fn expand_tilde(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/")
+        && let Some(home) = dirs::home_dir()
+    {
+        return home.join(rest);
+    }
+    PathBuf::from(path)
+}
 
-        result.push(chars[i]);
-        i += 1;
+/// Candidate config paths, deduplicated, in precedence order
+/// (see [`DprintxConfig::default_candidates`]).
+fn config_candidates(
+    xdg_config_home: Option<PathBuf>,
+    platform_config_dir: Option<PathBuf>,
+    home: Option<PathBuf>,
+) -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    // Relative XDG_CONFIG_HOME is invalid per the spec and ignored.
+    if let Some(xdg) = xdg_config_home.filter(|p| p.is_absolute()) {
+        candidates.push(xdg.join("dprintx").join("config.jsonc"));
     }
+    if let Some(dir) = platform_config_dir {
+        candidates.push(dir.join("dprint").join("dprintx.jsonc"));
+    }
+    if let Some(home) = home {
+        let host = home.join(".config");
+        candidates.push(host.join("dprintx").join("config.jsonc"));
+        candidates.push(host.join("dprint").join("dprintx.jsonc"));
+    }
+    let mut seen = std::collections::HashSet::new();
+    candidates.retain(|p| seen.insert(p.clone()));
+    candidates
+}
 
-    result
+/// Kill-switch decision: any `DPRINTX_DISABLE` value other than empty/"0"
+/// disables dprintx, as does an existing flag file.
+fn disable_requested(env: Option<&str>, flag_exists: bool) -> bool {
+    flag_exists || env.is_some_and(|v| !v.is_empty() && v != "0")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_strip_jsonc_comments() {
-        let input = r#"{
-  // line comment
-  "key": "value", // inline comment
-  /* block comment */
-  "key2": "val with // not a comment"
-}"#;
-        let result = strip_jsonc_comments(input);
-        assert!(result.contains("\"key\": \"value\""));
-        assert!(result.contains("\"key2\": \"val with // not a comment\""));
-        assert!(!result.contains("line comment"));
-        assert!(!result.contains("inline comment"));
-        assert!(!result.contains("block comment"));
+    fn options(on_error: LocalConfigErrors) -> MergeOptions<'static> {
+        MergeOptions {
+            on_error,
+            ..MergeOptions::default()
+        }
     }
 
-    #[test]
-    fn test_strip_trailing_commas() {
-        let input = r#"{"a": 1, "b": 2,}"#;
-        let result = strip_trailing_commas(input);
-        assert_eq!(result, r#"{"a": 1, "b": 2}"#);
+    fn build_merged_config(file_dir: &Path, profile: &Path) -> Result<Option<TempConfig>> {
+        match merge_local_config(file_dir, profile, MergeOptions::default())? {
+            Merge::Merged(tc) => Ok(Some(tc)),
+            Merge::Profile | Merge::Skip => Ok(None),
+        }
     }
 
     #[test]
@@ -516,8 +1315,7 @@ mod tests {
     "**": "default",
   },
 }"#;
-        let json = strip_jsonc_comments(input);
-        let config: DprintxConfig = serde_json::from_str(&json).unwrap();
+        let config: DprintxConfig = jsonc::from_str(input).unwrap();
 
         assert_eq!(config.dprint, "~/.cargo/bin/dprint");
         assert_eq!(config.profiles.len(), 2);
@@ -533,6 +1331,251 @@ mod tests {
         assert!(config.match_content.is_none());
     }
 
+    #[test]
+    fn test_lsp_error_for() {
+        let input = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": { "default": "/config/default.jsonc" },
+            "match": { "**": "default" },
+            "lsp_error_methods": ["textDocument/formatting"]
+        }"#;
+        let mut config: DprintxConfig = serde_json::from_str(input).unwrap();
+        assert!(config.lsp_error_for("textDocument/formatting"));
+        assert!(!config.lsp_error_for("textDocument/rangeFormatting"));
+
+        config.lsp_error_methods = vec!["*".into()];
+        assert!(config.lsp_error_for("textDocument/rangeFormatting"));
+    }
+
+    #[test]
+    fn test_dprint_candidates_order() {
+        let candidates = dprint_candidates(
+            Some("/usr/bin:/home/u/.local/bin".into()),
+            None,
+            Some(PathBuf::from("/opt/cargo")),
+            Some(PathBuf::from("/home/u")),
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                PathBuf::from("/usr/bin/dprint"),
+                PathBuf::from("/home/u/.local/bin/dprint"),
+                PathBuf::from("/home/u/.dprint/bin/dprint"),
+                PathBuf::from("/opt/cargo/bin/dprint"),
+                PathBuf::from("/opt/homebrew/bin/dprint"),
+                PathBuf::from("/usr/local/bin/dprint"),
+            ]
+        );
+
+        // Install locations already in PATH are tried once.
+        let candidates = dprint_candidates(
+            Some("/usr/local/bin:/srv/dprint/bin".into()),
+            Some(PathBuf::from("/srv/dprint")),
+            None,
+            None,
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                PathBuf::from("/usr/local/bin/dprint"),
+                PathBuf::from("/srv/dprint/bin/dprint"),
+                PathBuf::from("/opt/homebrew/bin/dprint"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_candidates_order() {
+        let candidates = config_candidates(
+            Some(PathBuf::from("/home/u/.var/app/org.editor/config")),
+            Some(PathBuf::from("/home/u/.var/app/org.editor/config")),
+            Some(PathBuf::from("/home/u")),
+        );
+        assert_eq!(
+            candidates,
+            vec![
+                PathBuf::from("/home/u/.var/app/org.editor/config/dprintx/config.jsonc"),
+                PathBuf::from("/home/u/.var/app/org.editor/config/dprint/dprintx.jsonc"),
+                PathBuf::from("/home/u/.config/dprintx/config.jsonc"),
+                PathBuf::from("/home/u/.config/dprint/dprintx.jsonc"),
+            ]
+        );
+
+        // Plain desktop: XDG dir is ~/.config, duplicates collapse.
+        let candidates = config_candidates(
+            Some(PathBuf::from("/home/u/.config")),
+            Some(PathBuf::from("/home/u/.config")),
+            Some(PathBuf::from("/home/u")),
+        );
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(
+            candidates[0],
+            PathBuf::from("/home/u/.config/dprintx/config.jsonc")
+        );
+
+        // Relative XDG_CONFIG_HOME is ignored.
+        let candidates = config_candidates(Some(PathBuf::from("cfg")), None, None);
+        assert!(candidates.is_empty());
+    }
+
+    #[test]
+    fn test_max_file_size_for() {
+        let input = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": {
+                "default": "/config/default.jsonc",
+                "data": { "config": "/config/data.jsonc", "max_file_size": 10485760 },
+                "skip": { "config": null }
+            },
+            "match": { "**": "default" },
+            "max_file_size": 1048576
+        }"#;
+        let config = DprintxConfig::parse(input).unwrap();
+        assert_eq!(
+            config.resolve_profile("data"),
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/data.jsonc"
+            )))
+        );
+        assert_eq!(
+            config.resolve_profile("skip"),
+            Some(ProfileResolution::Ignore)
+        );
+        assert_eq!(
+            config.max_file_size_for(Path::new("/config/data.jsonc")),
+            Some(10485760)
+        );
+        assert_eq!(
+            config.max_file_size_for(Path::new("/config/default.jsonc")),
+            Some(1048576)
+        );
+    }
+
+    #[test]
+    fn test_severity() {
+        let input = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": {
+                "default": "/config/default.jsonc",
+                "strict": { "config": "/config/strict.jsonc", "severity": "error" },
+                "new": { "config": "/config/new.jsonc", "severity": "warning" }
+            },
+            "match": { "**": "default" }
+        }"#;
+        let config = DprintxConfig::parse(input).unwrap();
+        assert!(config.validate().is_ok());
+        assert!(!config.warns_only("default"));
+        assert!(!config.warns_only("strict"));
+        assert!(config.warns_only("new"));
+        assert!(!config.warns_only("missing"));
+
+        let input = input.replace(r#""severity": "warning""#, r#""severity": "info""#);
+        let config = DprintxConfig::parse(&input).unwrap();
+        assert!(config.validate().unwrap_err().0.contains("severity"));
+    }
+
+    #[test]
+    fn test_diff_settings() {
+        let input = r#"{
+            "dprint": "/usr/bin/dprint",
+            "diff_pager": "delta -s",
+            "diff_context": 5,
+            "profiles": {
+                "default": "/config/default.jsonc",
+                "docs": {
+                    "config": "/config/docs.jsonc",
+                    "diff_pager": "less -R",
+                    "diff_format": "context",
+                    "diff_context": 1
+                }
+            },
+            "match": { "**": "default" }
+        }"#;
+        let config = DprintxConfig::parse(input).unwrap();
+        assert!(config.validate().is_ok());
+        assert!(config.diffs_enabled());
+        assert_eq!(
+            config.diff_settings("default"),
+            DiffSettings {
+                pager: Some("delta -s".to_string()),
+                format: DiffFormat::Unified,
+                context: 5,
+            }
+        );
+        assert_eq!(
+            config.diff_settings("docs"),
+            DiffSettings {
+                pager: Some("less -R".to_string()),
+                format: DiffFormat::Context,
+                context: 1,
+            }
+        );
+
+        // A profile's pager alone turns diffs on.
+        let input = input.replace(r#""diff_pager": "delta -s","#, "");
+        let config = DprintxConfig::parse(&input).unwrap();
+        assert!(config.diffs_enabled());
+        assert_eq!(config.diff_settings("default").pager, None);
+
+        let input = input.replace(r#""diff_format": "context""#, r#""diff_format": "word""#);
+        let config = DprintxConfig::parse(&input).unwrap();
+        assert!(config.validate().unwrap_err().0.contains("diff_format"));
+    }
+
+    #[test]
+    fn test_disable_requested() {
+        assert!(!disable_requested(None, false));
+        assert!(!disable_requested(Some(""), false));
+        assert!(!disable_requested(Some("0"), false));
+        assert!(disable_requested(Some("1"), false));
+        assert!(disable_requested(None, true));
+    }
+
+    #[test]
+    fn test_lsp_root_policy() {
+        let base = r#""dprint": "d", "profiles": {}, "match": {}"#;
+        let config = DprintxConfig::parse(&format!("{{{base}}}")).unwrap();
+        assert_eq!(config.lsp_root, LspRoot::ConfigDir);
+        let config =
+            DprintxConfig::parse(&format!(r#"{{{base}, "lsp_root": "file-dir"}}"#)).unwrap();
+        assert_eq!(config.lsp_root, LspRoot::FileDir);
+        assert!(DprintxConfig::parse(&format!(r#"{{{base}, "lsp_root": "home"}}"#)).is_err());
+    }
+
+    #[test]
+    fn test_profile_label() {
+        let input = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": { "default": "/config/default.jsonc" },
+            "match": { "**": "default" }
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(input).unwrap();
+        assert_eq!(
+            config.profile_label(Path::new("/config/default.jsonc")),
+            "default"
+        );
+        assert_eq!(
+            config.profile_label(Path::new("/tmp/x.json")),
+            "/tmp/x.json"
+        );
+    }
+
+    #[test]
+    fn test_parse_match_language() {
+        let input = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": { "ansible": "/config/ansible.jsonc" },
+            "match": { "**": "ansible" },
+            "match_language": { "yaml.ansible": "ansible" }
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(input).unwrap();
+
+        assert_eq!(config.language_profile("yaml.ansible"), Some("ansible"));
+        assert_eq!(config.language_profile("yaml"), None);
+        let rules: Vec<(&str, &str)> = config.match_language_rules_iter().collect();
+        assert_eq!(rules, vec![("yaml.ansible", "ansible")]);
+    }
+
     #[test]
     fn test_resolve_profile_config() {
         let config_json = r#"{
@@ -642,6 +1685,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_root_paths() {
+        let config_json = r#"{
+            "dprint": "dprint",
+            "profiles": { "default": "default.jsonc" },
+            "match": { "**": "default" },
+            "roots": ["../infra", "/srv/scripts"]
+        }"#;
+        let mut config: DprintxConfig = serde_json::from_str(config_json).unwrap();
+        config.config_dir = PathBuf::from("/home/user/.config/dprint");
+        assert_eq!(
+            config.root_paths(),
+            vec![
+                PathBuf::from("/home/user/.config/dprint/../infra"),
+                PathBuf::from("/srv/scripts"),
+            ]
+        );
+    }
+
     #[test]
     fn test_load_sets_config_dir() {
         let dir = std::env::temp_dir().join("dprintx-test-load-dir");
@@ -664,6 +1726,140 @@ mod tests {
         let _ = std::fs::remove_dir_all(&dir);
     }
 
+    #[test]
+    fn test_load_errors_point_at_location() {
+        let dir = std::env::temp_dir().join("dprintx-test-load-errors");
+        let _ = std::fs::create_dir_all(&dir);
+        let config_path = dir.join("dprintx.jsonc");
+
+        std::fs::write(
+            &config_path,
+            "{\n  \"dprint\": \"dprint\",\n  \"profiles\": {},\n  \"match\": { \"**\": 1 2 }\n}",
+        )
+        .unwrap();
+        let err = DprintxConfig::load(&config_path).unwrap_err().to_string();
+        assert!(
+            err.starts_with("invalid dprintx.jsonc format: expected"),
+            "{err}"
+        );
+        assert!(err.contains("dprintx.jsonc:4:22\n"), "{err}");
+        assert!(err.ends_with("4 |   \"match\": { \"**\": 1 2 }\n  |                      ^"));
+
+        std::fs::write(
+            &config_path,
+            r#"{
+  "dprint": "dprint",
+  "profiles": { "default": "/d.json" },
+  "match": {
+    "**/*.md": "docs",
+  },
+}"#,
+        )
+        .unwrap();
+        let err = DprintxConfig::load(&config_path).unwrap_err().to_string();
+        assert!(
+            err.contains("profile 'docs', which is not defined"),
+            "{err}"
+        );
+        assert!(err.contains("dprintx.jsonc:5:5\n"), "{err}");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rule_and_profile_descriptions() {
+        let input = r#"{
+            "dprint": "dprint",
+            "profiles": {
+                "default": { "config": "/d.json", "description": "Team defaults" },
+                "ignore": null
+            },
+            "match": {
+                "vendor/**": { "profile": "ignore", "description": "Third-party code" },
+                "**": "default"
+            },
+            "match_language": { "yaml.ansible": { "profile": "default" } }
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(input).unwrap();
+        assert!(config.validate().is_ok());
+        let rules: Vec<(&str, &str)> = config.match_rules_iter().collect();
+        assert_eq!(rules, vec![("vendor/**", "ignore"), ("**", "default")]);
+        assert_eq!(config.language_profile("yaml.ansible"), Some("default"));
+        assert_eq!(
+            description(&config.match_rules["vendor/**"]),
+            Some("Third-party code")
+        );
+        assert_eq!(description(&config.match_rules["**"]), None);
+        assert_eq!(config.profile_description("default"), Some("Team defaults"));
+        assert_eq!(config.profile_description("ignore"), None);
+
+        let bad = input.replace(
+            r#""profile": "default" }"#,
+            r#""profile": "default", "description": 1 }"#,
+        );
+        let config: DprintxConfig = serde_json::from_str(&bad).unwrap();
+        let (message, section, key) = config.validate().unwrap_err();
+        assert_eq!((section, key.as_str()), ("match_language", "yaml.ansible"));
+        assert!(
+            message.contains("description must be a string"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn test_apply_lsp_overrides() {
+        let input = r#"{
+            "dprint": "dprint",
+            "profiles": { "default": "/d.json", "docs": "/docs.json" },
+            "match": { "**/*.md": "default", "**": "default" },
+            "ignore": ["**/vendor/**"]
+        }"#;
+        let mut config: DprintxConfig = serde_json::from_str(input).unwrap();
+        config
+            .apply_lsp_overrides(None, &["**/*.md=docs".into(), "x/**=default".into()])
+            .unwrap();
+        let rules: Vec<(&str, &str)> = config.match_rules_iter().collect();
+        assert_eq!(
+            rules,
+            vec![("**/*.md", "docs"), ("x/**", "default"), ("**", "default")]
+        );
+        assert!(
+            config
+                .apply_lsp_overrides(None, &["**=missing".into()])
+                .is_err()
+        );
+
+        config
+            .apply_lsp_overrides(Some(Path::new("/p/dprint.json")), &[])
+            .unwrap();
+        let rules: Vec<(&str, &str)> = config.match_rules_iter().collect();
+        assert_eq!(rules, vec![("**", FORCED_PROFILE)]);
+        assert!(config.ignore.is_empty());
+        assert_eq!(config.lsp_untitled_profile.as_deref(), Some(FORCED_PROFILE));
+        assert_eq!(
+            config.resolve_profile(FORCED_PROFILE),
+            Some(ProfileResolution::Config(PathBuf::from("/p/dprint.json")))
+        );
+    }
+
+    #[test]
+    fn test_validate_ignore_globs() {
+        let input = r#"{
+            "dprint": "dprint",
+            "profiles": { "default": "/d.json" },
+            "match": { "**": "default" },
+            "ignore": ["**/vendor/**", "~/scratch/**"]
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(input).unwrap();
+        assert!(config.validate().is_ok());
+
+        let bad = input.replace("~/scratch/**", "src/{a,b");
+        let config: DprintxConfig = serde_json::from_str(&bad).unwrap();
+        let (message, section, key) = config.validate().unwrap_err();
+        assert_eq!((section, key.as_str()), ("ignore", "src/{a,b"));
+        assert!(message.contains("invalid pattern"), "{message}");
+    }
+
     #[test]
     fn test_parse_match_content() {
         let input = r#"{
@@ -952,6 +2148,156 @@ mod tests {
         let _ = std::fs::remove_dir_all(&dir);
     }
 
+    #[test]
+    fn test_merge_local_config_parse_failure_policy() {
+        let dir = std::env::temp_dir().join("dprintx-test-merge-policy");
+        let _ = std::fs::create_dir_all(&dir);
+        std::fs::write(dir.join("dprint.json"), "{ not json").unwrap();
+        let profile_path = Path::new("/profiles/main.jsonc");
+
+        assert!(build_merged_config(&dir, profile_path).is_err());
+        assert!(matches!(
+            merge_local_config(&dir, profile_path, options(LocalConfigErrors::UseProfile)),
+            Ok(Merge::Profile)
+        ));
+        assert!(matches!(
+            merge_local_config(&dir, profile_path, options(LocalConfigErrors::Skip)),
+            Ok(Merge::Skip)
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_merge_requires_trust() {
+        let dir = std::env::temp_dir().join("dprintx-test-merge-trust");
+        let _ = std::fs::create_dir_all(&dir);
+        std::fs::write(dir.join("dprint.json"), "{}").unwrap();
+        let profile = Path::new("/profiles/main.jsonc");
+        let merge = |trusted: &[PathBuf]| {
+            let options = MergeOptions {
+                trusted_dirs: Some(trusted),
+                ..MergeOptions::default()
+            };
+            merge_local_config(&dir, profile, options).unwrap()
+        };
+
+        assert!(matches!(merge(&[]), Merge::Profile));
+        let parent = std::fs::canonicalize(std::env::temp_dir()).unwrap();
+        assert!(matches!(merge(&[parent]), Merge::Merged(_)));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_merge_local_off() {
+        let dir = std::env::temp_dir().join("dprintx-test-merge-off");
+        let _ = std::fs::create_dir_all(&dir);
+        std::fs::write(dir.join("dprint.json"), "{}").unwrap();
+        let profile = Path::new("/profiles/main.jsonc");
+
+        let config =
+            DprintxConfig::parse(r#"{"dprint": "dprint", "profiles": {}, "match": {}}"#).unwrap();
+        assert!(config.merge_local);
+        assert!(matches!(
+            merge_local_config(&dir, profile, config.merge_options(profile)).unwrap(),
+            Merge::Merged(_)
+        ));
+        let config = DprintxConfig::parse(
+            r#"{"dprint": "dprint", "profiles": {}, "match": {}, "merge_local": false}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            merge_local_config(&dir, profile, config.merge_options(profile)).unwrap(),
+            Merge::Profile
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_overlay_editor_options() {
+        let profile = Path::new("/profiles/main.jsonc");
+        let overlay = |options: serde_json::Value| {
+            overlay_editor_options(profile, &options)
+                .unwrap()
+                .map(|tc| {
+                    serde_json::from_slice::<serde_json::Value>(&std::fs::read(tc.path()).unwrap())
+                        .unwrap()
+                })
+        };
+
+        assert_eq!(
+            overlay(serde_json::json!({ "tabSize": 2, "insertSpaces": false })),
+            Some(serde_json::json!({
+                "extends": "/profiles/main.jsonc",
+                "indentWidth": 2,
+                "useTabs": true,
+            }))
+        );
+        assert_eq!(
+            overlay(serde_json::json!({ "insertSpaces": true })),
+            Some(serde_json::json!({ "extends": "/profiles/main.jsonc", "useTabs": false }))
+        );
+        assert_eq!(
+            overlay(serde_json::json!({ "trimFinalNewlines": true })),
+            None
+        );
+    }
+
+    #[test]
+    fn test_merge_local_plugins() {
+        let dir = std::env::temp_dir().join("dprintx-test-merge-plugins");
+        let _ = std::fs::create_dir_all(&dir);
+        std::fs::write(
+            dir.join("dprint.json"),
+            r#"{ "plugins": [
+                "https://plugins.dprint.dev/typescript-0.93.0.wasm",
+                "https://evil.example/x.wasm"
+            ] }"#,
+        )
+        .unwrap();
+        let config: DprintxConfig = serde_json::from_str(
+            r#"{
+                "dprint": "dprint",
+                "profiles": {
+                    "any": "/profiles/any.jsonc",
+                    "allow": { "config": "/profiles/allow.jsonc",
+                               "local_plugins": ["https://plugins.dprint.dev/*"] },
+                    "forced": { "config": "/profiles/forced.jsonc", "local_plugins": "profile" }
+                },
+                "match": {}
+            }"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let merged = |profile: &str| {
+            let profile = Path::new(profile);
+            let Merge::Merged(tc) =
+                merge_local_config(&dir, profile, config.merge_options(profile)).unwrap()
+            else {
+                panic!("expected a merged config");
+            };
+            let content = std::fs::read_to_string(tc.path()).unwrap();
+            serde_json::from_str::<serde_json::Value>(&content).unwrap()
+        };
+
+        assert_eq!(
+            merged("/profiles/any.jsonc")["plugins"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            merged("/profiles/allow.jsonc")["plugins"],
+            serde_json::json!(["https://plugins.dprint.dev/typescript-0.93.0.wasm"])
+        );
+        assert!(merged("/profiles/forced.jsonc").get("plugins").is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn test_build_merged_config_preserves_existing_extends() {
         let dir = std::env::temp_dir().join("dprintx-test-build-merged-extends");
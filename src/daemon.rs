@@ -0,0 +1,757 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+#[cfg(unix)]
+use std::io::{BufRead, BufReader, Read};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::cache::FormatCache;
+use crate::cli::{Cli, CliCommand};
+use crate::config::{self, DprintxConfig, Merge, ProfileResolution};
+use crate::editor::Backends;
+use crate::error::SpawnContext;
+use crate::fsutil;
+use crate::matcher::ProfileMatcher;
+#[cfg(unix)]
+use crate::runner;
+use crate::runner::DprintRunner;
+use crate::sink::StdinOutput;
+use crate::warnings;
+use crate::watch::ConfigWatcher;
+
+/// How long the daemon waits on a silent client before dropping it.
+pub(crate) const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A `fmt`/`check` invocation delegated by `--attach`. Stdin content, if any,
+/// follows as a second line once the daemon has accepted the request; it is
+/// UTF-8, so the response's output is too.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Request {
+    /// dprintx config the client would load; the daemon declines others.
+    config: PathBuf,
+    /// Client working directory: relative files are resolved against it.
+    cwd: PathBuf,
+    /// `fmt` or `check`.
+    command: String,
+    files: Vec<String>,
+    /// File name of stdin content (`fmt --stdin`).
+    stdin: Option<String>,
+    allow_no_files: bool,
+}
+
+/// First reply to a [`Request`]. A declined request is run by the client
+/// itself, so a daemon never makes `--attach` fail where a plain run works.
+#[cfg(unix)]
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Ack {
+    declined: bool,
+    reason: Option<String>,
+}
+
+/// Result of an accepted request: what the client prints and exits with.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Response {
+    pub(crate) code: i32,
+    pub(crate) stdout: String,
+    pub(crate) stderr: String,
+}
+
+/// Outcome of [`attach`].
+pub enum Attached {
+    /// The daemon ran the command: exit with this code.
+    Exit(i32),
+    /// Run the command in-process, with the stdin content already read for
+    /// the daemon, if any.
+    Local(Option<Vec<u8>>),
+}
+
+/// Socket used by `dprintx daemon` and `--attach`: `$DPRINTX_SOCKET`, else
+/// `dprintx/daemon.sock` in the runtime directory (cache directory if none).
+pub fn socket_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("DPRINTX_SOCKET") {
+        return Some(PathBuf::from(path));
+    }
+    dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .map(|d| d.join("dprintx").join("daemon.sock"))
+}
+
+/// Run `cli` through a daemon running on `socket` (default
+/// [`socket_path`]). The command is run in-process instead when it cannot be
+/// delegated or no daemon accepted it.
+#[cfg(unix)]
+pub fn attach(cli: &Cli, socket: Option<PathBuf>) -> Result<Attached> {
+    let Some(request) = attach_request(cli)? else {
+        return Ok(Attached::Local(None));
+    };
+    // Stdin is read up front: requests are JSON, so content that is not
+    // UTF-8 is formatted in-process, like non-UTF-8 paths.
+    let input = match &request.stdin {
+        Some(_) => {
+            let mut input = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut input)
+                .context("reading stdin")?;
+            match String::from_utf8(input) {
+                Ok(input) => Some(input),
+                Err(e) => return Ok(Attached::Local(Some(e.into_bytes()))),
+            }
+        }
+        None => None,
+    };
+    let local = |input: Option<String>| Ok(Attached::Local(input.map(String::into_bytes)));
+    let socket = socket.or_else(socket_path);
+    let Some(mut stream) = socket.and_then(|p| UnixStream::connect(p).ok()) else {
+        return local(input);
+    };
+    let mut reader = BufReader::new(stream.try_clone().context("cloning daemon socket")?);
+
+    // Until the daemon accepts, falling back is safe.
+    if send(&mut stream, &request).is_err() {
+        return local(input);
+    }
+    match receive::<Ack>(&mut reader) {
+        Ok(ack) if !ack.declined => {}
+        _ => return local(input),
+    }
+
+    if let Some(input) = &input {
+        send(&mut stream, input)?;
+    }
+    let response: Response = receive(&mut reader).context("reading daemon response")?;
+    std::io::stdout().write_all(response.stdout.as_bytes())?;
+    std::io::stderr().write_all(response.stderr.as_bytes())?;
+    Ok(Attached::Exit(response.code))
+}
+
+/// The daemon listens on a unix socket, so commands that would be delegated
+/// fail rather than silently run in-process.
+#[cfg(not(unix))]
+pub fn attach(cli: &Cli, _socket: Option<PathBuf>) -> Result<Attached> {
+    if attach_request(cli)?.is_some() {
+        bail!("--attach is not supported: the dprintx daemon is unix-only");
+    }
+    Ok(Attached::Local(None))
+}
+
+/// The request for an `--attach` command, or None if it is not delegated:
+/// only explicit files and `fmt --stdin` are, without `--follow-symlinks`,
+/// `--verify`, `--save-repro` or `--no-merge`. Requests are JSON, so
+/// non-UTF-8 paths are not delegated either.
+fn attach_request(cli: &Cli) -> Result<Option<Request>> {
+    let (command, stdin, files, allow_no_files) = match &cli.command {
+        CliCommand::Fmt {
+            attach: true,
+            stdin,
+            files,
+            follow_symlinks: false,
+            allow_no_files,
+            verify: false,
+            staged: false,
+            extra_match,
+            save_repro: None,
+            no_merge: false,
+        } if extra_match.is_empty() => ("fmt", stdin, files, *allow_no_files),
+        CliCommand::Check {
+            attach: true,
+            stdin: None,
+            files,
+            follow_symlinks: false,
+            allow_no_files,
+            rule_stats: false,
+            staged: false,
+            since: None,
+            format: None,
+            extra_match,
+            save_repro: None,
+            no_merge: false,
+        } if extra_match.is_empty() => ("check", &None, files, *allow_no_files),
+        _ => return Ok(None),
+    };
+    if stdin.is_none() && (files.is_empty() || files.iter().any(|f| Path::new(f).is_dir())) {
+        return Ok(None);
+    }
+    let Some(files) = files
+        .iter()
+        .map(|f| f.to_str().map(String::from))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return Ok(None);
+    };
+    let stdin = match stdin {
+        Some(name) => match name.to_str() {
+            Some(name) => Some(name.to_string()),
+            None => return Ok(None),
+        },
+        None => None,
+    };
+
+    let config = match &cli.config {
+        Some(path) => PathBuf::from(path),
+        None => DprintxConfig::default_path()?,
+    };
+    Ok(Some(Request {
+        config: std::path::absolute(config)?,
+        cwd: std::env::current_dir().context("getting current directory")?,
+        command: command.to_string(),
+        files,
+        stdin,
+        allow_no_files,
+    }))
+}
+
+/// Write `value` as one JSON line.
+#[cfg(unix)]
+fn send<T: Serialize>(stream: &mut UnixStream, value: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    stream.write_all(&line).context("writing to daemon socket")
+}
+
+/// Read one JSON line.
+#[cfg(unix)]
+fn receive<T: for<'de> Deserialize<'de>>(reader: &mut impl BufRead) -> Result<T> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        bail!("connection closed");
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// The daemon's resident state: config, matcher and merged configs, all
+/// dropped and rebuilt when the config file changes. Also backs
+/// `dprintx serve`. With a [`ConfigWatcher`], edits to the dprintx config,
+/// profile configs and local dprint configs apply as soon as they are
+/// saved; otherwise mtimes are checked per request.
+pub(crate) struct Daemon {
+    config_path: PathBuf,
+    modified: Option<SystemTime>,
+    config: DprintxConfig,
+    matcher: ProfileMatcher,
+    /// (local dprint config, profile config) → merge outcome, reused while
+    /// the local config's mtime is unchanged.
+    merged: HashMap<(PathBuf, PathBuf), (Option<SystemTime>, Merge)>,
+    /// Warm editor-service backends, with `editor_service` enabled.
+    backends: Option<Backends>,
+    /// Watches the config files the state was built from.
+    watcher: Option<Arc<ConfigWatcher>>,
+    /// Formatted output, if `format_cache` is on.
+    cache: Option<FormatCache>,
+}
+
+impl Daemon {
+    pub(crate) fn load(config_path: &Path) -> Result<Self> {
+        let modified = mtime(config_path);
+        let config = DprintxConfig::load(config_path)?;
+        let matcher = ProfileMatcher::from_config(&config)?;
+        let backends = config
+            .editor_service
+            .then(|| Backends::new(config.dprint_path()));
+        let cache = FormatCache::open(&config, &config.dprint_path());
+        Ok(Self {
+            config_path: config_path.to_path_buf(),
+            modified,
+            config,
+            matcher,
+            merged: HashMap::new(),
+            cache,
+            backends,
+            watcher: None,
+        })
+    }
+
+    /// Report changes of the config files in use to `watcher`, starting
+    /// with the dprintx config and its profile configs.
+    fn watch(&mut self, watcher: Arc<ConfigWatcher>) {
+        let profiles = self.config.profiles.keys().filter_map(|name| {
+            match self.config.resolve_profile(name) {
+                Some(ProfileResolution::Config(path)) => Some(path),
+                _ => None,
+            }
+        });
+        for path in std::iter::once(self.config_path.clone()).chain(profiles) {
+            watch_file(Some(&watcher), &path);
+        }
+        self.watcher = Some(watcher);
+    }
+
+    /// Effective config for a file in `parent` under `profile_config`,
+    /// merging the local dprint config only when it is new or changed.
+    /// None: the file is skipped.
+    fn effective_config(
+        merged: &mut HashMap<(PathBuf, PathBuf), (Option<SystemTime>, Merge)>,
+        config: &DprintxConfig,
+        watcher: Option<&ConfigWatcher>,
+        parent: &Path,
+        profile_config: &Path,
+    ) -> Result<Option<PathBuf>> {
+        let Some(local) = config::find_local_config(parent) else {
+            return Ok(Some(profile_config.to_path_buf()));
+        };
+        watch_file(watcher, &local);
+        let key = (local, profile_config.to_path_buf());
+        let modified = mtime(&key.0);
+        let fresh = merged.get(&key).is_some_and(|(m, _)| *m == modified);
+        if !fresh {
+            let merge = config::merge_local_config(
+                parent,
+                profile_config,
+                config.merge_options(profile_config),
+            )?;
+            merged.insert(key.clone(), (modified, merge));
+        }
+        Ok(match &merged[&key].1 {
+            Merge::Merged(tc) => Some(tc.path().to_path_buf()),
+            Merge::Profile => Some(profile_config.to_path_buf()),
+            Merge::Skip => None,
+        })
+    }
+
+    /// Reload the config if its file changed since it was loaded.
+    pub(crate) fn refresh(&mut self) -> Result<()> {
+        if mtime(&self.config_path) != self.modified {
+            let watcher = self.watcher.take();
+            let reloaded = Self::load(&self.config_path);
+            self.watcher = watcher;
+            *self = reloaded?;
+            if let Some(watcher) = self.watcher.take() {
+                self.watch(watcher);
+            }
+            eprintln!("dprintx: reloaded {}", self.config_path.display());
+        }
+        Ok(())
+    }
+
+    /// Apply edits of watched config files: a changed dprintx config is
+    /// reloaded; merged configs built from a changed profile or local
+    /// config are merged again, and warm backends of changed configs are
+    /// restarted.
+    fn config_changed(&mut self, changed: &[PathBuf]) {
+        for path in changed {
+            eprintln!("dprintx: {} changed", path.display());
+        }
+        if changed.contains(&self.config_path) {
+            // A config that no longer loads is kept until it is fixed; the
+            // next request reports the error.
+            if let Err(e) = self.refresh() {
+                eprintln!("dprintx: keeping previous config: {e:#}");
+            }
+            return;
+        }
+
+        let stale: Vec<(PathBuf, PathBuf)> = self
+            .merged
+            .keys()
+            .filter(|(local, profile)| changed.contains(local) || changed.contains(profile))
+            .cloned()
+            .collect();
+        for key in stale {
+            let (_, old) = self.merged.remove(&key).unwrap();
+            let Some(dir) = key.0.parent() else {
+                continue;
+            };
+            let merged = Self::effective_config(
+                &mut self.merged,
+                &self.config,
+                self.watcher.as_deref(),
+                dir,
+                &key.1,
+            );
+            if let (Some(backends), Merge::Merged(old)) = (&mut self.backends, &old) {
+                match merged {
+                    Ok(Some(new)) => backends.replace(old.path(), &new),
+                    _ => backends.retain(|config| config != old.path()),
+                }
+            }
+        }
+        if let Some(backends) = &mut self.backends {
+            for path in changed {
+                backends.replace(path, path);
+            }
+        }
+    }
+
+    /// Why this daemon cannot serve `request`, if it cannot.
+    #[cfg(unix)]
+    fn decline_reason(&mut self, request: &Request) -> Option<String> {
+        if request.config != self.config_path {
+            return Some(format!("serving {}", self.config_path.display()));
+        }
+        // A broken config is reported by the client's own run.
+        if let Err(e) = self.refresh() {
+            return Some(format!("{e:#}"));
+        }
+        if request.command == "check" && self.config.diffs_enabled() {
+            return Some("diff_pager output needs the client's terminal".to_string());
+        }
+        if request.stdin.is_none() && self.config.post_run.is_some() {
+            return Some("the post_run hook runs with the client's own run".to_string());
+        }
+        None
+    }
+
+    #[cfg(unix)]
+    fn handle(&mut self, stream: UnixStream) -> Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+
+        let request: Request = receive(&mut reader)?;
+        if let Some(reason) = self.decline_reason(&request) {
+            return send(
+                &mut writer,
+                &Ack {
+                    declined: true,
+                    reason: Some(reason),
+                },
+            );
+        }
+        send(&mut writer, &Ack::default())?;
+
+        let result = match &request.stdin {
+            Some(filename) => {
+                let input: String = receive(&mut reader)?;
+                self.fmt_content(&request.cwd, filename, input)
+            }
+            None => self.run_files(&request),
+        };
+        let mut response = result.unwrap_or_else(|e| Response {
+            code: 1,
+            stderr: format!("dprintx: {e:#}\n"),
+            ..Response::default()
+        });
+        for warning in warnings::take_rendered() {
+            response.stderr.push_str(&warning);
+            response.stderr.push('\n');
+        }
+        send(&mut writer, &response)
+    }
+
+    /// `fmt`/`check` of explicit files, like [`DprintRunner::fmt_files`]
+    /// but with dprint's output captured for the client.
+    #[cfg(unix)]
+    fn run_files(&mut self, request: &Request) -> Result<Response> {
+        let runner = DprintRunner::new(&self.config).with_allow_no_files(request.allow_no_files);
+        let files: Vec<PathBuf> = request.files.iter().map(|f| request.cwd.join(f)).collect();
+        let groups =
+            runner::group_files_with(&files, &self.matcher, &self.config, |parent, profile| {
+                Self::effective_config(
+                    &mut self.merged,
+                    &self.config,
+                    self.watcher.as_deref(),
+                    parent,
+                    profile,
+                )
+            })?;
+        // dprint gets the files as the client spelled them, so its output
+        // matches an in-process run.
+        let spelled: std::collections::HashMap<&Path, &str> = files
+            .iter()
+            .map(PathBuf::as_path)
+            .zip(request.files.iter().map(String::as_str))
+            .collect();
+
+        let mut response = Response::default();
+        let matched: usize = groups.iter().map(|(_, _, f)| f.len()).sum();
+        if matched == 0 && !request.allow_no_files {
+            response.code = runner::NO_FILES_EXIT_CODE;
+            response.stderr = format!(
+                "dprintx: no files found to {} (use --allow-no-files to exit 0)\n",
+                request.command
+            );
+            return Ok(response);
+        }
+
+        let mut failed: Vec<&str> = Vec::new();
+        for (config_path, profile, group_files) in &groups {
+            let mut cmd = runner.dprint_command(&request.command, config_path);
+            cmd.args(group_files.iter().map(|f| spelled[f]))
+                .current_dir(&request.cwd);
+            let output = runner
+                .output_recorded(&mut cmd, &request.command, profile, group_files.len())
+                .spawn_context(&self.config.dprint_path(), || {
+                    format!("{} --config {}", request.command, config_path.display())
+                })?;
+            response
+                .stdout
+                .push_str(&String::from_utf8_lossy(&output.stdout));
+            response
+                .stderr
+                .push_str(&String::from_utf8_lossy(&output.stderr));
+            if !output.status.success() {
+                failed.push(profile);
+            }
+        }
+        // Warnings of warning-level profiles reach the client with the
+        // request's other warnings.
+        let fail = match request.command.as_str() {
+            "check" => runner::check_fails(&self.config, failed),
+            _ => !failed.is_empty(),
+        };
+        if fail {
+            response.code = 1;
+        }
+        runner.flush_metrics();
+        Ok(response)
+    }
+
+    /// `fmt --stdin`, like [`DprintRunner::fmt_stdin`] with the content
+    /// received from the client. `filename` is resolved against `cwd`.
+    pub(crate) fn fmt_content(
+        &mut self,
+        cwd: &Path,
+        filename: &str,
+        input: String,
+    ) -> Result<Response> {
+        let unchanged = |input: String| Response {
+            stdout: input,
+            ..Response::default()
+        };
+        let path = cwd.join(filename);
+        let abs_path = fsutil::canonical(&path);
+        let resolution = self
+            .matcher
+            .resolve_config(&abs_path, &self.config)
+            .with_context(|| format!("resolving config for {filename}"))?;
+        let Some(ProfileResolution::Config(profile_config)) = resolution else {
+            return Ok(unchanged(input));
+        };
+        if let Some(limit) = self.config.max_file_size_for(&profile_config)
+            && input.len() as u64 > limit
+        {
+            warnings::warn(
+                "file-size",
+                format!("not formatting {filename}: stdin exceeds max_file_size ({limit})"),
+            );
+            return Ok(unchanged(input));
+        }
+        let effective_config = match abs_path.parent() {
+            Some(parent) => {
+                match Self::effective_config(
+                    &mut self.merged,
+                    &self.config,
+                    self.watcher.as_deref(),
+                    parent,
+                    &profile_config,
+                )? {
+                    Some(effective) => effective,
+                    None => return Ok(unchanged(input)),
+                }
+            }
+            None => profile_config.clone(),
+        };
+        let profile = self.config.profile_label(&profile_config);
+        let key = self
+            .cache
+            .as_ref()
+            .and_then(|c| c.key(&path, input.as_bytes(), &effective_config));
+        if let Some(formatted) = key
+            .as_ref()
+            .and_then(|k| self.cache.as_ref()?.get(k))
+            .and_then(|f| String::from_utf8(f).ok())
+        {
+            return Ok(unchanged(formatted));
+        }
+        let cache = |formatted: &[u8]| {
+            if let (Some(cache), Some(key)) = (&self.cache, &key) {
+                cache.put(key, formatted);
+            }
+        };
+
+        if let Some(backends) = &mut self.backends {
+            // Merged configs replaced since the last request are gone.
+            backends.retain(Path::exists);
+            let t0 = std::time::Instant::now();
+            if let Some(formatted) = backends.format(&effective_config, &path, &input) {
+                let runner = DprintRunner::new(&self.config);
+                runner.record("fmt", &profile, 1, t0.elapsed(), true);
+                runner.flush_metrics();
+                cache(formatted.as_bytes());
+                return Ok(unchanged(formatted));
+            }
+        }
+
+        let runner = DprintRunner::new(&self.config);
+        let t0 = std::time::Instant::now();
+        let mut child = std::process::Command::new(self.config.dprint_path())
+            .args(["fmt", "--stdin"])
+            .arg(&path)
+            .arg("--config")
+            .arg(&effective_config)
+            .current_dir(cwd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .spawn_context(&self.config.dprint_path(), || {
+                format!("fmt --stdin {}", path.display())
+            })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(input.as_bytes())
+                .context("writing to dprint stdin")?;
+        }
+        let output = child.wait_with_output().context("waiting for dprint")?;
+        runner.record("fmt", &profile, 1, t0.elapsed(), output.status.success());
+        runner.flush_metrics();
+        if output.status.success() {
+            cache(&output.stdout);
+        }
+
+        let mut out = StdinOutput::new(Vec::new(), Vec::new());
+        out.dprint(&output)?;
+        let (stdout, stderr) = out.finish()?;
+        Ok(Response {
+            code: output.status.code().unwrap_or(1),
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        })
+    }
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn watch_file(watcher: Option<&ConfigWatcher>, path: &Path) {
+    if let Some(watcher) = watcher
+        && let Err(e) = watcher.watch(path)
+    {
+        eprintln!("dprintx: not watching {}: {e:#}", path.display());
+    }
+}
+
+/// Share `daemon` with a thread applying config edits as they are saved.
+/// Without inotify the daemon works as before, checking mtimes per request.
+pub(crate) fn watch_configs(mut daemon: Daemon) -> Arc<Mutex<Daemon>> {
+    let watcher = match ConfigWatcher::new() {
+        Ok(watcher) => Some(Arc::new(watcher)),
+        Err(e) => {
+            eprintln!("dprintx: config changes apply on the next request: {e:#}");
+            None
+        }
+    };
+    if let Some(watcher) = &watcher {
+        daemon.watch(Arc::clone(watcher));
+    }
+    let daemon = Arc::new(Mutex::new(daemon));
+    if let Some(watcher) = watcher {
+        let daemon = Arc::clone(&daemon);
+        std::thread::spawn(move || {
+            loop {
+                match watcher.wait() {
+                    Ok(changed) => daemon.lock().unwrap().config_changed(&changed),
+                    Err(e) => {
+                        eprintln!("dprintx: config watching stopped: {e:#}");
+                        return;
+                    }
+                }
+            }
+        });
+    }
+    daemon
+}
+
+/// `dprintx daemon`: serve `--attach` clients on `socket` (default
+/// [`socket_path`]) until killed. Requests are handled one at a time.
+#[cfg(unix)]
+pub fn serve(config_path: &Path, socket: Option<PathBuf>) -> Result<()> {
+    let daemon = watch_configs(Daemon::load(&std::path::absolute(config_path)?)?);
+    let socket = match socket.or_else(socket_path) {
+        Some(socket) => socket,
+        None => bail!("cannot determine daemon socket path (set DPRINTX_SOCKET)"),
+    };
+    let listener = bind(&socket)?;
+    eprintln!(
+        "dprintx: daemon serving {} on {}",
+        config_path.display(),
+        socket.display()
+    );
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("dprintx: daemon accept failed: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = daemon.lock().unwrap().handle(stream) {
+            eprintln!("dprintx: daemon request failed: {e:#}");
+        }
+    }
+    Ok(())
+}
+
+/// Listen on `socket`, owner-only. A stale socket from a dead daemon is
+/// replaced; a live one is an error.
+#[cfg(unix)]
+fn bind(socket: &Path) -> Result<UnixListener> {
+    if let Some(dir) = socket.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("creating {}", dir.display()))?;
+    }
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            bail!("a daemon is already listening on {}", socket.display());
+        }
+        std::fs::remove_file(socket)
+            .with_context(|| format!("removing stale socket {}", socket.display()))?;
+    }
+    let listener =
+        UnixListener::bind(socket).with_context(|| format!("binding {}", socket.display()))?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+pub fn serve(_config_path: &Path, _socket: Option<PathBuf>) -> Result<()> {
+    bail!("dprintx daemon is unix-only; use `dprintx serve` instead")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cli(args: &str) -> Cli {
+        let args: Vec<_> = args.split_whitespace().map(Into::into).collect();
+        Cli::parse_from(&args)
+    }
+
+    #[test]
+    fn test_attach_request() {
+        let request = attach_request(&cli("--config /c.jsonc fmt --attach a.ts b.ts"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.config, PathBuf::from("/c.jsonc"));
+        assert_eq!(request.command, "fmt");
+        assert_eq!(request.files, vec!["a.ts", "b.ts"]);
+
+        let request = attach_request(&cli("--config /c.jsonc fmt --attach --stdin a.ts"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.stdin.as_deref(), Some("a.ts"));
+
+        // Not attached, not explicit files, or options the daemon lacks.
+        for args in [
+            "--config /c.jsonc fmt a.ts",
+            "--config /c.jsonc fmt --attach",
+            "--config /c.jsonc fmt --attach --verify a.ts",
+            "--config /c.jsonc check --attach --stdin a.ts",
+            "--config /c.jsonc check --attach /",
+        ] {
+            assert!(attach_request(&cli(args)).unwrap().is_none(), "{args}");
+        }
+    }
+}
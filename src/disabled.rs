@@ -0,0 +1,59 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Files skipped during a run because they opt out with a
+/// `dprintx-disable-file` comment, reported apart from other skipped files.
+/// None while not counting (the LSP proxy, `--stdin`).
+static FILES: Mutex<Option<BTreeSet<PathBuf>>> = Mutex::new(None);
+
+/// Start counting.
+pub fn enable() {
+    *FILES.lock().unwrap() = Some(BTreeSet::new());
+}
+
+/// Count `path` as explicitly disabled, if counting.
+pub fn record(path: &Path) {
+    if let Some(files) = FILES.lock().unwrap().as_mut() {
+        files.insert(path.to_path_buf());
+    }
+}
+
+/// The files counted so far, in path order; they are not reported again.
+pub fn take() -> Vec<PathBuf> {
+    FILES
+        .lock()
+        .unwrap()
+        .as_mut()
+        .map(|files| std::mem::take(files).into_iter().collect())
+        .unwrap_or_default()
+}
+
+/// Print how many files were explicitly disabled to stderr, if any.
+pub fn report() {
+    if let Some(line) = summary(take().len()) {
+        eprintln!("{line}");
+    }
+}
+
+fn summary(count: usize) -> Option<String> {
+    (count > 0).then(|| {
+        format!(
+            "dprintx: {count} file(s) explicitly disabled (dprintx-disable-file), not formatted"
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(summary(0), None);
+        assert_eq!(
+            summary(2).as_deref(),
+            Some("dprintx: 2 file(s) explicitly disabled (dprintx-disable-file), not formatted")
+        );
+    }
+}
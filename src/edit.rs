@@ -0,0 +1,634 @@
+use anyhow::{Context, Result, bail};
+use globset::Glob;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, DprintxConfig};
+use crate::fsutil;
+
+/// Most files looked at by [`sample_glob`].
+const SAMPLE_LIMIT: usize = 20_000;
+
+/// Existing files a match glob would cover, from a bounded walk.
+#[derive(Debug, Default)]
+pub struct GlobSample {
+    /// Directory the walk started from.
+    pub root: PathBuf,
+    /// Files looked at.
+    pub sampled: usize,
+    /// First few matching files, in walk order.
+    pub examples: Vec<PathBuf>,
+    /// Matching files.
+    pub matched: usize,
+    /// The walk stopped at [`SAMPLE_LIMIT`] files.
+    pub truncated: bool,
+}
+
+/// Kind of a JSONC token relevant for structural edits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    OpenBrace,
+    CloseBrace,
+    OpenBracket,
+    CloseBracket,
+    Colon,
+    Comma,
+    String,
+    /// Numbers, true/false/null.
+    Literal,
+}
+
+/// A JSONC token with its byte span in the source text.
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+/// Add a named profile to dprintx.jsonc, preserving comments and formatting.
+///
+/// Validates that the profile name is not already defined and that the
+/// config file it points to exists.
+pub fn add_profile(
+    config_path: &Path,
+    config: &DprintxConfig,
+    name: &str,
+    profile_path: &str,
+) -> Result<()> {
+    if config.profiles.contains_key(name) {
+        bail!("profile '{name}' is already defined");
+    }
+    let resolved = config.resolve_path(profile_path);
+    if !resolved.is_file() {
+        bail!("profile config not found: {}", resolved.display());
+    }
+
+    let content = read_config_text(config_path)?;
+    let updated = insert_entry(&content, "profiles", name, &json_string(profile_path), None)?;
+    write_config_text(config_path, &updated)
+}
+
+/// Add a match rule to dprintx.jsonc, preserving comments and formatting.
+///
+/// Without `before`, the rule is appended — but placed ahead of a trailing
+/// `"**"` catch-all, which would otherwise shadow it.
+/// With `before`, the rule is inserted right above the given existing glob.
+///
+/// `confirm` runs once the rule is known to be valid; the config is left
+/// alone (and false returned) if it declines.
+pub fn add_rule(
+    config_path: &Path,
+    config: &DprintxConfig,
+    glob: &str,
+    profile: &str,
+    before: Option<&str>,
+    confirm: impl FnOnce() -> Result<bool>,
+) -> Result<bool> {
+    if !config.profiles.contains_key(profile) {
+        bail!("profile '{profile}' is not defined in profiles");
+    }
+    if config.match_rules.contains_key(glob) {
+        bail!("match rule '{glob}' already exists");
+    }
+    let expanded = config::expand_tilde(glob);
+    Glob::new(&fsutil::glob_pattern(&expanded))
+        .with_context(|| format!("invalid glob pattern: {glob}"))?;
+
+    let anchor = match before {
+        Some(target) => {
+            if !config.match_rules.contains_key(target) {
+                bail!("match rule '{target}' not found (used with --before)");
+            }
+            Some(target)
+        }
+        None => match config.match_rules.keys().next_back() {
+            Some(last) if last == "**" => Some("**"),
+            _ => None,
+        },
+    };
+
+    if !confirm()? {
+        return Ok(false);
+    }
+    let content = read_config_text(config_path)?;
+    let updated = insert_entry(&content, "match", glob, &json_string(profile), anchor)?;
+    write_config_text(config_path, &updated)?;
+    Ok(true)
+}
+
+/// Route `file` to an ignore (`null`) profile in dprintx.jsonc, preserving
+/// comments and formatting: a match rule for its path goes first, so no
+/// other rule shadows it, and an `"ignore": null` profile is added if none
+/// exists. Returns the glob of the rule.
+pub fn ignore_file(config_path: &Path, file: &Path) -> Result<String> {
+    let content = read_config_text(config_path)?;
+    let (glob, updated) = ignore_entries(&content, file)?;
+    write_config_text(config_path, &updated)?;
+    Ok(glob)
+}
+
+fn ignore_entries(content: &str, file: &Path) -> Result<(String, String)> {
+    let config = DprintxConfig::parse(content)?;
+    let glob = globset::escape(&config::contract_tilde(file));
+    if config.match_rules.contains_key(&glob) {
+        bail!("match rule '{glob}' already exists");
+    }
+    let ignore = config
+        .profiles
+        .keys()
+        .find(|name| config.resolve_profile(name) == Some(config::ProfileResolution::Ignore));
+    let (profile, content) = match ignore {
+        Some(name) => (name.clone(), content.to_string()),
+        None if config.profiles.contains_key("ignore") => {
+            bail!("no ignore (null) profile, and 'ignore' is not one")
+        }
+        None => (
+            "ignore".to_string(),
+            insert_entry(content, "profiles", "ignore", "null", None)?,
+        ),
+    };
+    let first = config.match_rules.keys().next().map(String::as_str);
+    let updated = insert_entry(&content, "match", &glob, &json_string(&profile), first)?;
+    Ok((glob, updated))
+}
+
+/// Point `"dprint"` in dprintx.jsonc at another binary, preserving
+/// comments and formatting.
+pub fn set_dprint(config_path: &Path, dprint: &str) -> Result<()> {
+    let content = read_config_text(config_path)?;
+    let updated = replace_value(&content, "dprint", &json_string(dprint))?;
+    write_config_text(config_path, &updated)
+}
+
+/// Count existing files matched by `glob`, the way match rules see them
+/// (absolute paths, `~` expanded). The walk starts at the glob's literal
+/// directory prefix if it is absolute, otherwise at `cwd`; `.git` and
+/// symlinked directories are skipped.
+pub fn sample_glob(glob: &str, cwd: &Path) -> Result<GlobSample> {
+    let expanded = config::expand_tilde(glob);
+    let matcher = Glob::new(&fsutil::glob_pattern(&expanded))
+        .with_context(|| format!("invalid glob pattern: {glob}"))?
+        .compile_matcher();
+
+    let root = if expanded.is_absolute() {
+        literal_prefix(&expanded)
+    } else {
+        cwd.to_path_buf()
+    };
+    let mut sample = GlobSample {
+        root: root.clone(),
+        ..GlobSample::default()
+    };
+    let mut stack = vec![root];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries: Vec<_> = entries.flatten().collect();
+        entries.sort_by_key(|e| e.file_name());
+        let mut subdirs = Vec::new();
+        for entry in entries {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                if entry.file_name() != ".git" {
+                    subdirs.push(path);
+                }
+                continue;
+            }
+            if sample.sampled == SAMPLE_LIMIT {
+                sample.truncated = true;
+                return Ok(sample);
+            }
+            sample.sampled += 1;
+            if matcher.is_match(&path) {
+                sample.matched += 1;
+                if sample.examples.len() < 3 {
+                    sample.examples.push(path);
+                }
+            }
+        }
+        stack.extend(subdirs.into_iter().rev());
+    }
+    Ok(sample)
+}
+
+/// Leading components of `glob` without glob metacharacters.
+fn literal_prefix(glob: &Path) -> PathBuf {
+    glob.components()
+        .take_while(|c| {
+            !c.as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '[', '{'])
+        })
+        .collect::<PathBuf>()
+        .ancestors()
+        .find(|p| p.is_dir())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("/"))
+}
+
+fn read_config_text(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("cannot read config: {}", path.display()))
+}
+
+/// Validate the edited text and atomically replace the config file.
+fn write_config_text(path: &Path, content: &str) -> Result<()> {
+    DprintxConfig::parse(content).context("edited config failed to parse (not written)")?;
+
+    fsutil::write_atomic(path, content.as_bytes())
+}
+
+fn json_string(s: &str) -> String {
+    serde_json::Value::String(s.to_string()).to_string()
+}
+
+/// Insert `"key": value` into the top-level object `section`.
+///
+/// If `before` names an existing key in that object, the entry is inserted
+/// above it; otherwise it is appended at the end of the object.
+fn insert_entry(
+    content: &str,
+    section: &str,
+    key: &str,
+    value: &str,
+    before: Option<&str>,
+) -> Result<String> {
+    let tokens = tokenize(content)?;
+    let (open, close) = find_section(content, &tokens, section)
+        .with_context(|| format!("\"{section}\" object not found in config"))?;
+    let entry = format!("{}: {value}", json_string(key));
+
+    if let Some(target) = before {
+        let target_tok = object_keys(content, &tokens, open, close)
+            .into_iter()
+            .find(|(k, _)| k == target)
+            .map(|(_, idx)| tokens[idx])
+            .with_context(|| format!("key '{target}' not found in \"{section}\""))?;
+
+        let mut out = content.to_string();
+        match line_indent_if_first(content, target_tok.start) {
+            Some((line_start, indent)) => {
+                out.insert_str(line_start, &format!("{indent}{entry},\n"));
+            }
+            None => out.insert_str(target_tok.start, &format!("{entry}, ")),
+        }
+        return Ok(out);
+    }
+
+    let close_tok = tokens[close];
+    let prev_tok = tokens[close - 1];
+    let mut out = content.to_string();
+
+    match line_indent_if_first(content, close_tok.start) {
+        Some((line_start, close_indent)) => {
+            // Multi-line object: add a new line above the closing brace,
+            // indented like the existing entries.
+            let indent = object_keys(content, &tokens, open, close)
+                .first()
+                .and_then(|(_, idx)| line_indent_if_first(content, tokens[*idx].start))
+                .map(|(_, indent)| indent.to_string())
+                .unwrap_or_else(|| format!("{close_indent}  "));
+            out.insert_str(line_start, &format!("{indent}{entry},\n"));
+            if needs_comma(prev_tok.kind) {
+                out.insert(prev_tok.end, ',');
+            }
+        }
+        None => {
+            // Inline object: `{ "a": "b" }` → `{ "a": "b", "key": value }`.
+            let text = if needs_comma(prev_tok.kind) {
+                format!(", {entry}")
+            } else {
+                format!(" {entry}")
+            };
+            out.insert_str(prev_tok.end, &text);
+        }
+    }
+    Ok(out)
+}
+
+/// Replace the value of the top-level entry `key` (a string or literal).
+fn replace_value(content: &str, key: &str, value: &str) -> Result<String> {
+    let tokens = tokenize(content)?;
+    let mut depth = 0usize;
+    for (i, tok) in tokens.iter().enumerate() {
+        match tok.kind {
+            TokenKind::OpenBrace | TokenKind::OpenBracket => depth += 1,
+            TokenKind::CloseBrace | TokenKind::CloseBracket => depth = depth.saturating_sub(1),
+            TokenKind::String
+                if depth == 1
+                    && tokens.get(i + 1).map(|t| t.kind) == Some(TokenKind::Colon)
+                    && string_value(content, tok).as_deref() == Some(key) =>
+            {
+                let old = tokens
+                    .get(i + 2)
+                    .filter(|t| matches!(t.kind, TokenKind::String | TokenKind::Literal))
+                    .with_context(|| format!("\"{key}\" is not a plain value"))?;
+                let mut out = content.to_string();
+                out.replace_range(old.start..old.end, value);
+                return Ok(out);
+            }
+            _ => {}
+        }
+    }
+    bail!("\"{key}\" not found in config")
+}
+
+/// A comma is needed after the previous token unless it opens the object
+/// or is already a comma.
+fn needs_comma(kind: TokenKind) -> bool {
+    !matches!(kind, TokenKind::OpenBrace | TokenKind::Comma)
+}
+
+/// If the byte at `pos` is the first non-whitespace character on its line,
+/// return the line start offset and the line's indentation.
+fn line_indent_if_first(content: &str, pos: usize) -> Option<(usize, &str)> {
+    let line_start = content[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let prefix = &content[line_start..pos];
+    if prefix.chars().all(|c| c == ' ' || c == '\t') {
+        Some((line_start, prefix))
+    } else {
+        None
+    }
+}
+
+/// Find the top-level `"section": { ... }` object.
+/// Returns token indices of its opening and closing braces.
+fn find_section(content: &str, tokens: &[Token], section: &str) -> Option<(usize, usize)> {
+    let mut depth = 0usize;
+    for (i, tok) in tokens.iter().enumerate() {
+        match tok.kind {
+            TokenKind::OpenBrace | TokenKind::OpenBracket => depth += 1,
+            TokenKind::CloseBrace | TokenKind::CloseBracket => depth = depth.saturating_sub(1),
+            TokenKind::String
+                if depth == 1
+                    && tokens.get(i + 1).map(|t| t.kind) == Some(TokenKind::Colon)
+                    && tokens.get(i + 2).map(|t| t.kind) == Some(TokenKind::OpenBrace)
+                    && string_value(content, tok).as_deref() == Some(section) =>
+            {
+                let open = i + 2;
+                return matching_close(tokens, open).map(|close| (open, close));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Find the token index of the bracket closing the one at `open`.
+fn matching_close(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, tok) in tokens.iter().enumerate().skip(open) {
+        match tok.kind {
+            TokenKind::OpenBrace | TokenKind::OpenBracket => depth += 1,
+            TokenKind::CloseBrace | TokenKind::CloseBracket => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Collect direct keys of the object between token indices `open` and `close`.
+fn object_keys(content: &str, tokens: &[Token], open: usize, close: usize) -> Vec<(String, usize)> {
+    let mut keys = Vec::new();
+    let mut depth = 0usize;
+    for i in open + 1..close {
+        let tok = tokens[i];
+        match tok.kind {
+            TokenKind::OpenBrace | TokenKind::OpenBracket => depth += 1,
+            TokenKind::CloseBrace | TokenKind::CloseBracket => depth -= 1,
+            TokenKind::String
+                if depth == 0 && tokens.get(i + 1).map(|t| t.kind) == Some(TokenKind::Colon) =>
+            {
+                if let Some(key) = string_value(content, &tok) {
+                    keys.push((key, i));
+                }
+            }
+            _ => {}
+        }
+    }
+    keys
+}
+
+fn string_value(content: &str, tok: &Token) -> Option<String> {
+    serde_json::from_str(&content[tok.start..tok.end]).ok()
+}
+
+/// Tokenize JSONC text, skipping whitespace and comments.
+fn tokenize(content: &str) -> Result<Vec<Token>> {
+    let bytes = content.as_bytes();
+    let len = bytes.len();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < len {
+        let start = i;
+        let kind = match bytes[i] {
+            b if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'/' if i + 1 < len && bytes[i + 1] == b'/' => {
+                while i < len && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if i + 1 < len && bytes[i + 1] == b'*' => {
+                i += 2;
+                while i + 1 < len && !(bytes[i] == b'*' && bytes[i + 1] == b'/') {
+                    i += 1;
+                }
+                i = (i + 2).min(len);
+                continue;
+            }
+            b'"' => {
+                i += 1;
+                while i < len && bytes[i] != b'"' {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                if i >= len {
+                    bail!("unterminated string in config");
+                }
+                i += 1;
+                tokens.push(Token {
+                    kind: TokenKind::String,
+                    start,
+                    end: i,
+                });
+                continue;
+            }
+            b'{' => TokenKind::OpenBrace,
+            b'}' => TokenKind::CloseBrace,
+            b'[' => TokenKind::OpenBracket,
+            b']' => TokenKind::CloseBracket,
+            b':' => TokenKind::Colon,
+            b',' => TokenKind::Comma,
+            _ => {
+                while i < len
+                    && !b"{}[]:,\"/".contains(&bytes[i])
+                    && !bytes[i].is_ascii_whitespace()
+                {
+                    i += 1;
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Literal,
+                    start,
+                    end: i,
+                });
+                continue;
+            }
+        };
+        i += 1;
+        tokens.push(Token {
+            kind,
+            start,
+            end: i,
+        });
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"{
+  "dprint": "~/.cargo/bin/dprint",
+
+  "profiles": {
+    "default": "~/.config/dprint/dprint-default.jsonc",
+    "ignore": null // skip
+  },
+
+  "match": {
+    // project rules
+    "**/noc/cmdb/**": "default",
+    "**": "default",
+  },
+}
+"#;
+
+    #[test]
+    fn test_sample_glob() {
+        let dir = std::env::temp_dir().join("dprintx-test-sample-glob");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src/nested")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        for file in ["src/a.ts", "src/nested/b.ts", "src/c.md", ".git/d.ts"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+
+        let sample = sample_glob("**/*.ts", &dir).unwrap();
+        assert_eq!((sample.sampled, sample.matched), (3, 2));
+        assert_eq!(sample.examples[0], dir.join("src/a.ts"));
+
+        let absolute = format!("{}/src/nested/**", dir.display());
+        let sample = sample_glob(&absolute, Path::new("/nonexistent")).unwrap();
+        assert_eq!(sample.root, dir.join("src/nested"));
+        assert_eq!(sample.matched, 1);
+
+        assert_eq!(sample_glob("**/*.go", &dir).unwrap().matched, 0);
+        assert!(sample_glob("src/[", &dir).is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_insert_profile_appends_with_comma() {
+        let out = insert_entry(CONFIG, "profiles", "strict", "\"/s.jsonc\"", None).unwrap();
+        assert!(out.contains("\"ignore\": null, // skip\n    \"strict\": \"/s.jsonc\",\n  },"));
+        let config = DprintxConfig::parse(&out).unwrap();
+        assert_eq!(config.profiles.keys().next_back().unwrap(), "strict");
+    }
+
+    #[test]
+    fn test_insert_rule_before_key() {
+        let out = insert_entry(CONFIG, "match", "**/*.md", "\"ignore\"", Some("**")).unwrap();
+        let config = DprintxConfig::parse(&out).unwrap();
+        let rules: Vec<(&str, &str)> = config.match_rules_iter().collect();
+        assert_eq!(rules[1], ("**/*.md", "ignore"));
+        assert_eq!(rules[2], ("**", "default"));
+        // Comments survive.
+        assert!(out.contains("// project rules"));
+    }
+
+    #[test]
+    fn test_insert_into_inline_object() {
+        let input = r#"{"dprint": "x", "profiles": {}, "match": { "**": "a" }}"#;
+        let out = insert_entry(input, "match", "*.go", "\"b\"", None).unwrap();
+        assert!(out.contains(r#"{ "**": "a", "*.go": "b" }"#));
+        let out = insert_entry(input, "profiles", "a", "null", None).unwrap();
+        assert!(out.contains(r#""profiles": { "a": null}"#));
+    }
+
+    #[test]
+    fn test_insert_ignores_nested_and_commented_keys() {
+        let input = r#"{
+  /* "match": { "fake": "x" } */
+  "dprint": "x",
+  "profiles": { "a": "p" },
+  "match": {
+    "**": "a"
+  }
+}"#;
+        let out = insert_entry(input, "match", "src/**", "\"a\"", Some("**")).unwrap();
+        assert!(out.contains("  \"match\": {\n    \"src/**\": \"a\",\n    \"**\": \"a\"\n  }"));
+    }
+
+    #[test]
+    fn test_insert_missing_section() {
+        let input = r#"{"dprint": "x"}"#;
+        assert!(insert_entry(input, "match", "*", "\"a\"", None).is_err());
+    }
+
+    #[test]
+    fn test_ignore_entries() {
+        let (glob, out) = ignore_entries(CONFIG, Path::new("/src/a[1].ts")).unwrap();
+        assert_eq!(glob, "/src/a[[]1[]].ts");
+        let config = DprintxConfig::parse(&out).unwrap();
+        let rules: Vec<(&str, &str)> = config.match_rules_iter().collect();
+        assert_eq!(rules[0], ("/src/a[[]1[]].ts", "ignore"));
+        assert_eq!(config.profiles.len(), 2);
+        assert!(ignore_entries(&out, Path::new("/src/a[1].ts")).is_err());
+
+        // Without a null profile, one is added.
+        let input = r#"{"dprint": "x", "profiles": { "a": "p" }, "match": { "**": "a" }}"#;
+        let (_, out) = ignore_entries(input, Path::new("/b.ts")).unwrap();
+        let config = DprintxConfig::parse(&out).unwrap();
+        assert_eq!(
+            config.resolve_profile("ignore"),
+            Some(config::ProfileResolution::Ignore)
+        );
+        assert_eq!(config.match_rules_iter().next(), Some(("/b.ts", "ignore")));
+    }
+
+    #[test]
+    fn test_replace_dprint() {
+        let out = replace_value(CONFIG, "dprint", "\"~/.dprint/bin/dprint\"").unwrap();
+        assert!(out.starts_with("{\n  \"dprint\": \"~/.dprint/bin/dprint\",\n"));
+        assert_eq!(
+            DprintxConfig::parse(&out).unwrap().dprint,
+            "~/.dprint/bin/dprint"
+        );
+        // Only the top-level entry.
+        let input = r#"{ "profiles": { "dprint": "p" }, "dprint": "x", "match": {} }"#;
+        let out = replace_value(input, "dprint", "\"y\"").unwrap();
+        assert_eq!(
+            out,
+            r#"{ "profiles": { "dprint": "p" }, "dprint": "y", "match": {} }"#
+        );
+        assert!(replace_value(r#"{"profiles": {}}"#, "dprint", "\"y\"").is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use crate::error::SpawnContext;
+
+// Message kinds of dprint's editor-service protocol (schema version 5).
+const KIND_ERROR: u32 = 1;
+const KIND_SHUTDOWN: u32 = 2;
+const KIND_FORMAT_FILE: u32 = 6;
+const KIND_FORMAT_FILE_RESPONSE: u32 = 7;
+
+/// Trailer closing every message.
+const SUCCESS_BYTES: [u8; 4] = [255; 4];
+
+/// Long-lived `dprint editor-service` processes, one per dprint config.
+/// Formatting through them saves a process start (and plugin loading) per
+/// file. A backend that fails in any way is dropped and its config is not
+/// retried, so callers fall back to `fmt --stdin`.
+pub(crate) struct Backends {
+    dprint: PathBuf,
+    services: HashMap<PathBuf, Option<EditorService>>,
+}
+
+impl Backends {
+    pub(crate) fn new(dprint: PathBuf) -> Self {
+        Self {
+            dprint,
+            services: HashMap::new(),
+        }
+    }
+
+    /// Shut down the backends of configs failing `keep`.
+    pub(crate) fn retain(&mut self, keep: impl Fn(&Path) -> bool) {
+        self.services.retain(|config, _| keep(config));
+    }
+
+    /// If a backend runs for `old`, shut it down and start one for `new`
+    /// (the same path restarts it, to load an edited config).
+    pub(crate) fn replace(&mut self, old: &Path, new: &Path) {
+        if self.services.remove(old).is_some() {
+            let service = EditorService::spawn(&self.dprint, new).ok();
+            self.services.insert(new.to_path_buf(), service);
+        }
+    }
+
+    /// `text` of `file` formatted under `config`, or None if no backend
+    /// could format it (the caller then runs `dprint fmt --stdin`), as for
+    /// a path that is not UTF-8: the protocol sends it as a string.
+    /// Unchanged text comes back as is.
+    pub(crate) fn format(&mut self, config: &Path, file: &Path, text: &str) -> Option<String> {
+        let file = file.to_str()?;
+        let slot = self
+            .services
+            .entry(config.to_path_buf())
+            .or_insert_with(|| EditorService::spawn(&self.dprint, config).ok());
+        let service = slot.as_mut()?;
+        match service.format(file, text) {
+            Ok(Reply::Formatted(formatted)) => Some(formatted.unwrap_or_else(|| text.to_string())),
+            // dprint's own error (e.g. a syntax error): fmt --stdin reports it.
+            Ok(Reply::Failed) => None,
+            Err(_) => {
+                *slot = None;
+                None
+            }
+        }
+    }
+}
+
+/// Answer to a `FormatFile` request.
+#[derive(Debug, PartialEq)]
+enum Reply {
+    /// New text, or None if the file was already formatted.
+    Formatted(Option<String>),
+    /// dprint could not format the file; the message is left to
+    /// `fmt --stdin`.
+    Failed,
+}
+
+/// One `dprint editor-service` process for one config.
+struct EditorService {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u32,
+}
+
+impl EditorService {
+    fn spawn(dprint: &Path, config: &Path) -> Result<Self> {
+        let mut child = Command::new(dprint)
+            .args(["editor-service", "--config"])
+            .arg(config)
+            .arg("--parent-pid")
+            .arg(std::process::id().to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .spawn_context(dprint, || {
+                format!("editor-service --config {}", config.display())
+            })?;
+        let stdin = child.stdin.take().context("editor-service stdin")?;
+        let stdout = BufReader::new(child.stdout.take().context("editor-service stdout")?);
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+            next_id: 1,
+        })
+    }
+
+    fn format(&mut self, file: &str, text: &str) -> Result<Reply> {
+        let id = self.next_id;
+        self.next_id += 1;
+        write_message(
+            &mut self.stdin,
+            id,
+            KIND_FORMAT_FILE,
+            &format_file_body(file, text)?,
+        )?;
+        loop {
+            let (_, kind, body) = read_message(&mut self.stdout)?;
+            let mut body = body.as_slice();
+            if kind != KIND_FORMAT_FILE_RESPONSE && kind != KIND_ERROR {
+                continue;
+            }
+            if read_u32(&mut body)? != id {
+                continue;
+            }
+            return Ok(if kind == KIND_ERROR {
+                Reply::Failed
+            } else if read_u32(&mut body)? == 0 {
+                Reply::Formatted(None)
+            } else {
+                Reply::Formatted(Some(read_string(&mut body)?))
+            });
+        }
+    }
+}
+
+impl Drop for EditorService {
+    fn drop(&mut self) {
+        let _ = write_message(&mut self.stdin, self.next_id, KIND_SHUTDOWN, &[]);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// `FormatFile` body: path, byte range (the whole text), override config,
+/// text.
+fn format_file_body(file: &str, text: &str) -> Result<Vec<u8>> {
+    let len = u32::try_from(text.len()).context("file too large for editor-service")?;
+    let mut body = Vec::with_capacity(text.len() + file.len() + 24);
+    put_string(&mut body, file)?;
+    body.extend(0u32.to_be_bytes());
+    body.extend(len.to_be_bytes());
+    put_string(&mut body, "{}")?;
+    put_string(&mut body, text)?;
+    Ok(body)
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) -> Result<()> {
+    let len = u32::try_from(s.len()).context("string too large for editor-service")?;
+    buf.extend(len.to_be_bytes());
+    buf.extend(s.as_bytes());
+    Ok(())
+}
+
+/// Write one message: id, kind, body length (big-endian u32s), body,
+/// trailer.
+fn write_message(writer: &mut impl Write, id: u32, kind: u32, body: &[u8]) -> Result<()> {
+    let len = u32::try_from(body.len()).context("message too large for editor-service")?;
+    let mut message = Vec::with_capacity(body.len() + 16);
+    message.extend(id.to_be_bytes());
+    message.extend(kind.to_be_bytes());
+    message.extend(len.to_be_bytes());
+    message.extend(body);
+    message.extend(SUCCESS_BYTES);
+    writer.write_all(&message)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read one message as (id, kind, body).
+fn read_message(reader: &mut impl Read) -> Result<(u32, u32, Vec<u8>)> {
+    let id = read_u32(reader)?;
+    let kind = read_u32(reader)?;
+    let len = read_u32(reader)?;
+    let mut body = vec![0; len as usize];
+    reader.read_exact(&mut body)?;
+    let mut trailer = [0; 4];
+    reader.read_exact(&mut trailer)?;
+    if trailer != SUCCESS_BYTES {
+        bail!("editor-service message {id} has a corrupt trailer");
+    }
+    Ok((id, kind, body))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let len = read_u32(reader)?;
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    String::from_utf8(buf).context("editor-service sent invalid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_framing() {
+        let mut wire = Vec::new();
+        write_message(
+            &mut wire,
+            7,
+            KIND_FORMAT_FILE,
+            &format_file_body("a.ts", "x").unwrap(),
+        )
+        .unwrap();
+        let (id, kind, body) = read_message(&mut wire.as_slice()).unwrap();
+        assert_eq!((id, kind), (7, KIND_FORMAT_FILE));
+        let mut body = body.as_slice();
+        assert_eq!(read_string(&mut body).unwrap(), "a.ts");
+        assert_eq!(read_u32(&mut body).unwrap(), 0);
+        assert_eq!(read_u32(&mut body).unwrap(), 1);
+        assert_eq!(read_string(&mut body).unwrap(), "{}");
+        assert_eq!(read_string(&mut body).unwrap(), "x");
+        assert!(body.is_empty());
+
+        let mut corrupt = wire.clone();
+        *corrupt.last_mut().unwrap() = 0;
+        assert!(read_message(&mut corrupt.as_slice()).is_err());
+        assert!(read_message(&mut &wire[..wire.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Exit code for configuration problems (sysexits `EX_CONFIG`).
+pub const CONFIG_EXIT_CODE: i32 = 78;
+/// Exit code when dprint (or git) cannot be started (sysexits
+/// `EX_UNAVAILABLE`).
+pub const UNAVAILABLE_EXIT_CODE: i32 = 69;
+
+/// Failures that callers — the exit code, `--events` — need to tell apart.
+/// Raised at module boundaries and carried inside `anyhow::Error`, so
+/// context added on the way up is kept; [`classify`] finds them again.
+#[derive(Debug)]
+pub enum DprintxError {
+    /// dprintx.jsonc or a local dprint config is unreadable or invalid.
+    Config {
+        path: PathBuf,
+        message: String,
+        /// 1-based line and column, with the source snippet to show.
+        at: Option<(usize, usize, String)>,
+    },
+    /// A match rule does not compile or names a missing profile.
+    Match { rule: String, message: String },
+    /// A program could not be started.
+    Spawn {
+        program: PathBuf,
+        command: String,
+        source: io::Error,
+    },
+    /// An LSP stream broke (framing, EOF mid-message).
+    Lsp { message: String },
+    /// A git command failed.
+    Git { command: String, message: String },
+}
+
+impl DprintxError {
+    /// Short machine-readable category, as in `--events` output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DprintxError::Config { .. } => "config",
+            DprintxError::Match { .. } => "match",
+            DprintxError::Spawn { .. } => "spawn",
+            DprintxError::Lsp { .. } => "lsp",
+            DprintxError::Git { .. } => "git",
+        }
+    }
+
+    /// Process exit code for a run that fails with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DprintxError::Config { .. } | DprintxError::Match { .. } => CONFIG_EXIT_CODE,
+            DprintxError::Spawn { .. } => UNAVAILABLE_EXIT_CODE,
+            DprintxError::Lsp { .. } | DprintxError::Git { .. } => 1,
+        }
+    }
+
+    /// Structured fields for `--events`, without the message.
+    fn fields(&self) -> serde_json::Value {
+        match self {
+            DprintxError::Config { path, at, .. } => {
+                let mut fields = serde_json::json!({ "path": path.display().to_string() });
+                if let Some((line, column, _)) = at {
+                    fields["line"] = (*line).into();
+                    fields["column"] = (*column).into();
+                }
+                fields
+            }
+            DprintxError::Match { rule, .. } => serde_json::json!({ "rule": rule }),
+            DprintxError::Spawn {
+                program, command, ..
+            } => serde_json::json!({
+                "program": program.display().to_string(),
+                "command": command,
+            }),
+            DprintxError::Lsp { .. } => serde_json::json!({}),
+            DprintxError::Git { command, .. } => serde_json::json!({ "command": command }),
+        }
+    }
+}
+
+impl fmt::Display for DprintxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DprintxError::Config {
+                path,
+                message,
+                at: Some((line, column, snippet)),
+            } => write!(
+                f,
+                "{message}\n --> {}:{line}:{column}\n{snippet}",
+                path.display()
+            ),
+            DprintxError::Config { path, message, .. } => {
+                write!(f, "{message}\n --> {}", path.display())
+            }
+            DprintxError::Match { message, .. } | DprintxError::Lsp { message } => {
+                f.write_str(message)
+            }
+            DprintxError::Spawn {
+                program, command, ..
+            } => write!(f, "cannot run {} ({command})", program.display()),
+            DprintxError::Git { command, message } => write!(f, "{command} failed: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for DprintxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DprintxError::Spawn { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// The classified failure behind `err`, if any link of its chain is one.
+pub fn classify(err: &anyhow::Error) -> Option<&DprintxError> {
+    err.chain().find_map(|e| e.downcast_ref::<DprintxError>())
+}
+
+/// `err` as an `--events` JSON line: `{"event":"error","kind":..,
+/// "message":..}` plus the structured fields of a classified error.
+pub fn to_event(err: &anyhow::Error) -> serde_json::Value {
+    let classified = classify(err);
+    let mut event = serde_json::json!({
+        "event": "error",
+        "kind": classified.map_or("other", DprintxError::kind),
+        "message": format!("{err:#}"),
+    });
+    if let (Some(e), Some(event)) = (classified, event.as_object_mut())
+        && let serde_json::Value::Object(fields) = e.fields()
+    {
+        event.extend(fields);
+    }
+    event
+}
+
+/// Turn a failed process start into [`DprintxError::Spawn`].
+pub trait SpawnContext<T> {
+    /// `command` describes the invocation, e.g. `fmt --config <path>`.
+    fn spawn_context(
+        self,
+        program: &Path,
+        command: impl FnOnce() -> String,
+    ) -> Result<T, DprintxError>;
+}
+
+impl<T> SpawnContext<T> for io::Result<T> {
+    fn spawn_context(
+        self,
+        program: &Path,
+        command: impl FnOnce() -> String,
+    ) -> Result<T, DprintxError> {
+        self.map_err(|source| DprintxError::Spawn {
+            program: program.to_path_buf(),
+            command: command(),
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_classify_through_context() {
+        let err: anyhow::Result<()> = Err(io::Error::from(io::ErrorKind::NotFound))
+            .spawn_context(Path::new("/bin/dprint"), || "check --config /c.json".into())
+            .context("checking files");
+        let err = err.unwrap_err();
+        let classified = classify(&err).unwrap();
+        assert_eq!(classified.kind(), "spawn");
+        assert_eq!(classified.exit_code(), UNAVAILABLE_EXIT_CODE);
+
+        let event = to_event(&err);
+        assert_eq!(event["event"], "error");
+        assert_eq!(event["kind"], "spawn");
+        assert_eq!(event["program"], "/bin/dprint");
+        assert!(
+            event["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("checking files: cannot run /bin/dprint (check --config /c.json)")
+        );
+
+        let plain = anyhow::anyhow!("something else");
+        assert!(classify(&plain).is_none());
+        assert_eq!(to_event(&plain)["kind"], "other");
+    }
+
+    #[test]
+    fn test_config_display() {
+        let err = DprintxError::Config {
+            path: PathBuf::from("/c/dprintx.jsonc"),
+            message: "bad".into(),
+            at: Some((3, 5, "   |\n 3 | x".into())),
+        };
+        assert_eq!(
+            err.to_string(),
+            "bad\n --> /c/dprintx.jsonc:3:5\n   |\n 3 | x"
+        );
+        assert_eq!(err.exit_code(), CONFIG_EXIT_CODE);
+    }
+}
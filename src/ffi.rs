@@ -0,0 +1,224 @@
+//! C ABI for editor plugins that want routing answers without spawning
+//! `dprintx` per buffer. Build the `cdylib` (`libdprintx.so`) and see
+//! `include/dprintx.h`.
+//!
+//! Strings in and out are NUL-terminated UTF-8. Results are JSON objects
+//! owned by the caller and released with [`dprintx_string_free`].
+
+use std::ffi::{CStr, CString, c_char};
+use std::path::Path;
+
+use crate::config::{DprintxConfig, ProfileResolution};
+use crate::matcher::ProfileMatcher;
+
+/// A loaded dprintx config with its compiled matcher. Opaque to C.
+pub struct DprintxRouter {
+    config: DprintxConfig,
+    matcher: ProfileMatcher,
+}
+
+impl DprintxRouter {
+    fn load(config_path: Option<&str>) -> anyhow::Result<Self> {
+        let config = match config_path {
+            Some(path) => DprintxConfig::load(Path::new(path))?,
+            None => DprintxConfig::try_load_default()?
+                .ok_or_else(|| anyhow::anyhow!("no dprintx config found"))?,
+        };
+        let matcher = ProfileMatcher::from_config(&config)?;
+        Ok(Self { config, matcher })
+    }
+
+    /// Routing for `path` as JSON: `{"resolution":"config","profile":..,
+    /// "config":..}`, `{"resolution":"ignore"}`, `{"resolution":"none"}` or
+    /// `{"error":..}`.
+    fn resolve(&self, path: &str, content_head: Option<&str>) -> serde_json::Value {
+        let path = Path::new(path);
+        let resolution = match content_head {
+            Some(head) => self
+                .matcher
+                .resolve_config_for_content(path, head, &self.config),
+            None => self.matcher.resolve_config(path, &self.config),
+        };
+        match resolution {
+            Ok(Some(ProfileResolution::Config(config))) => serde_json::json!({
+                "resolution": "config",
+                "profile": self.config.profile_label(&config),
+                "config": config.display().to_string(),
+            }),
+            Ok(Some(ProfileResolution::Ignore)) => serde_json::json!({ "resolution": "ignore" }),
+            Ok(None) => serde_json::json!({ "resolution": "none" }),
+            Err(e) => serde_json::json!({ "error": format!("{e:#}") }),
+        }
+    }
+}
+
+/// Load a dprintx config (NULL: the default location) for repeated
+/// [`dprintx_resolve_profile`] calls. On failure returns NULL and, if
+/// `error` is not NULL, stores a message there (free it with
+/// [`dprintx_string_free`]).
+///
+/// # Safety
+///
+/// `config_path` must be NULL or a valid NUL-terminated string; `error`
+/// must be NULL or valid for writing a pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dprintx_router_new(
+    config_path: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut DprintxRouter {
+    // SAFETY: guaranteed by the caller.
+    let config_path = unsafe { opt_str(config_path) };
+    let result = match config_path {
+        Ok(path) => DprintxRouter::load(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(router) => Box::into_raw(Box::new(router)),
+        Err(e) => {
+            if !error.is_null() {
+                // SAFETY: `error` is valid for writes per the contract.
+                unsafe { *error = into_c_string(format!("{e:#}")) };
+            }
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Resolve the profile for `path`. `content_head` (NULL: read the file) is
+/// the start of the buffer, used for pin comments and `match_content`.
+/// Returns a JSON object (see the header) to free with
+/// [`dprintx_string_free`].
+///
+/// # Safety
+///
+/// `router` must come from [`dprintx_router_new`] and not be freed;
+/// `path` must be a valid NUL-terminated string and `content_head` NULL
+/// or one.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dprintx_resolve_profile(
+    router: *const DprintxRouter,
+    path: *const c_char,
+    content_head: *const c_char,
+) -> *mut c_char {
+    // SAFETY: guaranteed by the caller.
+    let args = unsafe { (router.as_ref(), opt_str(path), opt_str(content_head)) };
+    let result = match args {
+        (None, _, _) => serde_json::json!({ "error": "router is NULL" }),
+        (_, Ok(None), _) => serde_json::json!({ "error": "path is NULL" }),
+        (_, Err(e), _) | (_, _, Err(e)) => serde_json::json!({ "error": format!("{e:#}") }),
+        (Some(router), Ok(Some(path)), Ok(head)) => router.resolve(path, head),
+    };
+    into_c_string(result.to_string())
+}
+
+/// Release a router from [`dprintx_router_new`]. NULL is ignored.
+///
+/// # Safety
+///
+/// `router` must be NULL or come from [`dprintx_router_new`], freed once.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dprintx_router_free(router: *mut DprintxRouter) {
+    if !router.is_null() {
+        // SAFETY: allocated by `dprintx_router_new` via Box.
+        drop(unsafe { Box::from_raw(router) });
+    }
+}
+
+/// Release a string returned by this library. NULL is ignored.
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by this library, freed once.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn dprintx_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        // SAFETY: allocated by `into_c_string` via CString.
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+/// Borrow a C string argument; NULL is None.
+///
+/// # Safety
+///
+/// `s` must be NULL or a valid NUL-terminated string outliving the result.
+unsafe fn opt_str<'a>(s: *const c_char) -> anyhow::Result<Option<&'a str>> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    // SAFETY: guaranteed by the caller.
+    let s = unsafe { CStr::from_ptr(s) };
+    Ok(Some(s.to_str().map_err(|_| {
+        anyhow::anyhow!("argument is not valid UTF-8")
+    })?))
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    // JSON and error text never contain NUL; strip defensively anyway.
+    CString::new(s.replace('\0', ""))
+        .expect("NUL bytes removed")
+        .into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(router: *const DprintxRouter, path: &str, head: Option<&str>) -> serde_json::Value {
+        let path = CString::new(path).unwrap();
+        let head = head.map(|h| CString::new(h).unwrap());
+        let head_ptr = head.as_ref().map_or(std::ptr::null(), |h| h.as_ptr());
+        unsafe {
+            let out = dprintx_resolve_profile(router, path.as_ptr(), head_ptr);
+            let json = CStr::from_ptr(out).to_str().unwrap().to_string();
+            dprintx_string_free(out);
+            serde_json::from_str(&json).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_router_resolve() {
+        let dir = std::env::temp_dir().join("dprintx-test-ffi");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("dprintx.jsonc");
+        std::fs::write(
+            &config_path,
+            r#"{
+                "dprint": "dprint",
+                "profiles": { "default": "/d.json", "docs": "/docs.json", "ignore": null },
+                "match": { "**/vendor/**": "ignore", "**/*.ts": "default" }
+            }"#,
+        )
+        .unwrap();
+
+        let c_path = CString::new(config_path.to_str().unwrap()).unwrap();
+        let router = unsafe { dprintx_router_new(c_path.as_ptr(), std::ptr::null_mut()) };
+        assert!(!router.is_null());
+
+        let json = call(router, "/src/a.ts", Some("let a = 1;\n"));
+        assert_eq!(json["resolution"], "config");
+        assert_eq!(json["profile"], "default");
+        assert_eq!(json["config"], "/d.json");
+        // A pin in the buffer head wins; the file need not exist.
+        let json = call(router, "/src/a.ts", Some("// dprintx-profile: docs\n"));
+        assert_eq!(json["profile"], "docs");
+        assert_eq!(call(router, "/vendor/x.ts", None)["resolution"], "ignore");
+        assert_eq!(call(router, "/src/a.go", None)["resolution"], "none");
+
+        unsafe { dprintx_router_free(router) };
+
+        let missing = CString::new(dir.join("missing.jsonc").to_str().unwrap()).unwrap();
+        let mut error: *mut c_char = std::ptr::null_mut();
+        let router = unsafe { dprintx_router_new(missing.as_ptr(), &mut error) };
+        assert!(router.is_null());
+        let message = unsafe { CStr::from_ptr(error) }
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(message.contains("cannot read config"), "{message}");
+        unsafe { dprintx_string_free(error) };
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
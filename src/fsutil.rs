@@ -0,0 +1,543 @@
+use anyhow::{Context, Result};
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counter for unique temp file names within a process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Replace `path` with `content` so readers (and crashes) never observe a
+/// partially written file.
+///
+/// The content goes to a temp file in the same directory (same filesystem,
+/// so the rename is atomic), is synced, takes over the original's
+/// permissions and — where allowed — ownership, and is then renamed over
+/// `path`. Symlinks are resolved first, so the link itself is kept.
+pub fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let target = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let tmp = temp_path(&target);
+
+    let result = write_temp(&tmp, &target, content).and_then(|()| {
+        std::fs::rename(&tmp, &target).with_context(|| format!("replacing {}", target.display()))
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Create a new file `{prefix}-{unique}{suffix}` in `dir` holding `content`
+/// and return its path.
+///
+/// The name combines the pid, a per-process counter and a random part, and
+/// the file is created exclusively (`O_EXCL`) with a fresh name on collision.
+/// Concurrent processes — a long-lived LSP proxy next to CLI runs, or a new
+/// process that reuses the pid of one whose files are still around — never
+/// write to the same file.
+pub fn create_unique(dir: &Path, prefix: &str, suffix: &str, content: &[u8]) -> Result<PathBuf> {
+    let names = std::iter::repeat_with(|| format!("{prefix}-{}{suffix}", unique_part()));
+    create_exclusive(dir, names.take(CREATE_ATTEMPTS), content)
+}
+
+/// How many names [`create_unique`] tries before giving up.
+const CREATE_ATTEMPTS: usize = 16;
+
+/// Create the first of `names` that does not exist yet in `dir`.
+fn create_exclusive(
+    dir: &Path,
+    names: impl Iterator<Item = String>,
+    content: &[u8],
+) -> Result<PathBuf> {
+    for name in names {
+        let path = dir.join(name);
+        let mut file = match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("creating {}", path.display())),
+        };
+        if let Err(e) = file.write_all(content) {
+            let _ = std::fs::remove_file(&path);
+            return Err(e).with_context(|| format!("writing {}", path.display()));
+        }
+        return Ok(path);
+    }
+    anyhow::bail!("no unused file name in {}", dir.display())
+}
+
+/// `{pid}-{seq}-{random}`: readable for debugging, unique across processes.
+fn unique_part() -> String {
+    use std::hash::{BuildHasher, Hasher};
+
+    let seq = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    // RandomState is seeded from the OS, so the hash differs per process
+    // even when pid and counter repeat.
+    let mut hasher = std::hash::RandomState::new().build_hasher();
+    hasher.write_u64(seq);
+    if let Ok(now) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    format!(
+        "{}-{seq}-{:08x}",
+        std::process::id(),
+        hasher.finish() as u32
+    )
+}
+
+/// All files under `root`, sorted, skipping `.git` directories.
+///
+/// Symlinked directories are traversed only with `follow_symlinks`; each
+/// directory is visited once (by device and inode where available), so
+/// symlink cycles and forests of links into the same tree — common in
+/// `node_modules` — terminate. Symlinks to files are always listed.
+pub fn walk_files(root: &Path, follow_symlinks: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut visited = std::collections::HashSet::new();
+    let mut stack = vec![root.to_path_buf()];
+
+    while let Some(dir) = stack.pop() {
+        let meta = std::fs::metadata(&dir).with_context(|| format!("reading {}", dir.display()))?;
+        if !visited.insert(dir_identity(&dir, &meta)) {
+            continue;
+        }
+        let entries =
+            std::fs::read_dir(&dir).with_context(|| format!("reading {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("reading {}", dir.display()))?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            let is_dir = if file_type.is_symlink() {
+                // Dangling links are neither files nor directories: skip them.
+                match std::fs::metadata(&path) {
+                    Ok(target) if target.is_dir() => {
+                        if !follow_symlinks {
+                            continue;
+                        }
+                        true
+                    }
+                    Ok(_) => false,
+                    Err(_) => continue,
+                }
+            } else {
+                file_type.is_dir()
+            };
+            if is_dir {
+                if entry.file_name() != ".git" {
+                    stack.push(path);
+                }
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Identity of a directory for cycle detection.
+#[cfg(unix)]
+fn dir_identity(_dir: &Path, meta: &std::fs::Metadata) -> (u64, u64, PathBuf) {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino(), PathBuf::new())
+}
+
+#[cfg(not(unix))]
+fn dir_identity(dir: &Path, _meta: &std::fs::Metadata) -> (u64, u64, PathBuf) {
+    (
+        0,
+        0,
+        std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf()),
+    )
+}
+
+/// Hidden sibling of `target`, unique per process and call.
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{name}.dprintx-{}.tmp", unique_part()))
+}
+
+fn write_temp(tmp: &Path, target: &Path, content: &[u8]) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(tmp)
+        .with_context(|| format!("writing {}", tmp.display()))?;
+    file.write_all(content)
+        .with_context(|| format!("writing {}", tmp.display()))?;
+
+    if let Ok(meta) = std::fs::metadata(target) {
+        file.set_permissions(meta.permissions())
+            .with_context(|| format!("setting permissions on {}", tmp.display()))?;
+        preserve_owner(tmp, &meta);
+    }
+
+    file.sync_all()
+        .with_context(|| format!("syncing {}", tmp.display()))
+}
+
+/// Best effort: only root (or the owner, for group changes) may chown.
+#[cfg(unix)]
+fn preserve_owner(tmp: &Path, meta: &std::fs::Metadata) {
+    use std::os::unix::fs::MetadataExt;
+    let _ = std::os::unix::fs::chown(tmp, Some(meta.uid()), Some(meta.gid()));
+}
+
+#[cfg(not(unix))]
+fn preserve_owner(_tmp: &Path, _meta: &std::fs::Metadata) {}
+
+/// The path spelled by `bytes` (git and dprint output, decoded URIs): any
+/// bytes on unix, UTF-8 elsewhere, lossily.
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// `path` as bytes, the way [`path_from_bytes`] reads it back.
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+/// `path` with symlinks resolved, or `path` itself if it cannot be resolved
+/// (e.g. it does not exist yet), in the form match globs are written
+/// against: on Windows without the `\\?\` prefix canonicalization adds
+/// (std re-adds it for long paths when the file is opened).
+pub fn canonical(path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    let resolved = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    match routable(&resolved) {
+        Ok(Cow::Owned(normalized)) => normalized,
+        _ => resolved,
+    }
+}
+
+/// `path` ready for routing: on Windows, verbatim forms (`\\?\C:\...`,
+/// `\\?\UNC\server\share\...`) become plain drive or UNC paths. Forms that
+/// cannot be matched or handed to dprint — device paths, volume GUID paths,
+/// alternate data streams — are an error naming the form. Elsewhere `path`
+/// is returned unchanged.
+pub fn routable(path: &Path) -> std::result::Result<Cow<'_, Path>, &'static str> {
+    if !cfg!(windows) {
+        return Ok(Cow::Borrowed(path));
+    }
+    let Some(s) = path.to_str() else {
+        return Ok(Cow::Borrowed(path));
+    };
+    Ok(match windows_routable(s)? {
+        Some(normalized) => Cow::Owned(PathBuf::from(normalized)),
+        None => Cow::Borrowed(path),
+    })
+}
+
+/// [`routable`] on a Windows path spelled `s`. None: already plain.
+fn windows_routable(s: &str) -> std::result::Result<Option<String>, &'static str> {
+    // `\\?\` (verbatim) and `\\.\` (device), also spelled with `/`.
+    let b = s.as_bytes();
+    let is_sep = |c: u8| c == b'\\' || c == b'/';
+    let special = b.len() >= 4 && is_sep(b[0]) && is_sep(b[1]) && is_sep(b[3]);
+    let normalized = match b.get(2) {
+        Some(b'.') if special => return Err("device paths are not supported"),
+        Some(b'?') if special => {
+            let rest = &s[4..];
+            let unc = rest
+                .get(..4)
+                .filter(|p| p[..3].eq_ignore_ascii_case("UNC") && is_sep(p.as_bytes()[3]));
+            if unc.is_some() {
+                Some(format!(r"\\{}", &rest[4..]))
+            } else if has_drive(rest) {
+                Some(rest.to_string())
+            } else {
+                return Err("volume GUID paths are not supported");
+            }
+        }
+        _ => None,
+    };
+
+    let plain = normalized.as_deref().unwrap_or(s);
+    let after_drive = if has_drive(plain) { &plain[2..] } else { plain };
+    if after_drive.contains(':') {
+        return Err("alternate data streams are not supported");
+    }
+    Ok(normalized)
+}
+
+/// `pattern` as globset takes it: on Windows with `\` separators as `/`,
+/// since globset matches Windows paths spelled with `/` and does not take
+/// `\` for a separator in patterns (paths expanded from `~` have them).
+/// Elsewhere `\` escapes, and `pattern` is unchanged.
+pub fn glob_pattern(pattern: &Path) -> Cow<'_, str> {
+    let pattern = pattern.to_string_lossy();
+    if !cfg!(windows) {
+        return pattern;
+    }
+    windows_glob(pattern)
+}
+
+/// [`glob_pattern`] on Windows.
+fn windows_glob(pattern: Cow<'_, str>) -> Cow<'_, str> {
+    if pattern.contains('\\') {
+        Cow::Owned(pattern.replace('\\', "/"))
+    } else {
+        pattern
+    }
+}
+
+/// Whether `s` starts with a drive letter (`C:`).
+fn has_drive(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() >= 2 && b[0].is_ascii_alphabetic() && b[1] == b':'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_routable() {
+        let ok = |s: &str| windows_routable(s).unwrap();
+        assert_eq!(ok(r"C:\src\app\main.ts"), None);
+        assert_eq!(ok(r"\\server\share\a.ts"), None);
+        assert_eq!(ok(r"\\?\C:\src\a.ts").as_deref(), Some(r"C:\src\a.ts"));
+        assert_eq!(ok("//?/C:/src/a.ts").as_deref(), Some("C:/src/a.ts"));
+        assert_eq!(
+            ok(r"\\?\UNC\server\share\a.ts").as_deref(),
+            Some(r"\\server\share\a.ts")
+        );
+        let long = format!(r"\\?\D:\{}\a.ts", ["deep"; 80].join(r"\"));
+        assert_eq!(ok(&long).as_deref(), Some(&long[4..]));
+
+        for exotic in [
+            r"C:\src\a.ts:hidden",
+            r"\\?\C:\src\a.ts:Zone.Identifier:$DATA",
+            r"\\server\share\a.ts:s",
+            r"\\.\COM1",
+            r"\\?\Volume{0b0a5c2e-0000-0000-0000-100000000000}\a.ts",
+        ] {
+            assert!(windows_routable(exotic).is_err(), "{exotic}");
+        }
+    }
+
+    #[test]
+    fn test_windows_glob() {
+        let glob = |s: &str| windows_glob(Cow::Borrowed(s)).into_owned();
+        assert_eq!(glob(r"C:\Users\me/src/**"), "C:/Users/me/src/**");
+        assert_eq!(glob(r"\\server\share\**\*.md"), "//server/share/**/*.md");
+        assert_eq!(glob("**/*.ts"), "**/*.ts");
+    }
+
+    #[test]
+    fn test_routable() {
+        let verbatim = Path::new(r"\\?\C:\src\a.ts");
+        let device = Path::new(r"\\.\COM1");
+        if cfg!(windows) {
+            assert_eq!(routable(verbatim).unwrap(), Path::new(r"C:\src\a.ts"));
+            assert!(routable(device).is_err());
+        } else {
+            // Plain file names elsewhere.
+            assert_eq!(routable(verbatim).unwrap(), verbatim);
+            assert_eq!(routable(device).unwrap(), device);
+        }
+    }
+
+    #[test]
+    fn test_create_exclusive_skips_taken_names() {
+        let dir = std::env::temp_dir().join("dprintx-test-create-exclusive");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // Left behind by another process with the same pid and counter.
+        std::fs::write(dir.join("merged-1-0.json"), "theirs").unwrap();
+
+        let names = ["merged-1-0.json", "merged-1-0b.json"].map(String::from);
+        let path = create_exclusive(&dir, names.into_iter(), b"ours").unwrap();
+        assert_eq!(path, dir.join("merged-1-0b.json"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("merged-1-0.json")).unwrap(),
+            "theirs"
+        );
+
+        let names = ["merged-1-0.json".to_string()];
+        assert!(create_exclusive(&dir, names.into_iter(), b"ours").is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_create_unique_concurrent() {
+        let dir = std::env::temp_dir().join("dprintx-test-create-unique");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Two runs creating merged configs at the same time.
+        let runs: Vec<_> = (0..2)
+            .map(|run| {
+                let dir = dir.clone();
+                std::thread::spawn(move || {
+                    (0..50)
+                        .map(|i| {
+                            let content = format!("{run}-{i}");
+                            let path =
+                                create_unique(&dir, "merged", ".json", content.as_bytes()).unwrap();
+                            (path, content)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let created: Vec<(PathBuf, String)> =
+            runs.into_iter().flat_map(|h| h.join().unwrap()).collect();
+
+        let unique: std::collections::HashSet<_> = created.iter().map(|(p, _)| p).collect();
+        assert_eq!(unique.len(), 100);
+        for (path, content) in &created {
+            assert_eq!(&std::fs::read_to_string(path).unwrap(), content);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Set in the child processes of [`test_create_unique_processes`]: the
+    /// directory to create files in.
+    const CREATE_UNIQUE_CHILD: &str = "DPRINTX_TEST_CREATE_UNIQUE_DIR";
+
+    #[test]
+    fn test_create_unique_processes() {
+        if let Some(dir) = std::env::var_os(CREATE_UNIQUE_CHILD) {
+            for i in 0..50 {
+                let content = format!("{}-{i}", std::process::id());
+                create_unique(Path::new(&dir), "merged", ".json", content.as_bytes()).unwrap();
+            }
+            return;
+        }
+
+        let dir = std::env::temp_dir().join("dprintx-test-create-unique-processes");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // This test again, twice at once, in child mode.
+        let children: Vec<_> = (0..2)
+            .map(|_| {
+                std::process::Command::new(std::env::current_exe().unwrap())
+                    .args(["--exact", "fsutil::tests::test_create_unique_processes"])
+                    .env(CREATE_UNIQUE_CHILD, &dir)
+                    .stdout(std::process::Stdio::null())
+                    .spawn()
+                    .unwrap()
+            })
+            .collect();
+        for mut child in children {
+            assert!(child.wait().unwrap().success());
+        }
+
+        let mut contents = std::collections::HashSet::new();
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let content = std::fs::read_to_string(&path).unwrap();
+            // Each name carries the pid of the process that wrote it.
+            let pid = content.split('-').next().unwrap();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            assert!(
+                name.starts_with(&format!("merged-{pid}-")),
+                "{name}: {content}"
+            );
+            contents.insert(content);
+        }
+        assert_eq!(contents.len(), 100);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_path_bytes_round_trip() {
+        let path = path_from_bytes(b"/repo/caf\xc3\xa9.md");
+        assert_eq!(path, Path::new("/repo/café.md"));
+        assert_eq!(&*path_bytes(&path), b"/repo/caf\xc3\xa9.md");
+        // Not UTF-8: kept as is on unix.
+        #[cfg(unix)]
+        assert_eq!(&*path_bytes(&path_from_bytes(b"caf\xe9.md")), b"caf\xe9.md");
+    }
+
+    #[test]
+    fn test_write_atomic_new_file() {
+        let dir = std::env::temp_dir().join("dprintx-test-atomic-new");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.ts");
+
+        write_atomic(&path, b"let a = 1;\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "let a = 1;\n");
+        // No temp files left behind.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_files_symlinks() {
+        let dir = std::env::temp_dir().join("dprintx-test-walk");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src/.git")).unwrap();
+        std::fs::create_dir_all(dir.join("other")).unwrap();
+        std::fs::write(dir.join("src/a.ts"), "").unwrap();
+        std::fs::write(dir.join("src/.git/config"), "").unwrap();
+        std::fs::write(dir.join("other/b.ts"), "").unwrap();
+        // A cycle back to the root and a link to a sibling tree.
+        std::os::unix::fs::symlink(&dir, dir.join("src/loop")).unwrap();
+        std::os::unix::fs::symlink(dir.join("other"), dir.join("src/linked")).unwrap();
+
+        let files = walk_files(&dir.join("src"), false).unwrap();
+        assert_eq!(files, vec![dir.join("src/a.ts")]);
+
+        let files = walk_files(&dir.join("src"), true).unwrap();
+        assert_eq!(files.len(), 2, "{files:?}");
+        assert!(files.contains(&dir.join("src/a.ts")));
+        assert!(files.iter().any(|f| f.ends_with("b.ts")));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_preserves_mode_and_symlink() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join("dprintx-test-atomic-mode");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.sh");
+        std::fs::write(&path, "old\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o750)).unwrap();
+        let link = dir.join("link.sh");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+
+        write_atomic(&link, b"new\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        assert!(std::fs::symlink_metadata(&link).unwrap().is_symlink());
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::error::{DprintxError, SpawnContext};
+use crate::fsutil;
+
+/// Root of the git worktree containing `path`: the nearest ancestor with a
+/// `.git` entry. That entry is a directory in the primary worktree and a
+/// file (`gitdir: ...`) in linked worktrees and submodules.
+pub fn repo_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Primary worktree of the linked worktree rooted at `root`, or None if
+/// `root` is not a linked worktree (a primary worktree, a submodule, or a
+/// bare repository's worktree).
+///
+/// A linked worktree's `.git` file points at `<common>/worktrees/<name>`,
+/// whose `commondir` file leads back to the shared `<primary>/.git`.
+pub fn primary_worktree(root: &Path) -> Option<PathBuf> {
+    let dot_git = std::fs::read_to_string(root.join(".git")).ok()?;
+    let gitdir = root.join(dot_git.strip_prefix("gitdir:")?.trim());
+    let commondir = std::fs::read_to_string(gitdir.join("commondir")).ok()?;
+    let common = std::fs::canonicalize(gitdir.join(commondir.trim())).ok()?;
+    if common.file_name()? != ".git" {
+        return None;
+    }
+    common.parent().map(Path::to_path_buf)
+}
+
+/// `path` moved to the same place in the primary worktree, if it lies in
+/// a linked worktree.
+pub fn in_primary_worktree(path: &Path) -> Option<PathBuf> {
+    let root = repo_root(path)?;
+    let primary = primary_worktree(&root)?;
+    Some(primary.join(path.strip_prefix(&root).ok()?))
+}
+
+/// Files staged for commit in the repository containing the current
+/// directory, as absolute paths. Deletions are left out.
+pub fn staged_files() -> Result<Vec<PathBuf>> {
+    let root = toplevel()?;
+    let out = git(&[
+        "diff",
+        "--cached",
+        "--name-only",
+        "--diff-filter=ACMR",
+        "-z",
+    ])?;
+    Ok(split_paths(&root, &out))
+}
+
+/// Files changed between the merge base of `rev` and HEAD and the working
+/// tree, as absolute paths: what a branch changes relative to `rev`,
+/// uncommitted edits included. Deletions and untracked files are left out.
+pub fn changed_since(rev: &str) -> Result<Vec<PathBuf>> {
+    let root = toplevel()?;
+    let base = String::from_utf8(git(&["merge-base", rev, "HEAD"])?)
+        .context("git merge-base output is not valid UTF-8")?;
+    let out = git(&[
+        "diff",
+        "--name-only",
+        "--diff-filter=ACMR",
+        "-z",
+        base.trim_end_matches('\n'),
+        "--",
+    ])?;
+    Ok(split_paths(&root, &out))
+}
+
+/// Staged files that also have unstaged changes. Formatting and re-staging
+/// them would sneak the unstaged changes into the commit.
+pub fn partially_staged(staged: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let root = toplevel()?;
+    let out = git(&["diff", "--name-only", "-z"])?;
+    let unstaged = split_paths(&root, &out);
+    Ok(staged
+        .iter()
+        .filter(|f| unstaged.contains(f))
+        .cloned()
+        .collect())
+}
+
+/// Stage `files` again after formatting.
+pub fn add(files: &[PathBuf]) -> Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    let status = Command::new("git")
+        .arg("add")
+        .arg("--")
+        .args(files)
+        .status()
+        .spawn_context(Path::new("git"), || "add".to_string())?;
+    if !status.success() {
+        return Err(DprintxError::Git {
+            command: "git add".to_string(),
+            message: format!("exited with {status}"),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Top-level directory of the current worktree.
+fn toplevel() -> Result<PathBuf> {
+    let out = git(&["rev-parse", "--show-toplevel"])?;
+    let out = out.strip_suffix(b"\n").unwrap_or(&out);
+    Ok(fsutil::path_from_bytes(out))
+}
+
+/// Run git with `args` and return its stdout, as bytes: paths in it need
+/// not be UTF-8.
+fn git(args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .spawn_context(Path::new("git"), || args.join(" "))?;
+    if !output.status.success() {
+        return Err(DprintxError::Git {
+            command: format!("git {}", args.join(" ")),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    Ok(output.stdout)
+}
+
+/// NUL-separated repository-relative paths (`-z` output) made absolute.
+fn split_paths(root: &Path, out: &[u8]) -> Vec<PathBuf> {
+    out.split(|&b| b == 0)
+        .filter(|p| !p.is_empty())
+        .map(|p| root.join(fsutil::path_from_bytes(p)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linked_worktree() {
+        let dir = std::env::temp_dir().join("dprintx-test-git-worktree");
+        let _ = std::fs::remove_dir_all(&dir);
+        let main = dir.join("main");
+        let linked = dir.join("feature");
+        let admin = main.join(".git/worktrees/feature");
+        std::fs::create_dir_all(&admin).unwrap();
+        std::fs::create_dir_all(main.join("src")).unwrap();
+        std::fs::create_dir_all(linked.join("src")).unwrap();
+        std::fs::write(admin.join("commondir"), "../..\n").unwrap();
+        std::fs::write(
+            linked.join(".git"),
+            format!("gitdir: {}\n", admin.display()),
+        )
+        .unwrap();
+        let main = std::fs::canonicalize(&main).unwrap();
+
+        let file = linked.join("src/a.ts");
+        assert_eq!(repo_root(&file), Some(linked.clone()));
+        assert_eq!(primary_worktree(&linked), Some(main.clone()));
+        assert_eq!(in_primary_worktree(&file), Some(main.join("src/a.ts")));
+
+        // The primary worktree maps to nothing.
+        assert_eq!(repo_root(&main.join("src/b.ts")), Some(main.clone()));
+        assert_eq!(in_primary_worktree(&main.join("src/b.ts")), None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_split_paths() {
+        let root = Path::new("/repo");
+        assert_eq!(
+            split_paths(root, b"src/a b.ts\0README.md\0caf\xe9.md\0"),
+            vec![
+                PathBuf::from("/repo/src/a b.ts"),
+                PathBuf::from("/repo/README.md"),
+                root.join(fsutil::path_from_bytes(b"caf\xe9.md")),
+            ]
+        );
+        assert!(split_paths(root, b"").is_empty());
+    }
+}
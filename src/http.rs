@@ -0,0 +1,283 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+
+use crate::daemon::{self, CLIENT_TIMEOUT, Daemon};
+
+/// Largest request body accepted by `POST /fmt`.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// Longest accepted request or header line.
+const MAX_HEADER_LINE: usize = 8 * 1024;
+
+/// Most header lines accepted in one request.
+const MAX_HEADERS: usize = 100;
+
+/// Largest accepted request head: request line and headers together.
+const MAX_HEADER_BYTES: usize = 64 * 1024;
+
+/// Body of `POST /fmt`.
+#[derive(Debug, Deserialize)]
+struct FmtRequest {
+    /// Routes the content like `fmt --stdin <filename>`; relative names are
+    /// resolved against the server's working directory.
+    filename: String,
+    content: String,
+}
+
+/// A parsed HTTP request: only what the API looks at.
+#[derive(Debug, PartialEq)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// A request head over one of the header limits: answered with 431.
+#[derive(Debug)]
+struct HeadersTooLarge(String);
+
+impl std::fmt::Display for HeadersTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for HeadersTooLarge {}
+
+/// A response about to be written: status and JSON body.
+#[derive(Debug)]
+struct HttpResponse {
+    status: u16,
+    body: serde_json::Value,
+}
+
+impl HttpResponse {
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: serde_json::json!({ "error": message.into() }),
+        }
+    }
+}
+
+/// `dprintx serve`: answer `POST /fmt` on `listen` until killed, one request
+/// at a time, with the same resident state as `dprintx daemon`.
+pub fn serve(config_path: &Path, listen: &str) -> Result<()> {
+    let daemon = daemon::watch_configs(Daemon::load(&std::path::absolute(config_path)?)?);
+    let cwd = std::env::current_dir().context("getting current directory")?;
+    let listener = TcpListener::bind(listen).with_context(|| format!("binding {listen}"))?;
+    let addr = listener.local_addr()?;
+    if !addr.ip().is_loopback() {
+        eprintln!("dprintx: warning: {addr} is not a loopback address and has no authentication");
+    }
+    eprintln!("dprintx: serving http://{addr}/fmt");
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("dprintx: serve accept failed: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = handle(&mut daemon.lock().unwrap(), &cwd, stream) {
+            eprintln!("dprintx: serve request failed: {e:#}");
+        }
+    }
+    Ok(())
+}
+
+fn handle(daemon: &mut Daemon, cwd: &Path, stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let response = match read_request(&mut BufReader::new(stream)) {
+        Ok(request) => route(daemon, cwd, &request),
+        Err(e) if e.is::<HeadersTooLarge>() => HttpResponse::error(431, format!("{e:#}")),
+        Err(e) => HttpResponse::error(400, format!("{e:#}")),
+    };
+    if response.status >= 500 {
+        eprintln!("dprintx: serve: {}", response.body["error"]);
+    }
+    write_response(&mut writer, &response)
+}
+
+fn route(daemon: &mut Daemon, cwd: &Path, request: &HttpRequest) -> HttpResponse {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/fmt") => {}
+        (_, "/fmt") => return HttpResponse::error(405, "use POST /fmt"),
+        _ => return HttpResponse::error(404, format!("no such endpoint: {}", request.path)),
+    }
+    let fmt: FmtRequest = match serde_json::from_slice(&request.body) {
+        Ok(fmt) => fmt,
+        Err(e) => return HttpResponse::error(400, format!("invalid request body: {e}")),
+    };
+    if let Err(e) = daemon.refresh() {
+        return HttpResponse::error(500, format!("{e:#}"));
+    }
+    match daemon.fmt_content(cwd, &fmt.filename, fmt.content) {
+        Ok(out) if out.code == 0 => HttpResponse {
+            status: 200,
+            body: serde_json::json!({ "content": out.stdout, "stderr": out.stderr }),
+        },
+        // dprint rejected the content, e.g. a syntax error.
+        Ok(out) => HttpResponse::error(422, out.stderr),
+        Err(e) => HttpResponse::error(500, format!("{e:#}")),
+    }
+}
+
+/// Read the request line, headers and a `Content-Length` body. A head over
+/// [`MAX_HEADER_LINE`], [`MAX_HEADERS`] or [`MAX_HEADER_BYTES`] is a
+/// [`HeadersTooLarge`] error.
+fn read_request(reader: &mut impl BufRead) -> Result<HttpRequest> {
+    let mut line = String::new();
+    let mut head = read_line(reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("malformed request line");
+    };
+    let method = method.to_string();
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let mut length = 0;
+    for count in 0.. {
+        let read = read_line(reader, &mut line)?;
+        if read == 0 {
+            bail!("connection closed in headers");
+        }
+        head += read;
+        if head > MAX_HEADER_BYTES {
+            return Err(HeadersTooLarge(format!("headers exceed {MAX_HEADER_BYTES} bytes")).into());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(HeadersTooLarge(format!("more than {MAX_HEADERS} headers")).into());
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            bail!("malformed header: {header}");
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.trim().parse().context("invalid Content-Length")?;
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            bail!("chunked bodies are not supported; send Content-Length");
+        }
+    }
+    if length > MAX_BODY {
+        bail!("request body exceeds {MAX_BODY} bytes");
+    }
+
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .context("reading request body")?;
+    Ok(HttpRequest { method, path, body })
+}
+
+/// Replace `line` with the next line of the request head, reading at most
+/// [`MAX_HEADER_LINE`] bytes. Returns the bytes read, 0 at end of stream.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> Result<usize> {
+    line.clear();
+    let read = reader
+        .by_ref()
+        .take(MAX_HEADER_LINE as u64)
+        .read_line(line)?;
+    if read == MAX_HEADER_LINE && !line.ends_with('\n') {
+        return Err(HeadersTooLarge(format!("line longer than {MAX_HEADER_LINE} bytes")).into());
+    }
+    Ok(read)
+}
+
+fn write_response(writer: &mut impl Write, response: &HttpResponse) -> Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    let body = serde_json::to_vec(&response.body)?;
+    write!(
+        writer,
+        "HTTP/1.1 {} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        body.len()
+    )?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = "POST /fmt?x=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 13\r\n\r\n{\"a\":\"hello\"}";
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(
+            request,
+            HttpRequest {
+                method: "POST".into(),
+                path: "/fmt".into(),
+                body: br#"{"a":"hello"}"#.to_vec(),
+            }
+        );
+
+        let raw = "GET / HTTP/1.1\r\n\r\n";
+        assert!(read_request(&mut raw.as_bytes()).unwrap().body.is_empty());
+
+        for raw in [
+            "\r\n",
+            "POST /fmt HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "POST /fmt HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n",
+            "POST /fmt HTTP/1.1\r\nContent-Length: 5\r\n\r\nab",
+        ] {
+            assert!(read_request(&mut raw.as_bytes()).is_err(), "{raw:?}");
+        }
+    }
+
+    #[test]
+    fn test_read_request_header_limits() {
+        let too_large = |raw: String| {
+            let err = read_request(&mut raw.as_bytes()).unwrap_err();
+            assert!(err.is::<HeadersTooLarge>(), "{err:#}");
+        };
+        let long = "x".repeat(MAX_HEADER_LINE);
+        too_large(format!("POST /fmt HTTP/1.1\r\nCookie: {long}\r\n\r\n"));
+        too_large(format!("POST /{long} HTTP/1.1\r\n\r\n"));
+        too_large(format!(
+            "POST /fmt HTTP/1.1\r\n{}\r\n",
+            "X-A: b\r\n".repeat(MAX_HEADERS + 1)
+        ));
+        let header = format!("X-A: {}\r\n", "x".repeat(MAX_HEADER_LINE / 2));
+        too_large(format!(
+            "POST /fmt HTTP/1.1\r\n{}\r\n",
+            header.repeat(MAX_HEADER_BYTES / header.len() + 1)
+        ));
+
+        // Just under the limits.
+        let raw = format!(
+            "POST /fmt HTTP/1.1\r\n{}\r\n",
+            "X-A: b\r\n".repeat(MAX_HEADERS)
+        );
+        assert!(read_request(&mut raw.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut out = Vec::new();
+        write_response(&mut out, &HttpResponse::error(404, "nope")).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(out.contains("Content-Length: 16\r\n"));
+        assert!(out.ends_with("\r\n\r\n{\"error\":\"nope\"}"));
+    }
+}
@@ -0,0 +1,298 @@
+use serde::de::DeserializeOwned;
+
+/// A JSONC syntax or deserialization error at a position in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    /// 1-based line.
+    pub line: usize,
+    /// 1-based column, in bytes.
+    pub column: usize,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at line {} column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<serde_json::Error> for ParseError {
+    fn from(e: serde_json::Error) -> Self {
+        let (line, column) = (e.line(), e.column());
+        let message = e.to_string();
+        let message = message
+            .strip_suffix(&format!(" at line {line} column {column}"))
+            .unwrap_or(&message)
+            .to_string();
+        Self {
+            message,
+            line,
+            column,
+        }
+    }
+}
+
+/// Parse JSONC (comments, trailing commas, optional BOM) into `T`.
+///
+/// Errors — from JSONC syntax or from deserialization — carry the line and
+/// column in `input`, because [`to_json`] keeps every byte in place.
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, ParseError> {
+    let json = to_json(input)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Convert JSONC to plain JSON without moving anything: comments, trailing
+/// commas and a leading BOM become spaces (newlines are kept), so line and
+/// column numbers in the result match the original text.
+///
+/// Strings are copied verbatim, escapes included — comment markers inside
+/// keys or values are never touched. Malformed JSON is left for the JSON
+/// parser to report; only unterminated block comments fail here.
+pub fn to_json(input: &str) -> Result<String, ParseError> {
+    let src = input.as_bytes();
+    let mut out = src.to_vec();
+    let mut i = 0;
+
+    if src.starts_with("\u{feff}".as_bytes()) {
+        blank(&mut out, 0, 3);
+        i = 3;
+    }
+
+    while i < src.len() {
+        match src[i] {
+            b'"' => i = skip_string(src, i),
+            b'/' => match skip_comment(src, i)? {
+                Some(end) => {
+                    blank(&mut out, i, end);
+                    i = end;
+                }
+                None => i += 1,
+            },
+            b',' => {
+                let next = skip_trivia(src, i + 1)?;
+                if matches!(src.get(next), Some(b'}' | b']')) {
+                    out[i] = b' ';
+                }
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+
+    // Only ASCII bytes (or whole multi-byte characters) were replaced.
+    Ok(String::from_utf8(out).expect("blanking keeps UTF-8 valid"))
+}
+
+/// 1-based line and column of byte offset `pos` in `src`. Columns count
+/// bytes, like serde_json's error positions.
+pub fn line_col(src: &[u8], pos: usize) -> (usize, usize) {
+    let before = &src[..pos.min(src.len())];
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    let line_start = before
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |n| n + 1);
+    (line, pos - line_start + 1)
+}
+
+/// Line and column of `key` inside the top-level `section` object, e.g. a
+/// glob in `match`. Matches the key's plain JSON spelling; comments are
+/// skipped. Used to point validation errors at the offending entry.
+pub fn find_key(input: &str, section: &str, key: &str) -> Option<(usize, usize)> {
+    let json = to_json(input).ok()?;
+    let quoted = |s: &str| serde_json::to_string(s).ok();
+    let section_at = find_token(&json, &quoted(section)?, 0)?;
+    let key_at = find_token(&json, &quoted(key)?, section_at)?;
+    Some(line_col(input.as_bytes(), key_at))
+}
+
+/// Offset of the first `token` at or after `from` that starts a string and
+/// is followed by a colon (i.e. an object key).
+fn find_token(json: &str, token: &str, from: usize) -> Option<usize> {
+    let src = json.as_bytes();
+    let mut i = from;
+    while i < src.len() {
+        if src[i] == b'"' {
+            let end = skip_string(src, i);
+            if &json[i..end] == token && json[end..].trim_start().starts_with(':') {
+                return Some(i);
+            }
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// The source line at `line` with a caret under `column`, rustc-style:
+///
+/// ```text
+///    |
+/// 12 |     "**": "default",
+///    |     ^
+/// ```
+pub fn snippet(input: &str, line: usize, column: usize) -> String {
+    let Some(text) = input.lines().nth(line.saturating_sub(1)) else {
+        return String::new();
+    };
+    let number = line.to_string();
+    let gutter = " ".repeat(number.len());
+    // Columns count bytes; pad by characters so multi-byte text lines up.
+    let prefix = text.get(..column.saturating_sub(1)).unwrap_or(text);
+    let pad: String = prefix
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    format!("{gutter} |\n{number} | {text}\n{gutter} | {pad}^")
+}
+
+/// Replace `out[start..end]` with spaces, keeping newlines.
+fn blank(out: &mut [u8], start: usize, end: usize) {
+    for b in &mut out[start..end] {
+        if *b != b'\n' && *b != b'\r' {
+            *b = b' ';
+        }
+    }
+}
+
+/// Offset just past the string starting at `start` (a `"`), or the end of
+/// input if it is unterminated.
+fn skip_string(src: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < src.len() {
+        match src[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    src.len()
+}
+
+/// If a comment starts at `start`, the offset just past it.
+fn skip_comment(src: &[u8], start: usize) -> Result<Option<usize>, ParseError> {
+    if src[start] != b'/' {
+        return Ok(None);
+    }
+    match src.get(start + 1) {
+        Some(b'/') => Ok(Some(
+            src[start..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(src.len(), |n| start + n),
+        )),
+        Some(b'*') => match src[start + 2..].windows(2).position(|w| w == b"*/") {
+            Some(n) => Ok(Some(start + 2 + n + 2)),
+            None => {
+                let (line, column) = line_col(src, start);
+                Err(ParseError {
+                    message: "unterminated block comment".to_string(),
+                    line,
+                    column,
+                })
+            }
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Offset of the next byte after whitespace and comments.
+fn skip_trivia(src: &[u8], mut i: usize) -> Result<usize, ParseError> {
+    while i < src.len() {
+        if src[i].is_ascii_whitespace() {
+            i += 1;
+        } else if let Some(end) = skip_comment(src, i)? {
+            i = end;
+        } else {
+            break;
+        }
+    }
+    Ok(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comments_and_trailing_commas() {
+        let input = r#"{
+  // line comment
+  "key": "value", // inline comment
+  /* block comment */
+  "key2": "val with // not a comment",
+  "list": [1, 2, /* last */ ],
+}"#;
+        let json = to_json(input).unwrap();
+        assert_eq!(json.len(), input.len());
+        assert_eq!(json.lines().count(), input.lines().count());
+        assert!(json.contains("\"key2\": \"val with // not a comment\""));
+        assert!(!json.contains("comment\n"));
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["list"], serde_json::json!([1, 2]));
+
+        // Keys starting with `*` after a comma are not comments.
+        let value: serde_json::Value = from_str("{\"a\": 1,\n \"**\": 2,}").unwrap();
+        assert_eq!(value["**"], 2);
+    }
+
+    #[test]
+    fn test_strings_with_markers_and_escapes() {
+        let input = r#"{ "a//b": "x\"/*y", "/*k*/": "é, }", "s": "\\", }"#;
+        let value: serde_json::Value = from_str(input).unwrap();
+        assert_eq!(value["a//b"], "x\"/*y");
+        assert_eq!(value["/*k*/"], "é, }");
+        assert_eq!(value["s"], "\\");
+    }
+
+    #[test]
+    fn test_bom() {
+        let value: serde_json::Value = from_str("\u{feff}{\"a\": 1}").unwrap();
+        assert_eq!(value["a"], 1);
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let err = to_json("{\n  \"a\": 1 /* open\n}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unterminated block comment at line 2 column 10"
+        );
+        assert_eq!((err.line, err.column), (2, 10));
+
+        // Positions after comments still match the original text.
+        let err =
+            from_str::<serde_json::Value>("{\n  // c\n  \"a\": 1 /* x */ \"b\": 2\n}").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 18));
+        assert_eq!(err.message, "expected `,` or `}`");
+    }
+
+    #[test]
+    fn test_find_key() {
+        let input = r#"{
+  // "**": "not this one"
+  "profiles": { "**": "/p.json" },
+  "match": {
+    "a/**": "x",
+    /* "**" */ "**": "y",
+  },
+}"#;
+        assert_eq!(find_key(input, "match", "**"), Some((6, 16)));
+        assert_eq!(find_key(input, "match", "b/**"), None);
+    }
+
+    #[test]
+    fn test_snippet() {
+        let input = "{\n  \"a\": tru\n}";
+        assert_eq!(snippet(input, 2, 8), "  |\n2 |   \"a\": tru\n  |        ^");
+        assert_eq!(snippet(input, 9, 1), "");
+    }
+}
@@ -0,0 +1,32 @@
+//! dprintx internals, shared by the `dprintx` binary and the C ABI in
+//! [`ffi`] for editor plugins that query routing without spawning the CLI.
+
+pub mod cache;
+pub mod cli;
+pub mod completions;
+pub mod config;
+pub mod daemon;
+pub mod disabled;
+pub mod edit;
+pub mod editor;
+pub mod error;
+pub mod ffi;
+pub mod fsutil;
+pub mod git;
+pub mod http;
+pub mod jsonc;
+pub mod lsp;
+pub mod lsplog;
+pub mod matcher;
+pub mod metrics;
+pub mod recent;
+pub mod remote;
+pub mod repro;
+pub mod rulestats;
+pub mod runner;
+pub mod sink;
+#[cfg(feature = "test-support")]
+pub mod testing;
+pub mod trust;
+pub mod warnings;
+pub mod watch;
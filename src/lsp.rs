@@ -1,17 +1,85 @@
 use anyhow::{Context, Result, bail};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, BufRead, BufReader, Write};
-use std::path::PathBuf;
-use std::process::{Child, Command, Stdio};
+use std::net::{Ipv4Addr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
-use crate::config::{self, DprintxConfig, ProfileResolution};
+use crate::cache::{CacheKey, FormatCache};
+use crate::config::{self, DprintxConfig, LspRoot, Merge, ProfileResolution};
+use crate::edit;
+use crate::error::{DprintxError, SpawnContext};
+use crate::fsutil;
+use crate::lsplog::{self, LspLog};
 use crate::matcher::ProfileMatcher;
+use crate::metrics::Metrics;
+use crate::warnings;
+use crate::watch::ConfigWatcher;
 
-/// Timeout for reading LSP responses from backends.
-const READ_TIMEOUT: Duration = Duration::from_secs(1);
+/// Default timeout for reading LSP responses from backends.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timeout for a backend to answer `initialize` (cold plugin loads can be slow).
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a backend that closed its stdout gets to exit before it is killed.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a formatting request that missed its deadline is still
+/// listened for, and its late result kept for a retry.
+const SALVAGE_TTL: Duration = Duration::from_secs(60);
+
+/// How often requests are checked for missed deadlines.
+const REAP_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Interval between `--metrics` file dumps.
+const METRICS_DUMP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// LSP error code: the request failed but was syntactically correct.
+const REQUEST_FAILED: i64 = -32803;
+
+/// How long a profile health probe may run at startup. Slower probes (e.g.
+/// first-time plugin downloads) are not treated as failures.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default upper bound on an LSP message body (`lsp_max_message_size`).
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Longest accepted LSP header line.
+const MAX_HEADER_LINE: usize = 8 * 1024;
+
+/// Largest line diff (old × new changed lines) computed exactly when
+/// cutting formatting edits to a range; larger changes stay one edit.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// JSON-RPC error code: the message is not a valid request.
+const INVALID_REQUEST: i64 = -32600;
+
+/// `window/showMessage` type for warnings.
+const MESSAGE_TYPE_WARNING: i64 = 2;
+
+/// `window/showMessage` type for information.
+const MESSAGE_TYPE_INFO: i64 = 3;
+
+/// `window/logMessage` type for plain log lines.
+const MESSAGE_TYPE_LOG: i64 = 4;
+
+/// The proxy's command routing a document to an ignore profile, with the
+/// document's URI as argument.
+const IGNORE_FILE_COMMAND: &str = "dprintx.ignoreFile";
+
+/// The proxy's command reloading the dprintx config and restarting the
+/// backends, for edits the config watching missed.
+const RELOAD_CONFIG_COMMAND: &str = "dprintx.reloadConfig";
+
+/// Diagnostic severities of unformatted documents (`lsp_diagnostics`), by
+/// the profile's `severity`.
+const DIAGNOSTIC_ERROR: i64 = 1;
+const DIAGNOSTIC_WARNING: i64 = 2;
 
 /// Map LSP languageId to file extension (without dot).
 /// Used to rewrite URIs so dprint can match files by extension
@@ -59,6 +127,15 @@ fn rewrite_uri(uri: &str, language_id: &str) -> Option<String> {
     Some(format!("{uri}.{ext}"))
 }
 
+/// Whether the editor's languageId disagrees with the file extension.
+/// True for languageIds without a known extension (e.g. `yaml.ansible`).
+fn language_disagrees(path: &Path, language_id: &str) -> bool {
+    match language_ext(language_id) {
+        Some(ext) => path.extension().and_then(|e| e.to_str()) != Some(ext),
+        None => true,
+    }
+}
+
 /// Apply URI rewriting to an LSP message based on the language map.
 /// Modifies params.textDocument.uri in-place if a rewrite is needed.
 fn apply_uri_rewrite(msg: &mut serde_json::Value, uri_languages: &HashMap<String, String>) {
@@ -79,61 +156,925 @@ fn apply_uri_rewrite(msg: &mut serde_json::Value, uri_languages: &HashMap<String
     }
 }
 
+/// Undo [`apply_uri_rewrite`] in a message from a backend: `params.uri`
+/// (e.g. `publishDiagnostics`) or `params.textDocument.uri` naming a
+/// rewritten URI gets the editor's URI back.
+fn restore_uri(msg: &mut serde_json::Value, rewritten: &HashMap<String, String>) {
+    for pointer in ["/params/uri", "/params/textDocument/uri"] {
+        if let Some(uri) = msg.pointer_mut(pointer)
+            && let Some(original) = uri.as_str().and_then(|u| rewritten.get(u))
+        {
+            *uri = serde_json::Value::String(original.clone());
+        }
+    }
+}
+
+/// LSP trace level set by the client (`initialize` params or `$/setTrace`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TraceLevel {
+    Off,
+    Messages,
+    Verbose,
+}
+
+impl TraceLevel {
+    fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("messages") => Self::Messages,
+            Some("verbose") => Self::Verbose,
+            _ => Self::Off,
+        }
+    }
+}
+
+/// Emit a `$/logTrace` notification if tracing is enabled.
+/// `verbose` details are only included at the verbose level.
+fn log_trace(
+    stdout: &ClientOut,
+    level: TraceLevel,
+    message: &str,
+    verbose: impl FnOnce() -> String,
+) -> Result<()> {
+    if level == TraceLevel::Off {
+        return Ok(());
+    }
+    let mut params = serde_json::json!({ "message": message });
+    if level == TraceLevel::Verbose {
+        params["verbose"] = serde_json::Value::String(verbose());
+    }
+    let notification = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "$/logTrace",
+        "params": params,
+    });
+    write_lsp_message(stdout, &serde_json::to_string(&notification)?)
+}
+
+/// True if the client opened a single file rather than a workspace:
+/// `initialize` carried neither rootUri/rootPath nor workspaceFolders.
+fn is_single_file_mode(params: &serde_json::Value) -> bool {
+    let empty = |key: &str| match params.get(key) {
+        None | Some(serde_json::Value::Null) => true,
+        Some(serde_json::Value::Array(items)) => items.is_empty(),
+        Some(serde_json::Value::String(s)) => s.is_empty(),
+        Some(_) => false,
+    };
+    empty("rootUri") && empty("rootPath") && empty("workspaceFolders")
+}
+
+/// Build `initialize` params for a backend serving `config_path`.
+///
+/// Under the default `config-dir` policy, workspace clients get rootUri
+/// overridden to the config file's directory so dprint knows which workspace
+/// the backend serves; in single-file mode the root comes from the document
+/// instead. `file-dir` always uses the document's directory and `client`
+/// keeps the client's root, or takes the workspace `folder` holding the
+/// document. Without a root to apply the client's params pass through.
+fn backend_init_params(
+    client_params: &serde_json::Value,
+    policy: LspRoot,
+    config_path: &Path,
+    document_dir: Option<&Path>,
+    folder: Option<&serde_json::Value>,
+) -> serde_json::Value {
+    let mut params = client_params.clone();
+    let root = match policy {
+        LspRoot::Client => None,
+        LspRoot::FileDir => document_dir,
+        LspRoot::ConfigDir if is_single_file_mode(client_params) => document_dir,
+        LspRoot::ConfigDir => config_path.parent(),
+    };
+    if let Some(root) = root {
+        let root = fsutil::routable(root).unwrap_or(std::borrow::Cow::Borrowed(root));
+        params["rootUri"] = serde_json::Value::String(path_to_uri(&root));
+        // Also set rootPath for older LSP compat.
+        params["rootPath"] = serde_json::Value::String(root.display().to_string());
+    } else if policy == LspRoot::Client
+        && let Some(uri) = folder.and_then(|f| f.get("uri")).and_then(|u| u.as_str())
+    {
+        // The client's own spelling of the folder.
+        params["rootUri"] = uri.into();
+        params["rootPath"] = uri_to_path(uri).display().to_string().into();
+    }
+    params
+}
+
+/// The editor's workspace folders from its `initialize` params: its
+/// `workspaceFolders`, or else its root as the only folder.
+fn workspace_folders(params: &serde_json::Value) -> Vec<serde_json::Value> {
+    if let Some(folders) = params.get("workspaceFolders").and_then(|f| f.as_array())
+        && !folders.is_empty()
+    {
+        return folders.clone();
+    }
+    let Some(uri) = params
+        .get("rootUri")
+        .and_then(|u| u.as_str())
+        .filter(|u| !u.is_empty())
+    else {
+        return Vec::new();
+    };
+    let path = uri_to_path(uri);
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    vec![serde_json::json!({ "uri": uri, "name": name.to_string_lossy() })]
+}
+
+/// Apply the `event` of a `workspace/didChangeWorkspaceFolders` to
+/// `folders`: removals first, then additions not already there.
+fn change_workspace_folders(folders: &mut Vec<serde_json::Value>, event: &serde_json::Value) {
+    let listed = |key: &str| {
+        event
+            .get(key)
+            .and_then(|f| f.as_array())
+            .cloned()
+            .unwrap_or_default()
+    };
+    let removed = listed("removed");
+    folders.retain(|folder| !removed.iter().any(|r| r.get("uri") == folder.get("uri")));
+    for added in listed("added") {
+        if !folders.iter().any(|f| f.get("uri") == added.get("uri")) {
+            folders.push(added);
+        }
+    }
+}
+
+/// The workspace folder holding `file`: the innermost one, as folders may
+/// nest.
+fn containing_folder<'a>(
+    folders: &'a [serde_json::Value],
+    file: &Path,
+) -> Option<&'a serde_json::Value> {
+    folders
+        .iter()
+        .filter_map(|folder| {
+            let uri = folder.get("uri")?.as_str()?;
+            let path = uri_to_path(uri);
+            (uri.starts_with("file://") && file.starts_with(&path))
+                .then(|| (path.components().count(), folder))
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, folder)| folder)
+}
+
+/// The editor's end of the proxy, shared with the backend reader threads.
+type ClientOut = Arc<Mutex<Box<dyn Write + Send>>>;
+
 /// LSP proxy: spawns dprint lsp per profile, routes requests by file URI.
 pub struct LspProxy {
     dprint_bin: PathBuf,
     matcher: ProfileMatcher,
     config: DprintxConfig,
+    metrics: Arc<Metrics>,
+    /// Routing records, if `lsp_log` is set.
+    log: Arc<LspLog>,
+    /// If set, metrics are periodically dumped to this file as JSON.
+    metrics_file: Option<PathBuf>,
+    /// Timeout for reading LSP responses from backends.
+    read_timeout: Duration,
+    /// Requests awaiting a backend response.
+    in_flight: Arc<InFlight>,
+    /// Backend requests forwarded to the editor, awaiting its response.
+    server_requests: Arc<ServerRequests>,
+    /// Configs whose backend died: respawned on their next request.
+    crashed: Arc<Mutex<HashSet<PathBuf>>>,
+    /// URIs rewritten by `lsp_rewrite_uris` → the editor's URI, restored
+    /// in messages from backends.
+    rewritten_uris: Arc<Mutex<HashMap<String, String>>>,
+    /// The dprintx config file and how to load it again, to pick up its
+    /// edits mid-session.
+    reload: Option<(PathBuf, Reload)>,
+    /// `dprintx` settings sections the editor sent, applied again on top
+    /// of a reloaded config.
+    client_settings: Vec<serde_json::Value>,
+    /// Formatted output, if `format_cache` is on.
+    cache: Option<Arc<FormatCache>>,
+    /// Documents the last check found unformatted (`lsp_diagnostics`).
+    unformatted: Unformatted,
 }
 
+/// Loads the dprintx config as the proxy was started with it.
+pub type Reload = Box<dyn Fn() -> Result<DprintxConfig> + Send + Sync>;
+
 /// A running dprint lsp backend.
 struct Backend {
-    _child: Child,
-    stdin: std::process::ChildStdin,
-    responses: mpsc::Receiver<String>,
+    child: Child,
+    /// Locked while writing one message, or while replaying the queue once
+    /// the backend is ready so nothing overtakes it.
+    stdin: Arc<Mutex<ChildStdin>>,
+    /// Id of the in-flight `initialize` request; None once the backend is ready.
+    init_id: Option<u64>,
+    /// Client messages queued while initializing, replayed once ready.
+    pending: Vec<serde_json::Value>,
+    /// Last time a client message was dispatched to this backend.
+    last_used: std::time::Instant,
+    /// Serves a merged config (spawned on demand): may be evicted when
+    /// `lsp_max_backends` is reached. Profile backends are never evicted.
+    evictable: bool,
+    /// Being shut down by the proxy: its exit is not a crash.
+    stopping: bool,
+    /// Capabilities from its `initialize` result; None until it answered.
+    capabilities: Option<serde_json::Value>,
+}
+
+/// Requests sent to backends and not answered yet, keyed by request id.
+/// Every request gets an id assigned here rather than the editor's, so
+/// requests of the proxy itself never clash with the editor's and each
+/// response maps back to exactly one waiter. Each backend's reader thread
+/// completes its requests as responses arrive, so a slow backend holds up
+/// only its own requests.
+///
+/// Formatting requests that miss their deadline are answered with the
+/// fallback but still listened for: their late result is kept for a retry
+/// of the same request on the same document version, and a retry arriving
+/// before it waits for it instead of queueing the work again.
+#[derive(Default)]
+struct InFlight {
+    next_id: AtomicU64,
+    requests: Mutex<HashMap<u64, Waiter>>,
+    /// Timed-out formatting requests still listened for.
+    late: Mutex<HashMap<u64, Late>>,
+    /// Salvage key → late result, with when it arrived.
+    salvaged: Mutex<HashMap<String, (Instant, serde_json::Value)>>,
+}
+
+/// A formatting request answered with its fallback, whose result may still
+/// come.
+struct Late {
+    backend: PathBuf,
+    salvage: String,
+    method: String,
+    profile: String,
+    started: Instant,
+    expired: Instant,
+}
+
+/// An in-flight request: which backend answers it, where the response
+/// goes, and until when.
+struct Waiter {
+    backend: PathBuf,
+    reply: Reply,
+    deadline: Instant,
+    /// For formatting requests: key of the result, see [`salvage_key`].
+    salvage: Option<String>,
+}
+
+/// Where a backend response goes.
+enum Reply {
+    /// To the editor as the answer to its request `id`, with metrics and
+    /// trace logging. `fallback` answers the request if the backend misses
+    /// the deadline.
+    Client {
+        id: serde_json::Value,
+        method: String,
+        profile: String,
+        started: Instant,
+        trace: TraceLevel,
+        fallback: Box<serde_json::Value>,
+        /// Set when a `rangeFormatting` request went out as `formatting`:
+        /// the result is cut down to the requested range.
+        range: Option<Box<RangeSlice>>,
+        /// Set for whole-document formatting with `format_cache` on: the
+        /// result is stored.
+        fill: Option<Box<CacheFill>>,
+    },
+    /// To the proxy itself (`initialize`, `shutdown`), blocked in
+    /// [`LspProxy::await_response`].
+    Internal(mpsc::Sender<serde_json::Value>),
+    /// To the document's diagnostics, for a check of the proxy's own.
+    Check(Box<Check>),
+    /// Nowhere: the request went to several backends and another one
+    /// answers it.
+    Discard,
+}
+
+/// Outcome of merging a local dprint config into a profile config, cached
+/// until the local config changes. Merged configs are held in the proxy's
+/// guards under their [`content_key`].
+#[derive(Clone, Copy, PartialEq)]
+enum MergeOutcome {
+    Merged(u64),
+    Profile,
+    Skip,
+}
+
+/// Merge outcomes by (local config, profile config), with the local
+/// config's mtime when merged.
+type Merges = HashMap<(PathBuf, PathBuf), (Option<std::time::SystemTime>, MergeOutcome)>;
+
+/// A formatting request whose result goes to the format cache.
+struct CacheFill {
+    cache: Arc<FormatCache>,
+    key: CacheKey,
+    /// Document text the request was sent for.
+    text: String,
+}
+
+/// A formatting request checking a document (`lsp_diagnostics`).
+struct Check {
+    /// The document's URI in the editor.
+    uri: String,
+    /// The document's version, if known.
+    version: serde_json::Value,
+    /// Document text the request was sent for.
+    text: String,
+    profile: String,
+    severity: i64,
+    unformatted: Unformatted,
+    fill: Option<Box<CacheFill>>,
+}
+
+/// Editor URI → the fix for its diagnostic, offered as a code action.
+type Unformatted = Arc<Mutex<HashMap<String, Fix>>>;
+
+/// Edits formatting a document.
+struct Fix {
+    /// Document text the edits apply to.
+    text: String,
+    edits: serde_json::Value,
+    diagnostic: serde_json::Value,
+    profile: String,
+}
+
+/// A `rangeFormatting` request served by formatting the whole document.
+struct RangeSlice {
+    /// Document text the request was sent for.
+    text: String,
+    /// The requested range.
+    range: serde_json::Value,
+}
+
+impl InFlight {
+    /// A fresh request id, not registered to any backend.
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Register a request to `backend` and return the id to send it with.
+    fn insert(&self, backend: &Path, reply: Reply, timeout: Duration) -> u64 {
+        let id = self.next_id();
+        let waiter = Waiter {
+            backend: backend.to_path_buf(),
+            reply,
+            deadline: Instant::now() + timeout,
+            salvage: None,
+        };
+        self.requests.lock().unwrap().insert(id, waiter);
+        id
+    }
+
+    /// Register a formatting request whose result has the key `salvage`.
+    /// If the same request to `backend` timed out and is still listened
+    /// for, the new one waits for its response: returns that id, and true
+    /// as there is nothing to send.
+    fn insert_formatting(
+        &self,
+        backend: &Path,
+        reply: Reply,
+        timeout: Duration,
+        salvage: String,
+    ) -> (u64, bool) {
+        let mut late = self.late.lock().unwrap();
+        let resumed = late
+            .iter()
+            .find(|(_, l)| l.backend == backend && l.salvage == salvage)
+            .map(|(id, _)| *id);
+        if let Some(id) = resumed {
+            late.remove(&id);
+        }
+        let id = resumed.unwrap_or_else(|| self.next_id());
+        let waiter = Waiter {
+            backend: backend.to_path_buf(),
+            reply,
+            deadline: Instant::now() + timeout,
+            salvage: Some(salvage),
+        };
+        // Still holding `late`: the response is found in one map or the other.
+        self.requests.lock().unwrap().insert(id, waiter);
+        (id, resumed.is_some())
+    }
+
+    /// Remove the request `id` sent to `backend`, if it is still waiting.
+    fn take(&self, backend: &Path, id: &serde_json::Value) -> Option<Reply> {
+        let id = id.as_u64()?;
+        let mut requests = self.requests.lock().unwrap();
+        if requests.get(&id)?.backend != backend {
+            return None;
+        }
+        requests.remove(&id).map(|waiter| waiter.reply)
+    }
+
+    /// The backend working on the editor's request `id`, and the id it was
+    /// sent with. None once the request is answered, or if no backend got it.
+    fn client_request(&self, id: &serde_json::Value) -> Option<(PathBuf, u64)> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, waiter)| matches!(&waiter.reply, Reply::Client { id: client, .. } if client == id))
+            .map(|(backend_id, waiter)| (waiter.backend.clone(), *backend_id))
+    }
+
+    /// Stop waiting for request `id`.
+    fn forget(&self, id: u64) {
+        self.requests.lock().unwrap().remove(&id);
+    }
+
+    /// Whether a request sent to `backend` is still waiting.
+    fn has(&self, backend: &Path) -> bool {
+        self.requests
+            .lock()
+            .unwrap()
+            .values()
+            .any(|waiter| waiter.backend == backend)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.requests.lock().unwrap().is_empty()
+    }
+
+    /// Remove all requests sent to `backend`.
+    fn take_backend(&self, backend: &Path) -> Vec<Reply> {
+        let mut requests = self.requests.lock().unwrap();
+        let ids: Vec<u64> = requests
+            .iter()
+            .filter(|(_, waiter)| waiter.backend == backend)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| requests.remove(&id))
+            .map(|waiter| waiter.reply)
+            .collect()
+    }
+
+    /// Remove the requests whose deadline has passed at `now`. Formatting
+    /// requests among them are still listened for until [`SALVAGE_TTL`].
+    fn take_expired(&self, now: Instant) -> Vec<(PathBuf, Reply)> {
+        let mut requests = self.requests.lock().unwrap();
+        let expired: Vec<u64> = requests
+            .iter()
+            .filter(|(_, waiter)| waiter.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+        let expired: Vec<(u64, Waiter)> = expired
+            .into_iter()
+            .filter_map(|id| requests.remove(&id).map(|waiter| (id, waiter)))
+            .collect();
+        drop(requests);
+
+        let mut late = self.late.lock().unwrap();
+        late.retain(|_, l| now.duration_since(l.expired) < SALVAGE_TTL);
+        for (id, waiter) in &expired {
+            if let (
+                Some(salvage),
+                Reply::Client {
+                    method,
+                    profile,
+                    started,
+                    ..
+                },
+            ) = (&waiter.salvage, &waiter.reply)
+            {
+                let request = Late {
+                    backend: waiter.backend.clone(),
+                    salvage: salvage.clone(),
+                    method: method.clone(),
+                    profile: profile.clone(),
+                    started: *started,
+                    expired: now,
+                };
+                late.insert(*id, request);
+            }
+        }
+        drop(late);
+        self.salvaged
+            .lock()
+            .unwrap()
+            .retain(|_, (arrived, _)| now.duration_since(*arrived) < SALVAGE_TTL);
+
+        expired
+            .into_iter()
+            .map(|(_, waiter)| (waiter.backend, waiter.reply))
+            .collect()
+    }
+
+    /// If `response` of `backend` answers a timed-out formatting request,
+    /// keep its result for a retry and return the request.
+    fn salvage(
+        &self,
+        backend: &Path,
+        id: &serde_json::Value,
+        response: &serde_json::Value,
+    ) -> Option<Late> {
+        let id = id.as_u64()?;
+        let mut late = self.late.lock().unwrap();
+        if late.get(&id)?.backend != backend {
+            return None;
+        }
+        let request = late.remove(&id)?;
+        drop(late);
+        if let Some(result) = response.get("result") {
+            self.salvaged
+                .lock()
+                .unwrap()
+                .insert(request.salvage.clone(), (Instant::now(), result.clone()));
+        }
+        Some(request)
+    }
+
+    /// The late result kept under `salvage`, once.
+    fn take_salvaged(&self, salvage: &str) -> Option<serde_json::Value> {
+        self.salvaged
+            .lock()
+            .unwrap()
+            .remove(salvage)
+            .map(|(_, result)| result)
+    }
+}
+
+/// Key of a formatting request's result: the backend, the document version
+/// and the request params, so a late result only answers the very same
+/// request. None for other methods, or documents without a known version.
+fn salvage_key(
+    method: &str,
+    backend: &Path,
+    open: Option<&serde_json::Value>,
+    msg: &serde_json::Value,
+) -> Option<String> {
+    if !matches!(
+        method,
+        "textDocument/formatting" | "textDocument/rangeFormatting"
+    ) {
+        return None;
+    }
+    let version = open?.pointer("/params/textDocument/version")?;
+    Some(format!(
+        "{}\n{version}\n{}",
+        backend.display(),
+        msg.get("params")?
+    ))
+}
+
+/// Requests from backends to the editor (`workspace/configuration`, ...),
+/// forwarded under ids from [`InFlight::next_id`] since backends number
+/// their requests independently. The editor's response goes back to the
+/// asking backend only, with its own id.
+#[derive(Default)]
+struct ServerRequests {
+    requests: Mutex<HashMap<u64, (PathBuf, serde_json::Value)>>,
+}
+
+impl ServerRequests {
+    fn insert(&self, id: u64, backend: &Path, backend_id: serde_json::Value) {
+        self.requests
+            .lock()
+            .unwrap()
+            .insert(id, (backend.to_path_buf(), backend_id));
+    }
+
+    /// The backend that sent request `id` and its id for it.
+    fn take(&self, id: &serde_json::Value) -> Option<(PathBuf, serde_json::Value)> {
+        self.requests.lock().unwrap().remove(&id.as_u64()?)
+    }
+
+    /// Forget the requests of a backend that went away.
+    fn remove_backend(&self, backend: &Path) {
+        self.requests
+            .lock()
+            .unwrap()
+            .retain(|_, (path, _)| path != backend);
+    }
+}
+
+impl Reply {
+    /// Deliver the response of `backend`; None means it missed the deadline.
+    fn finish(
+        self,
+        backend: &Path,
+        response: Option<serde_json::Value>,
+        stdout: &ClientOut,
+        metrics: &Metrics,
+        log: &LspLog,
+    ) {
+        match self {
+            Reply::Internal(tx) => {
+                if let Some(response) = response {
+                    let _ = tx.send(response);
+                }
+            }
+            Reply::Discard => {}
+            Reply::Check(check) => {
+                let formatted = response
+                    .as_ref()
+                    .and_then(|r| r.get("result"))
+                    .and_then(|result| formatted_text(&check.text, result));
+                if let Some(formatted) = formatted {
+                    if let Some(fill) = &check.fill {
+                        fill.cache.put(&fill.key, formatted.as_bytes());
+                    }
+                    check.publish(&formatted, stdout);
+                }
+            }
+            Reply::Client {
+                id,
+                method,
+                profile,
+                started,
+                trace,
+                fallback,
+                range,
+                fill,
+            } => {
+                let elapsed = started.elapsed();
+                let latency = response.as_ref().map(|_| elapsed);
+                metrics.record_request(backend, &profile, latency);
+                log.response(&id, &method, &profile, backend, latency);
+                let message = match &response {
+                    Some(_) => format!("dprintx: {method} via {profile}: responded in {elapsed:?}"),
+                    None => {
+                        format!("dprintx: {method} via {profile}: no response after {elapsed:?}")
+                    }
+                };
+                eprintln!("{message}");
+                let _ = log_trace(stdout, trace, &message, || {
+                    format!("backend: {}", backend.display())
+                });
+                if let Some(fill) = fill
+                    && let Some(formatted) = response
+                        .as_ref()
+                        .and_then(|r| r.get("result"))
+                        .and_then(|result| formatted_text(&fill.text, result))
+                {
+                    fill.cache.put(&fill.key, formatted.as_bytes());
+                }
+                let body = match response {
+                    Some(mut response) => {
+                        response["id"] = id;
+                        if let Some(slice) = range
+                            && let Some(result) = response.get_mut("result")
+                        {
+                            *result = slice_edits(&slice.text, result, &slice.range)
+                                .unwrap_or_else(|| {
+                                    eprintln!(
+                                        "dprintx: {method} via {profile}: cannot apply the edits, dropped"
+                                    );
+                                    serde_json::Value::Null
+                                });
+                        }
+                        response
+                    }
+                    None => *fallback,
+                };
+                let _ = write_lsp_message(stdout, &body.to_string());
+            }
+        }
+    }
+}
+
+impl CacheFill {
+    /// The cached formatted text, if any.
+    fn cached(&self) -> Option<String> {
+        String::from_utf8(self.cache.get(&self.key)?).ok()
+    }
+}
+
+impl Check {
+    /// Publish the document's diagnostic given its `formatted` text: none
+    /// if it is formatted, otherwise one at its first unformatted lines,
+    /// whose fix is kept for code actions.
+    fn publish(self, formatted: &str, stdout: &ClientOut) {
+        let mut unformatted = self.unformatted.lock().unwrap();
+        let diagnostics = if formatted == self.text {
+            unformatted.remove(&self.uri);
+            Vec::new()
+        } else {
+            let edits = text_edits(&self.text, formatted);
+            let range = edits.pointer("/0/range").cloned().unwrap_or_else(|| {
+                serde_json::json!({
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 0 },
+                })
+            });
+            let diagnostic = serde_json::json!({
+                "range": range,
+                "severity": self.severity,
+                "source": "dprintx",
+                "code": "unformatted",
+                "message": format!("file is not formatted per profile {}", self.profile),
+            });
+            unformatted.insert(
+                self.uri.clone(),
+                Fix {
+                    text: self.text,
+                    edits,
+                    diagnostic: diagnostic.clone(),
+                    profile: self.profile,
+                },
+            );
+            vec![diagnostic]
+        };
+        drop(unformatted);
+        publish_diagnostics(stdout, &self.uri, &self.version, diagnostics);
+    }
 }
 
 impl LspProxy {
     pub fn new(dprint_bin: PathBuf, matcher: ProfileMatcher, config: DprintxConfig) -> Self {
+        let read_timeout = config
+            .lsp_timeout_ms
+            .map_or(READ_TIMEOUT, Duration::from_millis);
+        let cache = FormatCache::open(&config, &dprint_bin).map(Arc::new);
         Self {
             dprint_bin,
             matcher,
             config,
+            metrics: Arc::new(Metrics::default()),
+            log: Arc::new(LspLog::default()),
+            metrics_file: None,
+            read_timeout,
+            in_flight: Arc::new(InFlight::default()),
+            server_requests: Arc::new(ServerRequests::default()),
+            crashed: Arc::new(Mutex::new(HashSet::new())),
+            rewritten_uris: Arc::new(Mutex::new(HashMap::new())),
+            reload: None,
+            client_settings: Vec::new(),
+            cache,
+            unformatted: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// Watch `path` (the dprintx config) and the profile configs, and apply
+    /// their edits without restarting: `reload` loads the config again.
+    pub fn with_config_reload(mut self, path: PathBuf, reload: Reload) -> Self {
+        self.reload = Some((path, reload));
+        self
+    }
+
+    /// Periodically dump per-backend metrics to `path` (JSON).
+    pub fn with_metrics_file(mut self, path: Option<PathBuf>) -> Self {
+        self.metrics_file = path;
+        self
+    }
+
     /// Run the LSP proxy. Blocks forever (until stdin closes).
-    pub fn run(&self) -> Result<()> {
+    pub fn run(self) -> Result<()> {
+        self.run_on(io::stdin().lock(), io::stdout())
+    }
+
+    /// Run the LSP proxy on the first TCP connection to `port` on
+    /// localhost instead of stdio; 0 picks a free port. The port is logged.
+    pub fn run_tcp(self, port: u16) -> Result<()> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+            .with_context(|| format!("listening on localhost port {port}"))?;
+        self.run_listener(listener)
+    }
+
+    /// Run the LSP proxy on the first connection `listener` accepts, until
+    /// `exit` or the end of the connection.
+    pub fn run_listener(self, listener: TcpListener) -> Result<()> {
+        eprintln!("dprintx: lsp proxy listening on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept().context("accepting an LSP client")?;
+        // One editor per proxy, like stdio: nobody else gets in.
+        drop(listener);
+        eprintln!("dprintx: lsp client connected from {peer}");
+        let input = BufReader::new(stream.try_clone().context("cloning the LSP connection")?);
+        self.run_on(input, stream)
+    }
+
+    /// Run the LSP proxy on `input` and `output` in place of stdin and
+    /// stdout, until `exit` or the end of `input`. Backend reader threads
+    /// may still write to `output` afterwards.
+    pub fn run_on(
+        mut self,
+        input: impl BufRead,
+        output: impl Write + Send + 'static,
+    ) -> Result<()> {
         eprintln!(
             "dprintx: lsp proxy starting (timeout={}ms)",
-            READ_TIMEOUT.as_millis()
+            self.read_timeout.as_millis()
         );
 
+        self.log.open(self.config.lsp_log_path().as_deref());
+
+        let textfile = self.config.metrics_textfile_path();
+        if self.metrics_file.is_some() || textfile.is_some() {
+            let metrics = Arc::clone(&self.metrics);
+            let metrics_file = self.metrics_file.clone();
+            let textfile = textfile.clone();
+            std::thread::spawn(move || {
+                loop {
+                    std::thread::sleep(METRICS_DUMP_INTERVAL);
+                    dump_metrics(&metrics, metrics_file.as_deref(), textfile.as_deref());
+                }
+            });
+        }
+
         // Map: profile config path -> backend.
         let backends: Arc<Mutex<HashMap<PathBuf, Backend>>> = Arc::new(Mutex::new(HashMap::new()));
 
         // Shared stdout lock for writing responses.
-        let stdout = Arc::new(Mutex::new(io::stdout()));
+        let stdout: ClientOut = Arc::new(Mutex::new(Box::new(output)));
+
+        // Answer requests whose backend missed the deadline.
+        {
+            let in_flight = Arc::clone(&self.in_flight);
+            let metrics = Arc::clone(&self.metrics);
+            let log = Arc::clone(&self.log);
+            let stdout = Arc::clone(&stdout);
+            std::thread::spawn(move || {
+                loop {
+                    std::thread::sleep(REAP_INTERVAL);
+                    for (backend, reply) in in_flight.take_expired(Instant::now()) {
+                        reply.finish(&backend, None, &stdout, &metrics, &log);
+                    }
+                }
+            });
+        }
+
+        // Edits of the dprintx and profile configs, applied before the next
+        // message from the editor.
+        let (config_tx, config_rx) = mpsc::channel::<Vec<PathBuf>>();
+        let watcher = self.watch_configs(config_tx);
 
-        let stdin = io::stdin();
-        let mut reader = BufReader::new(stdin.lock());
+        let mut reader = input;
 
         // Track initialize state for lazy backend spawning.
         let mut _initialized = false;
         let mut last_init_params: Option<serde_json::Value> = None;
-        // Hold merged config guards alive for the lifetime of LSP backends.
-        let mut _merged_guards: Vec<config::TempConfig> = Vec::new();
+        // The editor's workspace folders, kept current for backends spawned
+        // later.
+        let mut folders: Vec<serde_json::Value> = Vec::new();
+        // Hold merged config guards alive for the lifetime of LSP backends,
+        // keyed by content: byte-identical merges (directories with the same
+        // local dprint.json) share one temp config and so one backend.
+        let mut merged_guards: HashMap<u64, config::TempConfig> = HashMap::new();
+        let mut merges: Merges = HashMap::new();
+        // Configs with the editor's formatting options on top
+        // (`lsp_honor_editor_options`, guards among the merged ones) -> the
+        // effective config they extend.
+        let mut overlays: HashMap<PathBuf, PathBuf> = HashMap::new();
         // Track URI -> languageId from textDocument/didOpen for URI rewriting.
         let mut uri_languages: HashMap<String, String> = HashMap::new();
-        let rewrite_uris = self.config.lsp_rewrite_uris;
+        // Track URI -> backend config it was last routed to, for executeCommand.
+        let mut uri_backends: HashMap<String, PathBuf> = HashMap::new();
+        // Track URI -> didOpen as last forwarded, with the current text: for
+        // content routing, and to reopen documents in a backend that has not
+        // seen them (respawned after a crash or eviction, or newly routed to).
+        let mut open_documents: HashMap<String, serde_json::Value> = HashMap::new();
+        // Track URI -> how its open buffer resolved, with the deciding rule,
+        // while the buffer and the config stay as they are: later requests
+        // skip the glob matching and content scan. (Merged configs are
+        // cached by local config already, see `effective_config`.)
+        let mut resolutions: HashMap<String, (Option<ProfileResolution>, Option<String>)> =
+            HashMap::new();
+        let mut rewrite_uris = self.config.lsp_rewrite_uris;
+        // Options of the editor's last formatting request, for formatting
+        // on its behalf (willSaveWaitUntil carries none).
+        let mut formatting_options = serde_json::json!({ "tabSize": 4, "insertSpaces": true });
+        // Client trace level: $/logTrace notifications describe routing and round-trips.
+        let mut trace = TraceLevel::Off;
+        // Profile configs that failed the startup probe → reason. Never spawned.
+        let mut unhealthy: HashMap<PathBuf, String> = HashMap::new();
+        let max_message_size = self
+            .config
+            .lsp_max_message_size
+            .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE);
 
         loop {
             // Read LSP message (Content-Length header + body).
-            let msg = match read_lsp_message(&mut reader) {
+            let msg = match read_lsp_message(&mut reader, max_message_size) {
                 Ok(msg) => msg,
+                // Refused but skipped: the request id is unknown, so answer with null.
+                Err(e) if e.is::<RejectedMessage>() => {
+                    warnings::warn("lsp-message", format!("rejected client message: {e}"));
+                    let resp = error_response(
+                        serde_json::Value::Null,
+                        INVALID_REQUEST,
+                        format!("dprintx: {e}"),
+                    );
+                    write_lsp_message(&stdout, &serde_json::to_string(&resp)?)?;
+                    continue;
+                }
                 Err(_) => break, // EOF or error, exit.
             };
 
+            if let Some(watcher) = &watcher {
+                let changed: Vec<PathBuf> = config_rx.try_iter().flatten().collect();
+                if !changed.is_empty() {
+                    if let Err(e) = self.config_changed(
+                        &changed,
+                        Some(watcher),
+                        &backends,
+                        &mut merges,
+                        &mut merged_guards,
+                        &mut unhealthy,
+                    ) {
+                        warnings::warn("config", format!("keeping previous config: {e:#}"));
+                    }
+                    rewrite_uris = self.config.lsp_rewrite_uris;
+                    resolutions.clear();
+                }
+            }
+
             // Parse as JSON.
             let parsed: serde_json::Value = match serde_json::from_str(&msg) {
                 Ok(v) => v,
@@ -148,6 +1089,13 @@ impl LspProxy {
                     let id = parsed.get("id").cloned();
                     let params = parsed.get("params").cloned();
                     last_init_params = params.clone();
+                    folders = params.as_ref().map(workspace_folders).unwrap_or_default();
+                    trace = TraceLevel::parse(
+                        params
+                            .as_ref()
+                            .and_then(|p| p.get("trace"))
+                            .and_then(|t| t.as_str()),
+                    );
 
                     // Spawn backends for each unique profile.
                     let mut profile_configs = Vec::new();
@@ -161,73 +1109,128 @@ impl LspProxy {
                         }
                     }
 
-                    // Spawn all backends.
-                    let mut first_response = None;
-                    for config_path in &profile_configs {
-                        let backend = self.spawn_backend(config_path)?;
-                        let mut backends_lock = backends.lock().unwrap();
-                        backends_lock.insert(config_path.clone(), backend);
-                        drop(backends_lock);
-
-                        // Send initialize to this backend.
-                        // Override rootUri to the config file's directory so dprint
-                        // knows which workspace this backend serves.
-                        let mut init_params = params.clone().unwrap_or(serde_json::json!({}));
-                        if let Some(config_dir) = config_path.parent() {
-                            let root_uri = format!("file://{}", config_dir.display());
-                            init_params["rootUri"] = serde_json::Value::String(root_uri.clone());
-                            // Also set rootPath for older LSP compat.
-                            init_params["rootPath"] =
-                                serde_json::Value::String(config_dir.display().to_string());
+                    // Probe profiles; broken ones get no backend.
+                    unhealthy = self.probe_profiles(&profile_configs);
+                    profile_configs.retain(|p| !unhealthy.contains_key(p));
+
+                    // Spawn and initialize every backend on a thread of its own:
+                    // they load their plugins at the same time.
+                    let client_params = params.clone().unwrap_or(serde_json::json!({}));
+                    let started: Vec<Result<Option<serde_json::Value>>> =
+                        std::thread::scope(|scope| {
+                            let starting: Vec<_> = profile_configs
+                                .iter()
+                                .map(|config_path| {
+                                    let (this, backends, stdout) = (&self, &backends, &stdout);
+                                    let client_params = &client_params;
+                                    scope.spawn(move || {
+                                        this.start_backend(
+                                            config_path,
+                                            backends,
+                                            stdout,
+                                            client_params,
+                                        )
+                                    })
+                                })
+                                .collect();
+                            starting
+                                .into_iter()
+                                .map(|t| t.join().expect("backend start panicked"))
+                                .collect()
+                        });
+                    let mut responses = Vec::new();
+                    for response in started {
+                        responses.extend(response?);
+                    }
+
+                    // The client gets what any backend can do.
+                    if let (Some(mut resp), Some(id)) = (merge_initialize_results(responses), &id) {
+                        resp["id"] = id.clone();
+                        if let Some(capabilities) = resp.pointer_mut("/result/capabilities") {
+                            extend_capabilities(
+                                capabilities,
+                                self.config.lsp_diagnostics,
+                                self.reload.is_some(),
+                            );
                         }
-                        let init_msg = serde_json::json!({
+                        write_lsp_message(&stdout, &resp.to_string())?;
+                    } else if profile_configs.is_empty()
+                        && let Some(id) = id
+                    {
+                        // Nothing healthy to forward to: answer ourselves.
+                        let response = serde_json::json!({
                             "jsonrpc": "2.0",
                             "id": id,
-                            "method": "initialize",
-                            "params": init_params,
+                            "result": {
+                                "capabilities": {},
+                                "serverInfo": { "name": "dprintx" },
+                            },
                         });
-
-                        self.send_to_backend(&backends, config_path, &init_msg)?;
-
-                        // Read response from this backend.
-                        if let Ok(resp) = self.read_from_backend(&backends, config_path, &stdout)
-                            && first_response.is_none()
-                        {
-                            first_response = Some(resp);
-                        }
+                        write_lsp_message(&stdout, &serde_json::to_string(&response)?)?;
                     }
 
-                    // Send first backend's response as our response.
-                    if let Some(resp) = first_response {
-                        write_lsp_message(&stdout, &resp)?;
+                    let mut reported: Vec<_> = unhealthy.iter().collect();
+                    reported.sort();
+                    for (config_path, reason) in reported {
+                        let notification = serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "method": "window/showMessage",
+                            "params": {
+                                "type": MESSAGE_TYPE_WARNING,
+                                "message": format!(
+                                    "dprintx: profile {} is unhealthy: {reason}",
+                                    self.config.profile_label(config_path)
+                                ),
+                            },
+                        });
+                        write_lsp_message(&stdout, &serde_json::to_string(&notification)?)?;
                     }
 
                     _initialized = true;
                 }
 
+                Some("workspace/didChangeConfiguration") => {
+                    // dprintx's own section is applied here; the rest goes to backends.
+                    let mut msg = parsed.clone();
+                    if let Some(settings) = take_dprintx_settings(&mut msg) {
+                        match self.apply_client_settings(&settings) {
+                            Ok(()) => {
+                                rewrite_uris = self.config.lsp_rewrite_uris;
+                                resolutions.clear();
+                                self.client_settings.push(settings);
+                            }
+                            Err(e) => warnings::warn(
+                                "client-settings",
+                                format!("ignoring dprintx settings from the editor: {e:#}"),
+                            ),
+                        }
+                    }
+                    let keys: Vec<PathBuf> = backends.lock().unwrap().keys().cloned().collect();
+                    for config_path in &keys {
+                        let _ = self.dispatch(&backends, config_path, &msg);
+                    }
+                }
+
                 Some("initialized") => {
-                    // Forward to all backends.
+                    // Forward to all backends (queued for those still initializing).
                     let backends_lock = backends.lock().unwrap();
                     let keys: Vec<PathBuf> = backends_lock.keys().cloned().collect();
                     drop(backends_lock);
 
                     for config_path in &keys {
-                        let _ = self.send_to_backend(&backends, config_path, &parsed);
+                        let _ = self.dispatch(&backends, config_path, &parsed);
                     }
                 }
 
                 Some("shutdown") => {
-                    // Forward to all backends.
-                    let backends_lock = backends.lock().unwrap();
-                    let keys: Vec<PathBuf> = backends_lock.keys().cloned().collect();
-                    drop(backends_lock);
-
-                    for config_path in &keys {
-                        let _ = self.send_to_backend(&backends, config_path, &parsed);
-                        // Read response (with timeout).
-                        let _ = self.read_from_backend(&backends, config_path, &stdout);
+                    // Let outstanding requests be answered (or time out) first.
+                    let deadline = Instant::now() + self.read_timeout;
+                    while !self.in_flight.is_empty() && Instant::now() < deadline {
+                        std::thread::sleep(REAP_INTERVAL);
                     }
 
+                    self.shutdown_backends(&backends);
+
                     // Respond with null result.
                     let response = serde_json::json!({
                         "jsonrpc": "2.0",
@@ -237,18 +1240,65 @@ impl LspProxy {
                     write_lsp_message(&stdout, &serde_json::to_string(&response)?)?;
                 }
 
-                Some("exit") => {
-                    // Forward to all backends and exit.
-                    let backends_lock = backends.lock().unwrap();
-                    let keys: Vec<PathBuf> = backends_lock.keys().cloned().collect();
-                    drop(backends_lock);
+                Some("dprintx/status") => {
+                    // Proxy-level request: report per-backend metrics.
+                    if let Some(id) = parsed.get("id").cloned() {
+                        let response = serde_json::json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "result": self.metrics.to_json(),
+                        });
+                        write_lsp_message(&stdout, &serde_json::to_string(&response)?)?;
+                    }
+                }
+
+                Some("$/setTrace") => {
+                    trace = TraceLevel::parse(
+                        parsed
+                            .get("params")
+                            .and_then(|p| p.get("value"))
+                            .and_then(|v| v.as_str()),
+                    );
+                    // Backends trace their own work too.
+                    let keys: Vec<PathBuf> = backends.lock().unwrap().keys().cloned().collect();
+                    for config_path in &keys {
+                        let _ = self.dispatch(&backends, config_path, &parsed);
+                    }
+                }
 
+                Some("workspace/didChangeWorkspaceFolders") => {
+                    // Backends spawned from now on start with the current
+                    // folders; live ones hear of the change.
+                    if let Some(event) = parsed.pointer("/params/event") {
+                        change_workspace_folders(&mut folders, event);
+                        if let Some(params) = last_init_params.as_mut() {
+                            params["workspaceFolders"] = folders.clone().into();
+                        }
+                    }
+                    let keys: Vec<PathBuf> = backends.lock().unwrap().keys().cloned().collect();
                     for config_path in &keys {
-                        let _ = self.send_to_backend(&backends, config_path, &parsed);
+                        let _ = self.dispatch(&backends, config_path, &parsed);
                     }
-                    break;
                 }
 
+                Some("$/cancelRequest") => {
+                    // Only the backend working on the request can cancel it, by
+                    // the id it got. A request answered already, or by the
+                    // proxy itself, has nothing left to cancel.
+                    let request = parsed
+                        .get("params")
+                        .and_then(|p| p.get("id"))
+                        .and_then(|id| self.in_flight.client_request(id));
+                    let Some((config_path, backend_id)) = request else {
+                        continue;
+                    };
+                    let mut msg = parsed.clone();
+                    msg["params"]["id"] = backend_id.into();
+                    let _ = self.dispatch(&backends, &config_path, &msg);
+                }
+
+                Some("exit") => break,
+
                 Some(method) if method.starts_with("textDocument/") => {
                     let method_name = method.to_string();
                     let has_id = parsed.get("id").is_some();
@@ -258,23 +1308,24 @@ impl LspProxy {
                         if has_id { "request" } else { "notification" }
                     );
 
-                    // Track languageId from didOpen, clean up on didClose.
-                    if method_name == "textDocument/didOpen" {
-                        if let Some(td) = parsed.get("params").and_then(|p| p.get("textDocument"))
-                            && let (Some(uri), Some(lang_id)) = (
-                                td.get("uri").and_then(|u| u.as_str()),
-                                td.get("languageId").and_then(|l| l.as_str()),
-                            )
-                        {
-                            if rewrite_uris {
-                                eprintln!("dprintx: track {uri} as {lang_id}");
-                            }
-                            uri_languages.insert(uri.to_string(), lang_id.to_string());
+                    // Track languageId from didOpen.
+                    if method_name == "textDocument/didOpen"
+                        && let Some(td) = parsed.get("params").and_then(|p| p.get("textDocument"))
+                        && let (Some(uri), Some(lang_id)) = (
+                            td.get("uri").and_then(|u| u.as_str()),
+                            td.get("languageId").and_then(|l| l.as_str()),
+                        )
+                    {
+                        if rewrite_uris {
+                            eprintln!("dprintx: track {uri} as {lang_id}");
                         }
-                    } else if method_name == "textDocument/didClose"
-                        && let Some(uri) = extract_uri(&parsed)
+                        uri_languages.insert(uri.to_string(), lang_id.to_string());
+                    }
+
+                    if let Some(options) = parsed.pointer("/params/options")
+                        && options.is_object()
                     {
-                        uri_languages.remove(&uri);
+                        formatting_options = options.clone();
                     }
 
                     // Clone and optionally rewrite URI based on languageId.
@@ -283,73 +1334,384 @@ impl LspProxy {
                     if rewrite_uris {
                         apply_uri_rewrite(&mut msg, &uri_languages);
                     }
+                    // Editor languageId routes via match_language only when it
+                    // disagrees with the file's own extension.
+                    let language_id = original_uri
+                        .as_deref()
+                        .and_then(|u| uri_languages.get(u).map(|l| (u, l.as_str())))
+                        .filter(|(u, l)| language_disagrees(&uri_to_path(u), l))
+                        .map(|(_, l)| l.to_string());
+                    let language_id = language_id.as_deref();
                     // Use rewritten URI for routing, fall back to original.
-                    let uri = extract_uri(&msg).or(original_uri);
+                    let uri = extract_uri(&msg).or(original_uri.clone());
+                    if let (Some(rewritten), Some(original)) = (&uri, &original_uri)
+                        && rewritten != original
+                    {
+                        self.rewritten_uris
+                            .lock()
+                            .unwrap()
+                            .insert(rewritten.clone(), original.clone());
+                    }
+
+                    // Track the buffer before routing: pin comments and
+                    // match_content see what the editor shows, saved or not.
+                    if let Some(original) = &original_uri {
+                        if matches!(
+                            method_name.as_str(),
+                            "textDocument/didOpen"
+                                | "textDocument/didChange"
+                                | "textDocument/didSave"
+                                | "textDocument/didClose"
+                        ) {
+                            resolutions.remove(original);
+                        }
+                        if method_name == "textDocument/didOpen" {
+                            open_documents.insert(original.clone(), msg.clone());
+                        } else if method_name == "textDocument/didChange"
+                            && let Some(open) = open_documents.get_mut(original)
+                            && !track_change(open, &msg)
+                        {
+                            open_documents.remove(original);
+                        }
+                    }
+                    let buffer = original_uri
+                        .as_ref()
+                        .and_then(|u| open_documents.get(u))
+                        .and_then(|open| open.pointer("/params/textDocument/text"))
+                        .and_then(|t| t.as_str())
+                        .map(str::to_string);
+
+                    // The backend the document was last routed to, also for its
+                    // didClose.
+                    let routed_to = original_uri
+                        .as_ref()
+                        .and_then(|u| uri_backends.get(u))
+                        .cloned();
+                    // Forget closed documents, once the didClose itself got the
+                    // URI the backend knows the document by.
+                    if method_name == "textDocument/didClose"
+                        && let Some(original) = &original_uri
+                    {
+                        uri_languages.remove(original);
+                        uri_backends.remove(original);
+                        open_documents.remove(original);
+                        self.clear_diagnostics(original, &stdout);
+                        self.rewritten_uris
+                            .lock()
+                            .unwrap()
+                            .retain(|_, o| o != original);
+                    }
+
+                    // Untitled buffers and virtual documents have no path to
+                    // route by, nor one a backend could open: they route by
+                    // languageId, and their formatting requests are answered
+                    // with `dprint fmt --stdin`. Nothing is forwarded.
+                    if let Some(original) = &original_uri
+                        && !is_file_uri(original)
+                    {
+                        let language_id = uri_languages.get(original).map(String::as_str);
+                        let decided = self.untitled_profile(language_id);
+                        let profile_config = decided.as_ref().and_then(|(profile, _)| {
+                            match self.config.resolve_profile(profile) {
+                                Some(ProfileResolution::Config(path)) => Some(path),
+                                _ => None,
+                            }
+                        });
+                        let profile_label = profile_config
+                            .as_deref()
+                            .map(|path| self.config.profile_label(path));
+                        self.log.route(&lsplog::Route {
+                            method: &method_name,
+                            id: parsed.get("id"),
+                            uri: original,
+                            rule: decided.as_ref().map(|(_, rule)| rule.as_str()),
+                            profile: profile_label.as_deref(),
+                            backend: profile_config.as_deref(),
+                            merged: false,
+                        });
+                        if let Some(id) = parsed.get("id").cloned()
+                            && let Some(response) = self.answer_untitled(
+                                id,
+                                &parsed,
+                                language_id,
+                                profile_config.as_deref(),
+                                buffer.as_deref(),
+                                trace,
+                                &stdout,
+                            )
+                        {
+                            write_lsp_message(&stdout, &response.to_string())?;
+                        }
+                        continue;
+                    }
 
                     if let Some(uri) = uri {
                         let file_path = uri_to_path(&uri);
-                        let profile_config =
-                            match self.matcher.resolve_config(&file_path, &self.config) {
-                                Ok(Some(ProfileResolution::Config(p))) => p,
-                                _ => {
-                                    // No profile matched — respond with null result if it's a request.
-                                    if let Some(id) = parsed.get("id").cloned() {
-                                        let null_resp = serde_json::json!({
-                                            "jsonrpc": "2.0",
-                                            "id": id,
-                                            "result": null,
-                                        });
-                                        write_lsp_message(
-                                            &stdout,
-                                            &serde_json::to_string(&null_resp)?,
-                                        )?;
-                                    }
-                                    continue;
-                                }
-                            };
-
-                        // Resolve effective config (merged local + profile, or just profile).
-                        let effective_config = if let Some(parent) = file_path.parent() {
-                            match config::build_merged_config(parent, &profile_config) {
-                                Ok(Some(tc)) => {
-                                    let p = tc.path().to_path_buf();
-                                    _merged_guards.push(tc);
-                                    p
+                        let cached = original_uri.as_ref().and_then(|u| resolutions.get(u));
+                        self.metrics.record_resolution(cached.is_some());
+                        let (resolved, rule) = match cached {
+                            Some((resolution, rule)) => (Ok(resolution.clone()), rule.clone()),
+                            None => match self.matcher.resolve_buffer_with_rule(
+                                &file_path,
+                                language_id,
+                                buffer.as_deref(),
+                                &self.config,
+                            ) {
+                                Ok((resolution, rule)) => (Ok(resolution), rule),
+                                Err(e) => (Err(e), None),
+                            },
+                        };
+                        if let (Some(original), Some(_), Ok(resolution)) =
+                            (&original_uri, &buffer, &resolved)
+                        {
+                            resolutions
+                                .entry(original.clone())
+                                .or_insert_with(|| (resolution.clone(), rule.clone()));
+                        }
+                        let profile_config = match resolved {
+                            Ok(Some(ProfileResolution::Config(p))) => p,
+                            resolved => {
+                                self.log.route(&lsplog::Route {
+                                    method: &method_name,
+                                    id: parsed.get("id"),
+                                    uri: &uri,
+                                    rule: rule.as_deref(),
+                                    profile: None,
+                                    backend: None,
+                                    merged: false,
+                                });
+                                // A document whose content no longer routes anywhere
+                                // is closed in the backend that had it.
+                                if let Some(original) = &original_uri
+                                    && let Some(previous) = uri_backends.remove(original)
+                                {
+                                    let _ = self.dispatch(&backends, &previous, &did_close(&uri));
+                                    self.clear_diagnostics(original, &stdout);
                                 }
-                                Ok(None) => profile_config,
-                                Err(e) => {
-                                    eprintln!("dprintx: warning: build_merged_config failed: {e}");
-                                    profile_config
+                                let reason = match &resolved {
+                                    Ok(Some(_)) => "ignored (null profile)".to_string(),
+                                    Ok(None) => "no matching profile".to_string(),
+                                    Err(e) => format!("error: {e:#}"),
+                                };
+                                log_trace(
+                                    &stdout,
+                                    trace,
+                                    &format!("dprintx: {method_name} {uri}: {reason}"),
+                                    || format!("path: {}", file_path.display()),
+                                )?;
+                                // No profile matched or ignored — respond if it's a request.
+                                // Ignored files always get an answer: nothing to do.
+                                if let Some(id) = parsed.get("id").cloned() {
+                                    let resp = match resolved {
+                                        Ok(Some(ProfileResolution::Ignore)) => {
+                                            ignored_response(id, &method_name)
+                                        }
+                                        Ok(_) => self.fallback_response(
+                                            id,
+                                            &method_name,
+                                            format!(
+                                                "dprintx: no profile matches {}",
+                                                file_path.display()
+                                            ),
+                                        ),
+                                        Err(e) => self.fallback_response(
+                                            id,
+                                            &method_name,
+                                            format!("dprintx: {e:#}"),
+                                        ),
+                                    };
+                                    write_lsp_message(&stdout, &serde_json::to_string(&resp)?)?;
                                 }
+                                continue;
+                            }
+                        };
+                        let profile_label = self.config.profile_label(&profile_config);
+
+                        if let Some(reason) = unhealthy.get(&profile_config) {
+                            log_trace(
+                                &stdout,
+                                trace,
+                                &format!(
+                                    "dprintx: {method_name} {uri}: profile {profile_label} is unhealthy"
+                                ),
+                                || reason.clone(),
+                            )?;
+                            if let Some(id) = parsed.get("id").cloned() {
+                                let resp = self.fallback_response(
+                                    id,
+                                    &method_name,
+                                    format!(
+                                        "dprintx: profile {profile_label} is unhealthy: {reason}"
+                                    ),
+                                );
+                                write_lsp_message(&stdout, &serde_json::to_string(&resp)?)?;
+                            }
+                            continue;
+                        }
+
+                        // Resolve effective config (merged local + profile, or just profile).
+                        let merged = match file_path.parent() {
+                            Some(parent) => self.effective_config(
+                                parent,
+                                &profile_config,
+                                &mut merges,
+                                &mut merged_guards,
+                                &backends,
+                            ),
+                            None => Ok(Some(profile_config.clone())),
+                        };
+                        let effective_config = match merged {
+                            Ok(Some(effective)) => effective,
+                            Ok(None) => {
+                                if let Some(id) = parsed.get("id").cloned() {
+                                    let resp = self.fallback_response(
+                                        id,
+                                        &method_name,
+                                        format!(
+                                            "dprintx: local dprint config for {} is unusable",
+                                            file_path.display()
+                                        ),
+                                    );
+                                    write_lsp_message(&stdout, &serde_json::to_string(&resp)?)?;
+                                }
+                                continue;
+                            }
+                            // Never abort an editor session: fall back to the profile.
+                            Err(e) => {
+                                warnings::warn(
+                                    "local-config",
+                                    format!("merging local config failed: {e:#}"),
+                                );
+                                profile_config.clone()
                             }
-                        } else {
-                            profile_config
                         };
+                        // Formatting requests carry the editor's options: with
+                        // lsp_honor_editor_options they go to a backend of the
+                        // effective config with these on top, and the document
+                        // stays there as long as its route does not change.
+                        let effective_config = match parsed.pointer("/params/options") {
+                            _ if !self.config.lsp_honor_editor_options => effective_config,
+                            Some(options)
+                                if matches!(
+                                    method_name.as_str(),
+                                    "textDocument/formatting"
+                                        | "textDocument/rangeFormatting"
+                                        | "textDocument/onTypeFormatting"
+                                ) =>
+                            {
+                                editor_overlay(
+                                    &effective_config,
+                                    options,
+                                    &mut merged_guards,
+                                    &mut overlays,
+                                )
+                            }
+                            _ => match routed_to {
+                                Some(current)
+                                    if overlays.get(&current) == Some(&effective_config)
+                                        && merged_guards.values().any(|g| g.path() == current) =>
+                                {
+                                    current
+                                }
+                                _ => effective_config,
+                            },
+                        };
+                        let is_merged = effective_config != profile_config;
+                        self.log.route(&lsplog::Route {
+                            method: &method_name,
+                            id: parsed.get("id"),
+                            uri: &uri,
+                            rule: rule.as_deref(),
+                            profile: Some(&profile_label),
+                            backend: Some(&effective_config),
+                            merged: is_merged,
+                        });
+                        log_trace(
+                            &stdout,
+                            trace,
+                            &format!("dprintx: {method_name} {uri} -> profile {profile_label}"),
+                            || {
+                                format!(
+                                    "effective config: {}\nlanguageId: {}",
+                                    effective_config.display(),
+                                    language_id.unwrap_or("-")
+                                )
+                            },
+                        )?;
+
+                        // Whether the current document was just opened in the backend
+                        // with its current text.
+                        let mut reopened = false;
 
                         // Ensure backend is spawned (lazily for merged configs).
                         {
                             let backends_lock = backends.lock().unwrap();
                             if !backends_lock.contains_key(&effective_config) {
                                 drop(backends_lock);
-                                let backend = self.spawn_backend(&effective_config)?;
+                                if let Some(max) = self.config.lsp_max_backends {
+                                    self.evict_backends(
+                                        &backends,
+                                        max.saturating_sub(1),
+                                        &mut merged_guards,
+                                    );
+                                }
+                                // A backend that died or was evicted is respawned like a
+                                // new one, and gets the documents it had open back.
+                                let restarted =
+                                    self.crashed.lock().unwrap().remove(&effective_config);
+                                let reopen: Vec<serde_json::Value> = open_documents
+                                    .iter()
+                                    .filter(|(u, _)| {
+                                        uri_backends.get(*u) == Some(&effective_config)
+                                    })
+                                    .map(|(_, open)| open.clone())
+                                    .collect();
+                                reopened = original_uri.as_ref().is_some_and(|u| {
+                                    open_documents.contains_key(u)
+                                        && uri_backends.get(u) == Some(&effective_config)
+                                });
+                                if restarted {
+                                    eprintln!(
+                                        "dprintx: restarting backend for profile {profile_label} ({} open document(s))",
+                                        reopen.len()
+                                    );
+                                    self.metrics
+                                        .record_restart(&effective_config, &profile_label);
+                                }
+                                let mut backend =
+                                    self.spawn_backend(&effective_config, &backends, &stdout)?;
+                                backend.evictable = is_merged;
+                                // Send initialize to the new backend without waiting:
+                                // the plugin load can be slow, so messages for it are
+                                // queued until it answers (see `mark_ready`), with
+                                // `initialized` first.
+                                let init_id = self.in_flight.next_id();
+                                if last_init_params.is_some() {
+                                    backend.init_id = Some(init_id);
+                                    backend.pending.push(serde_json::json!({
+                                        "jsonrpc": "2.0",
+                                        "method": "initialized",
+                                        "params": {},
+                                    }));
+                                }
                                 let mut backends_lock = backends.lock().unwrap();
                                 backends_lock.insert(effective_config.clone(), backend);
                                 drop(backends_lock);
+                                for open in &reopen {
+                                    let _ = self.dispatch(&backends, &effective_config, open);
+                                }
 
-                                // Send initialize to the new backend.
                                 if let Some(init_params) = &last_init_params {
-                                    let mut params = init_params.clone();
-                                    if let Some(config_dir) = effective_config.parent() {
-                                        let root_uri = format!("file://{}", config_dir.display());
-                                        params["rootUri"] =
-                                            serde_json::Value::String(root_uri.clone());
-                                        params["rootPath"] = serde_json::Value::String(
-                                            config_dir.display().to_string(),
-                                        );
-                                    }
+                                    let params = backend_init_params(
+                                        init_params,
+                                        self.config.lsp_root,
+                                        &effective_config,
+                                        file_path.parent(),
+                                        containing_folder(&folders, &file_path),
+                                    );
                                     let init_msg = serde_json::json!({
                                         "jsonrpc": "2.0",
-                                        "id": 1,
+                                        "id": init_id,
                                         "method": "initialize",
                                         "params": params,
                                     });
@@ -358,270 +1720,2827 @@ impl LspProxy {
                                         &effective_config,
                                         &init_msg,
                                     );
-                                    // Read and discard initialize response.
-                                    let _ = self.read_from_backend(
-                                        &backends,
-                                        &effective_config,
-                                        &stdout,
-                                    );
-
-                                    // Send initialized notification.
-                                    let initialized_msg = serde_json::json!({
-                                        "jsonrpc": "2.0",
-                                        "method": "initialized",
-                                        "params": {},
-                                    });
-                                    let _ = self.send_to_backend(
-                                        &backends,
-                                        &effective_config,
-                                        &initialized_msg,
-                                    );
                                 }
                             }
                         }
 
-                        // Send request to the right backend (with rewritten URI if enabled).
-                        self.send_to_backend(&backends, &effective_config, &msg)?;
+                        // Content routing follows the live buffer, so an open
+                        // document can change backends: the new one gets it opened
+                        // with its current text, the old one closes it.
+                        if method_name != "textDocument/didOpen"
+                            && let Some(original) = &original_uri
+                            && uri_backends.get(original) != Some(&effective_config)
+                            && let Some(open) = open_documents.get(original)
+                        {
+                            if let Some(previous) = uri_backends.get(original) {
+                                eprintln!("dprintx: {original} moves to profile {profile_label}");
+                                let _ = self.dispatch(&backends, previous, &did_close(&uri));
+                            }
+                            let _ = self.dispatch(&backends, &effective_config, open);
+                            reopened = true;
+                        }
 
-                        // If it's a request (has id), read response.
-                        if let Some(id) = parsed.get("id").cloned() {
-                            let t0 = std::time::Instant::now();
-                            match self.read_from_backend(&backends, &effective_config, &stdout) {
-                                Ok(resp) => {
-                                    eprintln!(
-                                        "dprintx: {} responded in {:?}",
-                                        method_name,
-                                        t0.elapsed()
-                                    );
-                                    write_lsp_message(&stdout, &resp)?;
-                                }
-                                Err(e) => {
-                                    eprintln!(
-                                        "dprintx: {} timeout/error in {:?}: {}",
-                                        method_name,
-                                        t0.elapsed(),
-                                        e
-                                    );
-                                    let error_resp = serde_json::json!({
-                                        "jsonrpc": "2.0",
-                                        "id": id,
-                                        "result": null,
-                                    });
-                                    write_lsp_message(
-                                        &stdout,
-                                        &serde_json::to_string(&error_resp)?,
-                                    )?;
+                        // Requests a backend does not implement go out as
+                        // whole-document formatting (also while it initializes):
+                        // rangeFormatting edits are cut to the range on the way
+                        // back, willSaveWaitUntil takes them as they are.
+                        let capabilities = match method_name.as_str() {
+                            "textDocument/rangeFormatting"
+                            | "textDocument/willSaveWaitUntil"
+                            | "textDocument/onTypeFormatting"
+                            | "textDocument/codeAction" => backends
+                                .lock()
+                                .unwrap()
+                                .get(&effective_config)
+                                .and_then(|b| b.capabilities.clone())
+                                .unwrap_or_default(),
+                            _ => serde_json::Value::Null,
+                        };
+                        let range = if method_name == "textDocument/rangeFormatting"
+                            && !provides(&capabilities, "documentRangeFormattingProvider")
+                        {
+                            buffer.clone().zip(msg.pointer("/params/range").cloned())
+                        } else {
+                            None
+                        };
+                        if range.is_some() {
+                            msg["method"] = "textDocument/formatting".into();
+                            if let Some(params) = msg["params"].as_object_mut() {
+                                params.remove("range");
+                            }
+                        }
+                        if method_name == "textDocument/willSaveWaitUntil"
+                            && capabilities.pointer("/textDocumentSync/willSaveWaitUntil")
+                                != Some(&serde_json::Value::Bool(true))
+                        {
+                            msg["method"] = "textDocument/formatting".into();
+                            msg["params"] = serde_json::json!({
+                                "textDocument": msg["params"]["textDocument"].clone(),
+                                "options": formatting_options.clone(),
+                            });
+                        }
+
+                        // Send the request to the right backend (with rewritten URI
+                        // if enabled), queued while the backend initializes. The
+                        // backend's reader thread answers the editor when the
+                        // response arrives, or the fallback goes out at the
+                        // deadline; meanwhile the proxy keeps serving other requests.
+                        if let Some(id) = parsed.get("id") {
+                            // A character the editor triggers on for other
+                            // backends is nothing to one that has not
+                            // registered it (known once it initialized).
+                            if method_name == "textDocument/onTypeFormatting"
+                                && !capabilities.is_null()
+                                && !formats_on_type(
+                                    &capabilities,
+                                    msg["params"]["ch"].as_str().unwrap_or_default(),
+                                )
+                            {
+                                write_lsp_message(&stdout, &null_response(id.clone()).to_string())?;
+                                continue;
+                            }
+                            // Code actions of a backend without any are the
+                            // proxy's: the fix for the document's diagnostic,
+                            // and ignoring the document.
+                            if method_name == "textDocument/codeAction"
+                                && (self.config.lsp_diagnostics || self.reload.is_some())
+                                && !provides(&capabilities, "codeActionProvider")
+                            {
+                                let unformatted = self.unformatted.lock().unwrap();
+                                let fix = original_uri.as_ref().and_then(|u| unformatted.get(u));
+                                let response = serde_json::json!({
+                                    "jsonrpc": "2.0",
+                                    "id": id,
+                                    "result": code_actions(
+                                        &parsed["params"],
+                                        fix,
+                                        buffer.as_deref(),
+                                        self.reload.is_some(),
+                                    ),
+                                });
+                                drop(unformatted);
+                                write_lsp_message(&stdout, &response.to_string())?;
+                                continue;
+                            }
+                            // Whole-document formatting of content formatted
+                            // before is answered from the format cache.
+                            let fill = match &buffer {
+                                Some(text)
+                                    if range.is_none()
+                                        && msg["method"] == "textDocument/formatting" =>
+                                {
+                                    self.cache_fill(&file_path, text, &effective_config)
                                 }
+                                _ => None,
+                            };
+                            if let Some(fill) = &fill
+                                && let Some(formatted) = fill.cached()
+                            {
+                                eprintln!(
+                                    "dprintx: {method_name} via {profile_label}: answered from the format cache"
+                                );
+                                let response = serde_json::json!({
+                                    "jsonrpc": "2.0",
+                                    "id": id,
+                                    "result": text_edits(&fill.text, &formatted),
+                                });
+                                write_lsp_message(&stdout, &response.to_string())?;
+                                continue;
+                            }
+                            // A retry of a formatting request that timed out
+                            // gets the result that came late, or waits for it.
+                            // Whole-document results for a range are not kept.
+                            let salvage = range
+                                .is_none()
+                                .then(|| {
+                                    salvage_key(
+                                        &method_name,
+                                        &effective_config,
+                                        original_uri.as_ref().and_then(|u| open_documents.get(u)),
+                                        &msg,
+                                    )
+                                })
+                                .flatten();
+                            if let Some(result) = salvage
+                                .as_deref()
+                                .and_then(|k| self.in_flight.take_salvaged(k))
+                            {
+                                eprintln!(
+                                    "dprintx: {method_name} via {profile_label}: answered from a late response"
+                                );
+                                let response = serde_json::json!({
+                                    "jsonrpc": "2.0",
+                                    "id": id,
+                                    "result": result,
+                                });
+                                write_lsp_message(&stdout, &response.to_string())?;
+                                continue;
                             }
+                            let (backend_id, sent) = self.expect_client_response(
+                                &backends,
+                                &effective_config,
+                                id,
+                                &method_name,
+                                &profile_label,
+                                trace,
+                                format!("dprintx: profile {profile_label} failed to respond"),
+                                salvage,
+                                range.map(|(text, range)| Box::new(RangeSlice { text, range })),
+                                fill,
+                            );
+                            if sent {
+                                eprintln!(
+                                    "dprintx: {method_name} via {profile_label}: waiting for the timed-out request"
+                                );
+                                continue;
+                            }
+                            msg["id"] = backend_id.into();
+                        }
+                        // A dead backend fails the write; its reader thread
+                        // answers its requests and the next one respawns it. A
+                        // change the backend got with the reopened document is
+                        // not sent again.
+                        if !(reopened && method_name == "textDocument/didChange")
+                            && let Err(e) = self.dispatch(&backends, &effective_config, &msg)
+                        {
+                            eprintln!("dprintx: {method_name} via {profile_label}: {e:#}");
+                        }
+                        if method_name != "textDocument/didClose"
+                            && let Some(original) = &original_uri
+                        {
+                            uri_backends.insert(original.clone(), effective_config.clone());
+                        }
+                        // Opened and saved documents are checked once the
+                        // backend has them.
+                        if self.config.lsp_diagnostics
+                            && matches!(
+                                method_name.as_str(),
+                                "textDocument/didOpen" | "textDocument/didSave"
+                            )
+                            && let Some(original) = &original_uri
+                            && let Some(open) = open_documents.get(original)
+                        {
+                            self.check_document(
+                                &backends,
+                                &effective_config,
+                                original,
+                                open,
+                                &formatting_options,
+                                &stdout,
+                            );
                         }
                     }
                 }
 
-                _ => {
-                    // Unknown method — forward to all backends.
-                    let backends_lock = backends.lock().unwrap();
-                    let keys: Vec<PathBuf> = backends_lock.keys().cloned().collect();
-                    drop(backends_lock);
-
-                    for config_path in &keys {
-                        let _ = self.send_to_backend(&backends, config_path, &parsed);
+                Some("workspace/executeCommand")
+                    if parsed.pointer("/params/command").and_then(|c| c.as_str())
+                        == Some(IGNORE_FILE_COMMAND) =>
+                {
+                    // The proxy's own command: no backend knows it. The edited
+                    // config applies like any other edit of it.
+                    let response = match self.ignore_document(&parsed["params"]) {
+                        Ok((uri, glob)) => {
+                            eprintln!("dprintx: {uri} ignored by match rule {glob}");
+                            self.clear_diagnostics(&uri, &stdout);
+                            let notification = serde_json::json!({
+                                "jsonrpc": "2.0",
+                                "method": "window/showMessage",
+                                "params": {
+                                    "type": MESSAGE_TYPE_INFO,
+                                    "message": format!(
+                                        "dprintx: {} is ignored now (match rule {glob})",
+                                        uri_to_path(&uri).display()
+                                    ),
+                                },
+                            });
+                            write_lsp_message(&stdout, &notification.to_string())?;
+                            parsed.get("id").cloned().map(null_response)
+                        }
+                        Err(e) => {
+                            warnings::warn("ignore-file", format!("cannot ignore file: {e:#}"));
+                            parsed.get("id").cloned().map(|id| {
+                                error_response(id, REQUEST_FAILED, format!("dprintx: {e:#}"))
+                            })
+                        }
+                    };
+                    if let Some(response) = response {
+                        write_lsp_message(&stdout, &response.to_string())?;
                     }
+                }
 
-                    // If it's a request, respond from first backend.
-                    if let Some(id) = parsed.get("id").cloned()
-                        && let Some(config_path) = keys.first()
-                    {
-                        match self.read_from_backend(&backends, config_path, &stdout) {
-                            Ok(resp) => {
-                                write_lsp_message(&stdout, &resp)?;
-                            }
-                            Err(_) => {
-                                let error_resp = serde_json::json!({
+                Some("workspace/executeCommand")
+                    if parsed.pointer("/params/command").and_then(|c| c.as_str())
+                        == Some(RELOAD_CONFIG_COMMAND) =>
+                {
+                    // Everything counts as changed: profile configs may have
+                    // been edited unnoticed too.
+                    let reloaded = match &self.reload {
+                        Some((config_path, _)) => {
+                            let mut changed = vec![config_path.clone()];
+                            changed.extend(profile_configs(&self.config));
+                            self.config_changed(
+                                &changed,
+                                watcher.as_deref(),
+                                &backends,
+                                &mut merges,
+                                &mut merged_guards,
+                                &mut unhealthy,
+                            )
+                        }
+                        None => Err(anyhow::anyhow!("the dprintx config file is unknown")),
+                    };
+                    rewrite_uris = self.config.lsp_rewrite_uris;
+                    resolutions.clear();
+                    let response = match reloaded {
+                        Ok(()) => {
+                            let profiles: Vec<&String> = self.config.profiles.keys().collect();
+                            let notification = serde_json::json!({
+                                "jsonrpc": "2.0",
+                                "method": "window/showMessage",
+                                "params": {
+                                    "type": MESSAGE_TYPE_INFO,
+                                    "message": format!(
+                                        "dprintx: config reloaded, {} profile(s): {}",
+                                        profiles.len(),
+                                        profiles
+                                            .iter()
+                                            .map(|p| p.as_str())
+                                            .collect::<Vec<_>>()
+                                            .join(", ")
+                                    ),
+                                },
+                            });
+                            write_lsp_message(&stdout, &notification.to_string())?;
+                            parsed.get("id").cloned().map(|id| {
+                                serde_json::json!({
                                     "jsonrpc": "2.0",
                                     "id": id,
-                                    "result": null,
-                                });
-                                write_lsp_message(&stdout, &serde_json::to_string(&error_resp)?)?;
+                                    "result": { "profiles": profiles },
+                                })
+                            })
+                        }
+                        Err(e) => {
+                            warnings::warn("config", format!("keeping previous config: {e:#}"));
+                            parsed.get("id").cloned().map(|id| {
+                                error_response(id, REQUEST_FAILED, format!("dprintx: {e:#}"))
+                            })
+                        }
+                    };
+                    if let Some(response) = response {
+                        write_lsp_message(&stdout, &response.to_string())?;
+                    }
+                }
+
+                None => {
+                    // The editor's response to a backend request: back to that
+                    // backend only, under the backend's own id.
+                    let Some(id) = parsed.get("id") else {
+                        continue;
+                    };
+                    let Some((config_path, backend_id)) = self.server_requests.take(id) else {
+                        eprintln!("dprintx: dropping response to unknown request (id={id})");
+                        continue;
+                    };
+                    let mut msg = parsed.clone();
+                    msg["id"] = backend_id;
+                    let _ = self.dispatch(&backends, &config_path, &msg);
+                }
+
+                _ => {
+                    // Unknown method — forward to all backends. Commands naming a
+                    // document go only to the backend serving it: plugins may act
+                    // on them, and running a command once per backend duplicates
+                    // its side effects.
+                    let backends_lock = backends.lock().unwrap();
+                    let owner = (method == Some("workspace/executeCommand"))
+                        .then(|| {
+                            command_uris(&parsed)
+                                .iter()
+                                .filter_map(|u| uri_backends.get(u))
+                                .find(|c| backends_lock.contains_key(*c))
+                                .cloned()
+                        })
+                        .flatten();
+                    let keys: Vec<PathBuf> = match owner {
+                        Some(config_path) => vec![config_path],
+                        None => backends_lock.keys().cloned().collect(),
+                    };
+                    drop(backends_lock);
+
+                    // If it's a request, the first backend answers it.
+                    for (i, config_path) in keys.iter().enumerate() {
+                        let mut msg = parsed.clone();
+                        if let Some(id) = parsed.get("id") {
+                            msg["id"] = match i {
+                                0 => {
+                                    self.expect_client_response(
+                                        &backends,
+                                        config_path,
+                                        id,
+                                        method.unwrap_or_default(),
+                                        &self.config.profile_label(config_path),
+                                        trace,
+                                        "dprintx: backend failed to respond".to_string(),
+                                        None,
+                                        None,
+                                        None,
+                                    )
+                                    .0
+                                }
+                                _ => self.in_flight.insert(
+                                    config_path,
+                                    Reply::Discard,
+                                    self.read_timeout,
+                                ),
                             }
+                            .into();
                         }
+                        let _ = self.dispatch(&backends, config_path, &msg);
                     }
                 }
             }
         }
 
+        // Whether the editor said `exit` or went away: no backend outlives
+        // the session, and merged configs go once nothing reads them.
+        self.exit_backends(&backends);
+        drop(merged_guards);
+
+        dump_metrics(
+            &self.metrics,
+            self.metrics_file.as_deref(),
+            textfile.as_deref(),
+        );
+
+        Ok(())
+    }
+
+    /// Spawn the backend for `config_path` and initialize it with the
+    /// editor's `client_params`, waiting up to [`STARTUP_TIMEOUT`] for its
+    /// answer, which is returned. A backend that does not answer in time
+    /// stays initializing, and messages for it are queued.
+    fn start_backend(
+        &self,
+        config_path: &PathBuf,
+        backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+        stdout: &ClientOut,
+        client_params: &serde_json::Value,
+    ) -> Result<Option<serde_json::Value>> {
+        let started = Instant::now();
+        let mut backend = self.spawn_backend(config_path, backends, stdout)?;
+        let (init_id, rx) = self.expect_response(config_path, STARTUP_TIMEOUT);
+        backend.init_id = Some(init_id);
+        backends
+            .lock()
+            .unwrap()
+            .insert(config_path.clone(), backend);
+
+        let init_params =
+            backend_init_params(client_params, self.config.lsp_root, config_path, None, None);
+        let init_msg = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": init_id,
+            "method": "initialize",
+            "params": init_params,
+        });
+        self.send_to_backend(backends, config_path, &init_msg)?;
+
+        let profile = self.config.profile_label(config_path);
+        match self.await_response(rx, init_id, started + STARTUP_TIMEOUT) {
+            Ok(response) => {
+                eprintln!(
+                    "dprintx: backend for profile {profile} initialized in {:?}",
+                    started.elapsed()
+                );
+                Ok(Some(response))
+            }
+            Err(_) => {
+                eprintln!(
+                    "dprintx: backend for profile {profile} did not initialize within {STARTUP_TIMEOUT:?}"
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Ask every backend to `shutdown` at once and wait for their
+    /// acknowledgements together, for `read_timeout` at most.
+    fn shutdown_backends(&self, backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>) {
+        let mut backends_lock = backends.lock().unwrap();
+        for backend in backends_lock.values_mut() {
+            backend.stopping = true;
+        }
+        let keys: Vec<PathBuf> = backends_lock.keys().cloned().collect();
+        drop(backends_lock);
+
+        let mut waiting = Vec::new();
+        for config_path in &keys {
+            let (id, rx) = self.expect_response(config_path, self.read_timeout);
+            let shutdown = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "shutdown" });
+            match self.send_to_backend(backends, config_path, &shutdown) {
+                Ok(()) => waiting.push((config_path, id, rx)),
+                Err(_) => self.in_flight.forget(id),
+            }
+        }
+        let deadline = Instant::now() + self.read_timeout;
+        for (config_path, id, rx) in waiting {
+            if self.await_response(rx, id, deadline).is_err() {
+                eprintln!(
+                    "dprintx: backend for profile {} did not acknowledge shutdown",
+                    self.config.profile_label(config_path)
+                );
+            }
+        }
+    }
+
+    /// Send `exit` to every backend, then reap them all, killing those still
+    /// running after `read_timeout`.
+    fn exit_backends(&self, backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>) {
+        let mut stopped: Vec<(PathBuf, Backend)> = backends.lock().unwrap().drain().collect();
+        let exit = serde_json::json!({ "jsonrpc": "2.0", "method": "exit" });
+        for (_, backend) in &mut stopped {
+            backend.stopping = true;
+            let _ = write_backend_message(&mut backend.stdin.lock().unwrap(), &exit);
+        }
+        let deadline = Instant::now() + self.read_timeout;
+        for (config_path, backend) in &mut stopped {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if reap(&mut backend.child, remaining).is_none() {
+                eprintln!(
+                    "dprintx: killed backend for profile {} after exit",
+                    self.config.profile_label(config_path)
+                );
+            }
+        }
+    }
+
+    /// Apply the editor's `dprintx` settings section: `lsp_rewrite_uris`,
+    /// `lsp_diagnostics`, `lsp_honor_editor_options`, `lsp_timeout_ms` and
+    /// `profiles` (entries replace those of dprintx.jsonc).
+    /// Nothing is applied unless the whole section is valid.
+    fn apply_client_settings(&mut self, settings: &serde_json::Value) -> Result<()> {
+        let Some(settings) = settings.as_object() else {
+            bail!("expected an object, got {settings}");
+        };
+        let mut config = self.config.clone();
+        let mut read_timeout = self.read_timeout;
+        for (key, value) in settings {
+            match key.as_str() {
+                "lsp_rewrite_uris" => {
+                    config.lsp_rewrite_uris = value.as_bool().with_context(|| {
+                        format!("lsp_rewrite_uris: expected a boolean, got {value}")
+                    })?;
+                }
+                "lsp_diagnostics" => {
+                    config.lsp_diagnostics = value.as_bool().with_context(|| {
+                        format!("lsp_diagnostics: expected a boolean, got {value}")
+                    })?;
+                }
+                "lsp_honor_editor_options" => {
+                    config.lsp_honor_editor_options = value.as_bool().with_context(|| {
+                        format!("lsp_honor_editor_options: expected a boolean, got {value}")
+                    })?;
+                }
+                "lsp_timeout_ms" => {
+                    let ms = value.as_u64().with_context(|| {
+                        format!("lsp_timeout_ms: expected milliseconds, got {value}")
+                    })?;
+                    read_timeout = Duration::from_millis(ms);
+                }
+                "profiles" => {
+                    let profiles = value
+                        .as_object()
+                        .with_context(|| format!("profiles: expected an object, got {value}"))?;
+                    for (name, profile) in profiles {
+                        config.profiles.insert(name.clone(), profile.clone());
+                    }
+                }
+                other => bail!("unknown setting {other:?}"),
+            }
+        }
+        let matcher = ProfileMatcher::from_config(&config)?;
+        eprintln!(
+            "dprintx: applied editor settings: {}",
+            settings.keys().cloned().collect::<Vec<_>>().join(", ")
+        );
+        self.config = config;
+        self.matcher = matcher;
+        self.read_timeout = read_timeout;
         Ok(())
     }
 
-    fn spawn_backend(&self, config_path: &PathBuf) -> Result<Backend> {
+    /// Start watching the dprintx config and the profile configs, if the
+    /// proxy can reload them; changes are sent to `tx`.
+    fn watch_configs(&self, tx: mpsc::Sender<Vec<PathBuf>>) -> Option<Arc<ConfigWatcher>> {
+        let (config_path, _) = self.reload.as_ref()?;
+        let watcher = match ConfigWatcher::new() {
+            Ok(watcher) => Arc::new(watcher),
+            Err(e) => {
+                eprintln!("dprintx: config changes apply after a restart: {e:#}");
+                return None;
+            }
+        };
+        for path in std::iter::once(config_path.clone()).chain(profile_configs(&self.config)) {
+            watch_file(&watcher, &path);
+        }
+        let waiting = Arc::clone(&watcher);
+        std::thread::spawn(move || {
+            loop {
+                match waiting.wait() {
+                    Ok(changed) => {
+                        if tx.send(changed).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("dprintx: config watching stopped: {e:#}");
+                        return;
+                    }
+                }
+            }
+        });
+        Some(watcher)
+    }
+
+    /// Apply edits of config files. A changed dprintx config is reloaded,
+    /// with the editor's settings on top; one that no longer loads is kept
+    /// until it is fixed, and its error returned. Backends and merged
+    /// configs of profile configs that changed or are gone are shut down:
+    /// documents are reopened in whichever backend serves them next.
+    fn config_changed(
+        &mut self,
+        changed: &[PathBuf],
+        watcher: Option<&ConfigWatcher>,
+        backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+        merges: &mut Merges,
+        merged_guards: &mut HashMap<u64, config::TempConfig>,
+        unhealthy: &mut HashMap<PathBuf, String>,
+    ) -> Result<()> {
+        for path in changed {
+            eprintln!("dprintx: {} changed", path.display());
+        }
+        let mut stale: HashSet<PathBuf> = changed.iter().cloned().collect();
+        let mut result = Ok(());
+        let reloaded = match &self.reload {
+            Some((config_path, reload)) if changed.contains(config_path) => {
+                Some((config_path.clone(), reload()))
+            }
+            _ => None,
+        };
+        if let Some((config_path, reloaded)) = reloaded {
+            let reloaded = reloaded.and_then(|c| ProfileMatcher::from_config(&c).map(|m| (c, m)));
+            match reloaded {
+                Ok((config, matcher)) => {
+                    let before = profile_configs(&self.config);
+                    self.read_timeout = config
+                        .lsp_timeout_ms
+                        .map_or(READ_TIMEOUT, Duration::from_millis);
+                    self.dprint_bin = config.dprint_path();
+                    self.cache = FormatCache::open(&config, &self.dprint_bin).map(Arc::new);
+                    self.log.open(config.lsp_log_path().as_deref());
+                    self.config = config;
+                    self.matcher = matcher;
+                    for settings in self.client_settings.clone() {
+                        if let Err(e) = self.apply_client_settings(&settings) {
+                            warnings::warn(
+                                "client-settings",
+                                format!("dropping dprintx settings from the editor: {e:#}"),
+                            );
+                        }
+                    }
+                    eprintln!("dprintx: reloaded {}", config_path.display());
+                    let after = profile_configs(&self.config);
+                    stale.extend(before.difference(&after).cloned());
+                    if let Some(watcher) = watcher {
+                        for path in &after {
+                            watch_file(watcher, path);
+                        }
+                    }
+                }
+                Err(e) => result = Err(e),
+            }
+        }
+
+        let mut retired = Vec::new();
+        merges.retain(|(_, profile), (_, outcome)| {
+            if !stale.contains(profile) {
+                return true;
+            }
+            if let MergeOutcome::Merged(k) = outcome {
+                retired.push(*k);
+            }
+            false
+        });
+        for k in retired {
+            if !merges.values().any(|(_, o)| *o == MergeOutcome::Merged(k)) {
+                self.retire_merged(backends, merged_guards, k);
+            }
+        }
+        let current = profile_configs(&self.config);
+        let mut recheck = Vec::new();
+        for config_path in &stale {
+            if backends.lock().unwrap().contains_key(config_path) {
+                eprintln!(
+                    "dprintx: {} changed, shutting down its backend",
+                    config_path.display()
+                );
+                self.shutdown_backend(backends, config_path);
+            }
+            unhealthy.remove(config_path);
+            if current.contains(config_path) {
+                recheck.push(config_path.clone());
+            }
+        }
+        unhealthy.extend(self.probe_profiles(&recheck));
+        result
+    }
+
+    /// Cache entry of `text`, the content of `file_path` formatted under
+    /// `effective_config`, if `format_cache` is on.
+    fn cache_fill(
+        &self,
+        file_path: &Path,
+        text: &str,
+        effective_config: &Path,
+    ) -> Option<Box<CacheFill>> {
+        let cache = self.cache.as_ref()?;
+        let key = cache.key(file_path, text.as_bytes(), effective_config)?;
+        Some(Box::new(CacheFill {
+            cache: Arc::clone(cache),
+            key,
+            text: text.to_string(),
+        }))
+    }
+
+    /// Profile of a document without a file path: by its languageId
+    /// (`match_language`), or else `lsp_untitled_profile`. Also returns
+    /// the label of the deciding rule.
+    fn untitled_profile(&self, language_id: Option<&str>) -> Option<(String, String)> {
+        if let Some(language_id) = language_id
+            && let Some(profile) = self.config.language_profile(language_id)
+        {
+            let rule = format!("match_language {language_id} -> {profile}");
+            return Some((profile.to_string(), rule));
+        }
+        let profile = self.config.lsp_untitled_profile.as_ref()?;
+        Some((
+            profile.clone(),
+            format!("lsp_untitled_profile -> {profile}"),
+        ))
+    }
+
+    /// The answer to request `msg` for a document without a file path, with
+    /// buffer `text`: formatting requests get `dprint fmt --stdin` of the
+    /// buffer, under a file name made up from `language_id`, in the config
+    /// of its profile (`profile_config`); anything else a null result.
+    /// dprint runs on a thread of its own, answering like a backend would:
+    /// None then, the answer is on its way.
+    #[allow(clippy::too_many_arguments)]
+    fn answer_untitled(
+        &self,
+        id: serde_json::Value,
+        msg: &serde_json::Value,
+        language_id: Option<&str>,
+        profile_config: Option<&Path>,
+        text: Option<&str>,
+        trace: TraceLevel,
+        stdout: &ClientOut,
+    ) -> Option<serde_json::Value> {
+        let method = msg["method"].as_str().unwrap_or_default();
+        if !matches!(
+            method,
+            "textDocument/formatting"
+                | "textDocument/rangeFormatting"
+                | "textDocument/willSaveWaitUntil"
+        ) {
+            return Some(null_response(id));
+        }
+        let uri = msg
+            .pointer("/params/textDocument/uri")
+            .and_then(|u| u.as_str())
+            .unwrap_or_default();
+        let (Some(config_path), Some(text)) = (profile_config, text) else {
+            return Some(self.fallback_response(
+                id,
+                method,
+                format!(
+                    "dprintx: no profile for {uri} (languageId {})",
+                    language_id.unwrap_or("-")
+                ),
+            ));
+        };
+        let file_name = match language_id.and_then(language_ext) {
+            Some(ext) => format!("untitled.{ext}"),
+            None => "untitled".to_string(),
+        };
+
+        // Registered like a backend request to the profile's config: a
+        // dprint that does not finish in time is left to it, and the
+        // editor gets the fallback.
+        let fallback = Box::new(self.fallback_response(
+            id.clone(),
+            method,
+            format!("dprintx: dprint fmt --stdin {file_name} timed out"),
+        ));
+        let reply = Reply::Client {
+            id,
+            method: method.to_string(),
+            profile: self.config.profile_label(config_path),
+            started: Instant::now(),
+            trace,
+            fallback,
+            range: msg.pointer("/params/range").map(|range| {
+                Box::new(RangeSlice {
+                    text: text.to_string(),
+                    range: range.clone(),
+                })
+            }),
+            fill: None,
+        };
+        let request_id = self.in_flight.insert(config_path, reply, self.read_timeout);
+
+        let error_for = self.config.lsp_error_for(method);
+        let (method, uri) = (method.to_string(), uri.to_string());
+        let (dprint_bin, config_path, text) = (
+            self.dprint_bin.clone(),
+            config_path.to_path_buf(),
+            text.to_string(),
+        );
+        let in_flight = Arc::clone(&self.in_flight);
+        let metrics = Arc::clone(&self.metrics);
+        let log = Arc::clone(&self.log);
+        let stdout = Arc::clone(stdout);
+        std::thread::spawn(move || {
+            let response = match format_stdin(&dprint_bin, &config_path, &file_name, &text) {
+                Ok(formatted) => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": request_id,
+                    "result": text_edits(&text, &formatted),
+                }),
+                Err(e) => {
+                    eprintln!("dprintx: {method} {uri}: {e:#}");
+                    let message = format!("dprintx: {e:#}");
+                    if error_for {
+                        error_response(request_id.into(), REQUEST_FAILED, message)
+                    } else {
+                        null_response(request_id.into())
+                    }
+                }
+            };
+            if let Some(reply) = in_flight.take(&config_path, &request_id.into()) {
+                reply.finish(&config_path, Some(response), &stdout, &metrics, &log);
+            }
+        });
+        None
+    }
+
+    /// Check the document `uri`, opened in the backend of `effective_config`
+    /// as `open`, by formatting it: its diagnostic is published when the
+    /// result arrives, or right away from the format cache.
+    fn check_document(
+        &self,
+        backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+        effective_config: &PathBuf,
+        uri: &str,
+        open: &serde_json::Value,
+        options: &serde_json::Value,
+        stdout: &ClientOut,
+    ) {
+        let document = &open["params"]["textDocument"];
+        let Some(text) = document["text"].as_str() else {
+            return;
+        };
+        let profile = self.config.profile_label(effective_config);
+        let mut check = Check {
+            uri: uri.to_string(),
+            version: document["version"].clone(),
+            text: text.to_string(),
+            severity: if self.config.warns_only(&profile) {
+                DIAGNOSTIC_WARNING
+            } else {
+                DIAGNOSTIC_ERROR
+            },
+            profile,
+            unformatted: Arc::clone(&self.unformatted),
+            fill: self.cache_fill(&uri_to_path(uri), text, effective_config),
+        };
+        if let Some(formatted) = check.fill.as_ref().and_then(|fill| fill.cached()) {
+            check.fill = None;
+            check.publish(&formatted, stdout);
+            return;
+        }
+        // Nobody waits for a check: it may take as long as a backend start.
+        let id = self.in_flight.insert(
+            effective_config,
+            Reply::Check(Box::new(check)),
+            STARTUP_TIMEOUT,
+        );
+        let request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "textDocument/formatting",
+            "params": { "textDocument": { "uri": document["uri"] }, "options": options },
+        });
+        if let Err(e) = self.dispatch(backends, effective_config, &request) {
+            eprintln!("dprintx: checking {uri}: {e:#}");
+            self.in_flight.forget(id);
+        }
+    }
+
+    /// Run [`IGNORE_FILE_COMMAND`] with `params`: add a match rule routing
+    /// the document to an ignore profile to the dprintx config. Returns the
+    /// document's URI and the rule's glob.
+    fn ignore_document(&self, params: &serde_json::Value) -> Result<(String, String)> {
+        let (config_path, _) = self
+            .reload
+            .as_ref()
+            .context("the dprintx config file is unknown")?;
+        let uri = params
+            .pointer("/arguments/0")
+            .and_then(|u| u.as_str())
+            .context("expected the document URI as argument")?;
+        let glob = edit::ignore_file(config_path, &uri_to_path(uri))?;
+        Ok((uri.to_string(), glob))
+    }
+
+    /// Withdraw the diagnostic of the document `uri`, if it has one.
+    fn clear_diagnostics(&self, uri: &str, stdout: &ClientOut) {
+        if self.unformatted.lock().unwrap().remove(uri).is_some() {
+            publish_diagnostics(stdout, uri, &serde_json::Value::Null, Vec::new());
+        }
+    }
+
+    /// Response for a request the proxy could not serve.
+    /// A JSON-RPC `RequestFailed` error if the method is listed in
+    /// `lsp_error_methods`, otherwise a null result.
+    fn fallback_response(
+        &self,
+        id: serde_json::Value,
+        method: &str,
+        message: String,
+    ) -> serde_json::Value {
+        if self.config.lsp_error_for(method) {
+            error_response(id, REQUEST_FAILED, message)
+        } else {
+            null_response(id)
+        }
+    }
+
+    /// Check each profile config with `dprint output-resolved-config`, which
+    /// loads the config and its plugins without touching files. Probes run in
+    /// parallel; returns failed configs with the last line of dprint's stderr.
+    fn probe_profiles(&self, configs: &[PathBuf]) -> HashMap<PathBuf, String> {
+        let mut unhealthy = HashMap::new();
+        let mut probes = Vec::new();
+        for config_path in configs {
+            let spawned = Command::new(&self.dprint_bin)
+                .args(["output-resolved-config", "--config"])
+                .arg(config_path)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped())
+                .spawn();
+            match spawned {
+                Ok(child) => probes.push((config_path, child)),
+                Err(e) => {
+                    unhealthy.insert(config_path.clone(), format!("cannot run dprint: {e}"));
+                }
+            }
+        }
+
+        let deadline = std::time::Instant::now() + PROBE_TIMEOUT;
+        for (config_path, mut child) in probes {
+            let status = loop {
+                match child.try_wait() {
+                    Ok(Some(status)) => break Some(status),
+                    Ok(None) if std::time::Instant::now() < deadline => {
+                        std::thread::sleep(Duration::from_millis(20));
+                    }
+                    _ => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        break None;
+                    }
+                }
+            };
+            let Some(status) = status else {
+                eprintln!(
+                    "dprintx: probe for {} timed out, assuming healthy",
+                    config_path.display()
+                );
+                continue;
+            };
+            if !status.success() {
+                let mut stderr = String::new();
+                if let Some(mut pipe) = child.stderr.take() {
+                    let _ = io::Read::read_to_string(&mut pipe, &mut stderr);
+                }
+                let reason = stderr
+                    .lines()
+                    .rev()
+                    .find(|l| !l.trim().is_empty())
+                    .map(|l| l.trim().to_string())
+                    .unwrap_or_else(|| format!("dprint exited with {status}"));
+                eprintln!(
+                    "dprintx: profile config {} is unhealthy: {reason}",
+                    config_path.display()
+                );
+                unhealthy.insert(config_path.clone(), reason);
+            }
+        }
+        unhealthy
+    }
+
+    /// Spawn `dprint lsp` for `config_path`. Its reader thread forwards
+    /// notifications to the editor, marks the backend ready on its
+    /// `initialize` response and completes [`InFlight`] requests.
+    fn spawn_backend(
+        &self,
+        config_path: &PathBuf,
+        backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+        stdout: &ClientOut,
+    ) -> Result<Backend> {
         let mut child = Command::new(&self.dprint_bin)
             .args(["lsp", "--config"])
             .arg(config_path)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::inherit())
+            .stderr(Stdio::piped())
             .spawn()
-            .with_context(|| format!("spawning dprint lsp --config {}", config_path.display()))?;
+            .spawn_context(&self.dprint_bin, || {
+                format!("lsp --config {}", config_path.display())
+            })?;
 
-        let stdin = child.stdin.take().context("no stdin on dprint lsp")?;
+        let stdin = Arc::new(Mutex::new(
+            child.stdin.take().context("no stdin on dprint lsp")?,
+        ));
         let child_stdout = child.stdout.take().context("no stdout on dprint lsp")?;
 
-        // Spawn a reader thread that reads LSP messages and sends them via channel.
-        let (tx, rx) = mpsc::channel();
+        let max_message_size = self
+            .config
+            .lsp_max_message_size
+            .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE);
+        let label = self.config.profile_label(config_path);
+        if let Some(stderr) = child.stderr.take() {
+            let (label, config_path) = (label.clone(), config_path.clone());
+            let (log, stdout) = (Arc::clone(&self.log), Arc::clone(stdout));
+            std::thread::spawn(move || relay_stderr(stderr, &label, &config_path, &log, &stdout));
+        }
+        let config_path = config_path.clone();
+        let backends = Arc::clone(backends);
+        let stdout = Arc::clone(stdout);
+        let in_flight = Arc::clone(&self.in_flight);
+        let server_requests = Arc::clone(&self.server_requests);
+        let metrics = Arc::clone(&self.metrics);
+        let log = Arc::clone(&self.log);
+        let crashed = Arc::clone(&self.crashed);
+        let rewritten_uris = Arc::clone(&self.rewritten_uris);
+        let own_stdin = Arc::downgrade(&stdin);
         std::thread::spawn(move || {
             let mut reader = BufReader::new(child_stdout);
-            while let Ok(msg) = read_lsp_message(&mut reader) {
-                if tx.send(msg).is_err() {
-                    break; // Receiver dropped.
+            loop {
+                let msg = match read_lsp_message(&mut reader, max_message_size) {
+                    Ok(msg) => msg,
+                    Err(e) if e.is::<RejectedMessage>() => {
+                        warnings::warn(
+                            "lsp-message",
+                            format!("dropped message from profile {label}: {e}"),
+                        );
+                        continue;
+                    }
+                    Err(_) => break,
+                };
+                let Ok(mut parsed) = serde_json::from_str::<serde_json::Value>(&msg) else {
+                    continue;
+                };
+
+                // Notifications go straight to the editor, requests under an
+                // id of the proxy. Both name documents as the editor does,
+                // log lines and progress say which profile they come from.
+                if parsed.get("method").is_some() {
+                    restore_uri(&mut parsed, &rewritten_uris.lock().unwrap());
+                    prefix_profile(&mut parsed, &label);
+                    if let Some(backend_id) = parsed.get("id").cloned() {
+                        let id = in_flight.next_id();
+                        server_requests.insert(id, &config_path, backend_id);
+                        parsed["id"] = id.into();
+                    }
+                    let _ = write_lsp_message(&stdout, &parsed.to_string());
+                    continue;
+                }
+                let Some(id) = parsed.get("id").cloned() else {
+                    continue;
+                };
+                let was_init = mark_ready(&backends, &config_path, &id, &parsed);
+                // Late first: a retry moves a late request back in flight.
+                if let Some(late) = in_flight.salvage(&config_path, &id, &parsed) {
+                    eprintln!(
+                        "dprintx: {} via {}: late response after {:?}, kept for a retry",
+                        late.method,
+                        late.profile,
+                        late.started.elapsed()
+                    );
+                } else if let Some(reply) = in_flight.take(&config_path, &id) {
+                    reply.finish(&config_path, Some(parsed), &stdout, &metrics, &log);
+                } else if !was_init {
+                    // Late answers to other timed-out requests, or from
+                    // backends that were not asked to answer.
+                    eprintln!("dprintx: dropping stale backend response (id={id})");
                 }
             }
+            server_requests.remove_backend(&config_path);
+
+            // Output ended without a shutdown: the backend died. Forget it so
+            // the next request for its config spawns a fresh one, and answer
+            // its requests now rather than at their deadlines.
+            let Some(mut backend) = take_crashed(&backends, &config_path, &own_stdin) else {
+                return;
+            };
+            let status = match reap(&mut backend.child, EXIT_TIMEOUT) {
+                Some(status) => status.to_string(),
+                None => "killed after closing its output".to_string(),
+            };
+            warnings::warn(
+                "lsp-backend",
+                format!(
+                    "dprint lsp for profile {label} died ({status}), restarting on next request"
+                ),
+            );
+            crashed.lock().unwrap().insert(config_path.clone());
+            for reply in in_flight.take_backend(&config_path) {
+                reply.finish(&config_path, None, &stdout, &metrics, &log);
+            }
         });
 
         Ok(Backend {
-            _child: child,
+            child,
             stdin,
-            responses: rx,
+            init_id: None,
+            pending: Vec::new(),
+            last_used: std::time::Instant::now(),
+            evictable: false,
+            stopping: false,
+            capabilities: None,
         })
     }
 
-    fn send_to_backend(
+    /// Effective config for files in `parent`: `profile_config` merged with
+    /// the local dprint config above them, or the profile config itself.
+    /// None if the local config says to skip them. Merges are cached until
+    /// the local config's mtime changes; the config a re-merge supersedes
+    /// is retired once nothing routes to it.
+    fn effective_config(
         &self,
+        parent: &Path,
+        profile_config: &Path,
+        merges: &mut Merges,
+        merged_guards: &mut HashMap<u64, config::TempConfig>,
         backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
-        config_path: &PathBuf,
+    ) -> Result<Option<PathBuf>> {
+        // Checked ahead of the cache: a reload may have turned merging off.
+        let local = if self.config.merge_local {
+            config::find_local_config(parent)
+        } else {
+            None
+        };
+        let Some(local) = local else {
+            return Ok(Some(profile_config.to_path_buf()));
+        };
+        let modified = std::fs::metadata(&local).and_then(|m| m.modified()).ok();
+        let key = (local, profile_config.to_path_buf());
+        // An evicted backend took its merged config along.
+        let cached = merges.get(&key).filter(|(m, outcome)| {
+            *m == modified
+                && match outcome {
+                    MergeOutcome::Merged(k) => merged_guards.contains_key(k),
+                    _ => true,
+                }
+        });
+        let outcome = match cached {
+            Some((_, outcome)) => *outcome,
+            None => {
+                let outcome = match config::merge_local_config(
+                    parent,
+                    profile_config,
+                    self.config.merge_options(profile_config),
+                )? {
+                    Merge::Merged(tc) => {
+                        let k = content_key(tc.path());
+                        merged_guards.entry(k).or_insert(tc);
+                        MergeOutcome::Merged(k)
+                    }
+                    Merge::Profile => MergeOutcome::Profile,
+                    Merge::Skip => MergeOutcome::Skip,
+                };
+                if let Some((_, MergeOutcome::Merged(old))) =
+                    merges.insert(key, (modified, outcome))
+                    && !merges
+                        .values()
+                        .any(|(_, o)| *o == MergeOutcome::Merged(old))
+                {
+                    self.retire_merged(backends, merged_guards, old);
+                }
+                outcome
+            }
+        };
+        Ok(match outcome {
+            MergeOutcome::Merged(k) => Some(merged_guards[&k].path().to_path_buf()),
+            MergeOutcome::Profile => Some(profile_config.to_path_buf()),
+            MergeOutcome::Skip => None,
+        })
+    }
+
+    /// Delete the merged config under `key`, superseded by an edit of its
+    /// local or profile config, and shut down its backend. A backend still starting or
+    /// answering a request keeps its config until it is evicted.
+    fn retire_merged(
+        &self,
+        backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+        merged_guards: &mut HashMap<u64, config::TempConfig>,
+        key: u64,
+    ) {
+        let Some(path) = merged_guards.get(&key).map(|g| g.path().to_path_buf()) else {
+            return;
+        };
+        let running = match backends.lock().unwrap().get(&path) {
+            Some(b) if b.init_id.is_some() || self.in_flight.has(&path) => return,
+            running => running.is_some(),
+        };
+        if running {
+            eprintln!(
+                "dprintx: {} superseded by a config edit, shutting down its backend",
+                path.display()
+            );
+            self.shutdown_backend(backends, &path);
+        }
+        merged_guards.remove(&key);
+    }
+
+    /// Shut down least-recently-used evictable backends until at most `keep`
+    /// remain, deleting their merged temp configs. Stops early if only
+    /// profile or busy backends are left.
+    fn evict_backends(
+        &self,
+        backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+        keep: usize,
+        merged_guards: &mut HashMap<u64, config::TempConfig>,
+    ) {
+        loop {
+            let victim = {
+                let backends_lock = backends.lock().unwrap();
+                if backends_lock.len() <= keep {
+                    return;
+                }
+                backends_lock
+                    .iter()
+                    .filter(|(path, b)| {
+                        b.evictable && b.init_id.is_none() && !self.in_flight.has(path)
+                    })
+                    .min_by_key(|(_, b)| b.last_used)
+                    .map(|(path, _)| path.clone())
+            };
+            let Some(victim) = victim else {
+                return;
+            };
+            eprintln!(
+                "dprintx: lsp_max_backends reached, shutting down {}",
+                victim.display()
+            );
+            self.shutdown_backend(backends, &victim);
+            merged_guards.retain(|_, g| g.path() != victim);
+        }
+    }
+
+    /// Gracefully stop one backend (`shutdown` + `exit`), then reap it.
+    fn shutdown_backend(
+        &self,
+        backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+        config_path: &PathBuf,
+    ) {
+        if let Some(backend) = backends.lock().unwrap().get_mut(config_path) {
+            backend.stopping = true;
+        }
+        let (id, rx) = self.expect_response(config_path, self.read_timeout);
+        let shutdown = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": "shutdown" });
+        if self
+            .send_to_backend(backends, config_path, &shutdown)
+            .is_ok()
+        {
+            let deadline = Instant::now() + self.read_timeout;
+            let _ = self.await_response(rx, id, deadline);
+        } else {
+            self.in_flight.forget(id);
+        }
+        let exit = serde_json::json!({ "jsonrpc": "2.0", "method": "exit" });
+        let _ = self.send_to_backend(backends, config_path, &exit);
+
+        let Some(mut backend) = backends.lock().unwrap().remove(config_path) else {
+            return;
+        };
+        reap(&mut backend.child, self.read_timeout);
+    }
+
+    /// Send a message to a backend, or queue it while the backend is initializing.
+    fn dispatch(
+        &self,
+        backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+        config_path: &PathBuf,
         msg: &serde_json::Value,
     ) -> Result<()> {
-        let json = serde_json::to_string(msg)?;
         let mut backends_lock = backends.lock().unwrap();
         if let Some(backend) = backends_lock.get_mut(config_path) {
-            let header = format!("Content-Length: {}\r\n\r\n", json.len());
-            backend.stdin.write_all(header.as_bytes())?;
-            backend.stdin.write_all(json.as_bytes())?;
-            backend.stdin.flush()?;
+            backend.last_used = std::time::Instant::now();
+            if backend.init_id.is_some() {
+                backend.pending.push(msg.clone());
+                return Ok(());
+            }
         }
-        Ok(())
+        drop(backends_lock);
+        self.send_to_backend(backends, config_path, msg)
     }
 
-    /// Read a response from a backend, skipping notifications.
-    /// Notifications (messages without "id") are forwarded to the editor.
-    fn read_from_backend(
+    fn send_to_backend(
         &self,
         backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
         config_path: &PathBuf,
-        stdout: &Arc<Mutex<io::Stdout>>,
-    ) -> Result<String> {
-        let backends_lock = backends.lock().unwrap();
-        let backend = backends_lock
+        msg: &serde_json::Value,
+    ) -> Result<()> {
+        let stdin = match backends.lock().unwrap().get(config_path) {
+            Some(backend) => Arc::clone(&backend.stdin),
+            None => return Ok(()),
+        };
+        let mut stdin = stdin.lock().unwrap();
+        write_backend_message(&mut stdin, msg)
+    }
+
+    /// Register the editor's request `id` to `config_path`, answered by the
+    /// backend's reader thread, or with a fallback built from `failure` once
+    /// it times out. Returns the id to send it to the backend with, and
+    /// whether it was sent already: a retry of a timed-out formatting
+    /// request (same `salvage` key) waits for the original's response.
+    /// With `range`, the result is cut down to it (see [`RangeSlice`]).
+    /// Requests queued behind a backend's startup get [`STARTUP_TIMEOUT`] on
+    /// top.
+    #[allow(clippy::too_many_arguments)]
+    fn expect_client_response(
+        &self,
+        backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+        config_path: &Path,
+        id: &serde_json::Value,
+        method: &str,
+        profile: &str,
+        trace: TraceLevel,
+        failure: String,
+        salvage: Option<String>,
+        range: Option<Box<RangeSlice>>,
+        fill: Option<Box<CacheFill>>,
+    ) -> (u64, bool) {
+        let initializing = backends
+            .lock()
+            .unwrap()
             .get(config_path)
-            .context("backend not found")?;
+            .is_some_and(|b| b.init_id.is_some());
+        let timeout = if initializing {
+            STARTUP_TIMEOUT + self.read_timeout
+        } else {
+            self.read_timeout
+        };
+        let fallback = Box::new(self.fallback_response(
+            id.clone(),
+            method,
+            format!("{failure}: backend read timeout"),
+        ));
+        let reply = Reply::Client {
+            id: id.clone(),
+            method: method.to_string(),
+            profile: profile.to_string(),
+            started: Instant::now(),
+            trace,
+            fallback,
+            range,
+            fill,
+        };
+        match salvage {
+            Some(salvage) => self
+                .in_flight
+                .insert_formatting(config_path, reply, timeout, salvage),
+            None => (self.in_flight.insert(config_path, reply, timeout), false),
+        }
+    }
 
-        let deadline = std::time::Instant::now() + READ_TIMEOUT;
-        loop {
-            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
-            if remaining.is_zero() {
-                bail!("backend read timeout");
+    /// Register a request of the proxy itself to `config_path`: send it with
+    /// the returned id, then pass the receiver to [`Self::await_response`].
+    fn expect_response(
+        &self,
+        config_path: &Path,
+        timeout: Duration,
+    ) -> (u64, mpsc::Receiver<serde_json::Value>) {
+        let (tx, rx) = mpsc::channel();
+        let id = self
+            .in_flight
+            .insert(config_path, Reply::Internal(tx), timeout);
+        (id, rx)
+    }
+
+    /// The response to request `id` registered with
+    /// [`Self::expect_response`], waiting until `deadline` at most.
+    fn await_response(
+        &self,
+        rx: mpsc::Receiver<serde_json::Value>,
+        id: u64,
+        deadline: Instant,
+    ) -> Result<serde_json::Value> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(remaining) {
+            Ok(response) => Ok(response),
+            Err(_) => {
+                self.in_flight.forget(id);
+                bail!("backend read timeout")
+            }
+        }
+    }
+}
+
+/// If `id` answers the pending `initialize` of the backend for
+/// `config_path` with `response`, note its capabilities, mark it ready and
+/// replay the messages queued meanwhile. Returns whether it did.
+fn mark_ready(
+    backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+    config_path: &PathBuf,
+    id: &serde_json::Value,
+    response: &serde_json::Value,
+) -> bool {
+    let mut backends_lock = backends.lock().unwrap();
+    let Some(backend) = backends_lock.get_mut(config_path) else {
+        return false;
+    };
+    if backend.init_id.is_none() || backend.init_id != id.as_u64() {
+        return false;
+    }
+    backend.init_id = None;
+    backend.capabilities = response.pointer("/result/capabilities").cloned();
+    let pending = std::mem::take(&mut backend.pending);
+    let stdin = Arc::clone(&backend.stdin);
+    // Hold the backend's stdin before releasing the map, so messages
+    // dispatched from now on go after the queued ones.
+    let mut stdin = stdin.lock().unwrap();
+    drop(backends_lock);
+
+    if !pending.is_empty() {
+        eprintln!(
+            "dprintx: backend {} ready, replaying {} queued message(s)",
+            config_path.display(),
+            pending.len()
+        );
+    }
+    for msg in &pending {
+        if write_backend_message(&mut stdin, msg).is_err() {
+            break;
+        }
+    }
+    true
+}
+
+/// Remove the backend for `config_path` from the map if it is the one
+/// writing to `stdin` and was not being shut down.
+fn take_crashed(
+    backends: &Arc<Mutex<HashMap<PathBuf, Backend>>>,
+    config_path: &Path,
+    stdin: &Weak<Mutex<ChildStdin>>,
+) -> Option<Backend> {
+    let mut backends_lock = backends.lock().unwrap();
+    let backend = backends_lock.get(config_path)?;
+    if backend.stopping || !std::ptr::eq(Arc::as_ptr(&backend.stdin), stdin.as_ptr()) {
+        return None;
+    }
+    backends_lock.remove(config_path)
+}
+
+/// Relay the stderr of the backend for `backend` (profile `label`) line by
+/// line, instead of mixing it into the proxy's own: to the lsp log when
+/// there is one, else to the editor as `window/logMessage`.
+fn relay_stderr(
+    stderr: impl io::Read,
+    label: &str,
+    backend: &Path,
+    log: &LspLog,
+    stdout: &ClientOut,
+) {
+    for line in BufReader::new(stderr).split(b'\n') {
+        let Ok(line) = line else {
+            return;
+        };
+        let line = String::from_utf8_lossy(&line);
+        let line = line.trim_end();
+        if line.is_empty() || log.stderr(label, backend, line) {
+            continue;
+        }
+        let _ = write_lsp_message(stdout, &log_message(label, line).to_string());
+    }
+}
+
+/// Prefix the text of a backend's `window/logMessage` or `$/progress`
+/// notification `msg` with its profile `label`, as [`log_message`] does:
+/// the log line, or the title a progress begins with.
+fn prefix_profile(msg: &mut serde_json::Value, label: &str) {
+    let pointer = match msg["method"].as_str() {
+        Some("window/logMessage") => "/params/message",
+        Some("$/progress") if msg.pointer("/params/value/kind") == Some(&"begin".into()) => {
+            "/params/value/title"
+        }
+        _ => return,
+    };
+    if let Some(text) = msg.pointer_mut(pointer)
+        && let Some(s) = text.as_str()
+    {
+        *text = format!("[{label}] {s}").into();
+    }
+}
+
+/// `window/logMessage` of a backend's stderr `line`, prefixed with its
+/// profile `label`.
+fn log_message(label: &str, line: &str) -> serde_json::Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "window/logMessage",
+        "params": { "type": MESSAGE_TYPE_LOG, "message": format!("[{label}] {line}") },
+    })
+}
+
+/// Wait up to `timeout` for `child` to exit, then kill it. Returns the exit
+/// status unless it had to be killed.
+fn reap(child: &mut Child, timeout: Duration) -> Option<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Some(status);
+        }
+        if Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    None
+}
+
+/// Bring the didOpen `open` up to date with the didChange `change`.
+/// False if a content change could not be applied.
+fn track_change(open: &mut serde_json::Value, change: &serde_json::Value) -> bool {
+    let document = &mut open["params"]["textDocument"];
+    let mut text = document["text"].as_str().unwrap_or_default().to_string();
+    let Some(changes) = change["params"]["contentChanges"].as_array() else {
+        return false;
+    };
+    if !changes.iter().all(|c| apply_content_change(&mut text, c)) {
+        return false;
+    }
+    document["text"] = text.into();
+    document["version"] = change["params"]["textDocument"]["version"].clone();
+    true
+}
+
+/// Apply one `contentChanges` entry to `text`: a full replacement, or an
+/// edit of a range whose positions count UTF-16 code units. Positions past
+/// the end of a line or of the text are clamped. False if malformed.
+fn apply_content_change(text: &mut String, change: &serde_json::Value) -> bool {
+    let Some(new_text) = change.get("text").and_then(|t| t.as_str()) else {
+        return false;
+    };
+    let Some(range) = change.get("range") else {
+        *text = new_text.to_string();
+        return true;
+    };
+    let offset = |position| text_offset(text, position);
+    let (Some(start), Some(end)) = (
+        range.get("start").and_then(offset),
+        range.get("end").and_then(offset),
+    ) else {
+        return false;
+    };
+    if start > end {
+        return false;
+    }
+    text.replace_range(start..end, new_text);
+    true
+}
+
+/// Whether server `capabilities` enable `provider` (true or an options
+/// object).
+fn provides(capabilities: &serde_json::Value, provider: &str) -> bool {
+    capabilities
+        .get(provider)
+        .is_some_and(|p| !matches!(p, serde_json::Value::Null | serde_json::Value::Bool(false)))
+}
+
+/// The config extending `effective_config` with the editor's formatting
+/// `options`, kept among the `merged_guards` (identical ones are shared) and
+/// recorded in `overlays`; `effective_config` itself if the options set
+/// nothing or the config cannot be written.
+fn editor_overlay(
+    effective_config: &Path,
+    options: &serde_json::Value,
+    merged_guards: &mut HashMap<u64, config::TempConfig>,
+    overlays: &mut HashMap<PathBuf, PathBuf>,
+) -> PathBuf {
+    match config::overlay_editor_options(effective_config, options) {
+        Ok(Some(tc)) => {
+            let k = content_key(tc.path());
+            let path = merged_guards.entry(k).or_insert(tc).path().to_path_buf();
+            overlays.insert(path.clone(), effective_config.to_path_buf());
+            path
+        }
+        Ok(None) => effective_config.to_path_buf(),
+        Err(e) => {
+            warnings::warn("editor-options", format!("{e:#}"));
+            effective_config.to_path_buf()
+        }
+    }
+}
+
+/// Whether a backend with `capabilities` formats on typing `ch`.
+fn formats_on_type(capabilities: &serde_json::Value, ch: &str) -> bool {
+    let provider = &capabilities["documentOnTypeFormattingProvider"];
+    provider["firstTriggerCharacter"] == ch
+        || provider["moreTriggerCharacter"]
+            .as_array()
+            .is_some_and(|more| more.iter().any(|c| c == ch))
+}
+
+/// Add the trigger characters of another backend's on-type formatting
+/// `provider` to `merged`, as more trigger characters.
+fn merge_trigger_characters(
+    merged: &mut serde_json::Map<String, serde_json::Value>,
+    provider: &serde_json::Value,
+) {
+    let theirs = std::iter::once(&provider["firstTriggerCharacter"])
+        .chain(
+            provider["moreTriggerCharacter"]
+                .as_array()
+                .into_iter()
+                .flatten(),
+        )
+        .filter(|c| c.is_string());
+    for ch in theirs {
+        let known = merged.get("firstTriggerCharacter") == Some(ch)
+            || merged
+                .get("moreTriggerCharacter")
+                .and_then(|m| m.as_array())
+                .is_some_and(|more| more.contains(ch));
+        if known {
+            continue;
+        }
+        let more = merged
+            .entry("moreTriggerCharacter")
+            .or_insert_with(|| serde_json::json!([]));
+        if let Some(more) = more.as_array_mut() {
+            more.push(ch.clone());
+        }
+    }
+}
+
+/// Key of a merged config among the proxy's guards: a hash of its content,
+/// or of its path if it cannot be read (then it is shared with nothing).
+fn content_key(path: &Path) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    match std::fs::read(path) {
+        Ok(content) => content.hash(&mut hasher),
+        Err(_) => path.hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+/// The `initialize` response for the editor from the backends' `responses`,
+/// in profile order: the first one, with the capabilities only later ones
+/// have added, and the on-type formatting trigger characters of all of
+/// them. None without any.
+fn merge_initialize_results(responses: Vec<serde_json::Value>) -> Option<serde_json::Value> {
+    let mut responses = responses.into_iter();
+    let mut merged = responses.next()?;
+    for other in responses {
+        let (Some(capabilities), Some(others)) = (
+            merged
+                .pointer_mut("/result/capabilities")
+                .and_then(|c| c.as_object_mut()),
+            other
+                .pointer("/result/capabilities")
+                .and_then(|c| c.as_object()),
+        ) else {
+            continue;
+        };
+        for (key, value) in others {
+            let missing = capabilities
+                .get(key)
+                .is_none_or(|v| v.is_null() || *v == false);
+            if missing {
+                capabilities.insert(key.clone(), value.clone());
+            } else if key == "documentOnTypeFormattingProvider"
+                && let Some(provider) = capabilities[key].as_object_mut()
+            {
+                merge_trigger_characters(provider, value);
             }
+        }
+    }
+    Some(merged)
+}
+
+/// Extend backend `capabilities` for the editor with what the proxy serves
+/// through whole-document formatting: range formatting,
+/// `willSaveWaitUntil` and, with `diagnostics`, the fix of unformatted
+/// documents as a code action; with `config_commands` (the dprintx config
+/// file is known), also [`IGNORE_FILE_COMMAND`] with its code action and
+/// [`RELOAD_CONFIG_COMMAND`].
+fn extend_capabilities(
+    capabilities: &mut serde_json::Value,
+    diagnostics: bool,
+    config_commands: bool,
+) {
+    if !provides(capabilities, "documentFormattingProvider") {
+        return;
+    }
+    capabilities["documentRangeFormattingProvider"] = true.into();
+    if (diagnostics || config_commands) && !provides(capabilities, "codeActionProvider") {
+        capabilities["codeActionProvider"] = serde_json::json!({ "codeActionKinds": ["quickfix"] });
+    }
+    if config_commands {
+        let provider = &mut capabilities["executeCommandProvider"];
+        if !provider["commands"].is_array() {
+            *provider = serde_json::json!({ "commands": [] });
+        }
+        if let Some(commands) = provider["commands"].as_array_mut() {
+            commands.push(IGNORE_FILE_COMMAND.into());
+            commands.push(RELOAD_CONFIG_COMMAND.into());
+        }
+    }
+    // Folders come and go without a restart: backends spawned later start
+    // with the current ones.
+    let workspace = &mut capabilities["workspace"];
+    if !workspace.is_object() {
+        *workspace = serde_json::json!({});
+    }
+    workspace["workspaceFolders"] =
+        serde_json::json!({ "supported": true, "changeNotifications": true });
+    let mut sync = match capabilities.get("textDocumentSync") {
+        Some(serde_json::Value::Object(sync)) => sync.clone(),
+        // A bare sync kind: the short form of openClose plus change.
+        Some(kind @ serde_json::Value::Number(_)) => {
+            let mut sync = serde_json::Map::new();
+            sync.insert("openClose".into(), true.into());
+            sync.insert("change".into(), kind.clone());
+            sync
+        }
+        _ => serde_json::Map::new(),
+    };
+    sync.insert("willSaveWaitUntil".into(), true.into());
+    capabilities["textDocumentSync"] = sync.into();
+}
+
+/// Byte offset in `text` of an LSP position (UTF-16 code units), clamped
+/// to the end of its line and of the text.
+fn text_offset(text: &str, position: &serde_json::Value) -> Option<usize> {
+    let line = position.get("line")?.as_u64()? as usize;
+    let character = position.get("character")?.as_u64()? as usize;
+    let start: usize = text.split_inclusive('\n').take(line).map(str::len).sum();
+    let line_text = text[start..].split('\n').next().unwrap_or_default();
+    let line_text = line_text.strip_suffix('\r').unwrap_or(line_text);
+    let mut units = 0;
+    for (i, c) in line_text.char_indices() {
+        if units >= character {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(start + line_text.len())
+}
+
+/// `text` with the `TextEdit`s of a formatting result applied. None if an
+/// edit is malformed.
+fn apply_edits(text: &str, edits: &[serde_json::Value]) -> Option<String> {
+    let mut spans = edits
+        .iter()
+        .map(|edit| {
+            let start = text_offset(text, edit.pointer("/range/start")?)?;
+            let end = text_offset(text, edit.pointer("/range/end")?)?;
+            let new_text = edit.get("newText")?.as_str()?;
+            (start <= end).then_some((start, end, new_text))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    // Back to front, so earlier offsets stay valid.
+    spans.sort_by_key(|(start, _, _)| std::cmp::Reverse(*start));
+    let mut text = text.to_string();
+    for (start, end, new_text) in spans {
+        text.replace_range(start..end, new_text);
+    }
+    Some(text)
+}
+
+/// Cut a whole-document formatting `result` for `text` down to the lines
+/// of `range`: one edit per changed block of lines touching it. None if
+/// the edits cannot be applied.
+fn slice_edits(
+    text: &str,
+    result: &serde_json::Value,
+    range: &serde_json::Value,
+) -> Option<serde_json::Value> {
+    let Some(edits) = result.as_array() else {
+        return Some(result.clone()); // null: nothing to change
+    };
+    let formatted = apply_edits(text, edits)?;
+    let old: Vec<&str> = text.split_inclusive('\n').collect();
+    let new: Vec<&str> = formatted.split_inclusive('\n').collect();
+
+    let line =
+        |pointer: &str| -> Option<usize> { Some(range.pointer(pointer)?.as_u64()? as usize) };
+    let first = line("/start/line")?;
+    // A range ending at the start of a line does not include that line.
+    let end = match (line("/end/line")?, line("/end/character")?) {
+        (end, 0) if end > first => end,
+        (end, _) => end + 1,
+    };
+
+    Some(hunk_edits(&old, &new, |old_start, old_end| {
+        if old_start == old_end {
+            first <= old_start && old_start <= end
+        } else {
+            old_start < end && first < old_end
+        }
+    }))
+}
+
+/// `text` formatted per a formatting `result`: its edits applied, or as it
+/// is for a null result. None if the result is malformed.
+fn formatted_text(text: &str, result: &serde_json::Value) -> Option<String> {
+    match result.as_array() {
+        Some(edits) => apply_edits(text, edits),
+        None => result.is_null().then(|| text.to_string()),
+    }
+}
+
+/// Result of a `codeAction` request with `params`, if quick fixes are
+/// asked for: the `fix` of the document's diagnostic, if it still applies
+/// to `text`, the document as the editor has it, and with `ignore_file`
+/// ignoring the document.
+fn code_actions(
+    params: &serde_json::Value,
+    fix: Option<&Fix>,
+    text: Option<&str>,
+    ignore_file: bool,
+) -> serde_json::Value {
+    let wanted = match params.pointer("/context/only").and_then(|o| o.as_array()) {
+        Some(only) => only.iter().any(|kind| kind == "quickfix"),
+        None => true,
+    };
+    let Some(uri) = params.pointer("/textDocument/uri").and_then(|u| u.as_str()) else {
+        return serde_json::json!([]);
+    };
+    let mut actions = Vec::new();
+    if let Some(fix) = fix.filter(|fix| wanted && text == Some(fix.text.as_str())) {
+        let mut changes = serde_json::Map::new();
+        changes.insert(uri.to_string(), fix.edits.clone());
+        actions.push(serde_json::json!({
+            "title": format!("Format with dprintx (profile {})", fix.profile),
+            "kind": "quickfix",
+            "diagnostics": [fix.diagnostic],
+            "isPreferred": true,
+            "edit": { "changes": changes },
+        }));
+    }
+    if ignore_file && wanted {
+        let title = "Ignore this file in dprintx";
+        actions.push(serde_json::json!({
+            "title": title,
+            "kind": "quickfix",
+            "command": { "title": title, "command": IGNORE_FILE_COMMAND, "arguments": [uri] },
+        }));
+    }
+    actions.into()
+}
+
+/// `dprint fmt --stdin <file_name>` of `text` with `config_path`, run in
+/// the config's directory.
+fn format_stdin(
+    dprint_bin: &Path,
+    config_path: &Path,
+    file_name: &str,
+    text: &str,
+) -> Result<String> {
+    let mut child = Command::new(dprint_bin)
+        .args(["fmt", "--stdin", file_name, "--config"])
+        .arg(config_path)
+        .current_dir(config_path.parent().unwrap_or(Path::new("/")))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .spawn_context(dprint_bin, || format!("fmt --stdin {file_name}"))?;
+    // Written first: dprint may exit without reading its input.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    let output = child.wait_with_output().context("waiting for dprint")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().rev().find(|l| !l.trim().is_empty());
+        bail!(
+            "dprint fmt --stdin {file_name} failed: {}",
+            reason.map_or_else(|| output.status.to_string(), |l| l.trim().to_string())
+        );
+    }
+    String::from_utf8(output.stdout).context("dprint output is not UTF-8")
+}
+
+/// Edits turning `text` into `formatted`: one per changed block of lines.
+fn text_edits(text: &str, formatted: &str) -> serde_json::Value {
+    let old: Vec<&str> = text.split_inclusive('\n').collect();
+    let new: Vec<&str> = formatted.split_inclusive('\n').collect();
+    hunk_edits(&old, &new, |_, _| true)
+}
+
+/// One edit per block of lines that differs between `old` and `new` and
+/// whose old line range passes `keep`.
+fn hunk_edits(
+    old: &[&str],
+    new: &[&str],
+    keep: impl Fn(usize, usize) -> bool,
+) -> serde_json::Value {
+    let position = |line: usize| match old.get(line.wrapping_sub(1)) {
+        // Past a last line without a newline: its end.
+        Some(last) if line == old.len() && !last.ends_with('\n') => serde_json::json!({
+            "line": line - 1,
+            "character": last.encode_utf16().count(),
+        }),
+        _ => serde_json::json!({ "line": line, "character": 0 }),
+    };
+    line_hunks(old, new)
+        .into_iter()
+        .filter(|&(old_start, old_end, _, _)| keep(old_start, old_end))
+        .map(|(old_start, old_end, new_start, new_end)| {
+            serde_json::json!({
+                "range": { "start": position(old_start), "end": position(old_end) },
+                "newText": new[new_start..new_end].concat(),
+            })
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Blocks of lines that differ between `old` and `new`, as
+/// `(old_start, old_end, new_start, new_end)` line ranges in order.
+fn line_hunks(old: &[&str], new: &[&str]) -> Vec<(usize, usize, usize, usize)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+    if a.is_empty() && b.is_empty() {
+        return Vec::new();
+    }
+    if a.len() * b.len() > MAX_DIFF_CELLS {
+        return vec![(prefix, prefix + a.len(), prefix, prefix + b.len())];
+    }
+
+    // Longest common subsequence lengths of the suffixes a[i..], b[j..].
+    let width = b.len() + 1;
+    let mut lcs = vec![0u32; (a.len() + 1) * width];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i * width + j] = if a[i] == b[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let mut open: Option<(usize, usize)> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            if let Some((i0, j0)) = open.take() {
+                hunks.push((prefix + i0, prefix + i, prefix + j0, prefix + j));
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        open.get_or_insert((i, j));
+        if j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    if let Some((i0, j0)) = open {
+        hunks.push((prefix + i0, prefix + i, prefix + j0, prefix + j));
+    }
+    hunks
+}
+
+/// Profile configs of `config`'s profiles.
+fn profile_configs(config: &DprintxConfig) -> HashSet<PathBuf> {
+    config
+        .profiles
+        .keys()
+        .filter_map(|name| match config.resolve_profile(name) {
+            Some(ProfileResolution::Config(path)) => Some(path),
+            _ => None,
+        })
+        .collect()
+}
+
+fn watch_file(watcher: &ConfigWatcher, path: &Path) {
+    if let Err(e) = watcher.watch(path) {
+        eprintln!("dprintx: not watching {}: {e:#}", path.display());
+    }
+}
+
+/// Write one LSP message to a backend.
+fn write_backend_message(stdin: &mut ChildStdin, msg: &serde_json::Value) -> Result<()> {
+    let json = serde_json::to_string(msg)?;
+    let header = format!("Content-Length: {}\r\n\r\n", json.len());
+    stdin.write_all(header.as_bytes())?;
+    stdin.write_all(json.as_bytes())?;
+    stdin.flush()?;
+    Ok(())
+}
+
+/// Write metrics to the `--metrics` JSON file and/or the Prometheus textfile.
+fn dump_metrics(metrics: &Metrics, json_file: Option<&Path>, textfile: Option<&Path>) {
+    if let Some(path) = json_file
+        && let Err(e) = metrics.write_json(path)
+    {
+        warnings::warn("metrics", format!("metrics dump failed: {e:#}"));
+    }
+    if let Some(path) = textfile
+        && let Err(e) = metrics.write_textfile(path)
+    {
+        warnings::warn("metrics", format!("metrics textfile update failed: {e:#}"));
+    }
+}
+
+/// Send the editor `diagnostics` of the document `uri` at `version`
+/// (null if unknown).
+fn publish_diagnostics(
+    stdout: &ClientOut,
+    uri: &str,
+    version: &serde_json::Value,
+    diagnostics: Vec<serde_json::Value>,
+) {
+    let mut params = serde_json::json!({ "uri": uri, "diagnostics": diagnostics });
+    if version.is_number() {
+        params["version"] = version.clone();
+    }
+    let notification = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": params,
+    });
+    let _ = write_lsp_message(stdout, &notification.to_string());
+}
+
+/// `textDocument/didClose` for `uri`.
+fn did_close(uri: &str) -> serde_json::Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didClose",
+        "params": { "textDocument": { "uri": uri } },
+    })
+}
+
+/// JSON-RPC response with a null result.
+fn null_response(id: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "result": null,
+    })
+}
+
+/// Answer to a request for an ignored document: no edits for formatting
+/// requests, so the editor knows there is nothing to do; null otherwise.
+fn ignored_response(id: serde_json::Value, method: &str) -> serde_json::Value {
+    let formatting = matches!(
+        method,
+        "textDocument/formatting"
+            | "textDocument/rangeFormatting"
+            | "textDocument/onTypeFormatting"
+            | "textDocument/willSaveWaitUntil"
+    );
+    if formatting {
+        serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": [] })
+    } else {
+        null_response(id)
+    }
+}
+
+/// JSON-RPC error response.
+fn error_response(id: serde_json::Value, code: i64, message: String) -> serde_json::Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": code,
+            "message": message,
+        },
+    })
+}
+
+/// Read an LSP message from a buffered reader.
+/// Format: "Content-Length: N\r\n\r\n" followed by N bytes.
+///
+/// Bodies larger than `max_size` or with a non-UTF-8 `Content-Type` charset
+/// are skipped without being buffered and reported as [`RejectedMessage`];
+/// the stream stays usable. Other errors (EOF, broken framing) are fatal.
+pub(crate) fn read_lsp_message<R: BufRead>(reader: &mut R, max_size: usize) -> Result<String> {
+    let mut content_length: Option<usize> = None;
+    let mut charset_error: Option<String> = None;
+
+    // Read headers.
+    loop {
+        let mut line = String::new();
+        let bytes_read =
+            io::Read::take(&mut *reader, MAX_HEADER_LINE as u64).read_line(&mut line)?;
+        if bytes_read == 0 {
+            return Err(lsp_error("EOF while reading LSP headers"));
+        }
+        if bytes_read == MAX_HEADER_LINE && !line.ends_with('\n') {
+            return Err(lsp_error(format!(
+                "LSP header line longer than {MAX_HEADER_LINE} bytes"
+            )));
+        }
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            break; // End of headers.
+        }
+
+        if let Some(rest) = trimmed.strip_prefix("Content-Length:") {
+            content_length = Some(
+                rest.trim()
+                    .parse()
+                    .map_err(|_| lsp_error(format!("invalid Content-Length: {}", rest.trim())))?,
+            );
+        } else if let Some(rest) = trimmed.strip_prefix("Content-Type:") {
+            charset_error = unsupported_charset(rest);
+        }
+    }
+
+    let length = content_length.ok_or_else(|| lsp_error("missing Content-Length header"))?;
+
+    let rejected = if length > max_size {
+        Some(format!(
+            "message of {length} bytes exceeds the {max_size} byte limit"
+        ))
+    } else {
+        charset_error
+    };
+    if let Some(reason) = rejected {
+        let skipped = io::copy(
+            &mut io::Read::take(&mut *reader, length as u64),
+            &mut io::sink(),
+        )?;
+        if skipped < length as u64 {
+            return Err(lsp_error("EOF while reading LSP body"));
+        }
+        return Err(RejectedMessage(reason).into());
+    }
+
+    // Read body.
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+
+    String::from_utf8(body).context("invalid UTF-8 in LSP message body")
+}
+
+/// A broken client or backend stream.
+fn lsp_error(message: impl Into<String>) -> anyhow::Error {
+    DprintxError::Lsp {
+        message: message.into(),
+    }
+    .into()
+}
+
+/// An LSP message that was read past but refused (see [`read_lsp_message`]).
+#[derive(Debug)]
+struct RejectedMessage(String);
+
+impl std::fmt::Display for RejectedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RejectedMessage {}
+
+/// Reason to refuse a `Content-Type` header value, if its charset is not
+/// UTF-8. LSP only defines `utf-8` (and `utf8` for backwards compatibility).
+fn unsupported_charset(content_type: &str) -> Option<String> {
+    let charset = content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches('"'))?;
+    if charset.eq_ignore_ascii_case("utf-8") || charset.eq_ignore_ascii_case("utf8") {
+        None
+    } else {
+        Some(format!(
+            "unsupported charset {charset:?}, only utf-8 is accepted"
+        ))
+    }
+}
+
+/// Write an LSP message to stdout.
+fn write_lsp_message(stdout: &ClientOut, body: &str) -> Result<()> {
+    let mut out = stdout.lock().unwrap();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Remove the `dprintx` section from `didChangeConfiguration` settings and
+/// return it. dprint's own settings stay in `msg` for the backends.
+fn take_dprintx_settings(msg: &mut serde_json::Value) -> Option<serde_json::Value> {
+    msg.get_mut("params")?
+        .get_mut("settings")?
+        .as_object_mut()?
+        .remove("dprintx")
+}
+
+/// Extract file URI from LSP params.
+/// Looks for params.textDocument.uri.
+fn extract_uri(msg: &serde_json::Value) -> Option<String> {
+    msg.get("params")?
+        .get("textDocument")?
+        .get("uri")?
+        .as_str()
+        .map(|s| s.to_string())
+}
+
+/// Document URIs named in `workspace/executeCommand` arguments: `file://`
+/// strings and `uri` fields at any depth, in order of appearance.
+fn command_uris(msg: &serde_json::Value) -> Vec<String> {
+    fn collect(value: &serde_json::Value, out: &mut Vec<String>) {
+        match value {
+            serde_json::Value::String(s) if s.starts_with("file://") => out.push(s.clone()),
+            serde_json::Value::Array(items) => items.iter().for_each(|v| collect(v, out)),
+            serde_json::Value::Object(map) => {
+                for (key, v) in map {
+                    match v.as_str() {
+                        Some(s) if key == "uri" && !s.starts_with("file://") => {
+                            out.push(s.to_string())
+                        }
+                        _ => collect(v, out),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut uris = Vec::new();
+    if let Some(args) = msg.get("params").and_then(|p| p.get("arguments")) {
+        collect(args, &mut uris);
+    }
+    uris
+}
+
+/// Convert file:// URI to a filesystem path.
+fn uri_to_path(uri: &str) -> PathBuf {
+    match uri.get(..5).filter(|s| s.eq_ignore_ascii_case("file:")) {
+        Some(_) => fsutil::path_from_bytes(&file_uri_path(&uri[5..], cfg!(windows))),
+        None => PathBuf::from(uri),
+    }
+}
+
+/// The path named by the part of a `file:` URI after the scheme, decoded
+/// to bytes; a Windows path if `windows`. A host other than `localhost`
+/// makes it a UNC path (`//server/share/x`). On Windows `/C:/x` is `C:\x`
+/// and `//server/share/x` is `\\server\share\x`.
+fn file_uri_path(rest: &str, windows: bool) -> Vec<u8> {
+    let (host, path) = match rest.strip_prefix("//") {
+        Some(authority) => authority.split_at(authority.find('/').unwrap_or(authority.len())),
+        None => ("", rest),
+    };
+    let mut decoded = percent_decode(path);
+    if !host.is_empty() && !host.eq_ignore_ascii_case("localhost") {
+        decoded.splice(..0, format!("//{host}").into_bytes());
+    } else if windows
+        && let [b'/', drive, b':', ..] = decoded[..]
+        && drive.is_ascii_alphabetic()
+    {
+        decoded.remove(0);
+    }
+    if windows {
+        for byte in &mut decoded {
+            if *byte == b'/' {
+                *byte = b'\\';
+            }
+        }
+    }
+    decoded
+}
+
+/// `file:` URI of `path`, as a backend's workspace root.
+fn path_to_uri(path: &Path) -> String {
+    file_uri(&path.display().to_string(), cfg!(windows))
+}
+
+/// [`path_to_uri`] of a path spelled `path`, a Windows path if `windows`:
+/// `C:\src` is `file:///C:/src`, `\\server\share` is `file://server/share`.
+fn file_uri(path: &str, windows: bool) -> String {
+    if !windows {
+        return format!("file://{path}");
+    }
+    let path = path.replace('\\', "/");
+    match path.strip_prefix("//") {
+        Some(unc) => format!("file://{unc}"),
+        None if path.starts_with('/') => format!("file://{path}"),
+        None => format!("file:///{path}"),
+    }
+}
+
+/// Whether `uri` names a file: `file:` or no scheme at all (a path).
+/// Untitled buffers and virtual documents have other schemes.
+fn is_file_uri(uri: &str) -> bool {
+    let Some((scheme, _)) = uri.split_once(':') else {
+        return true;
+    };
+    // A single letter is a Windows drive, not a scheme.
+    let is_scheme = scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    !is_scheme || scheme.eq_ignore_ascii_case("file")
+}
+
+/// Simple percent-decoding for file URIs, to bytes: the escapes of a
+/// file name need not decode to UTF-8.
+fn percent_decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = bytes.get(i + 1..i + 3)
+            && let Some(byte) = std::str::from_utf8(hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            result.push(byte);
+            i += 3;
+            continue;
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_flight() {
+        let in_flight = InFlight::default();
+        let (a, b) = (Path::new("/a.json"), Path::new("/b.json"));
+        let (tx, _rx) = mpsc::channel();
+        let expiring = in_flight.insert(a, Reply::Internal(tx.clone()), Duration::ZERO);
+        let waiting = in_flight.insert(b, Reply::Internal(tx), Duration::from_secs(60));
+        assert_ne!(expiring, waiting);
+        assert!(in_flight.has(a) && in_flight.has(b));
+
+        // Only the backend a request went to can answer it, by number.
+        assert!(in_flight.take(a, &serde_json::json!(waiting)).is_none());
+        assert!(
+            in_flight
+                .take(b, &serde_json::json!(waiting.to_string()))
+                .is_none()
+        );
+        let expired = in_flight.take_expired(Instant::now());
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, a);
+        assert!(in_flight.take(a, &serde_json::json!(expiring)).is_none());
+        assert!(in_flight.take(b, &serde_json::json!(waiting)).is_some());
+        assert!(in_flight.is_empty());
+    }
+
+    #[test]
+    fn test_in_flight_client_request() {
+        let in_flight = InFlight::default();
+        let (a, b) = (Path::new("/a.json"), Path::new("/b.json"));
+        let client = |id: i64| Reply::Client {
+            id: serde_json::json!(id),
+            method: "textDocument/hover".to_string(),
+            profile: "a".to_string(),
+            started: Instant::now(),
+            trace: TraceLevel::Off,
+            fallback: Box::new(serde_json::Value::Null),
+            range: None,
+            fill: None,
+        };
+        let first = in_flight.insert(a, client(7), Duration::from_secs(60));
+        in_flight.insert(a, Reply::Discard, Duration::from_secs(60));
+        let second = in_flight.insert(b, client(8), Duration::from_secs(60));
+
+        // Found by the editor's id, not the backend's.
+        assert_eq!(
+            in_flight.client_request(&serde_json::json!(8)),
+            Some((b.to_path_buf(), second))
+        );
+        assert_eq!(
+            in_flight.client_request(&serde_json::json!(7)),
+            Some((a.to_path_buf(), first))
+        );
+        assert_eq!(in_flight.client_request(&serde_json::json!("7")), None);
+        assert!(in_flight.take(a, &serde_json::json!(first)).is_some());
+        assert_eq!(in_flight.client_request(&serde_json::json!(7)), None);
+    }
+
+    #[test]
+    fn test_in_flight_salvage() {
+        let in_flight = InFlight::default();
+        let backend = Path::new("/a.json");
+        let insert = |salvage: &str| {
+            let reply = Reply::Client {
+                id: serde_json::json!(1),
+                method: "textDocument/formatting".to_string(),
+                profile: "a".to_string(),
+                started: Instant::now(),
+                trace: TraceLevel::Off,
+                fallback: Box::new(serde_json::Value::Null),
+                range: None,
+                fill: None,
+            };
+            in_flight.insert_formatting(backend, reply, Duration::ZERO, salvage.to_string())
+        };
+        let (id, sent) = insert("k");
+        assert!(!sent);
+        assert_eq!(in_flight.take_expired(Instant::now()).len(), 1);
+
+        // A retry waits for the timed-out request; once that times out too,
+        // its late response is kept for the next retry, once.
+        assert_eq!(insert("k"), (id, true));
+        assert!(!insert("other").1);
+        assert_eq!(in_flight.take_expired(Instant::now()).len(), 2);
+        let response = serde_json::json!({ "id": id, "result": [] });
+        assert!(
+            in_flight
+                .salvage(Path::new("/b.json"), &serde_json::json!(id), &response)
+                .is_none()
+        );
+        assert!(
+            in_flight
+                .salvage(backend, &serde_json::json!(id), &response)
+                .is_some()
+        );
+        assert!(in_flight.take_salvaged("other").is_none());
+        assert_eq!(in_flight.take_salvaged("k"), Some(serde_json::json!([])));
+        assert!(in_flight.take_salvaged("k").is_none());
+    }
+
+    #[test]
+    fn test_salvage_key() {
+        let backend = Path::new("/a.json");
+        let open = serde_json::json!({ "params": { "textDocument": { "version": 3 } } });
+        let msg = serde_json::json!({ "params": { "textDocument": { "uri": "file:///a.ts" } } });
+        let key = salvage_key("textDocument/formatting", backend, Some(&open), &msg);
+        assert!(key.is_some());
+        let changed = serde_json::json!({ "params": { "textDocument": { "version": 4 } } });
+        assert_ne!(
+            key,
+            salvage_key("textDocument/formatting", backend, Some(&changed), &msg)
+        );
+        assert!(salvage_key("textDocument/formatting", backend, None, &msg).is_none());
+        assert!(salvage_key("textDocument/hover", backend, Some(&open), &msg).is_none());
+    }
+
+    #[test]
+    fn test_server_requests() {
+        let requests = ServerRequests::default();
+        requests.insert(1, Path::new("/a.json"), serde_json::json!(0));
+        requests.insert(2, Path::new("/b.json"), serde_json::json!(0));
+        requests.insert(3, Path::new("/b.json"), serde_json::json!("x"));
+        assert_eq!(
+            requests.take(&serde_json::json!(1)),
+            Some((PathBuf::from("/a.json"), serde_json::json!(0)))
+        );
+        assert_eq!(requests.take(&serde_json::json!(1)), None);
+        requests.remove_backend(Path::new("/b.json"));
+        assert_eq!(requests.take(&serde_json::json!(2)), None);
+        assert_eq!(requests.take(&serde_json::json!(3)), None);
+    }
+
+    #[test]
+    fn test_content_key() {
+        let dir = std::env::temp_dir().join(format!("dprintx-test-key-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+        let a = write("a.json", r#"{"extends": ["/p.json"]}"#);
+        let b = write("b.json", r#"{"extends": ["/p.json"]}"#);
+        let c = write("c.json", r#"{"extends": ["/q.json"]}"#);
+        assert_eq!(content_key(&a), content_key(&b));
+        assert_ne!(content_key(&a), content_key(&c));
+        // Unreadable configs are never shared.
+        assert_ne!(content_key(&dir.join("x")), content_key(&dir.join("y")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_log_message() {
+        assert_eq!(
+            log_message("docs", "Compiling plugin"),
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "window/logMessage",
+                "params": { "type": 4, "message": "[docs] Compiling plugin" },
+            })
+        );
+    }
 
-            let msg = backend
-                .responses
-                .recv_timeout(remaining)
-                .context("backend read timeout")?;
+    #[test]
+    fn test_prefix_profile() {
+        let prefixed = |mut msg: serde_json::Value| {
+            prefix_profile(&mut msg, "docs");
+            msg
+        };
+        assert_eq!(
+            prefixed(serde_json::json!({
+                "method": "window/logMessage",
+                "params": { "type": 3, "message": "Loaded plugins" },
+            }))["params"]["message"],
+            "[docs] Loaded plugins"
+        );
+        let begin = serde_json::json!({
+            "method": "$/progress",
+            "params": { "token": 1, "value": { "kind": "begin", "title": "Formatting" } },
+        });
+        assert_eq!(
+            prefixed(begin)["params"]["value"]["title"],
+            "[docs] Formatting"
+        );
+        for unchanged in [
+            serde_json::json!({
+                "method": "$/progress",
+                "params": { "token": 1, "value": { "kind": "report", "message": "50%" } },
+            }),
+            serde_json::json!({
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": "file:///a.ts", "diagnostics": [] },
+            }),
+        ] {
+            assert_eq!(prefixed(unchanged.clone()), unchanged);
+        }
+    }
 
-            // Check if this is a response (has "id") or a notification.
-            let parsed: serde_json::Value = serde_json::from_str(&msg)?;
-            if parsed.get("id").is_some() {
-                // It's a response — return it.
-                return Ok(msg);
-            }
+    #[test]
+    fn test_merge_initialize_results() {
+        let result = |capabilities: serde_json::Value| serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": { "capabilities": capabilities } });
+        assert_eq!(merge_initialize_results(Vec::new()), None);
+        let merged = merge_initialize_results(vec![
+            result(serde_json::json!({
+                "documentFormattingProvider": true,
+                "documentRangeFormattingProvider": false,
+            })),
+            result(serde_json::json!({
+                "documentFormattingProvider": { "workDoneProgress": true },
+                "documentRangeFormattingProvider": true,
+                "documentOnTypeFormattingProvider": { "firstTriggerCharacter": "}" },
+            })),
+            result(serde_json::json!({
+                "documentFormattingProvider": true,
+                "documentOnTypeFormattingProvider": {
+                    "firstTriggerCharacter": ";",
+                    "moreTriggerCharacter": ["}"],
+                },
+            })),
+        ]);
+        // The first backend's answer, with what only later ones have and
+        // every trigger character.
+        assert_eq!(
+            merged,
+            Some(result(serde_json::json!({
+                "documentFormattingProvider": true,
+                "documentRangeFormattingProvider": true,
+                "documentOnTypeFormattingProvider": {
+                    "firstTriggerCharacter": "}",
+                    "moreTriggerCharacter": [";"],
+                },
+            })))
+        );
+    }
 
-            // It's a notification — forward to editor and keep waiting.
-            let _ = write_lsp_message(stdout, &msg);
-        }
+    #[test]
+    fn test_formats_on_type() {
+        let capabilities = serde_json::json!({
+            "documentOnTypeFormattingProvider": {
+                "firstTriggerCharacter": "}",
+                "moreTriggerCharacter": [";", "\n"],
+            },
+        });
+        assert!(formats_on_type(&capabilities, "}"));
+        assert!(formats_on_type(&capabilities, "\n"));
+        assert!(!formats_on_type(&capabilities, ")"));
+        assert!(!formats_on_type(
+            &serde_json::json!({ "documentFormattingProvider": true }),
+            "}"
+        ));
     }
-}
 
-/// Read an LSP message from a buffered reader.
-/// Format: "Content-Length: N\r\n\r\n" followed by N bytes.
-fn read_lsp_message<R: BufRead>(reader: &mut R) -> Result<String> {
-    let mut content_length: Option<usize> = None;
+    #[test]
+    fn test_extend_capabilities() {
+        let mut capabilities = serde_json::json!({
+            "documentFormattingProvider": true,
+            "textDocumentSync": 2,
+        });
+        extend_capabilities(&mut capabilities, false, false);
+        assert_eq!(
+            capabilities,
+            serde_json::json!({
+                "documentFormattingProvider": true,
+                "documentRangeFormattingProvider": true,
+                "textDocumentSync": { "openClose": true, "change": 2, "willSaveWaitUntil": true },
+                "workspace": { "workspaceFolders": { "supported": true, "changeNotifications": true } },
+            })
+        );
 
-    // Read headers.
-    loop {
-        let mut line = String::new();
-        let bytes_read = reader.read_line(&mut line)?;
-        if bytes_read == 0 {
-            bail!("EOF while reading LSP headers");
-        }
+        let mut capabilities = serde_json::json!({
+            "documentFormattingProvider": {},
+            "textDocumentSync": { "openClose": true, "change": 1, "save": true },
+            "workspace": { "fileOperations": {} },
+        });
+        extend_capabilities(&mut capabilities, false, false);
+        assert_eq!(
+            capabilities["textDocumentSync"],
+            serde_json::json!({ "openClose": true, "change": 1, "save": true, "willSaveWaitUntil": true })
+        );
+        assert_eq!(
+            capabilities["workspace"]["fileOperations"],
+            serde_json::json!({})
+        );
+        assert_eq!(
+            capabilities["workspace"]["workspaceFolders"]["supported"],
+            true
+        );
 
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            break; // End of headers.
-        }
+        // Nothing to fall back to without formatting.
+        let mut capabilities = serde_json::json!({ "hoverProvider": true });
+        extend_capabilities(&mut capabilities, true, false);
+        assert_eq!(capabilities, serde_json::json!({ "hoverProvider": true }));
 
-        if let Some(rest) = trimmed.strip_prefix("Content-Length:") {
-            content_length = Some(rest.trim().parse().context("invalid Content-Length")?);
-        }
+        // Diagnostics come with their fix, unless the backend has code actions.
+        let mut capabilities = serde_json::json!({ "documentFormattingProvider": true });
+        extend_capabilities(&mut capabilities, true, false);
+        assert_eq!(
+            capabilities["codeActionProvider"],
+            serde_json::json!({ "codeActionKinds": ["quickfix"] })
+        );
+        let mut capabilities = serde_json::json!({
+            "documentFormattingProvider": true,
+            "codeActionProvider": true,
+        });
+        extend_capabilities(&mut capabilities, true, false);
+        assert_eq!(capabilities["codeActionProvider"], true);
+
+        // The config commands join the backend's commands.
+        let mut capabilities = serde_json::json!({
+            "documentFormattingProvider": true,
+            "executeCommandProvider": { "commands": ["dprint.fix"] },
+        });
+        extend_capabilities(&mut capabilities, false, true);
+        assert_eq!(
+            capabilities["executeCommandProvider"]["commands"],
+            serde_json::json!(["dprint.fix", IGNORE_FILE_COMMAND, RELOAD_CONFIG_COMMAND])
+        );
+        assert!(capabilities["codeActionProvider"].is_object());
     }
 
-    let length = content_length.context("missing Content-Length header")?;
+    #[test]
+    fn test_code_actions() {
+        let fix = Fix {
+            text: "let a=1\n".to_string(),
+            edits: text_edits("let a=1\n", "let a = 1;\n"),
+            diagnostic: serde_json::json!({ "code": "unformatted" }),
+            profile: "main".to_string(),
+        };
+        let params = serde_json::json!({
+            "textDocument": { "uri": "file:///a.ts" },
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } },
+            "context": { "diagnostics": [] },
+        });
+        let actions = code_actions(&params, Some(&fix), Some("let a=1\n"), false);
+        assert_eq!(actions[0]["title"], "Format with dprintx (profile main)");
+        assert_eq!(actions[0]["diagnostics"][0]["code"], "unformatted");
+        assert_eq!(
+            actions[0]["edit"]["changes"]["file:///a.ts"][0]["newText"],
+            "let a = 1;\n"
+        );
 
-    // Read body.
-    let mut body = vec![0u8; length];
-    reader.read_exact(&mut body)?;
+        // Edited since the check, not asked for, or formatted: no fix.
+        let none = serde_json::json!([]);
+        assert_eq!(
+            code_actions(&params, Some(&fix), Some("let a=2\n"), false),
+            none
+        );
+        let mut refactor = params.clone();
+        refactor["context"]["only"] = serde_json::json!(["refactor"]);
+        assert_eq!(
+            code_actions(&refactor, Some(&fix), Some("let a=1\n"), false),
+            none
+        );
+        assert_eq!(code_actions(&params, None, Some("let a=1\n"), false), none);
 
-    String::from_utf8(body).context("invalid UTF-8 in LSP message body")
-}
+        // Ignoring the file is offered next to the fix.
+        let actions = code_actions(&params, Some(&fix), Some("let a=1\n"), true);
+        assert_eq!(actions.as_array().unwrap().len(), 2);
+        assert_eq!(
+            actions[1]["command"],
+            serde_json::json!({
+                "title": "Ignore this file in dprintx",
+                "command": IGNORE_FILE_COMMAND,
+                "arguments": ["file:///a.ts"],
+            })
+        );
+        assert_eq!(code_actions(&refactor, None, None, true), none);
+    }
 
-/// Write an LSP message to stdout.
-fn write_lsp_message(stdout: &Arc<Mutex<io::Stdout>>, body: &str) -> Result<()> {
-    let mut out = stdout.lock().unwrap();
-    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
-    out.flush()?;
-    Ok(())
-}
+    #[test]
+    fn test_slice_edits() {
+        let text = "a\n  b\nc\n   d\n";
+        let whole = serde_json::json!([{
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 4, "character": 0 } },
+            "newText": "a\nb\nc\nd\n",
+        }]);
+        let range = |start: u64, end: u64, character: u64| {
+            serde_json::json!({
+                "start": { "line": start, "character": 0 },
+                "end": { "line": end, "character": character },
+            })
+        };
 
-/// Extract file URI from LSP params.
-/// Looks for params.textDocument.uri.
-fn extract_uri(msg: &serde_json::Value) -> Option<String> {
-    msg.get("params")?
-        .get("textDocument")?
-        .get("uri")?
-        .as_str()
-        .map(|s| s.to_string())
-}
+        // Only the changed line inside the range.
+        assert_eq!(
+            slice_edits(text, &whole, &range(0, 2, 0)).unwrap(),
+            serde_json::json!([{
+                "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 2, "character": 0 } },
+                "newText": "b\n",
+            }])
+        );
+        // Both changes.
+        assert_eq!(
+            slice_edits(text, &whole, &range(1, 3, 4))
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        // Nothing changed in the range; null results pass through.
+        assert_eq!(
+            slice_edits(text, &whole, &range(2, 2, 1)).unwrap(),
+            serde_json::json!([])
+        );
+        assert_eq!(
+            slice_edits(text, &serde_json::Value::Null, &range(0, 1, 0)).unwrap(),
+            serde_json::Value::Null
+        );
 
-/// Convert file:// URI to a filesystem path.
-fn uri_to_path(uri: &str) -> PathBuf {
-    if let Some(path) = uri.strip_prefix("file://") {
-        // URL-decode percent-encoded characters.
-        let decoded = percent_decode(path);
-        PathBuf::from(decoded)
-    } else {
-        PathBuf::from(uri)
+        // A last line without a newline ends at its last character.
+        let whole = serde_json::json!([{
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 1, "character": 4 } },
+            "newText": "a\nb",
+        }]);
+        assert_eq!(
+            slice_edits("a\n  b", &whole, &range(1, 1, 3)).unwrap(),
+            serde_json::json!([{
+                "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 1, "character": 3 } },
+                "newText": "b",
+            }])
+        );
     }
-}
 
-/// Simple percent-decoding for file URIs.
-fn percent_decode(input: &str) -> String {
-    let mut result = String::with_capacity(input.len());
-    let bytes = input.as_bytes();
-    let mut i = 0;
-    while i < bytes.len() {
-        if bytes[i] == b'%'
-            && i + 2 < bytes.len()
-            && let Ok(byte) = u8::from_str_radix(&input[i + 1..i + 3], 16)
-        {
-            result.push(byte as char);
-            i += 3;
-            continue;
-        }
-        result.push(bytes[i] as char);
-        i += 1;
+    #[test]
+    fn test_text_edits() {
+        let text = "a\n  b\nc\n   d";
+        let formatted = "a\nb\nc\nd\n";
+        let edits = text_edits(text, formatted);
+        assert_eq!(
+            edits,
+            serde_json::json!([
+                {
+                    "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 2, "character": 0 } },
+                    "newText": "b\n",
+                },
+                {
+                    "range": { "start": { "line": 3, "character": 0 }, "end": { "line": 3, "character": 4 } },
+                    "newText": "d\n",
+                },
+            ])
+        );
+        assert_eq!(
+            apply_edits(text, edits.as_array().unwrap()).as_deref(),
+            Some(formatted)
+        );
+        assert_eq!(text_edits(formatted, formatted), serde_json::json!([]));
     }
-    result
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_line_hunks() {
+        assert_eq!(line_hunks(&["a", "b"], &["a", "b"]), vec![]);
+        assert_eq!(
+            line_hunks(&["a", "x", "b", "y", "c"], &["a", "b", "z", "c"]),
+            vec![(1, 2, 1, 1), (3, 4, 2, 3)]
+        );
+        assert_eq!(line_hunks(&["a"], &["a", "b"]), vec![(1, 1, 1, 2)]);
+    }
+
+    #[test]
+    fn test_track_change() {
+        let mut open = serde_json::json!({
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///a.ts", "version": 1, "text": "let a;\r\nlet 😀b;\n" } },
+        });
+        let change = |version: u64, changes: serde_json::Value| {
+            serde_json::json!({
+                "params": { "textDocument": { "version": version }, "contentChanges": changes },
+            })
+        };
+        let range = |l1: u64, c1: u64, l2: u64, c2: u64| serde_json::json!({ "start": { "line": l1, "character": c1 }, "end": { "line": l2, "character": c2 } });
+
+        // The emoji counts two UTF-16 units; a past-the-end position clamps.
+        let edits = serde_json::json!([
+            { "range": range(1, 6, 1, 7), "text": "c" },
+            { "range": range(0, 4, 0, 99), "text": "x = 1;" },
+        ]);
+        assert!(track_change(&mut open, &change(2, edits)));
+        assert_eq!(
+            open["params"]["textDocument"]["text"],
+            "let x = 1;\r\nlet 😀c;\n"
+        );
+        assert_eq!(open["params"]["textDocument"]["version"], 2);
+
+        let full = serde_json::json!([{ "text": "new\n" }]);
+        assert!(track_change(&mut open, &change(3, full)));
+        assert_eq!(open["params"]["textDocument"]["text"], "new\n");
+
+        let backwards = serde_json::json!([{ "range": range(0, 2, 0, 1), "text": "" }]);
+        assert!(!track_change(&mut open, &change(4, backwards)));
+    }
 
     #[test]
     fn test_uri_to_path() {
@@ -633,6 +4552,49 @@ mod tests {
             uri_to_path("file:///home/user/my%20file.go"),
             PathBuf::from("/home/user/my file.go")
         );
+        assert_eq!(
+            uri_to_path("file:///home/user/caf%C3%A9.md"),
+            PathBuf::from("/home/user/café.md")
+        );
+        assert_eq!(
+            uri_to_path("file:///home/user/caf%E9.md"),
+            fsutil::path_from_bytes(b"/home/user/caf\xe9.md")
+        );
+        assert_eq!(
+            uri_to_path("file://localhost/home/user/file.go"),
+            PathBuf::from("/home/user/file.go")
+        );
+        assert_eq!(
+            uri_to_path("file://server/share/file.go"),
+            PathBuf::from("//server/share/file.go")
+        );
+    }
+
+    #[test]
+    fn test_windows_file_uris() {
+        let path = |rest: &str| String::from_utf8(file_uri_path(rest, true)).unwrap();
+        assert_eq!(path("///C:/Users/me/a.ts"), r"C:\Users\me\a.ts");
+        assert_eq!(path("///c%3A/Users/my%20file.ts"), r"c:\Users\my file.ts");
+        assert_eq!(path("//server/share/a.ts"), r"\\server\share\a.ts");
+        assert_eq!(path("//localhost/D:/a.ts"), r"D:\a.ts");
+
+        assert_eq!(file_uri(r"C:\Users\me", true), "file:///C:/Users/me");
+        assert_eq!(
+            file_uri(r"\\server\share\src", true),
+            "file://server/share/src"
+        );
+        assert_eq!(file_uri("/home/me", false), "file:///home/me");
+    }
+
+    #[test]
+    fn test_is_file_uri() {
+        assert!(is_file_uri("file:///home/user/file.go"));
+        assert!(is_file_uri("FILE:///home/user/file.go"));
+        assert!(is_file_uri("/home/user/file.go"));
+        assert!(is_file_uri("C:/Users/file.go"));
+        assert!(!is_file_uri("untitled:Untitled-1"));
+        assert!(!is_file_uri("vscode-notebook-cell:/nb.ipynb#W0"));
+        assert!(!is_file_uri("git+ssh://host/repo/file.go"));
     }
 
     #[test]
@@ -651,6 +4613,229 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_command_uris() {
+        let msg = serde_json::json!({
+            "method": "workspace/executeCommand",
+            "params": {
+                "command": "plugin.fix",
+                "arguments": [
+                    "file:///a.ts",
+                    { "textDocument": { "uri": "untitled:1" }, "n": 3 },
+                ]
+            }
+        });
+        assert_eq!(command_uris(&msg), vec!["file:///a.ts", "untitled:1"]);
+
+        let msg = serde_json::json!({ "params": { "command": "plugin.reload" } });
+        assert!(command_uris(&msg).is_empty());
+    }
+
+    #[test]
+    fn test_take_dprintx_settings() {
+        let mut msg = serde_json::json!({
+            "method": "workspace/didChangeConfiguration",
+            "params": { "settings": {
+                "dprintx": { "lsp_timeout_ms": 5000 },
+                "dprint": { "verbose": true },
+            }}
+        });
+        assert_eq!(
+            take_dprintx_settings(&mut msg),
+            Some(serde_json::json!({ "lsp_timeout_ms": 5000 }))
+        );
+        assert_eq!(
+            msg["params"]["settings"],
+            serde_json::json!({ "dprint": { "verbose": true } })
+        );
+        assert_eq!(take_dprintx_settings(&mut msg), None);
+    }
+
+    #[test]
+    fn test_apply_client_settings() {
+        let config = DprintxConfig::parse(
+            r#"{ "dprint": "dprint", "profiles": { "main": "/p/main.json" }, "match": { "**": "main" } }"#,
+        )
+        .unwrap();
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+        let mut proxy = LspProxy::new(PathBuf::from("dprint"), matcher, config);
+
+        proxy
+            .apply_client_settings(&serde_json::json!({
+                "lsp_rewrite_uris": true,
+                "lsp_diagnostics": true,
+                "lsp_honor_editor_options": true,
+                "lsp_timeout_ms": 2500,
+                "profiles": { "main": "/p/other.json" },
+            }))
+            .unwrap();
+        assert!(proxy.config.lsp_rewrite_uris);
+        assert!(proxy.config.lsp_diagnostics);
+        assert!(proxy.config.lsp_honor_editor_options);
+        assert_eq!(proxy.read_timeout, Duration::from_millis(2500));
+        assert_eq!(
+            proxy.config.resolve_profile("main"),
+            Some(ProfileResolution::Config(PathBuf::from("/p/other.json")))
+        );
+
+        // Invalid sections change nothing.
+        assert!(
+            proxy
+                .apply_client_settings(&serde_json::json!({ "lsp_timeout_ms": 1, "bogus": 1 }))
+                .is_err()
+        );
+        assert_eq!(proxy.read_timeout, Duration::from_millis(2500));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_config_changed_reload() {
+        let parse = |content: &str| DprintxConfig::parse(content).unwrap();
+        let config = parse(
+            r#"{ "dprint": "dprint", "profiles": { "a": "/p/a.json", "b": "/p/b.json" }, "match": { "**": "a" } }"#,
+        );
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+        let next = Arc::new(Mutex::new(String::new()));
+        let source = Arc::clone(&next);
+        let config_path = PathBuf::from("/p/dprintx.jsonc");
+        let mut proxy = LspProxy::new(PathBuf::from("dprint"), matcher, config).with_config_reload(
+            config_path.clone(),
+            Box::new(move || DprintxConfig::parse(&source.lock().unwrap())),
+        );
+        proxy
+            .apply_client_settings(&serde_json::json!({ "lsp_timeout_ms": 2500 }))
+            .unwrap();
+        proxy
+            .client_settings
+            .push(serde_json::json!({ "lsp_timeout_ms": 2500 }));
+
+        let watcher = ConfigWatcher::new().unwrap();
+        let backends = Arc::new(Mutex::new(HashMap::new()));
+        let mut merges: Merges = HashMap::new();
+        let mut guards = HashMap::new();
+        let mut unhealthy = HashMap::new();
+        unhealthy.insert(PathBuf::from("/p/b.json"), "broken".to_string());
+        let mut changed = |proxy: &mut LspProxy| {
+            proxy.config_changed(
+                std::slice::from_ref(&config_path),
+                Some(&watcher),
+                &backends,
+                &mut merges,
+                &mut guards,
+                &mut unhealthy,
+            )
+        };
+
+        // Profile b is dropped, and everything routes to a new profile c;
+        // the editor's timeout still applies.
+        *next.lock().unwrap() = r#"{ "dprint": "dprint", "profiles": { "a": "/p/a.json", "c": "/p/c.json" }, "match": { "**": "c" } }"#.to_string();
+        changed(&mut proxy).unwrap();
+        assert_eq!(
+            proxy
+                .matcher
+                .resolve_config(Path::new("/x/a.ts"), &proxy.config)
+                .unwrap(),
+            Some(ProfileResolution::Config(PathBuf::from("/p/c.json")))
+        );
+        assert_eq!(proxy.read_timeout, Duration::from_millis(2500));
+
+        // A broken config is not applied.
+        *next.lock().unwrap() = "{ broken".to_string();
+        assert!(changed(&mut proxy).is_err());
+        assert_eq!(
+            proxy
+                .matcher
+                .resolve_config(Path::new("/x/a.ts"), &proxy.config)
+                .unwrap(),
+            Some(ProfileResolution::Config(PathBuf::from("/p/c.json")))
+        );
+        assert!(unhealthy.is_empty());
+    }
+
+    #[test]
+    fn test_effective_config_cache() {
+        let dir = std::env::temp_dir().join(format!("dprintx-test-merges-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        let profile = dir.join("profile.json");
+        std::fs::write(&profile, "{}").unwrap();
+        let write_local = |d: &Path, content: &str, age: u64| {
+            let local = d.join("dprint.json");
+            std::fs::write(&local, content).unwrap();
+            let mtime = std::time::SystemTime::now() - Duration::from_secs(age);
+            std::fs::File::options()
+                .write(true)
+                .open(&local)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        };
+        write_local(&a, r#"{"lineWidth": 80}"#, 60);
+        write_local(&b, r#"{"lineWidth": 80}"#, 60);
+
+        let config = DprintxConfig::parse(
+            r#"{ "dprint": "dprint", "profiles": { "main": "/p/main.json" }, "match": { "**": "main" } }"#,
+        )
+        .unwrap();
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+        let proxy = LspProxy::new(PathBuf::from("dprint"), matcher, config);
+        let backends = Arc::new(Mutex::new(HashMap::new()));
+        let mut merges: Merges = HashMap::new();
+        let mut guards = HashMap::new();
+        let effective = |d: &Path, merges: &mut Merges, guards: &mut _| {
+            proxy
+                .effective_config(d, &profile, merges, guards, &backends)
+                .unwrap()
+                .unwrap()
+        };
+
+        // Reused while unchanged, and shared by identical merges.
+        let first = effective(&a, &mut merges, &mut guards);
+        assert_eq!(effective(&a, &mut merges, &mut guards), first);
+        assert_eq!(effective(&b, &mut merges, &mut guards), first);
+        assert_eq!(guards.len(), 1);
+
+        // An edit re-merges; the old config stays while b routes to it.
+        write_local(&a, r#"{"lineWidth": 100}"#, 0);
+        let second = effective(&a, &mut merges, &mut guards);
+        assert_ne!(second, first);
+        assert_eq!(guards.len(), 2);
+
+        // Once nothing routes to it, it is deleted.
+        write_local(&b, r#"{"lineWidth": 100}"#, 0);
+        assert_eq!(effective(&b, &mut merges, &mut guards), second);
+        assert_eq!(guards.len(), 1);
+        assert!(!first.exists());
+
+        drop(guards);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_lsp_message_limits() {
+        let input = concat!(
+            "Content-Length: 12\r\n\r\n{\"id\":\"big\"}",
+            "Content-Length: 2\r\nContent-Type: application/vscode-jsonrpc; charset=latin1\r\n\r\n{}",
+            "Content-Length: 2\r\nContent-Type: application/vscode-jsonrpc; charset=utf8\r\n\r\n{}",
+        );
+        let mut reader = BufReader::new(input.as_bytes());
+
+        let err = read_lsp_message(&mut reader, 10).unwrap_err();
+        assert!(err.is::<RejectedMessage>());
+        assert!(err.to_string().contains("12 bytes"));
+        let err = read_lsp_message(&mut reader, 10).unwrap_err();
+        assert!(err.to_string().contains("latin1"));
+        // The stream stays in sync after rejected messages.
+        assert_eq!(read_lsp_message(&mut reader, 10).unwrap(), "{}");
+        assert!(
+            !read_lsp_message(&mut reader, 10)
+                .unwrap_err()
+                .is::<RejectedMessage>()
+        );
+    }
+
     #[test]
     fn test_extract_uri_missing() {
         let msg = serde_json::json!({
@@ -705,6 +4890,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_trace_level_parse() {
+        assert_eq!(TraceLevel::parse(Some("off")), TraceLevel::Off);
+        assert_eq!(TraceLevel::parse(Some("messages")), TraceLevel::Messages);
+        assert_eq!(TraceLevel::parse(Some("verbose")), TraceLevel::Verbose);
+        assert_eq!(TraceLevel::parse(None), TraceLevel::Off);
+    }
+
+    #[test]
+    fn test_error_response() {
+        let resp = error_response(serde_json::json!(7), REQUEST_FAILED, "boom".into());
+        assert_eq!(resp["id"], 7);
+        assert_eq!(resp["error"]["code"], -32803);
+        assert_eq!(resp["error"]["message"], "boom");
+        assert!(resp.get("result").is_none());
+    }
+
+    #[test]
+    fn test_language_disagrees() {
+        assert!(!language_disagrees(Path::new("/a/main.go"), "go"));
+        assert!(language_disagrees(Path::new("/a/script"), "sh"));
+        assert!(language_disagrees(Path::new("/a/site.yml"), "yaml.ansible"));
+    }
+
     #[test]
     fn test_apply_uri_rewrite() {
         let mut uri_languages = HashMap::new();
@@ -764,4 +4973,146 @@ mod tests {
         // Already correct extension — no rewrite.
         assert_eq!(msg, original);
     }
+
+    #[test]
+    fn test_restore_uri() {
+        let mut rewritten = HashMap::new();
+        rewritten.insert(
+            "file:///home/user/myscript.sh".to_string(),
+            "file:///home/user/myscript".to_string(),
+        );
+
+        let mut msg = serde_json::json!({
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": "file:///home/user/myscript.sh", "diagnostics": [] },
+        });
+        restore_uri(&mut msg, &rewritten);
+        assert_eq!(msg["params"]["uri"], "file:///home/user/myscript");
+
+        let mut msg = serde_json::json!({
+            "method": "window/showDocument",
+            "params": { "uri": "file:///home/user/other.sh" },
+        });
+        let original = msg.clone();
+        restore_uri(&mut msg, &rewritten);
+        assert_eq!(msg, original);
+    }
+
+    #[test]
+    fn test_single_file_mode_detection() {
+        assert!(is_single_file_mode(&serde_json::json!({})));
+        assert!(is_single_file_mode(&serde_json::json!({
+            "rootUri": null,
+            "workspaceFolders": []
+        })));
+        assert!(!is_single_file_mode(&serde_json::json!({
+            "rootUri": "file:///home/user/project"
+        })));
+        assert!(!is_single_file_mode(&serde_json::json!({
+            "workspaceFolders": [{ "uri": "file:///home/user/project", "name": "project" }]
+        })));
+    }
+
+    #[test]
+    fn test_backend_init_params_root() {
+        let config = Path::new("/config/profiles/default.jsonc");
+        let doc_dir = Path::new("/home/user/notes");
+        let policy = LspRoot::ConfigDir;
+
+        let workspace = serde_json::json!({ "rootUri": "file:///home/user/project" });
+        let params = backend_init_params(&workspace, policy, config, Some(doc_dir), None);
+        assert_eq!(params["rootUri"], "file:///config/profiles");
+
+        let single = serde_json::json!({ "processId": 1 });
+        let params = backend_init_params(&single, policy, config, Some(doc_dir), None);
+        assert_eq!(params["rootUri"], "file:///home/user/notes");
+        assert_eq!(params["rootPath"], "/home/user/notes");
+
+        // No document yet: client params pass through.
+        assert_eq!(
+            backend_init_params(&single, policy, config, None, None),
+            single
+        );
+    }
+
+    #[test]
+    fn test_backend_init_params_policy() {
+        let config = Path::new("/config/profiles/default.jsonc");
+        let doc_dir = Path::new("/home/user/project/sub");
+        let workspace = serde_json::json!({ "rootUri": "file:///home/user/project" });
+
+        let params = backend_init_params(&workspace, LspRoot::Client, config, Some(doc_dir), None);
+        assert_eq!(params, workspace);
+
+        let params = backend_init_params(&workspace, LspRoot::FileDir, config, Some(doc_dir), None);
+        assert_eq!(params["rootUri"], "file:///home/user/project/sub");
+        let params = backend_init_params(&workspace, LspRoot::FileDir, config, None, None);
+        assert_eq!(params, workspace);
+
+        // A document in another workspace folder: that folder is its root.
+        let folder = serde_json::json!({ "uri": "file:///home/user/other%20lib", "name": "lib" });
+        let params = backend_init_params(
+            &workspace,
+            LspRoot::Client,
+            config,
+            Some(doc_dir),
+            Some(&folder),
+        );
+        assert_eq!(params["rootUri"], "file:///home/user/other%20lib");
+        assert_eq!(params["rootPath"], "/home/user/other lib");
+        let params = backend_init_params(
+            &workspace,
+            LspRoot::ConfigDir,
+            config,
+            Some(doc_dir),
+            Some(&folder),
+        );
+        assert_eq!(params["rootUri"], "file:///config/profiles");
+    }
+
+    #[test]
+    fn test_workspace_folders() {
+        let params = serde_json::json!({ "rootUri": "file:///home/user/app" });
+        let mut folders = workspace_folders(&params);
+        assert_eq!(
+            folders,
+            vec![serde_json::json!({ "uri": "file:///home/user/app", "name": "app" })]
+        );
+        assert!(workspace_folders(&serde_json::json!({ "rootUri": null })).is_empty());
+
+        let lib = serde_json::json!({ "uri": "file:///home/user/app/lib", "name": "lib" });
+        let docs = serde_json::json!({ "uri": "file:///srv/docs", "name": "docs" });
+        change_workspace_folders(
+            &mut folders,
+            &serde_json::json!({ "added": [lib, docs, lib], "removed": [] }),
+        );
+        assert_eq!(folders.len(), 3);
+
+        // The innermost folder holds a file.
+        let holder = |folders: &[serde_json::Value], file: &str| {
+            containing_folder(folders, Path::new(file)).map(|f| f["name"].clone())
+        };
+        assert_eq!(
+            holder(&folders, "/home/user/app/lib/a.ts"),
+            Some(serde_json::json!("lib"))
+        );
+        assert_eq!(
+            holder(&folders, "/home/user/app/main.ts"),
+            Some(serde_json::json!("app"))
+        );
+        assert_eq!(holder(&folders, "/home/user/application/main.ts"), None);
+
+        change_workspace_folders(
+            &mut folders,
+            &serde_json::json!({ "added": [], "removed": [{ "uri": "file:///home/user/app/lib", "name": "lib" }] }),
+        );
+        assert_eq!(
+            holder(&folders, "/home/user/app/lib/a.ts"),
+            Some(serde_json::json!("app"))
+        );
+        assert_eq!(
+            workspace_folders(&serde_json::json!({ "workspaceFolders": folders })),
+            folders
+        );
+    }
 }
@@ -0,0 +1,177 @@
+//! Structured log of the LSP proxy's routing (`--lsp-log`, `lsp_log`): one
+//! JSON object per line, with a UTC timestamp, for every routed message and
+//! every answered request. Editors tend to hide a server's stderr; this file
+//! can be tailed instead.
+
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::warnings;
+
+/// The log of one proxy, shared by its threads.
+#[derive(Default)]
+pub struct LspLog {
+    /// The open file and its path. None while not logging.
+    file: Mutex<Option<(PathBuf, File)>>,
+}
+
+/// A message routed to a backend (`backend`, the effective config), or not
+/// routed when `profile` is None. `rule` is the deciding rule, labeled as
+/// `--rule-stats` does.
+pub struct Route<'a> {
+    pub method: &'a str,
+    pub id: Option<&'a serde_json::Value>,
+    pub uri: &'a str,
+    pub rule: Option<&'a str>,
+    pub profile: Option<&'a str>,
+    pub backend: Option<&'a Path>,
+    pub merged: bool,
+}
+
+impl LspLog {
+    /// Log to `path` from now on, appending; None stops logging. Failing to
+    /// open the file is only a warning.
+    pub fn open(&self, path: Option<&Path>) {
+        let mut log = self.file.lock().unwrap();
+        if log.as_ref().map(|(open, _)| open.as_path()) == path {
+            return;
+        }
+        *log = path.and_then(|path| {
+            let file = File::options().create(true).append(true).open(path);
+            match file {
+                Ok(file) => Some((path.to_path_buf(), file)),
+                Err(e) => {
+                    warnings::warn("lsp-log", format!("opening {}: {e}", path.display()));
+                    None
+                }
+            }
+        });
+    }
+
+    /// Log a routing decision.
+    pub fn route(&self, route: &Route) {
+        self.write(serde_json::json!({
+            "event": "route",
+            "method": route.method,
+            "id": route.id,
+            "uri": route.uri,
+            "rule": route.rule,
+            "profile": route.profile,
+            "backend": route.backend,
+            "merged": route.merged,
+        }));
+    }
+
+    /// The answer of `backend` to request `id`, after `latency`; None if it
+    /// missed the deadline.
+    pub fn response(
+        &self,
+        id: &serde_json::Value,
+        method: &str,
+        profile: &str,
+        backend: &Path,
+        latency: Option<Duration>,
+    ) {
+        self.write(serde_json::json!({
+            "event": "response",
+            "method": method,
+            "id": id,
+            "profile": profile,
+            "backend": backend,
+            "latency_ms": latency.map(|l| l.as_secs_f64() * 1000.0),
+            "timed_out": latency.is_none(),
+        }));
+    }
+
+    /// A `line` the backend of `profile` wrote to its stderr. False if not
+    /// logging.
+    pub fn stderr(&self, profile: &str, backend: &Path, line: &str) -> bool {
+        self.write(serde_json::json!({
+            "event": "stderr",
+            "profile": profile,
+            "backend": backend,
+            "line": line,
+        }))
+    }
+
+    /// Append `record` with a `time` field, if logging; false if not. A
+    /// failed write is a warning and stops logging.
+    fn write(&self, mut record: serde_json::Value) -> bool {
+        let mut log = self.file.lock().unwrap();
+        let Some((path, file)) = log.as_mut() else {
+            return false;
+        };
+        record["time"] = timestamp(SystemTime::now()).into();
+        if let Err(e) = writeln!(file, "{record}") {
+            warnings::warn("lsp-log", format!("writing {}: {e}", path.display()));
+            *log = None;
+        }
+        true
+    }
+}
+
+/// RFC 3339 UTC time with milliseconds, e.g. `2024-03-01T12:00:00.250Z`.
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, day_secs) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01, in 400-year eras starting in
+    // March (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        day_secs / 3600,
+        day_secs / 60 % 60,
+        day_secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stderr() {
+        let path = std::env::temp_dir().join(format!("dprintx-test-lsplog-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = LspLog::default();
+        assert!(!log.stderr("a", Path::new("/pa.json"), "loading"));
+
+        log.open(Some(&path));
+        assert!(log.stderr("a", Path::new("/pa.json"), "loading"));
+        let record: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(record["event"], "stderr");
+        assert_eq!(record["profile"], "a");
+        assert_eq!(record["backend"], "/pa.json");
+        assert_eq!(record["line"], "loading");
+
+        log.open(None);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let at = |secs: u64, millis: u64| {
+            timestamp(SystemTime::UNIX_EPOCH + Duration::from_millis(secs * 1000 + millis))
+        };
+        assert_eq!(at(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(at(951_782_400, 5), "2000-02-29T00:00:00.005Z");
+        assert_eq!(at(1_709_294_400, 250), "2024-03-01T12:00:00.250Z");
+        assert_eq!(at(1_735_689_599, 999), "2024-12-31T23:59:59.999Z");
+    }
+}
@@ -1,32 +1,86 @@
-mod cli;
-mod config;
-mod lsp;
-mod matcher;
-mod runner;
-
 use anyhow::{Context, Result};
-use std::path::Path;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 
-use cli::{Cli, CliCommand};
-use config::{DprintxConfig, ProfileResolution};
-use matcher::ProfileMatcher;
-use runner::DprintRunner;
+use dprintx::cli::{Cli, CliCommand, ConfigCommand};
+use dprintx::config::{self, DprintxConfig, ProfileResolution};
+use dprintx::error::{self, SpawnContext};
+use dprintx::matcher::ProfileMatcher;
+use dprintx::repro::Repro;
+use dprintx::runner::{DprintRunner, PARITY_SAMPLE};
+use dprintx::{daemon, disabled, edit, fsutil, git, http, lsp, recent, rulestats, trust, warnings};
 
 /// Split arguments into plain files and directories.
-fn split_files_and_dirs(args: &[String]) -> (Vec<String>, Vec<String>) {
+fn split_files_and_dirs(args: &[OsString]) -> (Vec<PathBuf>, Vec<PathBuf>) {
     let mut files = Vec::new();
     let mut dirs = Vec::new();
-    for arg in args {
-        if Path::new(arg).is_dir() {
-            dirs.push(arg.clone());
+    for arg in args.iter().map(PathBuf::from) {
+        if arg.is_dir() {
+            dirs.push(arg);
         } else {
-            files.push(arg.clone());
+            files.push(arg);
         }
     }
     (files, dirs)
 }
 
-fn main() -> Result<()> {
+/// Split arguments into files and directories. With `follow_symlinks`,
+/// dprintx expands directories itself (see [`fsutil::walk_files`]) and
+/// returns no directories; otherwise dprint enumerates them.
+fn expand_dirs(args: &[OsString], follow_symlinks: bool) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let (mut files, dirs) = split_files_and_dirs(args);
+    if !follow_symlinks {
+        return Ok((files, dirs));
+    }
+    for dir in &dirs {
+        files.extend(fsutil::walk_files(dir, true)?);
+    }
+    Ok((files, Vec::new()))
+}
+
+/// Reject file arguments next to a git-selected file list (`flag`).
+fn no_file_args(flag: &str, stdin: bool, files: &[OsString]) -> Result<()> {
+    if stdin || !files.is_empty() {
+        anyhow::bail!("{flag} cannot be combined with file arguments or --stdin");
+    }
+    Ok(())
+}
+
+/// Files staged in git, for `--staged`. Files with unstaged changes too are
+/// an error: their working tree copy is not what would be committed.
+fn staged_files() -> Result<Vec<PathBuf>> {
+    let staged = git::staged_files()?;
+    let partial = git::partially_staged(&staged)?;
+    if !partial.is_empty() {
+        anyhow::bail!(
+            "--staged: stage or stash the unstaged changes in {} first",
+            display_paths(&partial)
+        );
+    }
+    Ok(staged)
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+    paths.join(", ")
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let events = cli.events;
+    if let Err(err) = run(cli) {
+        // Classified failures get their own exit code; with --events the
+        // error is a JSON line like the warnings before it.
+        if events {
+            eprintln!("{}", error::to_event(&err));
+        } else {
+            eprintln!("Error: {err:?}");
+        }
+        std::process::exit(error::classify(&err).map_or(1, |e| e.exit_code()));
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
     // Prevent infinite recursion when symlinked as `dprint` with no config.
     if std::env::var("DPRINTX_ACTIVE").is_ok() {
         anyhow::bail!(
@@ -35,18 +89,37 @@ fn main() -> Result<()> {
         );
     }
 
-    let cli = Cli::parse();
+    warnings::set_events(cli.events);
+    let _warnings = warnings::FlushGuard;
+
+    // Kill switch — no matching, merging or proxying, just the real dprint.
+    // A broken config must not block the bypass, so it is only consulted
+    // for the dprint path.
+    if DprintxConfig::is_disabled() {
+        let dprint = match load_config(cli.config.as_deref()) {
+            Ok(Some(config)) => config.dprint_path(),
+            _ => std::path::PathBuf::from("dprint"),
+        };
+        return exec_dprint(&dprint, "dprintx disabled");
+    }
+
+    // Delegate to a running daemon before paying for config loading.
+    let stdin_input = match daemon::attach(&cli, None)? {
+        daemon::Attached::Exit(code) => {
+            warnings::flush();
+            std::process::exit(code);
+        }
+        daemon::Attached::Local(input) => input,
+    };
+
     let config = load_config(cli.config.as_deref())?;
 
     // No config — passthrough everything to dprint.
-    let Some(config) = config else {
-        let args: Vec<String> = std::env::args().skip(1).collect();
-        let status = std::process::Command::new("dprint")
-            .env("DPRINTX_ACTIVE", "1")
-            .args(&args)
-            .status()
-            .context("cannot run dprint (no dprintx config, falling back to dprint in PATH)")?;
-        std::process::exit(status.code().unwrap_or(1));
+    let Some(mut config) = config else {
+        return exec_dprint(
+            Path::new("dprint"),
+            "no dprintx config, falling back to dprint in PATH",
+        );
     };
 
     // Commands that don't need matcher.
@@ -56,118 +129,430 @@ fn main() -> Result<()> {
             runner.passthrough_raw(args)?;
             return Ok(());
         }
-        CliCommand::Completions { shell } => {
+        CliCommand::Completions { shell, install } => {
             let runner = DprintRunner::new(&config);
-            runner.completions(shell)?;
+            if *install {
+                let (shell, path) = runner.install_completions(shell.as_deref())?;
+                println!("installed {shell} completions to {}", path.display());
+                if shell == "zsh" {
+                    let dir = path.parent().unwrap_or(&path);
+                    println!(
+                        "make sure ~/.zshrc has `fpath=({} $fpath)` before `compinit`",
+                        dir.display()
+                    );
+                }
+                println!("restart the shell to pick them up");
+            } else {
+                runner.completions(shell.as_deref().unwrap_or("zsh"))?;
+            }
+            return Ok(());
+        }
+        CliCommand::Complete { kind } => {
+            match kind.as_str() {
+                "profiles" => config.profiles.keys().for_each(|name| println!("{name}")),
+                "recent" => recent::load().iter().for_each(|path| println!("{path}")),
+                _ => anyhow::bail!("unknown completion kind: {kind}"),
+            }
+            return Ok(());
+        }
+        CliCommand::Daemon { socket } => {
+            let config_path = config_file_path(cli.config.as_deref())?;
+            daemon::serve(&config_path, socket.as_ref().map(std::path::PathBuf::from))?;
+            return Ok(());
+        }
+        CliCommand::Serve { listen } => {
+            let config_path = config_file_path(cli.config.as_deref())?;
+            http::serve(&config_path, listen)?;
+            return Ok(());
+        }
+        CliCommand::Trust { dir } => {
+            match dir {
+                Some(dir) => {
+                    let (dir, added) = trust::add(&config::expand_tilde(dir))?;
+                    if added {
+                        println!("trusted {}", dir.display());
+                    } else {
+                        println!("already trusted: {}", dir.display());
+                    }
+                }
+                None => trust::load()
+                    .iter()
+                    .for_each(|dir| println!("{}", dir.display())),
+            }
+            return Ok(());
+        }
+        CliCommand::Doctor { fix } => {
+            let config_path = config_file_path(cli.config.as_deref())?;
+            return cmd_doctor(&config_path, &config, *fix);
+        }
+        CliCommand::AddProfile { name, path } => {
+            let config_path = config_file_path(cli.config.as_deref())?;
+            edit::add_profile(&config_path, &config, name, path)?;
+            println!("added profile {name} -> {path}");
+            return Ok(());
+        }
+        CliCommand::AddRule {
+            glob,
+            profile,
+            before,
+            yes,
+        } => {
+            let config_path = config_file_path(cli.config.as_deref())?;
+            let confirm = || preview_rule(glob, *yes);
+            if edit::add_rule(
+                &config_path,
+                &config,
+                glob,
+                profile,
+                before.as_deref(),
+                confirm,
+            )? {
+                println!("added rule {glob} -> {profile}");
+            }
             return Ok(());
         }
         _ => {}
     }
 
+    match &cli.command {
+        CliCommand::Lsp {
+            force_config,
+            extra_match,
+            timeout,
+            log,
+            no_merge,
+            ..
+        } => apply_lsp_args(
+            &mut config,
+            force_config.as_deref(),
+            extra_match,
+            timeout.as_deref(),
+            log.as_deref(),
+            *no_merge,
+        )?,
+        CliCommand::Fmt {
+            extra_match,
+            no_merge,
+            ..
+        }
+        | CliCommand::Check {
+            extra_match,
+            no_merge,
+            ..
+        } => {
+            config.merge_local &= !no_merge;
+            config.prepend_match_rules("--match", extra_match)?
+        }
+        _ => {}
+    }
     let matcher = ProfileMatcher::from_config(&config)?;
-    let runner = DprintRunner::new(&config);
+    let allow_no_files = matches!(
+        cli.command,
+        CliCommand::Fmt {
+            allow_no_files: true,
+            ..
+        } | CliCommand::Check {
+            allow_no_files: true,
+            ..
+        }
+    );
+    let verify = matches!(cli.command, CliCommand::Fmt { verify: true, .. });
+    let json = matches!(
+        cli.command,
+        CliCommand::Check {
+            format: Some(_),
+            ..
+        }
+    );
+    if matches!(
+        cli.command,
+        CliCommand::Check {
+            rule_stats: true,
+            ..
+        }
+    ) {
+        rulestats::enable(matcher.rule_labels());
+    }
+    let repro = match &cli.command {
+        CliCommand::Fmt {
+            save_repro: Some(dir),
+            ..
+        }
+        | CliCommand::Check {
+            save_repro: Some(dir),
+            ..
+        } => {
+            let config_file = config_file_path(cli.config.as_deref())?;
+            let repro = Repro::create(Path::new(dir), &config_file, &config)?;
+            eprintln!("dprintx: saving repro to {dir}");
+            Some(repro)
+        }
+        _ => None,
+    };
+    let post_run = match &cli.command {
+        CliCommand::Fmt { stdin: None, .. } => Some("fmt"),
+        CliCommand::Check { stdin: None, .. } => Some("check"),
+        _ => None,
+    };
+    if post_run.is_some() {
+        disabled::enable();
+    }
+    let runner = DprintRunner::new(&config)
+        .with_post_run(post_run.zip(config.post_run.clone()))
+        .with_allow_no_files(allow_no_files)
+        .with_verify(verify)
+        .with_json(json)
+        .with_repro(repro)
+        .with_stdin(stdin_input);
 
     match cli.command {
-        CliCommand::Fmt { stdin, files } => {
-            if let Some(ref filename) = stdin {
-                runner.fmt_stdin(filename, &matcher, &config)?;
+        CliCommand::Fmt {
+            stdin,
+            files,
+            follow_symlinks,
+            staged,
+            ..
+        } => {
+            if staged {
+                no_file_args("--staged", stdin.is_some(), &files)?;
+                let staged = staged_files()?;
+                runner.fmt_files(&staged, &matcher, &config)?;
+                git::add(&staged)?;
+            } else if let Some(ref filename) = stdin {
+                runner.fmt_stdin(Path::new(filename), &matcher, &config)?;
             } else if files.is_empty() {
                 runner.fmt_all(&matcher, &config)?;
             } else {
-                let (plain_files, dirs) = split_files_and_dirs(&files);
+                let (plain_files, dirs) = expand_dirs(&files, follow_symlinks)?;
                 if !plain_files.is_empty() {
                     runner.fmt_files(&plain_files, &matcher, &config)?;
                 }
                 if !dirs.is_empty() {
-                    let dir_paths: Vec<_> = dirs
-                        .iter()
-                        .map(|d| {
-                            std::fs::canonicalize(d).unwrap_or_else(|_| std::path::PathBuf::from(d))
-                        })
-                        .collect();
+                    let dir_paths: Vec<_> = dirs.iter().map(fsutil::canonical).collect();
                     runner.fmt_dirs(&dir_paths, &matcher, &config)?;
                 }
             }
         }
-        CliCommand::Check { files } => {
-            if files.is_empty() {
+        CliCommand::Check {
+            stdin,
+            files,
+            follow_symlinks,
+            staged,
+            since,
+            ..
+        } => {
+            if staged && since.is_some() {
+                anyhow::bail!("--staged and --since cannot be combined");
+            }
+            if staged {
+                no_file_args("--staged", stdin.is_some(), &files)?;
+                let staged = staged_files()?;
+                runner.check_files(&staged, &matcher, &config)?;
+            } else if let Some(ref rev) = since {
+                no_file_args("--since", stdin.is_some(), &files)?;
+                let changed = git::changed_since(rev)?;
+                runner.check_files(&changed, &matcher, &config)?;
+            } else if let Some(ref filename) = stdin {
+                runner.check_stdin(Path::new(filename), &matcher, &config)?;
+            } else if files.is_empty() {
                 runner.check_all(&matcher, &config)?;
             } else {
-                let (plain_files, dirs) = split_files_and_dirs(&files);
+                let (plain_files, dirs) = expand_dirs(&files, follow_symlinks)?;
                 if !plain_files.is_empty() {
                     runner.check_files(&plain_files, &matcher, &config)?;
                 }
                 if !dirs.is_empty() {
-                    let dir_paths: Vec<_> = dirs
-                        .iter()
-                        .map(|d| {
-                            std::fs::canonicalize(d).unwrap_or_else(|_| std::path::PathBuf::from(d))
-                        })
-                        .collect();
+                    let dir_paths: Vec<_> = dirs.iter().map(fsutil::canonical).collect();
                     runner.check_dirs(&dir_paths, &matcher, &config)?;
                 }
             }
         }
-        CliCommand::Config { file } => {
-            cmd_config(&matcher, &config, file.as_deref())?;
+        CliCommand::Config {
+            command: Some(ConfigCommand::Diff { a, b }),
+            ..
+        } => runner.config_diff(&config, &a, &b)?,
+        CliCommand::Config { describe: true, .. } => cmd_describe(&config),
+        CliCommand::Config { file, .. } => {
+            cmd_config(&matcher, &config, file.as_deref().map(Path::new))?
+        }
+        CliCommand::Parity { sample, paths } => {
+            let sample = match sample {
+                Some(n) => n
+                    .parse()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .with_context(|| format!("--sample {n:?}: expected a number of files"))?,
+                None => PARITY_SAMPLE,
+            };
+            let (files, dirs) = split_files_and_dirs(&paths);
+            let dirs: Vec<_> = dirs.iter().map(fsutil::canonical).collect();
+            let different = runner.parity(&files, &dirs, sample, &matcher, &config)?;
+            if different > 0 {
+                anyhow::bail!("{different} file(s) format differently through dprintx");
+            }
         }
         CliCommand::OutputFilePaths => {
             runner.output_file_paths(&matcher, &config)?;
         }
-        CliCommand::Lsp => {
-            let proxy = lsp::LspProxy::new(config.dprint_path(), matcher, config);
-            proxy.run()?;
+        CliCommand::Lsp {
+            metrics,
+            force_config,
+            extra_match,
+            timeout,
+            log,
+            port,
+            no_merge,
+        } => {
+            warnings::set_immediate(true);
+            let config_path = std::path::absolute(config_file_path(cli.config.as_deref())?)?;
+            let path = config_path.clone();
+            let reload = move || {
+                let mut config = DprintxConfig::load(&path)?;
+                apply_lsp_args(
+                    &mut config,
+                    force_config.as_deref(),
+                    &extra_match,
+                    timeout.as_deref(),
+                    log.as_deref(),
+                    no_merge,
+                )?;
+                Ok(config)
+            };
+            let proxy = lsp::LspProxy::new(config.dprint_path(), matcher, config)
+                .with_metrics_file(metrics.map(std::path::PathBuf::from))
+                .with_config_reload(config_path, Box::new(reload));
+            match port {
+                Some(port) => proxy.run_tcp(port)?,
+                None => proxy.run()?,
+            }
         }
-        CliCommand::Completions { .. } | CliCommand::Passthrough { .. } => unreachable!(),
+        CliCommand::Completions { .. }
+        | CliCommand::Complete { .. }
+        | CliCommand::Passthrough { .. }
+        | CliCommand::Daemon { .. }
+        | CliCommand::Serve { .. }
+        | CliCommand::Trust { .. }
+        | CliCommand::Doctor { .. }
+        | CliCommand::AddProfile { .. }
+        | CliCommand::AddRule { .. } => unreachable!(),
     }
 
+    runner.post_run(0);
+    runner.flush_metrics();
+    rulestats::report();
+    disabled::report();
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_split_all_files() {
-        let args = vec!["foo.go".into(), "bar.rs".into()];
-        let (files, dirs) = split_files_and_dirs(&args);
-        // Non-existent paths are treated as files (not directories).
-        assert_eq!(files, vec!["foo.go", "bar.rs"]);
-        assert!(dirs.is_empty());
+/// Show how many existing files `glob` matches and, on a terminal, ask
+/// before the rule is written unless `yes`.
+fn preview_rule(glob: &str, yes: bool) -> Result<bool> {
+    let cwd = std::env::current_dir().context("getting current directory")?;
+    let sample = edit::sample_glob(glob, &cwd)?;
+    let more = if sample.truncated { "+" } else { "" };
+    eprintln!(
+        "{glob} matches {}{more} of {}{more} files under {}",
+        sample.matched,
+        sample.sampled,
+        sample.root.display()
+    );
+    for example in &sample.examples {
+        eprintln!("  {}", example.display());
     }
+    if sample.matched == 0 {
+        eprintln!("warning: the rule matches no existing file there");
+    }
+    if yes {
+        return Ok(true);
+    }
+    confirm("add rule?")
+}
 
-    #[test]
-    fn test_split_all_dirs() {
-        let dir = std::env::temp_dir().join("dprintx-test-split-dirs");
-        let _ = std::fs::remove_dir_all(&dir);
-        std::fs::create_dir_all(&dir).unwrap();
-
-        let args = vec![dir.to_string_lossy().into_owned()];
-        let (files, dirs) = split_files_and_dirs(&args);
-        assert!(files.is_empty());
-        assert_eq!(dirs.len(), 1);
+/// Ask `question` on a terminal; yes without one.
+fn confirm(question: &str) -> Result<bool> {
+    use std::io::{BufRead, IsTerminal, Write};
 
-        let _ = std::fs::remove_dir_all(&dir);
+    if !std::io::stdin().is_terminal() {
+        return Ok(true);
     }
+    eprint!("{question} [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
 
-    #[test]
-    fn test_split_mixed() {
-        let dir = std::env::temp_dir().join("dprintx-test-split-mixed");
-        let _ = std::fs::remove_dir_all(&dir);
-        std::fs::create_dir_all(&dir).unwrap();
+/// Check that the dprint binary and the profile configs exist. With `fix`,
+/// offer to point a missing `dprint` at the discovered binary.
+fn cmd_doctor(config_file: &Path, config: &DprintxConfig, fix: bool) -> Result<()> {
+    println!("config: {}", config_file.display());
+    let mut problems = 0;
 
-        let args = vec![
-            "explicit.go".into(),
-            dir.to_string_lossy().into_owned(),
-            "another.rs".into(),
-        ];
-        let (files, dirs) = split_files_and_dirs(&args);
-        assert_eq!(files, vec!["explicit.go", "another.rs"]);
-        assert_eq!(dirs.len(), 1);
+    let configured = config.configured_dprint_path();
+    if configured.is_file() {
+        let version = std::process::Command::new(&configured)
+            .arg("--version")
+            .output()
+            .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+            .unwrap_or_default();
+        if version.is_empty() {
+            println!("dprint: {} (no version reported)", configured.display());
+        } else {
+            println!("dprint: {} ({version})", configured.display());
+        }
+    } else {
+        match &config.dprint_discovered {
+            Some(found) => println!(
+                "dprint: {} not found; found {}",
+                configured.display(),
+                found.display()
+            ),
+            None => println!(
+                "dprint: {} not found, nor in PATH or the usual install locations",
+                configured.display()
+            ),
+        }
+        match &config.dprint_discovered {
+            Some(found)
+                if fix
+                    && confirm(&format!(
+                        "update \"dprint\" in {} to {}?",
+                        config_file.display(),
+                        found.display()
+                    ))? =>
+            {
+                edit::set_dprint(config_file, &config::contract_tilde(found))?;
+                println!("updated \"dprint\" to {}", found.display());
+            }
+            _ => problems += 1,
+        }
+    }
 
-        let _ = std::fs::remove_dir_all(&dir);
+    for name in config.profiles.keys() {
+        if let Some(ProfileResolution::Config(path)) = config.resolve_profile(name)
+            && !path.is_file()
+        {
+            println!("profile {name}: {} not found", path.display());
+            problems += 1;
+        }
+    }
+
+    if problems > 0 {
+        anyhow::bail!("{problems} problem(s) found");
     }
+    println!("no problems found");
+    Ok(())
+}
+
+/// Run dprint with the original arguments and exit with its status.
+fn exec_dprint(dprint: &Path, reason: &str) -> Result<()> {
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    let status = std::process::Command::new(dprint)
+        .env("DPRINTX_ACTIVE", "1")
+        .args(&args)
+        .status()
+        .spawn_context(dprint, || reason.to_string())?;
+    std::process::exit(status.code().unwrap_or(1));
 }
 
 fn load_config(config_path: Option<&str>) -> Result<Option<DprintxConfig>> {
@@ -179,14 +564,48 @@ fn load_config(config_path: Option<&str>) -> Result<Option<DprintxConfig>> {
     }
 }
 
+/// Apply the `dprintx lsp` options that override the config file.
+fn apply_lsp_args(
+    config: &mut DprintxConfig,
+    force_config: Option<&str>,
+    extra_match: &[String],
+    timeout: Option<&str>,
+    log: Option<&str>,
+    no_merge: bool,
+) -> Result<()> {
+    config.apply_lsp_overrides(force_config.map(Path::new), extra_match)?;
+    config.merge_local &= !no_merge;
+    if let Some(ms) = timeout {
+        let ms = ms
+            .parse()
+            .with_context(|| format!("--lsp-timeout {ms:?}: expected milliseconds"))?;
+        config.lsp_timeout_ms = Some(ms);
+    }
+    if let Some(log) = log {
+        // Relative to where dprintx runs, not to the config.
+        config.lsp_log = Some(std::path::absolute(log)?.display().to_string());
+    }
+    Ok(())
+}
+
+/// Path of the dprintx config file in effect (explicit --config or default).
+fn config_file_path(config_path: Option<&str>) -> Result<std::path::PathBuf> {
+    match config_path {
+        Some(path) => Ok(std::path::PathBuf::from(path)),
+        None => DprintxConfig::default_path(),
+    }
+}
+
 /// Show which config would be used for a given file.
-fn cmd_config(matcher: &ProfileMatcher, config: &DprintxConfig, file: Option<&str>) -> Result<()> {
+fn cmd_config(matcher: &ProfileMatcher, config: &DprintxConfig, file: Option<&Path>) -> Result<()> {
     match file {
         Some(f) => {
-            let abs_path = std::fs::canonicalize(f).unwrap_or_else(|_| std::path::PathBuf::from(f));
+            let abs_path = fsutil::canonical(f);
+            // Offered by `dprintx config <TAB>`; history is best effort.
+            let _ = recent::record(&abs_path);
             let config_path = matcher
                 .resolve_config(&abs_path, config)
-                .with_context(|| format!("resolving config for {f}"))?;
+                .with_context(|| format!("resolving config for {}", f.display()))?;
             match config_path {
                 Some(ProfileResolution::Config(p)) => println!("{}", p.display()),
                 Some(ProfileResolution::Ignore) => println!("(ignored)"),
@@ -207,10 +626,22 @@ fn cmd_config(matcher: &ProfileMatcher, config: &DprintxConfig, file: Option<&st
                     None => {}
                 }
             }
+            if !config.ignore.is_empty() {
+                println!("ignore:");
+                for pattern in &config.ignore {
+                    println!("  {pattern}");
+                }
+            }
             println!("match rules:");
             for (pattern, profile) in config.match_rules_iter() {
                 println!("  {pattern} -> {profile}");
             }
+            if config.match_language.is_some() {
+                println!("match language rules:");
+                for (language_id, profile) in config.match_language_rules_iter() {
+                    println!("  [{language_id}] -> {profile}");
+                }
+            }
             if config.match_content.is_some() {
                 println!("match content rules:");
                 for (pattern, profile) in config.match_content_rules_iter() {
@@ -221,3 +652,117 @@ fn cmd_config(matcher: &ProfileMatcher, config: &DprintxConfig, file: Option<&st
     }
     Ok(())
 }
+
+/// `config --describe`: profiles, then rules (in `dprintx config` order),
+/// as tables with their descriptions.
+fn cmd_describe(config: &DprintxConfig) {
+    let mut profiles = vec![[
+        "PROFILE".to_string(),
+        "CONFIG".to_string(),
+        "DESCRIPTION".to_string(),
+    ]];
+    for name in config.profiles.keys() {
+        let target = match config.resolve_profile(name) {
+            Some(ProfileResolution::Config(path)) => path.display().to_string(),
+            _ => "(ignore)".to_string(),
+        };
+        let description = config.profile_description(name).unwrap_or_default();
+        profiles.push([name.clone(), target, description.to_string()]);
+    }
+    print_table(&profiles);
+
+    let mut rules = vec![[
+        "RULE".to_string(),
+        "PROFILE".to_string(),
+        "DESCRIPTION".to_string(),
+    ]];
+    for pattern in &config.ignore {
+        rules.push([pattern.clone(), "(ignore)".to_string(), String::new()]);
+    }
+    let sections = [
+        (Some(&config.match_rules), "{}"),
+        (config.match_language.as_ref(), "[{}]"),
+        (config.match_content.as_ref(), "/{}/"),
+    ];
+    for (section, shape) in sections {
+        for (key, value) in section.into_iter().flatten() {
+            let Some(profile) = config::rule_profile(value) else {
+                continue;
+            };
+            rules.push([
+                shape.replace("{}", key),
+                profile.to_string(),
+                config::description(value).unwrap_or_default().to_string(),
+            ]);
+        }
+    }
+    println!();
+    print_table(&rules);
+}
+
+/// Print `rows` left-aligned in columns, the first row being the header.
+fn print_table(rows: &[[String; 3]]) {
+    let width = |col: usize| {
+        rows.iter()
+            .map(|r| r[col].chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let (first, second) = (width(0), width(1));
+    for [a, b, c] in rows {
+        let line = format!("{a:first$}  {b:second$}  {c}");
+        println!("{}", line.trim_end());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_all_files() {
+        let args = vec!["foo.go".into(), "bar.rs".into()];
+        let (files, dirs) = split_files_and_dirs(&args);
+        // Non-existent paths are treated as files (not directories).
+        assert_eq!(
+            files,
+            vec![PathBuf::from("foo.go"), PathBuf::from("bar.rs")]
+        );
+        assert!(dirs.is_empty());
+    }
+
+    #[test]
+    fn test_split_all_dirs() {
+        let dir = std::env::temp_dir().join("dprintx-test-split-dirs");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let args = vec![dir.clone().into_os_string()];
+        let (files, dirs) = split_files_and_dirs(&args);
+        assert!(files.is_empty());
+        assert_eq!(dirs.len(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_split_mixed() {
+        let dir = std::env::temp_dir().join("dprintx-test-split-mixed");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let args = vec![
+            "explicit.go".into(),
+            dir.clone().into_os_string(),
+            "another.rs".into(),
+        ];
+        let (files, dirs) = split_files_and_dirs(&args);
+        assert_eq!(
+            files,
+            vec![PathBuf::from("explicit.go"), PathBuf::from("another.rs")]
+        );
+        assert_eq!(dirs.len(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
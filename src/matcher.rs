@@ -1,16 +1,46 @@
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result};
 use globset::{Glob, GlobMatcher};
+use std::borrow::Cow;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::config::{self, ContentMatcher, DprintxConfig, ProfileResolution};
+use crate::disabled;
+use crate::error::DprintxError;
+use crate::fsutil;
+use crate::git;
+use crate::rulestats;
+use crate::warnings;
 
 /// Block size for reading file content during content matching.
 /// File is read in line-aligned blocks of approximately this size.
 const CONTENT_MATCH_BLOCK_BYTES: usize = 8192;
 
+/// Magic comment pinning a file to a profile: `// dprintx-profile: NAME`.
+const PIN_DIRECTIVE: &str = "dprintx-profile:";
+
+/// Magic comment opting a file out of formatting.
+const DISABLE_DIRECTIVE: &str = "dprintx-disable-file";
+
+/// A disable comment counts on one of a file's first this many lines.
+const DISABLE_SCAN_LINES: usize = 10;
+
+/// Most files read at once when resolving many files with content matching:
+/// the worker pool is this big, or the number of CPUs if fewer.
+const CONTENT_MATCH_WORKERS: usize = 8;
+
+/// Comment markers recognized before the pin directive by default.
+const DEFAULT_PIN_MARKERS: &[&str] = &["//", "#", "/*", "<!--", "--", ";", "%", "{{/*", "{#"];
+
+/// Only this many bytes of a file's first lines are read for a pin or
+/// disable comment.
+const PIN_SCAN_BYTES: u64 = 1024;
+
 /// A compiled match rule: glob matcher + profile name.
 struct Rule {
     matcher: GlobMatcher,
+    /// Pattern as written in the config, for `--rule-stats`.
+    pattern: String,
     profile: String,
 }
 
@@ -18,7 +48,16 @@ struct Rule {
 /// with optional content-based override.
 pub struct ProfileMatcher {
     rules: Vec<Rule>,
+    /// Top-level `ignore` globs, with their patterns as written.
+    ignore: Vec<(GlobMatcher, String)>,
     content_matcher: Option<ContentMatcher>,
+    /// Comment markers for `dprintx-profile:` pins; empty disables pinning.
+    pin_markers: Vec<String>,
+    /// Comment markers for `dprintx-disable-file` by file extension, where
+    /// they differ from `pin_markers`.
+    disable_markers: std::collections::HashMap<String, Vec<String>>,
+    /// Match linked-worktree files by their primary-worktree path.
+    primary_worktree: bool,
 }
 
 impl ProfileMatcher {
@@ -29,38 +68,115 @@ impl ProfileMatcher {
         for (pattern, profile) in config.match_rules_iter() {
             // Expand ~ to home directory so globs like ~/workspace/** work.
             let expanded = config::expand_tilde(pattern);
-            let expanded_str = expanded.to_string_lossy();
-            let glob = Glob::new(&expanded_str)
-                .with_context(|| format!("invalid glob pattern: {pattern}"))?;
+            let expanded_str = fsutil::glob_pattern(&expanded);
+            let glob = Glob::new(&expanded_str).map_err(|e| DprintxError::Match {
+                rule: pattern.to_string(),
+                message: format!("invalid glob pattern: {pattern}: {e}"),
+            })?;
             rules.push(Rule {
                 matcher: glob.compile_matcher(),
+                pattern: pattern.to_string(),
                 profile: profile.to_string(),
             });
         }
 
+        let mut ignore = Vec::new();
+        for pattern in &config.ignore {
+            let glob =
+                Glob::new(&fsutil::glob_pattern(&config::expand_tilde(pattern))).map_err(|e| {
+                    DprintxError::Match {
+                        rule: ignore_label(pattern),
+                        message: format!("invalid ignore pattern: {pattern}: {e}"),
+                    }
+                })?;
+            ignore.push((glob.compile_matcher(), pattern.to_string()));
+        }
+
         let content_matcher = config.compile_content_patterns()?;
+        let pin_markers = match &config.pin_markers {
+            Some(markers) => markers.clone(),
+            None => DEFAULT_PIN_MARKERS.iter().map(|m| m.to_string()).collect(),
+        };
 
         Ok(Self {
             rules,
+            ignore,
             content_matcher,
+            pin_markers,
+            disable_markers: config.disable_markers.clone(),
+            primary_worktree: config.match_primary_worktree,
         })
     }
 
     /// Find the first matching profile for a file path.
     /// Returns None if no rule matches.
+    #[cfg(test)]
     pub fn match_profile(&self, path: &Path) -> Option<&str> {
-        for rule in &self.rules {
-            if rule.matcher.is_match(path) {
-                return Some(&rule.profile);
-            }
+        self.match_rule(path).map(|rule| rule.profile.as_str())
+    }
+
+    /// First `match` rule matching a file path.
+    ///
+    /// With `match_primary_worktree`, a file in a linked git worktree is
+    /// matched by its path in the primary worktree.
+    fn match_rule(&self, path: &Path) -> Option<&Rule> {
+        let path = self.rule_path(path);
+        self.rules.iter().find(|rule| rule.matcher.is_match(&path))
+    }
+
+    /// First top-level `ignore` glob matching a file path, mapped like in
+    /// [`Self::match_rule`].
+    fn ignored(&self, path: &Path) -> Option<&str> {
+        if self.ignore.is_empty() {
+            return None;
         }
-        None
+        let path = self.rule_path(path);
+        self.ignore
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(&path))
+            .map(|(_, pattern)| pattern.as_str())
+    }
+
+    /// The path rules see for a file: its primary-worktree path with
+    /// `match_primary_worktree`, else the path itself.
+    fn rule_path<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        match self
+            .primary_worktree
+            .then(|| git::in_primary_worktree(path))
+            .flatten()
+        {
+            Some(primary) => Cow::Owned(primary),
+            None => Cow::Borrowed(path),
+        }
+    }
+
+    /// Labels of all `match` and `match_content` rules in config order, as
+    /// reported by `--rule-stats`.
+    pub fn rule_labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = self
+            .ignore
+            .iter()
+            .map(|(_, pattern)| ignore_label(pattern))
+            .chain(
+                self.rules
+                    .iter()
+                    .map(|rule| path_label(&rule.pattern, &rule.profile)),
+            )
+            .collect();
+        if let Some(cm) = &self.content_matcher {
+            labels.extend((0..cm.rule_count()).map(|idx| {
+                let (pattern, profile) = cm.rule(idx);
+                content_label(pattern, profile)
+            }));
+        }
+        labels
     }
 
     /// Resolve file path to profile resolution (path match only).
     ///
     /// Returns None if no match rule applies (file is skipped).
-    /// Returns Some(Ignore) if matched profile is null.
+    /// Returns Some(Ignore) if an `ignore` glob matches, or the matched
+    /// profile is null.
     /// Returns Some(Config(path)) if matched profile has a config path.
     /// Errors if a matched profile name is not defined in profiles.
     ///
@@ -72,60 +188,372 @@ impl ProfileMatcher {
         file_path: &Path,
         config: &DprintxConfig,
     ) -> Result<Option<ProfileResolution>> {
-        let path_resolution = self.resolve_config_by_path(file_path, config)?;
+        self.resolve_config_with_language(file_path, None, config)
+    }
 
-        // If no path match, file is unknown — skip without checking content.
-        if path_resolution.is_none() {
-            return Ok(None);
+    /// [`Self::resolve_config`] of each of `files`, in order.
+    ///
+    /// With content matching configured, every matched file is read, so the
+    /// files are shared out to a pool of worker threads instead; see
+    /// [`CONTENT_MATCH_WORKERS`].
+    pub fn resolve_configs<P: AsRef<Path> + Sync>(
+        &self,
+        files: &[P],
+        config: &DprintxConfig,
+    ) -> Vec<Result<Option<ProfileResolution>>> {
+        let workers = match self.content_matcher {
+            Some(_) => std::thread::available_parallelism()
+                .map_or(1, |n| n.get())
+                .min(CONTENT_MATCH_WORKERS)
+                .min(files.len()),
+            None => 1,
+        };
+        if workers <= 1 {
+            return files
+                .iter()
+                .map(|file| self.resolve_config(file.as_ref(), config))
+                .collect();
+        }
+
+        let next = AtomicUsize::new(0);
+        let mut resolved: Vec<(usize, Result<Option<ProfileResolution>>)> =
+            std::thread::scope(|scope| {
+                let workers: Vec<_> = (0..workers)
+                    .map(|_| {
+                        scope.spawn(|| {
+                            let mut resolved = Vec::new();
+                            loop {
+                                let i = next.fetch_add(1, Ordering::Relaxed);
+                                let Some(file) = files.get(i) else {
+                                    return resolved;
+                                };
+                                resolved.push((i, self.resolve_config(file.as_ref(), config)));
+                            }
+                        })
+                    })
+                    .collect();
+                workers
+                    .into_iter()
+                    .flat_map(|worker| {
+                        worker
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                    })
+                    .collect()
+            });
+        resolved.sort_by_key(|(i, _)| *i);
+        resolved.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Like `resolve_config`, but a `match_language` rule for `language_id`
+    /// (if configured) takes precedence over path rules.
+    /// Content-based matching still applies on top.
+    /// A `dprintx-profile:` pin comment on the file's first line beats all of them.
+    pub fn resolve_config_with_language(
+        &self,
+        file_path: &Path,
+        language_id: Option<&str>,
+        config: &DprintxConfig,
+    ) -> Result<Option<ProfileResolution>> {
+        self.resolve(file_path, language_id, None, config)
+    }
+
+    /// Like `resolve_config_with_language`, for an editor buffer: pin
+    /// comments and `match_content` patterns are checked against its `text`,
+    /// which may be unsaved, when known. Without it the file is read.
+    pub fn resolve_config_for_buffer(
+        &self,
+        file_path: &Path,
+        language_id: Option<&str>,
+        text: Option<&str>,
+        config: &DprintxConfig,
+    ) -> Result<Option<ProfileResolution>> {
+        self.resolve(file_path, language_id, text, config)
+    }
+
+    /// `resolve_config_for_buffer`, with the label of the rule that decided,
+    /// as `--rule-stats` reports it. None when the path cannot be routed.
+    pub fn resolve_buffer_with_rule(
+        &self,
+        file_path: &Path,
+        language_id: Option<&str>,
+        text: Option<&str>,
+        config: &DprintxConfig,
+    ) -> Result<(Option<ProfileResolution>, Option<String>)> {
+        self.resolve_labeled(file_path, language_id, text, config)
+    }
+
+    /// Like `resolve_config`, but pin comments and `match_content` patterns
+    /// are checked against `content_head` (the start of an editor buffer)
+    /// instead of the file on disk, which may not exist yet.
+    pub fn resolve_config_for_content(
+        &self,
+        file_path: &Path,
+        content_head: &str,
+        config: &DprintxConfig,
+    ) -> Result<Option<ProfileResolution>> {
+        self.resolve(file_path, None, Some(content_head), config)
+    }
+
+    /// Shared resolution: ignore globs, then disable comments and pins, then
+    /// language or path rules, then content.
+    /// `content` replaces reading the file when given. Path forms dprintx
+    /// cannot route (see [`fsutil::routable`]) match nothing, with a warning.
+    fn resolve(
+        &self,
+        file_path: &Path,
+        language_id: Option<&str>,
+        content: Option<&str>,
+        config: &DprintxConfig,
+    ) -> Result<Option<ProfileResolution>> {
+        Ok(self
+            .resolve_labeled(file_path, language_id, content, config)?
+            .0)
+    }
+
+    /// [`Self::resolve`], with the label of the deciding rule as
+    /// `--rule-stats` reports it; None for paths dprintx cannot route.
+    fn resolve_labeled(
+        &self,
+        file_path: &Path,
+        language_id: Option<&str>,
+        content: Option<&str>,
+        config: &DprintxConfig,
+    ) -> Result<(Option<ProfileResolution>, Option<String>)> {
+        let file_path = match fsutil::routable(file_path) {
+            Ok(path) => path,
+            Err(reason) => {
+                warnings::warn(
+                    "path",
+                    format!("not formatting {}: {reason}", file_path.display()),
+                );
+                return Ok((None, None));
+            }
+        };
+        let file_path = file_path.as_ref();
+        let (resolution, label) = self.decide(file_path, language_id, content, config)?;
+        rulestats::record(file_path, || label.clone());
+        Ok((resolution, Some(label)))
+    }
+
+    /// The resolution of a routable `file_path`, with the label of the
+    /// deciding rule.
+    fn decide(
+        &self,
+        file_path: &Path,
+        language_id: Option<&str>,
+        content: Option<&str>,
+        config: &DprintxConfig,
+    ) -> Result<(Option<ProfileResolution>, String)> {
+        if let Some(pattern) = self.ignored(file_path) {
+            return Ok((Some(ProfileResolution::Ignore), ignore_label(pattern)));
+        }
+        let head = match content {
+            Some(head) => Some(Cow::Borrowed(head)),
+            None => self.read_head(file_path).map(Cow::Owned),
+        };
+        if let Some(head) = &head
+            && self.disabled_in(file_path, head)
+        {
+            disabled::record(file_path);
+            return Ok((
+                Some(ProfileResolution::Ignore),
+                DISABLE_DIRECTIVE.to_string(),
+            ));
+        }
+        if let Some(profile_name) = head.as_deref().and_then(|head| self.pin_in(head)) {
+            return match config.resolve_profile(&profile_name) {
+                Some(resolution) => Ok((Some(resolution), format!("pin -> {profile_name}"))),
+                None => Err(DprintxError::Match {
+                    rule: format!("pin -> {profile_name}"),
+                    message: format!(
+                        "profile '{}' pinned by {PIN_DIRECTIVE} comment in {} but not defined in profiles",
+                        profile_name,
+                        file_path.display()
+                    ),
+                }
+                .into()),
+            };
         }
 
+        let language = language_id.and_then(|l| Some((l, config.language_profile(l)?)));
+        let path_resolution = match language {
+            Some((language_id, profile_name)) => match config.resolve_profile(profile_name) {
+                Some(resolution) => Some((
+                    resolution,
+                    format!("match_language {language_id} -> {profile_name}"),
+                )),
+                None => {
+                    return Err(DprintxError::Match {
+                        rule: format!("match_language {language_id} -> {profile_name}"),
+                        message: format!(
+                            "profile '{profile_name}' referenced in match_language but not defined in profiles"
+                        ),
+                    }
+                    .into());
+                }
+            },
+            None => self.resolve_config_by_path(file_path, config)?,
+        };
+
+        // If no path match, file is unknown — skip without checking content.
+        let Some((path_resolution, path_label)) = path_resolution else {
+            return Ok((None, "(no rule)".to_string()));
+        };
+
         // If no content matcher configured, return path result as-is.
         let content_matcher = match &self.content_matcher {
             Some(cm) => cm,
-            None => return Ok(path_resolution),
+            None => return Ok((Some(path_resolution), path_label)),
         };
 
         // Read file in blocks and check content patterns.
-        match match_file_content(file_path, content_matcher) {
-            Ok(Some(profile_name)) => {
-                if let Some(resolution) = config.resolve_profile(&profile_name) {
-                    return Ok(Some(resolution));
+        let content_match = match content {
+            Some(head) => Ok(content_matcher.match_index(head)),
+            None => match_file_content(file_path, content_matcher),
+        };
+        match content_match {
+            Ok(Some(idx)) => {
+                let (pattern, profile_name) = content_matcher.rule(idx);
+                if let Some(resolution) = config.resolve_profile(profile_name) {
+                    return Ok((Some(resolution), content_label(pattern, profile_name)));
                 }
-                bail!(
-                    "profile '{}' referenced in match_content but not defined in profiles",
-                    profile_name
-                );
+                return Err(DprintxError::Match {
+                    rule: content_label(pattern, profile_name),
+                    message: format!(
+                        "profile '{profile_name}' referenced in match_content but not defined in profiles"
+                    ),
+                }
+                .into());
             }
             Ok(None) => {} // No content match — keep path result.
             Err(_) => {}   // Can't read file — keep path result.
         }
 
-        Ok(path_resolution)
+        Ok((Some(path_resolution), path_label))
+    }
+
+    /// The first [`PIN_SCAN_BYTES`] of a file, to look for pin and disable
+    /// comments in. None without markers to look for.
+    fn read_head(&self, file_path: &Path) -> Option<String> {
+        use std::io::Read;
+
+        if self.pin_markers.is_empty() && self.disable_markers.values().all(Vec::is_empty) {
+            return None;
+        }
+        let file = std::fs::File::open(file_path).ok()?;
+        let mut head = Vec::new();
+        file.take(PIN_SCAN_BYTES).read_to_end(&mut head).ok()?;
+        Some(String::from_utf8_lossy(&head).into_owned())
+    }
+
+    /// Whether `head`, the start of `file_path`, has a disable comment on
+    /// one of its first lines.
+    fn disabled_in(&self, file_path: &Path, head: &str) -> bool {
+        let markers = file_path
+            .extension()
+            .and_then(|ext| self.disable_markers.get(ext.to_str()?))
+            .unwrap_or(&self.pin_markers);
+        head.lines()
+            .take(DISABLE_SCAN_LINES)
+            .any(|line| is_disable(line, markers))
+    }
+
+    /// Profile named by a pin comment at the start of `head`.
+    fn pin_in(&self, head: &str) -> Option<String> {
+        let mut lines = head.lines();
+        let mut line = lines.next()?;
+        if line.starts_with("#!") {
+            line = lines.next()?;
+        }
+        parse_pin(line, &self.pin_markers).map(str::to_string)
     }
 
-    /// Resolve file path by path matching only (no content check).
+    /// Resolve file path by path matching only (no content check), with
+    /// the label of the deciding rule.
     fn resolve_config_by_path(
         &self,
         file_path: &Path,
         config: &DprintxConfig,
-    ) -> Result<Option<ProfileResolution>> {
-        if let Some(profile_name) = self.match_profile(file_path) {
-            if let Some(resolution) = config.resolve_profile(profile_name) {
-                return Ok(Some(resolution));
+    ) -> Result<Option<(ProfileResolution, String)>> {
+        if let Some(rule) = self.match_rule(file_path) {
+            if let Some(resolution) = config.resolve_profile(&rule.profile) {
+                return Ok(Some((resolution, path_label(&rule.pattern, &rule.profile))));
             }
-            bail!(
-                "profile '{}' referenced in match rules but not defined in profiles",
-                profile_name
-            );
+            return Err(DprintxError::Match {
+                rule: path_label(&rule.pattern, &rule.profile),
+                message: format!(
+                    "profile '{}' referenced in match rules but not defined in profiles",
+                    rule.profile
+                ),
+            }
+            .into());
         }
         Ok(None)
     }
 }
 
+fn ignore_label(pattern: &str) -> String {
+    format!("ignore {pattern}")
+}
+
+fn path_label(pattern: &str, profile: &str) -> String {
+    format!("match {pattern} -> {profile}")
+}
+
+fn content_label(pattern: &str, profile: &str) -> String {
+    format!("match_content /{pattern}/ -> {profile}")
+}
+
+/// Parse `<marker> dprintx-profile: NAME [comment end]` from a line.
+fn parse_pin<'a>(line: &'a str, markers: &[String]) -> Option<&'a str> {
+    let line = line.trim();
+    // Longest marker first, so `{{/*` wins over shorter prefixes.
+    let rest = markers
+        .iter()
+        .filter(|m| line.starts_with(m.as_str()))
+        .max_by_key(|m| m.len())
+        .map(|m| &line[m.len()..])?;
+    let name = rest
+        .trim_start()
+        .strip_prefix(PIN_DIRECTIVE)?
+        .split_whitespace()
+        .next()?;
+    // Tolerate a closing marker glued to the name: `/* dprintx-profile: x*/`.
+    let name = name
+        .trim_end_matches("*/")
+        .trim_end_matches("-->")
+        .trim_end_matches("#}");
+    (!name.is_empty()).then_some(name)
+}
+
+/// Whether `line` is a `<marker> dprintx-disable-file[: reason] [comment end]`
+/// comment.
+fn is_disable(line: &str, markers: &[String]) -> bool {
+    let line = line.trim();
+    let Some(rest) = markers
+        .iter()
+        .filter(|m| line.starts_with(m.as_str()))
+        .max_by_key(|m| m.len())
+        .map(|m| &line[m.len()..])
+    else {
+        return false;
+    };
+    let Some(rest) = rest.trim_start().strip_prefix(DISABLE_DIRECTIVE) else {
+        return false;
+    };
+    rest.is_empty()
+        || rest.starts_with(char::is_whitespace)
+        || [":", "*/", "-->", "#}"]
+            .iter()
+            .any(|end| rest.starts_with(end))
+}
+
 /// Read a file in line-aligned blocks and match against content patterns.
-/// Returns the profile name of the first matching pattern, or None.
-/// Scans the entire file, matching each block independently.
-fn match_file_content(path: &Path, matcher: &ContentMatcher) -> Result<Option<String>> {
+/// Returns the index of the first matching pattern, or None.
+/// Scans the entire file, matching each block independently. A file that
+/// is not UTF-8 is binary: no content pattern matches it.
+fn match_file_content(path: &Path, matcher: &ContentMatcher) -> Result<Option<usize>> {
     use std::io::{BufRead, BufReader};
 
     let file = std::fs::File::open(path)
@@ -134,17 +562,20 @@ fn match_file_content(path: &Path, matcher: &ContentMatcher) -> Result<Option<St
     let mut block = String::with_capacity(CONTENT_MATCH_BLOCK_BYTES);
 
     loop {
-        let mut line = String::new();
+        let mut line = Vec::new();
         let bytes_read = reader
-            .read_line(&mut line)
+            .read_until(b'\n', &mut line)
             .with_context(|| format!("reading file for content match: {}", path.display()))?;
+        let Ok(line) = String::from_utf8(line) else {
+            return Ok(None);
+        };
 
         if bytes_read == 0 {
             // EOF — match remaining block.
             if !block.is_empty()
-                && let Some(profile) = matcher.match_content(&block)
+                && let Some(idx) = matcher.match_index(&block)
             {
-                return Ok(Some(profile.to_string()));
+                return Ok(Some(idx));
             }
             return Ok(None);
         }
@@ -152,8 +583,8 @@ fn match_file_content(path: &Path, matcher: &ContentMatcher) -> Result<Option<St
         block.push_str(&line);
 
         if block.len() >= CONTENT_MATCH_BLOCK_BYTES {
-            if let Some(profile) = matcher.match_content(&block) {
-                return Ok(Some(profile.to_string()));
+            if let Some(idx) = matcher.match_index(&block) {
+                return Ok(Some(idx));
             }
             block.clear();
         }
@@ -234,6 +665,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ignore_globs_come_first() {
+        let dir = std::env::temp_dir().join("dprintx-test-ignore-globs");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("vendor")).unwrap();
+        let pinned = dir.join("vendor/pinned.go");
+        std::fs::write(&pinned, "// dprintx-profile: strict\npackage v\n").unwrap();
+
+        let config_json = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": { "strict": "/config/strict.jsonc" },
+            "match": { "**/vendor/**": "strict", "**": "strict" },
+            "ignore": ["**/vendor/**", "**/*.min.js"]
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(config_json).unwrap();
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+
+        // Ahead of match rules and pin comments alike.
+        for ignored in [pinned.as_path(), Path::new("/srv/app/dist/app.min.js")] {
+            assert_eq!(
+                matcher.resolve_config(ignored, &config).unwrap(),
+                Some(ProfileResolution::Ignore),
+                "{}",
+                ignored.display()
+            );
+        }
+        assert_eq!(
+            matcher
+                .resolve_config(Path::new("/srv/app/app.js"), &config)
+                .unwrap(),
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/strict.jsonc"
+            )))
+        );
+        assert_eq!(
+            matcher.rule_labels()[..3],
+            [
+                "ignore **/vendor/**",
+                "ignore **/*.min.js",
+                "match **/vendor/** -> strict"
+            ]
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn test_resolve_config_no_match() {
         let config_json = r#"{
@@ -375,6 +852,62 @@ mod tests {
         let _ = std::fs::remove_dir_all(&dir);
     }
 
+    #[test]
+    fn test_resolve_configs_keeps_order() {
+        let dir = std::env::temp_dir().join("dprintx-test-content-pool");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // More files than workers, every third one generated.
+        let files: Vec<PathBuf> = (0..40)
+            .map(|i| {
+                let file = dir.join(format!("f{i}.go"));
+                let content = match i % 3 {
+                    0 => "// Code generated by stringer. DO NOT EDIT.\npackage x\n",
+                    _ => "package x\n",
+                };
+                std::fs::write(&file, content).unwrap();
+                file
+            })
+            .chain([dir.join("missing.go"), PathBuf::from("/elsewhere/a.txt")])
+            .collect();
+
+        let config_json = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": {
+                "default": "/config/default.jsonc",
+                "ignore": null
+            },
+            "match": { "**/*.go": "default" },
+            "match_content": {
+                "// Code generated .+ DO NOT EDIT\\.": "ignore"
+            }
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(config_json).unwrap();
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+
+        let resolved: Vec<_> = matcher
+            .resolve_configs(&files, &config)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let one_by_one: Vec<_> = files
+            .iter()
+            .map(|f| matcher.resolve_config(f, &config).unwrap())
+            .collect();
+        assert_eq!(resolved, one_by_one);
+        assert_eq!(resolved[0], Some(ProfileResolution::Ignore));
+        assert_eq!(
+            resolved[1],
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/default.jsonc"
+            )))
+        );
+        assert_eq!(resolved[41], None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn test_resolve_with_content_no_path_match_skips_content_check() {
         let dir = std::env::temp_dir().join("dprintx-test-content-nopath");
@@ -505,6 +1038,65 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_resolve_for_buffer_uses_unsaved_text() {
+        let dir = std::env::temp_dir().join("dprintx-test-content-buffer");
+        let _ = std::fs::create_dir_all(&dir);
+
+        let file = dir.join("main.go");
+        std::fs::write(&file, "// @format:strict\npackage main\n").unwrap();
+
+        let config_json = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": {
+                "default": "/config/default.jsonc",
+                "strict": "/config/strict.jsonc"
+            },
+            "match": { "**": "default" },
+            "match_content": {
+                "@format:strict": "strict"
+            }
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(config_json).unwrap();
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+        let resolve = |text| {
+            matcher
+                .resolve_config_for_buffer(&file, None, text, &config)
+                .unwrap()
+        };
+
+        // The buffer dropped the marker: disk content no longer counts.
+        assert_eq!(
+            resolve(Some("package main\n")),
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/default.jsonc"
+            )))
+        );
+        // Without a buffer the file is read.
+        assert_eq!(
+            resolve(None),
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/strict.jsonc"
+            )))
+        );
+        // A buffer for a file not saved yet.
+        assert_eq!(
+            matcher
+                .resolve_config_for_buffer(
+                    &dir.join("new.go"),
+                    None,
+                    Some("// @format:strict\n"),
+                    &config
+                )
+                .unwrap(),
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/strict.jsonc"
+            )))
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn test_content_match_scans_entire_file() {
         let dir = std::env::temp_dir().join("dprintx-test-full-scan");
@@ -552,6 +1144,17 @@ mod tests {
             )))
         );
 
+        // Not UTF-8 → binary, no content match.
+        let file3 = dir.join("latin1.go");
+        std::fs::write(&file3, b"// caf\xe9\n// DO NOT EDIT\n").unwrap();
+        let result = matcher.resolve_config(&file3, &config).unwrap();
+        assert_eq!(
+            result,
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/default.jsonc"
+            )))
+        );
+
         let _ = std::fs::remove_dir_all(&dir);
     }
 
@@ -620,6 +1223,43 @@ mod tests {
         let _ = std::fs::remove_dir_all(&dir);
     }
 
+    #[test]
+    fn test_resolve_with_language_overrides_path() {
+        let config_json = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": {
+                "default": "/config/default.jsonc",
+                "ansible": "/config/ansible.jsonc"
+            },
+            "match": { "**/*.yml": "default" },
+            "match_language": { "yaml.ansible": "ansible" }
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(config_json).unwrap();
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+        let path = Path::new("/nonexistent/site.yml");
+
+        let result = matcher
+            .resolve_config_with_language(path, Some("yaml.ansible"), &config)
+            .unwrap();
+        assert_eq!(
+            result,
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/ansible.jsonc"
+            )))
+        );
+
+        // Unmapped languageId falls back to path rules.
+        let result = matcher
+            .resolve_config_with_language(path, Some("yaml"), &config)
+            .unwrap();
+        assert_eq!(
+            result,
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/default.jsonc"
+            )))
+        );
+    }
+
     #[test]
     fn test_resolve_without_content_matcher() {
         // No match_content in config → content matching not applied.
@@ -641,4 +1281,145 @@ mod tests {
             )))
         );
     }
+
+    #[test]
+    fn test_parse_pin_markers() {
+        let markers: Vec<String> = DEFAULT_PIN_MARKERS.iter().map(|m| m.to_string()).collect();
+        assert_eq!(
+            parse_pin("// dprintx-profile: strict\n", &markers),
+            Some("strict")
+        );
+        assert_eq!(
+            parse_pin("# dprintx-profile:strict", &markers),
+            Some("strict")
+        );
+        assert_eq!(
+            parse_pin("/* dprintx-profile: strict */", &markers),
+            Some("strict")
+        );
+        assert_eq!(
+            parse_pin("<!-- dprintx-profile: strict-->", &markers),
+            Some("strict")
+        );
+        assert_eq!(
+            parse_pin("{{/* dprintx-profile: strict */}}", &markers),
+            Some("strict")
+        );
+        assert_eq!(
+            parse_pin("package main // dprintx-profile: x", &markers),
+            None
+        );
+        assert_eq!(parse_pin("// dprintx-profile:", &markers), None);
+        assert_eq!(parse_pin("' dprintx-profile: x", &markers), None);
+    }
+
+    #[test]
+    fn test_is_disable() {
+        let markers: Vec<String> = DEFAULT_PIN_MARKERS.iter().map(|m| m.to_string()).collect();
+        assert!(is_disable("// dprintx-disable-file", &markers));
+        assert!(is_disable("  # dprintx-disable-file  ", &markers));
+        assert!(is_disable(
+            "-- dprintx-disable-file: hand-aligned",
+            &markers
+        ));
+        assert!(is_disable("/* dprintx-disable-file*/", &markers));
+        assert!(is_disable("<!-- dprintx-disable-file -->", &markers));
+        assert!(!is_disable("// dprintx-disable-files", &markers));
+        assert!(!is_disable("x = 1 // dprintx-disable-file", &markers));
+        assert!(!is_disable("' dprintx-disable-file", &markers));
+    }
+
+    #[test]
+    fn test_disable_comment_beats_pin_and_rules() {
+        let dir = std::env::temp_dir().join("dprintx-test-disable");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let pinned = dir.join("gen.go");
+        std::fs::write(
+            &pinned,
+            "// dprintx-profile: default\n// Copyright\n\n// dprintx-disable-file\npackage x\n",
+        )
+        .unwrap();
+        let late = dir.join("late.go");
+        let mut text = "package x\n".repeat(DISABLE_SCAN_LINES);
+        text.push_str("// dprintx-disable-file\n");
+        std::fs::write(&late, text).unwrap();
+        let sql = dir.join("up.sql");
+        std::fs::write(&sql, "' dprintx-disable-file\nselect 1;\n").unwrap();
+
+        let config_json = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": { "default": "/config/default.jsonc" },
+            "match": { "**": "default" },
+            "disable_markers": { "sql": ["'"] }
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(config_json).unwrap();
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+        let default = Some(ProfileResolution::Config(PathBuf::from(
+            "/config/default.jsonc",
+        )));
+
+        assert_eq!(
+            matcher.resolve_config(&pinned, &config).unwrap(),
+            Some(ProfileResolution::Ignore)
+        );
+        assert_eq!(matcher.resolve_config(&late, &config).unwrap(), default);
+        assert_eq!(
+            matcher.resolve_config(&sql, &config).unwrap(),
+            Some(ProfileResolution::Ignore)
+        );
+        // The editor buffer decides in LSP mode.
+        assert_eq!(
+            matcher
+                .resolve_config_for_buffer(&pinned, None, Some("package x\n"), &config)
+                .unwrap(),
+            default
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_pin_comment_beats_content_and_path() {
+        let dir = std::env::temp_dir().join("dprintx-test-pin");
+        let _ = std::fs::create_dir_all(&dir);
+
+        let pinned = dir.join("run.sh");
+        std::fs::write(
+            &pinned,
+            "#!/bin/sh\n# dprintx-profile: strict\n# DO NOT EDIT\n",
+        )
+        .unwrap();
+
+        let config_json = r#"{
+            "dprint": "/usr/bin/dprint",
+            "profiles": {
+                "default": "/config/default.jsonc",
+                "strict": "/config/strict.jsonc",
+                "ignore": null
+            },
+            "match": { "**/other/**": "default" },
+            "match_content": { "DO NOT EDIT": "ignore" }
+        }"#;
+        let config: DprintxConfig = serde_json::from_str(config_json).unwrap();
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+
+        // Pin applies even though no glob matches the path.
+        let result = matcher.resolve_config(&pinned, &config).unwrap();
+        assert_eq!(
+            result,
+            Some(ProfileResolution::Config(PathBuf::from(
+                "/config/strict.jsonc"
+            )))
+        );
+
+        // Disabled with an empty marker list.
+        let mut config = config;
+        config.pin_markers = Some(Vec::new());
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+        assert_eq!(matcher.resolve_config(&pinned, &config).unwrap(), None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
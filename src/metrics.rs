@@ -0,0 +1,300 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::fsutil;
+
+/// Request counters for a single LSP backend.
+#[derive(Debug, Default, Clone)]
+pub struct BackendMetrics {
+    /// Profile name (or config path for merged configs) served by the backend.
+    pub profile: String,
+    /// Requests routed to the backend.
+    pub requests: u64,
+    /// Requests that got no response within the read timeout.
+    pub timeouts: u64,
+    /// Sum of latencies of answered requests.
+    pub total_latency: Duration,
+    /// Times the backend process was restarted (crashed and respawned).
+    pub restarts: u64,
+}
+
+impl BackendMetrics {
+    /// Average latency of answered requests.
+    pub fn avg_latency(&self) -> Duration {
+        let answered = self.requests - self.timeouts;
+        if answered == 0 {
+            return Duration::ZERO;
+        }
+        self.total_latency / answered as u32
+    }
+}
+
+/// Per-backend metrics shared between the LSP loop and the dump thread.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    backends: Mutex<BTreeMap<PathBuf, BackendMetrics>>,
+    /// Documents resolved to a profile by matching.
+    matched: AtomicU64,
+    /// Documents resolved from the per-document resolution cache.
+    cached: AtomicU64,
+}
+
+impl Metrics {
+    /// Record a request to a backend. `latency` is None on timeout/error.
+    pub fn record_request(&self, config_path: &Path, profile: &str, latency: Option<Duration>) {
+        let mut backends = self.backends.lock().unwrap();
+        let entry = backends.entry(config_path.to_path_buf()).or_default();
+        entry.profile = profile.to_string();
+        entry.requests += 1;
+        match latency {
+            Some(latency) => entry.total_latency += latency,
+            None => entry.timeouts += 1,
+        }
+    }
+
+    /// Record that the backend for `config_path` was respawned after a crash.
+    pub fn record_restart(&self, config_path: &Path, profile: &str) {
+        let mut backends = self.backends.lock().unwrap();
+        let entry = backends.entry(config_path.to_path_buf()).or_default();
+        entry.profile = profile.to_string();
+        entry.restarts += 1;
+    }
+
+    /// Record how a document was resolved to a profile: from the cache of
+    /// open documents' resolutions, or by matching.
+    pub fn record_resolution(&self, cached: bool) {
+        let counter = if cached { &self.cached } else { &self.matched };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Snapshot of all backend metrics, ordered by config path.
+    pub fn snapshot(&self) -> BTreeMap<PathBuf, BackendMetrics> {
+        self.backends.lock().unwrap().clone()
+    }
+
+    /// Metrics as JSON, used for the `dprintx/status` response and `--metrics` dumps.
+    pub fn to_json(&self) -> serde_json::Value {
+        let backends: Vec<serde_json::Value> = self
+            .snapshot()
+            .iter()
+            .map(|(config_path, m)| {
+                serde_json::json!({
+                    "config": config_path.display().to_string(),
+                    "profile": m.profile,
+                    "requests": m.requests,
+                    "timeouts": m.timeouts,
+                    "avg_latency_ms": m.avg_latency().as_secs_f64() * 1000.0,
+                    "restarts": m.restarts,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "backends": backends,
+            "resolutions": {
+                "matched": self.matched.load(Ordering::Relaxed),
+                "cached": self.cached.load(Ordering::Relaxed),
+            },
+        })
+    }
+
+    /// Update LSP series in a Prometheus textfile.
+    pub fn write_textfile(&self, path: &Path) -> Result<()> {
+        let mut textfile = Textfile::load(path);
+        for m in self.snapshot().values() {
+            let labels = [("profile", m.profile.as_str())];
+            textfile.set("dprintx_lsp_requests_total", &labels, m.requests as f64);
+            textfile.set("dprintx_lsp_timeouts_total", &labels, m.timeouts as f64);
+            textfile.set("dprintx_lsp_restarts_total", &labels, m.restarts as f64);
+            textfile.set(
+                "dprintx_lsp_avg_latency_seconds",
+                &labels,
+                m.avg_latency().as_secs_f64(),
+            );
+        }
+        for (source, counter) in [("matched", &self.matched), ("cached", &self.cached)] {
+            textfile.set(
+                "dprintx_lsp_resolutions_total",
+                &[("source", source)],
+                counter.load(Ordering::Relaxed) as f64,
+            );
+        }
+        textfile.write(path)
+    }
+
+    /// Atomically write the JSON snapshot to `path`.
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.to_json())?;
+        fsutil::write_atomic(path, json.as_bytes())
+    }
+}
+
+/// Metrics in Prometheus textfile-collector format.
+///
+/// Series are keyed by `name{labels}`. The file is read back before each
+/// update so counters from short-lived CLI runs accumulate across processes.
+#[derive(Debug, Default)]
+pub struct Textfile {
+    series: BTreeMap<String, f64>,
+}
+
+impl Textfile {
+    /// Load existing series from `path`. Missing file or unparsable lines are ignored.
+    pub fn load(path: &Path) -> Self {
+        let mut series = BTreeMap::new();
+        if let Ok(content) = std::fs::read_to_string(path) {
+            for line in content.lines() {
+                if line.starts_with('#') {
+                    continue;
+                }
+                if let Some((key, value)) = line.rsplit_once(' ')
+                    && let Ok(value) = value.parse::<f64>()
+                {
+                    series.insert(key.to_string(), value);
+                }
+            }
+        }
+        Self { series }
+    }
+
+    /// Add to a counter series.
+    pub fn add(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        *self.series.entry(series_key(name, labels)).or_insert(0.0) += value;
+    }
+
+    /// Set a gauge (or process-owned counter) series.
+    pub fn set(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.series.insert(series_key(name, labels), value);
+    }
+
+    /// Render in exposition format, with HELP/TYPE lines for known metrics.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut current: Option<&str> = None;
+        for (key, value) in &self.series {
+            let name = key.split('{').next().unwrap_or(key);
+            if current != Some(name) {
+                if let Some((kind, help)) = metric_meta(name) {
+                    out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+                }
+                current = Some(name);
+            }
+            out.push_str(&format!("{key} {value}\n"));
+        }
+        out
+    }
+
+    /// Atomically write the rendered metrics to `path`.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fsutil::write_atomic(path, self.render().as_bytes())
+    }
+}
+
+/// HELP/TYPE metadata for metrics written by dprintx.
+fn metric_meta(name: &str) -> Option<(&'static str, &'static str)> {
+    Some(match name {
+        "dprintx_runs_total" => ("counter", "dprint invocations by the CLI runner."),
+        "dprintx_files_total" => ("counter", "Files passed to dprint by the CLI runner."),
+        "dprintx_failures_total" => ("counter", "dprint invocations that exited non-zero."),
+        "dprintx_run_seconds_total" => ("counter", "Time spent in dprint invocations."),
+        "dprintx_lsp_requests_total" => ("counter", "LSP requests routed to a backend."),
+        "dprintx_lsp_timeouts_total" => ("counter", "LSP requests without a backend response."),
+        "dprintx_lsp_restarts_total" => ("counter", "LSP backend restarts."),
+        "dprintx_lsp_resolutions_total" => ("counter", "LSP documents resolved to a profile."),
+        "dprintx_lsp_avg_latency_seconds" => ("gauge", "Average LSP backend response latency."),
+        _ => return None,
+    })
+}
+
+fn series_key(name: &str, labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return name.to_string();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let escaped = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{k}=\"{escaped}\"")
+        })
+        .collect();
+    format!("{name}{{{}}}", labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_average() {
+        let metrics = Metrics::default();
+        let path = Path::new("/config/default.jsonc");
+        metrics.record_request(path, "default", Some(Duration::from_millis(10)));
+        metrics.record_request(path, "default", Some(Duration::from_millis(30)));
+        metrics.record_request(path, "default", None);
+        metrics.record_restart(path, "default");
+        metrics.record_resolution(false);
+        metrics.record_resolution(true);
+        metrics.record_resolution(true);
+
+        let snapshot = metrics.snapshot();
+        let m = &snapshot[path];
+        assert_eq!(m.requests, 3);
+        assert_eq!(m.timeouts, 1);
+        assert_eq!(m.restarts, 1);
+        assert_eq!(m.avg_latency(), Duration::from_millis(20));
+
+        let json = metrics.to_json();
+        assert_eq!(json["backends"][0]["profile"], "default");
+        assert_eq!(json["backends"][0]["avg_latency_ms"], 20.0);
+        assert_eq!(json["resolutions"]["matched"], 1);
+        assert_eq!(json["resolutions"]["cached"], 2);
+    }
+
+    #[test]
+    fn test_textfile_accumulates_across_loads() {
+        let dir = std::env::temp_dir().join("dprintx-test-textfile");
+        let _ = std::fs::create_dir_all(&dir);
+        let path = dir.join("dprintx.prom");
+        let _ = std::fs::remove_file(&path);
+
+        for _ in 0..2 {
+            let mut textfile = Textfile::load(&path);
+            let labels = [("command", "fmt"), ("profile", "default")];
+            textfile.add("dprintx_runs_total", &labels, 1.0);
+            textfile.add("dprintx_files_total", &labels, 3.0);
+            textfile.write(&path).unwrap();
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.contains("# TYPE dprintx_runs_total counter"));
+        assert!(content.contains("dprintx_runs_total{command=\"fmt\",profile=\"default\"} 2\n"));
+        assert!(content.contains("dprintx_files_total{command=\"fmt\",profile=\"default\"} 6\n"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_series_key_escapes_labels() {
+        assert_eq!(
+            series_key("m", &[("profile", "a\"b")]),
+            "m{profile=\"a\\\"b\"}"
+        );
+        assert_eq!(series_key("m", &[]), "m");
+    }
+
+    #[test]
+    fn test_avg_latency_no_answers() {
+        let m = BackendMetrics {
+            requests: 2,
+            timeouts: 2,
+            ..Default::default()
+        };
+        assert_eq!(m.avg_latency(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,57 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::fsutil;
+
+/// How many recently used paths are kept for shell completion.
+const MAX_RECENT: usize = 50;
+
+/// File holding recently used paths, most recent first, one per line.
+fn recent_file() -> Option<PathBuf> {
+    dirs::cache_dir().map(|d| d.join("dprintx").join("recent-paths"))
+}
+
+/// Recently used paths, most recent first. Empty if none recorded yet.
+pub fn load() -> Vec<String> {
+    recent_file()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .map(|content| content.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Remember `path` for completion. Failures are ignored by callers:
+/// completion history is a convenience, never a reason to fail a command.
+pub fn record(path: &Path) -> Result<()> {
+    let file = recent_file().context("cannot determine cache directory")?;
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    let updated = push(load(), &path.display().to_string());
+    fsutil::write_atomic(&file, (updated.join("\n") + "\n").as_bytes())
+}
+
+/// Move `path` to the front of `recent`, dropping duplicates and the oldest
+/// entries beyond [`MAX_RECENT`].
+fn push(mut recent: Vec<String>, path: &str) -> Vec<String> {
+    recent.retain(|p| p != path);
+    recent.insert(0, path.to_string());
+    recent.truncate(MAX_RECENT);
+    recent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_moves_to_front_and_caps() {
+        let recent = push(vec!["/a".into(), "/b".into()], "/b");
+        assert_eq!(recent, vec!["/b", "/a"]);
+
+        let many: Vec<String> = (0..MAX_RECENT).map(|i| format!("/f{i}")).collect();
+        let recent = push(many, "/new");
+        assert_eq!(recent.len(), MAX_RECENT);
+        assert_eq!(recent[0], "/new");
+        assert!(!recent.contains(&format!("/f{}", MAX_RECENT - 1)));
+    }
+}
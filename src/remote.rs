@@ -0,0 +1,194 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use crate::fsutil;
+use crate::warnings;
+
+/// `remote_extends` settings: when present, remote `extends` URLs in merged
+/// configs are downloaded by dprintx (bounded by a timeout and size limit),
+/// cached, and replaced with the local copy before dprint sees them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteExtends {
+    /// Download timeout in seconds.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Largest accepted download in bytes.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// How long a cached copy is used without re-downloading.
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+fn default_max_bytes() -> u64 {
+    1024 * 1024
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+impl RemoteExtends {
+    /// Replace remote URLs in a config's `extends` (string or array) with
+    /// vendored local copies. URLs that cannot be fetched and have no cached
+    /// copy are left for dprint to resolve, with a warning.
+    pub fn vendor_extends(&self, config: &mut serde_json::Value) {
+        let Some(extends) = config.get_mut("extends") else {
+            return;
+        };
+        match extends {
+            serde_json::Value::String(url) => self.vendor_entry(url),
+            serde_json::Value::Array(entries) => {
+                for entry in entries {
+                    if let serde_json::Value::String(url) = entry {
+                        self.vendor_entry(url);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn vendor_entry(&self, entry: &mut String) {
+        if !is_remote(entry) {
+            return;
+        }
+        match self.resolve(entry) {
+            Ok(path) => *entry = path.display().to_string(),
+            Err(e) => warnings::warn(
+                "remote-extends",
+                format!("{e:#}; leaving {entry} for dprint to fetch"),
+            ),
+        }
+    }
+
+    /// Local copy of `url`: fresh cache, else a new download, else a stale cache.
+    fn resolve(&self, url: &str) -> Result<PathBuf> {
+        let cached = cache_path(url)?;
+        let age = std::fs::metadata(&cached)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| SystemTime::now().duration_since(t).ok());
+        if age.is_some_and(|a| a < Duration::from_secs(self.cache_ttl_secs)) {
+            return Ok(cached);
+        }
+
+        match self.download(url, &cached) {
+            Ok(()) => Ok(cached),
+            Err(e) if age.is_some() => {
+                warnings::warn(
+                    "remote-extends",
+                    format!("{e:#}; using stale cached copy of {url}"),
+                );
+                Ok(cached)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fetch `url` into `dest` with curl, bounded by the timeout and size limit.
+    fn download(&self, url: &str, dest: &Path) -> Result<()> {
+        if let Some(dir) = dest.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        let output = Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--location"])
+            .arg("--max-time")
+            .arg(self.timeout_secs.to_string())
+            .arg("--max-filesize")
+            .arg(self.max_bytes.to_string())
+            .arg(url)
+            .output()
+            .context("running curl for remote extends")?;
+        if !output.status.success() {
+            bail!(
+                "fetching {url} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        // --max-filesize cannot stop servers that omit Content-Length.
+        if output.stdout.len() as u64 > self.max_bytes {
+            bail!("fetching {url}: larger than {} bytes", self.max_bytes);
+        }
+        fsutil::write_atomic(dest, &output.stdout)
+    }
+}
+
+fn is_remote(entry: &str) -> bool {
+    entry.starts_with("https://") || entry.starts_with("http://")
+}
+
+/// Cache file for `url`: a readable prefix of the URL plus a hash, so
+/// distinct URLs never collide and the directory stays browsable.
+fn cache_path(url: &str) -> Result<PathBuf> {
+    let dir = dirs::cache_dir().context("cannot determine cache directory")?;
+    Ok(dir.join("dprintx").join("extends").join(cache_name(url)))
+}
+
+fn cache_name(url: &str) -> String {
+    let readable: String = url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect();
+    format!("{readable}-{:016x}.json", fnv1a(url.as_bytes()))
+}
+
+/// 64-bit FNV-1a of `bytes`: unlike std's hashers, the same in every
+/// release, so cached files stay found across upgrades.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_name() {
+        let a = cache_name("https://example.com/dprint/base.json");
+        let b = cache_name("https://example.com/dprint_base.json");
+        assert!(a.starts_with("example.com_dprint_base.json-"));
+        assert!(a.ends_with(".json"));
+        assert_ne!(a, b);
+        // Pinned: a change would orphan every cached file.
+        assert_eq!(a, "example.com_dprint_base.json-9c6b3b40b15c61c7.json");
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn test_local_entries_untouched() {
+        let remote = RemoteExtends {
+            timeout_secs: 1,
+            max_bytes: 10,
+            cache_ttl_secs: 0,
+        };
+        let mut config = serde_json::json!({ "extends": ["/profiles/main.jsonc", "./base.json"] });
+        let original = config.clone();
+        remote.vendor_extends(&mut config);
+        assert_eq!(config, original);
+    }
+}
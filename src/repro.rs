@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{DprintxConfig, ProfileResolution};
+use crate::warnings;
+
+/// Script replaying the recorded dprint invocations.
+const SCRIPT: &str = "replay.sh";
+
+/// A `--save-repro` snapshot: what a `fmt`/`check` run handed to dprint —
+/// the dprintx config, profile and merged configs, the files and the
+/// dprint version — plus a script running the same dprint commands with
+/// the saved configs. It reproduces formatting differences between
+/// machines, and with dprintx out of the picture, for reports to dprint.
+///
+/// Files are written as the run goes, so the snapshot is complete even
+/// when the run exits early on a failed check.
+pub struct Repro {
+    dir: PathBuf,
+    /// Config path as passed to dprint → its copy, relative to `dir`.
+    configs: HashMap<PathBuf, String>,
+    /// Files named on dprint command lines.
+    files: BTreeSet<String>,
+}
+
+impl Repro {
+    /// Start a snapshot in `dir` of a run with `config`, loaded from
+    /// `config_file`.
+    pub fn create(dir: &Path, config_file: &Path, config: &DprintxConfig) -> Result<Self> {
+        fs::create_dir_all(dir.join("configs"))
+            .with_context(|| format!("creating {}", dir.display()))?;
+        let mut repro = Self {
+            dir: dir.to_path_buf(),
+            configs: HashMap::new(),
+            files: BTreeSet::new(),
+        };
+
+        fs::copy(config_file, dir.join("dprintx.jsonc"))
+            .with_context(|| format!("copying {}", config_file.display()))?;
+        // Routing as used by this run: command-line rules included,
+        // profiles pointing at their copies.
+        let mut profiles = serde_json::Map::new();
+        for name in config.profiles.keys() {
+            let saved = match config.resolve_profile(name) {
+                // An unused profile may point nowhere: not worth failing the run.
+                Some(ProfileResolution::Config(path)) => match repro.save_config(&path, name) {
+                    Ok(saved) => saved.into(),
+                    Err(e) => {
+                        warnings::warn("repro", format!("{e:#}"));
+                        path.display().to_string().into()
+                    }
+                },
+                _ => serde_json::Value::Null,
+            };
+            profiles.insert(name.clone(), saved);
+        }
+        let effective = serde_json::json!({
+            "dprint": config.dprint_path(),
+            "profiles": profiles,
+            "match": config.match_rules,
+            "match_content": config.match_content,
+            "match_language": config.match_language,
+        });
+        fs::write(
+            dir.join("effective.json"),
+            serde_json::to_string_pretty(&effective)? + "\n",
+        )?;
+
+        let dprint = config.dprint_path();
+        let dprint_version = match Command::new(&dprint).arg("--version").output() {
+            Ok(output) => String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            Err(e) => format!("unknown ({}: {e})", dprint.display()),
+        };
+        fs::write(
+            dir.join("versions.txt"),
+            format!("dprintx {}\n{dprint_version}\n", env!("CARGO_PKG_VERSION")),
+        )?;
+
+        let cwd = std::env::current_dir().context("getting current directory")?;
+        let script = dir.join(SCRIPT);
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\n\
+                 # Replays the dprint commands of a dprintx run with the configs saved\n\
+                 # next to this script. Set DPRINT to try another dprint binary.\n\
+                 HERE=$(cd \"$(dirname \"$0\")\" && pwd)\n\
+                 DPRINT=${{DPRINT:-{}}}\n\
+                 cd {} || exit 1\n",
+                quote(&dprint.to_string_lossy()),
+                quote(&cwd.to_string_lossy()),
+            ),
+        )?;
+        // A shell script: only executable where there is a shell to run it.
+        #[cfg(unix)]
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+        fs::write(dir.join("files.txt"), "")?;
+        Ok(repro)
+    }
+
+    /// Add a dprint command about to run: its config is saved if new, its
+    /// files listed, and the command appended to the replay script.
+    pub fn record(&mut self, cmd: &Command) -> Result<()> {
+        let args: Vec<String> = cmd
+            .get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        let mut line = vec!["\"$DPRINT\"".to_string()];
+        let mut stdin = None;
+        let mut i = 0;
+        while i < args.len() {
+            let arg = args[i].as_str();
+            match (arg, args.get(i + 1)) {
+                _ if i == 0 => line.push(quote(arg)),
+                ("--config", Some(path)) => {
+                    let saved = self.save_config(Path::new(path), "merged")?;
+                    line.push(format!("--config \"$HERE\"/{}", quote(&saved)));
+                    i += 1;
+                }
+                ("--stdin", Some(file)) => {
+                    self.files.insert(file.clone());
+                    line.push(format!("--stdin {}", quote(file)));
+                    stdin = Some(file);
+                    i += 1;
+                }
+                _ if arg.starts_with('-') => line.push(quote(arg)),
+                _ => {
+                    self.files.insert(arg.to_string());
+                    line.push(quote(arg));
+                }
+            }
+            i += 1;
+        }
+        if let Some(file) = stdin {
+            line.push(format!("< {}", quote(file)));
+        }
+
+        let mut script = OpenOptions::new()
+            .append(true)
+            .open(self.dir.join(SCRIPT))?;
+        writeln!(script, "{}", line.join(" "))?;
+        let files: String = self.files.iter().map(|f| format!("{f}\n")).collect();
+        fs::write(self.dir.join("files.txt"), files)?;
+        Ok(())
+    }
+
+    /// Copy the dprint config at `path` into `configs/` (once) and return
+    /// the copy's path relative to the snapshot. Copies are named after
+    /// `name` and numbered when names repeat.
+    fn save_config(&mut self, path: &Path, name: &str) -> Result<String> {
+        if let Some(saved) = self.configs.get(path) {
+            return Ok(saved.clone());
+        }
+        let ext = path
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        let name: String = name
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect();
+        let mut saved = format!("configs/{name}{ext}");
+        let mut n = 1;
+        while self.configs.values().any(|s| *s == saved) {
+            n += 1;
+            saved = format!("configs/{name}-{n}{ext}");
+        }
+        fs::copy(path, self.dir.join(&saved))
+            .with_context(|| format!("copying {}", path.display()))?;
+        self.configs.insert(path.to_path_buf(), saved.clone());
+        Ok(saved)
+    }
+}
+
+/// `s` quoted for a POSIX shell.
+fn quote(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("src/a.ts"), "src/a.ts");
+        assert_eq!(quote("my file.ts"), "'my file.ts'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("$HOME"), "'$HOME'");
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Files decided by each rule during a run (`check --rule-stats`).
+#[derive(Debug, Default)]
+struct State {
+    /// Rule labels in report order, with their file counts.
+    rules: Vec<(String, usize)>,
+    /// Files already counted: a file resolved once per profile listing
+    /// still counts once.
+    seen: HashSet<PathBuf>,
+}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+/// Start counting. `rules` are listed in the report even if nothing
+/// matches them — those are the interesting ones.
+pub fn enable(rules: Vec<String>) {
+    let rules = rules.into_iter().map(|label| (label, 0)).collect();
+    *STATE.lock().unwrap() = Some(State {
+        rules,
+        seen: HashSet::new(),
+    });
+}
+
+/// Count `path` for the rule labelled by `label()`, which is only called
+/// while counting.
+pub fn record(path: &Path, label: impl FnOnce() -> String) {
+    let mut state = STATE.lock().unwrap();
+    let Some(state) = state.as_mut() else {
+        return;
+    };
+    if !state.seen.insert(path.to_path_buf()) {
+        return;
+    }
+    let label = label();
+    match state.rules.iter_mut().find(|(l, _)| *l == label) {
+        Some((_, count)) => *count += 1,
+        None => state.rules.push((label, 1)),
+    }
+}
+
+/// Print the counts to stderr, once.
+pub fn report() {
+    let Some(state) = STATE.lock().unwrap().take() else {
+        return;
+    };
+    for line in render(&state.rules) {
+        eprintln!("{line}");
+    }
+}
+
+fn render(rules: &[(String, usize)]) -> Vec<String> {
+    let mut lines = vec!["dprintx: rule usage (files per rule):".to_string()];
+    let width = rules
+        .iter()
+        .map(|(_, n)| n.to_string().len())
+        .max()
+        .unwrap_or(1);
+    for (label, count) in rules {
+        lines.push(format!("  {count:>width$}  {label}"));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let rules = vec![
+            ("match **/noc/** -> maintainer".to_string(), 12),
+            ("match ** -> default".to_string(), 340),
+            ("match_content -> ignore".to_string(), 0),
+        ];
+        assert_eq!(
+            render(&rules),
+            vec![
+                "dprintx: rule usage (files per rule):",
+                "   12  match **/noc/** -> maintainer",
+                "  340  match ** -> default",
+                "    0  match_content -> ignore",
+            ]
+        );
+    }
+}
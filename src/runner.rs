@@ -1,75 +1,422 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
 use std::io::{self, IsTerminal, Read, Write};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-
-use crate::config::{self, DprintxConfig, ProfileResolution};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cache::FormatCache;
+use crate::completions;
+use crate::config::{self, DiffFormat, DprintxConfig, Merge, ProfileResolution};
+use crate::disabled;
+use crate::editor::Backends;
+use crate::error::SpawnContext;
+use crate::fsutil;
 use crate::matcher::ProfileMatcher;
+use crate::metrics::Textfile;
+use crate::repro::Repro;
+use crate::rulestats;
+use crate::sink::StdinOutput;
+use crate::warnings;
 
 /// Runs the real dprint binary with appropriate config.
 pub struct DprintRunner {
     dprint_bin: std::path::PathBuf,
+    /// Prometheus textfile updated at the end of the run, if configured.
+    metrics_textfile: Option<PathBuf>,
+    /// Per (command, profile) stats collected during this run.
+    stats: RefCell<BTreeMap<(String, String), RunStats>>,
+    /// Exit 0 instead of failing when no file matched (`--allow-no-files`).
+    allow_no_files: bool,
+    /// Re-check formatted files after `fmt` (`--verify`).
+    verify: bool,
+    /// Report `check` results as JSON on stdout (`--format json`).
+    json: bool,
+    /// Warm `dprint editor-service` backends for per-file formatting, if
+    /// `editor_service` is enabled.
+    backends: Option<RefCell<Backends>>,
+    /// Snapshot of the dprint commands run (`--save-repro`).
+    repro: Option<RefCell<Repro>>,
+    /// Formatted output of stdin content and diffs, if `format_cache` is on.
+    cache: Option<FormatCache>,
+    /// Command (`fmt`/`check`) and `post_run` hook reporting its outcome.
+    post_run: Option<(&'static str, String)>,
+    /// Files `fmt` rewrote, counted for the `post_run` hook.
+    changed: Cell<usize>,
+    /// Start of the run, for the `post_run` hook.
+    started: Instant,
+    /// Stdin content `--attach` read before running in-process after all.
+    stdin: Option<Vec<u8>>,
+}
+
+/// dprint's exit code when no files were found; dprintx uses it too.
+pub(crate) const NO_FILES_EXIT_CODE: i32 = 14;
+
+/// Files `dprintx parity` compares without `--sample`.
+pub const PARITY_SAMPLE: usize = 100;
+
+/// Counters for dprint invocations of one command/profile pair.
+#[derive(Debug, Default)]
+struct RunStats {
+    runs: u64,
+    files: u64,
+    failures: u64,
+    duration: Duration,
 }
 
 impl DprintRunner {
     pub fn new(config: &DprintxConfig) -> Self {
         Self {
             dprint_bin: config.dprint_path(),
+            metrics_textfile: config.metrics_textfile_path(),
+            stats: RefCell::new(BTreeMap::new()),
+            allow_no_files: config.allow_no_files,
+            verify: false,
+            json: false,
+            backends: config
+                .editor_service
+                .then(|| RefCell::new(Backends::new(config.dprint_path()))),
+            repro: None,
+            cache: FormatCache::open(config, &config.dprint_path()),
+            post_run: None,
+            changed: Cell::new(0),
+            started: Instant::now(),
+            stdin: None,
+        }
+    }
+
+    /// Run `dprint_bin` instead of the config's dprint, e.g. a fake one in tests.
+    pub fn with_dprint_bin(mut self, dprint_bin: PathBuf) -> Self {
+        if self.backends.is_some() {
+            self.backends = Some(RefCell::new(Backends::new(dprint_bin.clone())));
+        }
+        self.cache = self.cache.map(|c| c.for_dprint(&dprint_bin));
+        self.dprint_bin = dprint_bin;
+        self
+    }
+
+    /// Run a `post_run` hook when this `command` run ends.
+    pub fn with_post_run(mut self, post_run: Option<(&'static str, String)>) -> Self {
+        self.post_run = post_run;
+        self
+    }
+
+    /// Modification times of `files` before `fmt`, to count the files it
+    /// rewrites for the `post_run` hook. Empty without a hook.
+    fn mtimes<S: AsRef<Path>>(&self, files: &[S]) -> Vec<Option<SystemTime>> {
+        if self.post_run.is_none() {
+            return Vec::new();
+        }
+        files
+            .iter()
+            .map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    /// Count the `files` whose modification time moved since `before`.
+    fn count_changed<S: AsRef<Path>>(&self, files: &[S], before: &[Option<SystemTime>]) {
+        let changed = files
+            .iter()
+            .zip(before)
+            .filter(|(f, t)| std::fs::metadata(f).and_then(|m| m.modified()).ok() != **t)
+            .count();
+        self.changed.set(self.changed.get() + changed);
+    }
+
+    /// Run the `post_run` hook, if any, with the outcome of the run in its
+    /// environment. Its output goes to stderr, keeping stdout to dprint; a
+    /// failing hook is only a warning.
+    pub fn post_run(&self, code: i32) {
+        let Some((command, hook)) = &self.post_run else {
+            return;
+        };
+        let (files, failures) = self
+            .stats
+            .borrow()
+            .values()
+            .fold((0, 0), |(files, failures), s| {
+                (files + s.files, failures + s.failures)
+            });
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(hook)
+            .env("DPRINTX_COMMAND", command)
+            .env("DPRINTX_EXIT_CODE", code.to_string())
+            .env("DPRINTX_FILES", files.to_string())
+            .env("DPRINTX_FAILURES", failures.to_string())
+            .env(
+                "DPRINTX_DURATION_MS",
+                self.started.elapsed().as_millis().to_string(),
+            )
+            .stdin(Stdio::null())
+            .stdout(io::stderr());
+        if *command == "fmt" {
+            cmd.env("DPRINTX_FILES_CHANGED", self.changed.get().to_string());
+        }
+        match cmd.status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => warnings::warn("post_run", format!("post_run hook failed ({status})")),
+            Err(e) => warnings::warn("post_run", format!("running post_run hook: {e}")),
+        }
+    }
+
+    /// Make `check` print a JSON report instead of dprint's output
+    /// (`--format json`).
+    pub fn with_json(mut self, json: bool) -> Self {
+        self.json = json;
+        self
+    }
+
+    /// Record every dprint command in a `--save-repro` snapshot.
+    pub fn with_repro(mut self, repro: Option<Repro>) -> Self {
+        self.repro = repro.map(RefCell::new);
+        self
+    }
+
+    /// Add `cmd` to the `--save-repro` snapshot, if one is being taken.
+    fn snapshot(&self, cmd: &Command) {
+        if let Some(repro) = &self.repro
+            && let Err(e) = repro.borrow_mut().record(cmd)
+        {
+            warnings::warn("repro", format!("saving repro failed: {e:#}"));
+        }
+    }
+
+    /// Use `input`, already read from stdin, as the `--stdin` content.
+    pub fn with_stdin(mut self, input: Option<Vec<u8>>) -> Self {
+        self.stdin = input;
+        self
+    }
+
+    /// The `--stdin` content: what was read already, else stdin itself.
+    fn stdin(&self) -> Box<dyn Read + '_> {
+        match &self.stdin {
+            Some(input) => Box::new(input.as_slice()),
+            None => Box::new(io::stdin().lock()),
+        }
+    }
+
+    /// Re-check every file after `fmt` writes it (`--verify`).
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// With `--verify`, run `dprint check` on files just formatted with
+    /// `config_path` and return those that still differ — plugin
+    /// nondeterminism or a filesystem that did not persist the write.
+    fn unverified<S: AsRef<OsStr>>(
+        &self,
+        config_path: &PathBuf,
+        files: &[S],
+    ) -> Result<Vec<PathBuf>> {
+        if !self.verify {
+            return Ok(Vec::new());
+        }
+        Ok(self.list_different(config_path, files)?.unwrap_or_default())
+    }
+
+    /// Report files that failed `--verify` and exit 1 if there are any.
+    fn report_unverified(&self, unverified: &[PathBuf]) {
+        if unverified.is_empty() {
+            return;
+        }
+        eprintln!(
+            "dprintx: verify: {} file(s) still not formatted after fmt:",
+            unverified.len()
+        );
+        for file in unverified {
+            eprintln!("  {}", file.display());
+        }
+        self.exit(1);
+    }
+
+    /// Override the config's `allow_no_files` (set by `--allow-no-files`).
+    pub fn with_allow_no_files(mut self, allow: bool) -> Self {
+        self.allow_no_files = self.allow_no_files || allow;
+        self
+    }
+
+    /// Start a `dprint <subcmd> --config <config_path>` command. With
+    /// `allow_no_files`, dprint is told so as well: a group whose files are
+    /// all excluded by the profile's own config is not an error either.
+    pub(crate) fn dprint_command(&self, subcmd: &str, config_path: &Path) -> Command {
+        let mut cmd = Command::new(&self.dprint_bin);
+        cmd.arg(subcmd).arg("--config").arg(config_path);
+        if self.allow_no_files {
+            cmd.arg("--allow-no-files");
+        }
+        cmd
+    }
+
+    /// Fail like dprint when nothing matched, unless allowed. The same rule
+    /// applies to explicit files, directories and whole-config runs.
+    fn ensure_files(&self, subcmd: &str, matched: usize) {
+        if matched == 0 && !self.allow_no_files {
+            eprintln!("dprintx: no files found to {subcmd} (use --allow-no-files to exit 0)");
+            self.exit(NO_FILES_EXIT_CODE);
         }
     }
 
+    /// Record one dprint invocation for the metrics textfile.
+    pub(crate) fn record(
+        &self,
+        command: &str,
+        profile: &str,
+        files: usize,
+        elapsed: Duration,
+        ok: bool,
+    ) {
+        let mut stats = self.stats.borrow_mut();
+        let entry = stats
+            .entry((command.to_string(), profile.to_string()))
+            .or_default();
+        entry.runs += 1;
+        entry.files += files as u64;
+        entry.duration += elapsed;
+        if !ok {
+            entry.failures += 1;
+        }
+    }
+
+    /// Run a prepared dprint command, recording it for metrics.
+    fn run_recorded(
+        &self,
+        cmd: &mut Command,
+        command: &str,
+        profile: &str,
+        files: usize,
+    ) -> io::Result<ExitStatus> {
+        self.snapshot(cmd);
+        let t0 = Instant::now();
+        let status = cmd.status()?;
+        self.record(command, profile, files, t0.elapsed(), status.success());
+        Ok(status)
+    }
+
+    /// Run a prepared dprint command with captured output, recording it for
+    /// metrics. Used by the daemon, so unix-only.
+    #[cfg(unix)]
+    pub(crate) fn output_recorded(
+        &self,
+        cmd: &mut Command,
+        command: &str,
+        profile: &str,
+        files: usize,
+    ) -> io::Result<Output> {
+        self.snapshot(cmd);
+        let t0 = Instant::now();
+        let output = cmd.output()?;
+        self.record(
+            command,
+            profile,
+            files,
+            t0.elapsed(),
+            output.status.success(),
+        );
+        Ok(output)
+    }
+
+    /// Add this run's stats to the metrics textfile (if configured).
+    pub fn flush_metrics(&self) {
+        let Some(path) = &self.metrics_textfile else {
+            return;
+        };
+        let stats = std::mem::take(&mut *self.stats.borrow_mut());
+        if stats.is_empty() {
+            return;
+        }
+
+        let mut textfile = Textfile::load(path);
+        for ((command, profile), s) in &stats {
+            let labels = [("command", command.as_str()), ("profile", profile.as_str())];
+            textfile.add("dprintx_runs_total", &labels, s.runs as f64);
+            textfile.add("dprintx_files_total", &labels, s.files as f64);
+            textfile.add("dprintx_failures_total", &labels, s.failures as f64);
+            textfile.add(
+                "dprintx_run_seconds_total",
+                &labels,
+                s.duration.as_secs_f64(),
+            );
+        }
+        if let Err(e) = textfile.write(path) {
+            warnings::warn("metrics", format!("writing metrics textfile failed: {e:#}"));
+        }
+    }
+
+    /// Run the `post_run` hook, flush metrics and exit the process.
+    fn exit(&self, code: i32) -> ! {
+        self.post_run(code);
+        self.flush_metrics();
+        rulestats::report();
+        disabled::report();
+        warnings::flush();
+        std::process::exit(code)
+    }
+
     /// Format stdin for a single file. Reads stdin, resolves config by filename,
     /// pipes through dprint fmt --stdin <filename> --config <resolved>.
     pub fn fmt_stdin(
         &self,
-        filename: &str,
+        filename: &Path,
         matcher: &ProfileMatcher,
         config: &DprintxConfig,
     ) -> Result<()> {
-        let abs_path =
-            std::fs::canonicalize(filename).unwrap_or_else(|_| std::path::PathBuf::from(filename));
-
-        let config_path = matcher
-            .resolve_config(&abs_path, config)
-            .with_context(|| format!("resolving config for {filename}"))?;
-
-        let Some(ProfileResolution::Config(profile_config)) = config_path else {
-            // No profile matched or ignore — pass through stdin unchanged.
-            let mut input = Vec::new();
-            io::stdin()
-                .read_to_end(&mut input)
-                .context("reading stdin")?;
-            io::stdout().write_all(&input)?;
+        // Only the content goes to stdout; see `sink`.
+        let mut out = StdinOutput::stdio();
+        let mut stdin = self.stdin();
+        // No profile, ignored or local config skipped — pass through unchanged.
+        // Hold the merged guard alive until dprint finishes — it deletes the temp file on drop.
+        let Some((profile_config, merged_guard)) = resolve_stdin(filename, matcher, config)? else {
+            out.content_from(&mut stdin).context("copying stdin")?;
             return Ok(());
         };
-
-        // Try to build a merged config (local dprint.json + profile extends).
-        // Hold the guard alive until dprint finishes — it deletes the temp file on drop.
-        let merged_guard = if let Some(parent) = abs_path.parent() {
-            config::build_merged_config(parent, &profile_config)?
-        } else {
-            None
-        };
         let effective_config = match &merged_guard {
             Some(tc) => tc.path(),
             None => &profile_config,
         };
 
-        // Read all stdin.
-        let mut input = Vec::new();
-        io::stdin()
-            .read_to_end(&mut input)
-            .context("reading stdin")?;
+        // Oversized input is streamed back unformatted instead of being
+        // buffered for dprint.
+        let (input, oversized) = read_stdin(
+            &mut stdin,
+            filename,
+            config.max_file_size_for(&profile_config),
+        )?;
+        if oversized {
+            out.content(&input)?;
+            out.content_from(&mut stdin).context("copying stdin")?;
+            return Ok(());
+        }
+
+        let key = self
+            .cache
+            .as_ref()
+            .and_then(|c| c.key(filename, &input, effective_config));
+        if let Some(formatted) = key.as_ref().and_then(|k| self.cache.as_ref()?.get(k)) {
+            out.content(&formatted)?;
+            out.finish()?;
+            return Ok(());
+        }
 
         // Run: dprint fmt --stdin <filename> --config <config_path>
-        let mut child = Command::new(&self.dprint_bin)
-            .args(["fmt", "--stdin", filename, "--config"])
-            .arg(effective_config)
+        let t0 = Instant::now();
+        let mut cmd = Command::new(&self.dprint_bin);
+        cmd.args(["fmt", "--stdin"])
+            .arg(filename)
+            .arg("--config")
+            .arg(effective_config);
+        self.snapshot(&cmd);
+        let mut child = cmd
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
-            .with_context(|| format!("spawning dprint: {}", self.dprint_bin.display()))?;
+            .spawn_context(&self.dprint_bin, || {
+                format!("fmt --stdin {}", filename.display())
+            })?;
 
         // Write input to child stdin.
         if let Some(ref mut stdin) = child.stdin {
@@ -79,24 +426,62 @@ impl DprintRunner {
         drop(child.stdin.take());
 
         let output = child.wait_with_output().context("waiting for dprint")?;
-
-        // Forward stderr.
-        if !output.stderr.is_empty() {
-            io::stderr().write_all(&output.stderr)?;
-        }
-
-        // Forward stdout (formatted output).
-        if !output.stdout.is_empty() {
-            io::stdout().write_all(&output.stdout)?;
+        self.record(
+            "fmt",
+            &config.profile_label(&profile_config),
+            1,
+            t0.elapsed(),
+            output.status.success(),
+        );
+
+        if let (Some(cache), Some(key)) = (&self.cache, &key)
+            && output.status.success()
+        {
+            cache.put(key, &output.stdout);
         }
 
+        // Flushed before a failure exits, which skips stdout's buffer.
+        out.dprint(&output)?;
+        out.finish()?;
         if !output.status.success() {
-            std::process::exit(output.status.code().unwrap_or(1));
+            self.exit(output.status.code().unwrap_or(1));
         }
 
         Ok(())
     }
 
+    /// Check stdin content named `filename`: print a diff (through
+    /// `diff_pager` on a TTY) and exit 1 if it is not formatted. Content
+    /// that is not UTF-8 is diffed as binary.
+    pub fn check_stdin(
+        &self,
+        filename: &Path,
+        matcher: &ProfileMatcher,
+        config: &DprintxConfig,
+    ) -> Result<()> {
+        let Some((profile_config, merged_guard)) = resolve_stdin(filename, matcher, config)? else {
+            return Ok(());
+        };
+        let effective_config = match &merged_guard {
+            Some(tc) => tc.path().to_path_buf(),
+            None => profile_config.clone(),
+        };
+
+        let (input, oversized) = read_stdin(
+            &mut self.stdin(),
+            filename,
+            config.max_file_size_for(&profile_config),
+        )?;
+        if oversized {
+            return Ok(());
+        }
+        let profile = config.profile_label(&profile_config);
+        let diff = self.unified_diff(filename, &input, &effective_config, &profile, config)?;
+        let fail = check_fails(config, [profile.as_str()]);
+        let diffs = diff.map(|diff| (filename.to_path_buf(), profile, diff));
+        self.output_diff(diffs.into_iter().collect(), fail, config)
+    }
+
     /// Output file paths for all profiles (deduped, filtered by match rules).
     pub fn output_file_paths(
         &self,
@@ -122,36 +507,152 @@ impl DprintRunner {
                 .args(["output-file-paths", "--config"])
                 .arg(profile_config)
                 .output()
-                .with_context(|| {
-                    format!("getting file paths for config {}", profile_config.display())
+                .spawn_context(&self.dprint_bin, || {
+                    format!("output-file-paths --config {}", profile_config.display())
                 })?;
 
             if output.status.success() {
-                let file_list = String::from_utf8_lossy(&output.stdout);
-                for line in file_list.lines() {
-                    let resolved = matcher.resolve_config(std::path::Path::new(line), config);
+                let files = path_lines(&output.stdout);
+                let resolved = matcher.resolve_configs(&files, config);
+                for (file, resolved) in files.into_iter().zip(resolved) {
                     if let Ok(Some(ProfileResolution::Config(ref p))) = resolved
                         && p == profile_config
                     {
-                        all_files.insert(line.to_string());
+                        all_files.insert(file);
                     }
                 }
             }
         }
 
+        let mut stdout = io::stdout().lock();
         for file in &all_files {
-            println!("{file}");
+            stdout.write_all(&fsutil::path_bytes(file))?;
+            stdout.write_all(b"\n")?;
         }
 
         Ok(())
     }
 
+    /// Print how the effective settings of two profiles differ: plugins only
+    /// one of them configures, then every differing setting. Both configs are
+    /// resolved by `dprint output-resolved-config`, so `extends` is followed.
+    /// Exits 1 if they differ, like `diff`.
+    pub fn config_diff(&self, config: &DprintxConfig, a: &str, b: &str) -> Result<()> {
+        let resolved_a = self.resolved_config(config, a)?;
+        let resolved_b = self.resolved_config(config, b)?;
+
+        let plugins = |v: &serde_json::Value| -> Vec<String> {
+            v.as_object()
+                .map(|m| m.keys().cloned().collect())
+                .unwrap_or_default()
+        };
+        let (plugins_a, plugins_b) = (plugins(&resolved_a), plugins(&resolved_b));
+        let only_a: Vec<&str> = plugins_a
+            .iter()
+            .filter(|p| !plugins_b.contains(p))
+            .map(String::as_str)
+            .collect();
+        let only_b: Vec<&str> = plugins_b
+            .iter()
+            .filter(|p| !plugins_a.contains(p))
+            .map(String::as_str)
+            .collect();
+
+        let mut changes = Vec::new();
+        for plugin in plugins_a.iter().filter(|p| plugins_b.contains(p)) {
+            diff_settings(
+                plugin,
+                &resolved_a[plugin],
+                &resolved_b[plugin],
+                &mut changes,
+            );
+        }
+
+        if only_a.is_empty() && only_b.is_empty() && changes.is_empty() {
+            println!("profiles {a} and {b} resolve to the same settings");
+            return Ok(());
+        }
+        if !only_a.is_empty() {
+            println!("plugins only in {a}: {}", only_a.join(", "));
+        }
+        if !only_b.is_empty() {
+            println!("plugins only in {b}: {}", only_b.join(", "));
+        }
+        if !changes.is_empty() {
+            println!("settings ({a} -> {b}):");
+            for change in &changes {
+                println!("  {change}");
+            }
+        }
+        self.exit(1);
+    }
+
+    /// `dprint output-resolved-config` of a profile, as JSON.
+    fn resolved_config(&self, config: &DprintxConfig, profile: &str) -> Result<serde_json::Value> {
+        let config_path = match config.resolve_profile(profile) {
+            Some(ProfileResolution::Config(p)) => p,
+            Some(ProfileResolution::Ignore) => bail!("profile '{profile}' is an ignore profile"),
+            None => bail!("profile '{profile}' is not defined in profiles"),
+        };
+        let output = Command::new(&self.dprint_bin)
+            .args(["output-resolved-config", "--config"])
+            .arg(&config_path)
+            .output()
+            .spawn_context(&self.dprint_bin, || {
+                format!("output-resolved-config --config {}", config_path.display())
+            })?;
+        if !output.status.success() {
+            bail!(
+                "dprint output-resolved-config failed for profile {profile}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("parsing resolved config of profile {profile}"))
+    }
+
     /// Generate shell completions, patching dprint's output with dprintx extras.
     pub fn completions(&self, shell: &str) -> Result<()> {
+        io::stdout().write_all(self.completion_script(shell)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Write the completion script for `shell` (from `$SHELL` when not
+    /// given) to the directory that shell loads completions from. Returns
+    /// the shell and the written file.
+    pub fn install_completions(&self, shell: Option<&str>) -> Result<(String, PathBuf)> {
+        let shell = match shell {
+            Some(shell) => shell.to_string(),
+            None => std::env::var_os("SHELL")
+                .map(PathBuf::from)
+                .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+                .context(
+                    "cannot detect the shell from $SHELL; name it: dprintx completions --install <zsh|bash|fish>",
+                )?,
+        };
+        let path = completions_path(&shell)?;
+        let mut script = self.completion_script(&shell)?;
+        // zsh binds an fpath file to the commands on its first line.
+        if shell == "zsh" {
+            let rest = match script.strip_prefix("#compdef") {
+                Some(rest) => rest.split_once('\n').map_or("", |(_, rest)| rest),
+                None => &script,
+            };
+            script = format!("#compdef dprintx dprint\n{rest}");
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        std::fs::write(&path, script).with_context(|| format!("writing {}", path.display()))?;
+        Ok((shell, path))
+    }
+
+    /// dprint's completion script for `shell` with dprintx patches and extras.
+    fn completion_script(&self, shell: &str) -> Result<String> {
         let output = Command::new(&self.dprint_bin)
             .args(["completions", shell])
             .output()
-            .context("running dprint completions")?;
+            .spawn_context(&self.dprint_bin, || format!("completions {shell}"))?;
 
         if !output.status.success() {
             io::stderr().write_all(&output.stderr)?;
@@ -167,36 +668,26 @@ impl DprintRunner {
                 "in the config file.:'",
                 "in the config file.:_files'",
             )
-            // 2. Update config subcommand description.
-            .replace(
-                "'config:Functionality related to the configuration file.'",
-                "'config:Show resolved dprintx profiles and match rules.'",
-            )
-            // 3. Update --config description.
+            // 2. Update --config description.
             .replace(
                 "Path or url to JSON configuration file. Defaults to dprint.json(c) or .dprint.json(c) in current or ancestor directory when not provided.",
                 "Override dprintx config path (default: ~/.config/dprint/dprintx.jsonc).",
-            )
-            // 4. Update lsp description.
-            .replace(
-                "'lsp:Starts up a language server for formatting files.'",
-                "'lsp:Start LSP server with dprintx multi-profile support and URI rewriting.'",
             );
 
-        io::stdout().write_all(patched.as_bytes())?;
-        Ok(())
+        Ok(patched + &completions::script(shell))
     }
 
     /// Passthrough raw args to real dprint (unknown commands, --help, etc).
     /// For --help/-h: capture output and append dprintx section.
-    pub fn passthrough_raw(&self, args: &[String]) -> Result<()> {
+    pub fn passthrough_raw(&self, args: &[OsString]) -> Result<()> {
         let is_help = args.iter().any(|a| a == "--help" || a == "-h");
+        let joined = || args.join(OsStr::new(" ")).to_string_lossy().into_owned();
 
         if is_help {
             let output = Command::new(&self.dprint_bin)
                 .args(args)
                 .output()
-                .with_context(|| format!("running dprint {}", args.join(" ")))?;
+                .spawn_context(&self.dprint_bin, joined)?;
 
             io::stdout().write_all(&output.stdout)?;
             io::stderr().write_all(&output.stderr)?;
@@ -205,13 +696,31 @@ impl DprintRunner {
             println!();
             println!("DPRINTX OPTIONS:");
             println!("  --config <PATH>     Override config path (~/.config/dprint/dprintx.jsonc)");
+            println!("  --events            Print warnings as JSON lines on stderr.");
             println!();
             println!("DPRINTX SUBCOMMANDS:");
             println!("  config              Show resolved profiles and match rules.");
             println!("  config <FILE>       Show which dprint config would be used for a file.");
+            println!(
+                "  config diff <A> <B> Show how the resolved settings of two profiles differ."
+            );
+            println!("  add-profile <NAME> <PATH>");
+            println!("                      Add a profile to dprintx.jsonc.");
+            println!("  add-rule <GLOB> <PROFILE> [--before <GLOB>]");
+            println!("                      Add a match rule (inserted before a trailing \"**\").");
+            println!("  doctor [--fix]      Check the dprint binary and profile configs.");
+            println!("  parity [--sample <N>] [PATHS]");
+            println!(
+                "                      Compare output with plain dprint's on a sample of files."
+            );
             println!();
             println!("DPRINTX CONFIG (dprintx.jsonc):");
             println!("  diff_pager          Pager for `dprint check` diffs (e.g. \"delta -s\").");
+            println!("  diff_format         \"unified\" (default) or \"context\" diffs.");
+            println!("  diff_context        Context lines in diffs (default 3).");
+            println!("                      All three can be set per profile, too.");
+            println!();
+            println!("Run `dprintx <COMMAND> --help` for the options of a dprintx command.");
             println!();
 
             std::process::exit(output.status.code().unwrap_or(0));
@@ -220,7 +729,7 @@ impl DprintRunner {
         let status = Command::new(&self.dprint_bin)
             .args(args)
             .status()
-            .with_context(|| format!("running dprint {}", args.join(" ")))?;
+            .spawn_context(&self.dprint_bin, joined)?;
 
         if !status.success() {
             std::process::exit(status.code().unwrap_or(1));
@@ -232,58 +741,43 @@ impl DprintRunner {
     /// Format explicit files, grouped by effective config (profile or merged).
     pub fn fmt_files(
         &self,
-        files: &[String],
+        files: &[PathBuf],
         matcher: &ProfileMatcher,
         config: &DprintxConfig,
     ) -> Result<()> {
         // Hold all merged config guards alive until dprint finishes.
-        let mut _guards: Vec<config::TempConfig> = Vec::new();
-        let mut groups: std::collections::HashMap<PathBuf, Vec<&str>> =
-            std::collections::HashMap::new();
+        let (groups, _guards) = group_files(files, matcher, config)?;
 
-        for file in files {
-            let abs_path = std::fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file));
-            let resolution = matcher
-                .resolve_config(&abs_path, config)
-                .with_context(|| format!("resolving config for {file}"))?;
-            if let Some(ProfileResolution::Config(profile_config)) = resolution {
-                let effective = if let Some(parent) = abs_path.parent() {
-                    match config::build_merged_config(parent, &profile_config)? {
-                        Some(tc) => {
-                            let p = tc.path().to_path_buf();
-                            _guards.push(tc);
-                            p
-                        }
-                        None => profile_config,
-                    }
-                } else {
-                    profile_config
-                };
-                groups.entry(effective).or_default().push(file);
-            }
-        }
+        self.ensure_files("fmt", groups.iter().map(|(_, _, f)| f.len()).sum());
 
         // Run dprint once per group.
         let mut failed = false;
-        for (config_path, group_files) in &groups {
-            let mut cmd = Command::new(&self.dprint_bin);
-            cmd.arg("fmt").arg("--config").arg(config_path);
+        let mut unverified = Vec::new();
+        for (config_path, profile, group_files) in &groups {
+            let mut cmd = self.dprint_command("fmt", config_path);
             for f in group_files {
                 cmd.arg(f);
             }
 
-            let status = cmd.status().with_context(|| {
-                format!("running dprint fmt --config {}", config_path.display())
-            })?;
+            let before = self.mtimes(group_files);
+            let status = self
+                .run_recorded(&mut cmd, "fmt", profile, group_files.len())
+                .spawn_context(&self.dprint_bin, || {
+                    format!("fmt --config {}", config_path.display())
+                })?;
+            self.count_changed(group_files, &before);
 
             if !status.success() {
                 failed = true;
+            } else {
+                unverified.extend(self.unverified(config_path, group_files)?);
             }
         }
 
         if failed {
-            std::process::exit(1);
+            self.exit(1);
         }
+        self.report_unverified(&unverified);
 
         Ok(())
         // _guards drop here → temp files deleted
@@ -308,9 +802,13 @@ impl DprintRunner {
     }
 
     /// Check all files using all profiles.
-    /// If diff_pager is configured, produces unified diff output.
+    /// If diff_pager is configured, produces diff output.
     pub fn check_all(&self, matcher: &ProfileMatcher, config: &DprintxConfig) -> Result<()> {
-        if config.diff_pager.is_some() {
+        if self.json {
+            let (groups, _guards) = self.collect_all(matcher, config, None)?;
+            return self.check_json(&groups, config);
+        }
+        if config.diffs_enabled() {
             return self.check_diff_all(matcher, config, None);
         }
         self.run_all("check", matcher, config, None)
@@ -323,14 +821,114 @@ impl DprintRunner {
         matcher: &ProfileMatcher,
         config: &DprintxConfig,
     ) -> Result<()> {
-        if config.diff_pager.is_some() {
+        if self.json {
+            let (groups, _guards) = self.collect_all(matcher, config, Some(dirs))?;
+            return self.check_json(&groups, config);
+        }
+        if config.diffs_enabled() {
             return self.check_diff_all(matcher, config, Some(dirs));
         }
         self.run_all("check", matcher, config, Some(dirs))
     }
 
+    /// Format a sample of at most `sample` files both as dprintx routes and
+    /// merges them and with plain `dprint fmt`, which finds a config for
+    /// each file itself, and print how the outputs differ. `files` and
+    /// `dirs` select files like `check` arguments; neither selects all.
+    /// Returns how many files format differently.
+    pub fn parity(
+        &self,
+        files: &[PathBuf],
+        dirs: &[PathBuf],
+        sample: usize,
+        matcher: &ProfileMatcher,
+        config: &DprintxConfig,
+    ) -> Result<usize> {
+        // Hold the merged configs until the last file is formatted.
+        let mut guards = Vec::new();
+        let mut selected: Vec<(PathBuf, PathBuf, String)> = Vec::new();
+        if !files.is_empty() {
+            let (groups, file_guards) = group_files(files, matcher, config)?;
+            guards.extend(file_guards);
+            for (effective, profile, files) in groups {
+                selected.extend(
+                    files
+                        .into_iter()
+                        .map(|f| (fsutil::canonical(f), effective.clone(), profile.clone())),
+                );
+            }
+        }
+        if !dirs.is_empty() || files.is_empty() {
+            let dir_filter = (!dirs.is_empty()).then_some(dirs);
+            let (groups, dir_guards) = self.collect_all(matcher, config, dir_filter)?;
+            guards.extend(dir_guards);
+            for (effective, profile, files) in groups {
+                selected.extend(
+                    files
+                        .into_iter()
+                        .map(|f| (f, effective.clone(), profile.clone())),
+                );
+            }
+        }
+        selected.sort_by(|a, b| a.0.cmp(&b.0));
+        selected.dedup_by(|a, b| a.0 == b.0);
+        let total = selected.len();
+        let selected = spread_sample(selected, sample);
+
+        let mut compared = 0;
+        let mut different = Vec::new();
+        for (file, effective, profile) in &selected {
+            let text =
+                std::fs::read(file).with_context(|| format!("reading {}", file.display()))?;
+            let routed = self.format_text(file, &text, Some(effective))?;
+            let plain = self.format_text(file, &text, None)?;
+            let report = match (routed.status.success(), plain.status.success()) {
+                (true, true) => {
+                    let labels = [
+                        format!("{} (dprint)", file.display()),
+                        format!("{} (dprintx, profile {profile})", file.display()),
+                    ];
+                    let labels = [OsStr::new(&labels[0]), OsStr::new(&labels[1])];
+                    diff_texts(&plain.stdout, &routed.stdout, labels, profile, config)?
+                }
+                (true, false) => Some(format!(
+                    "{}: plain dprint failed: {}\n",
+                    file.display(),
+                    first_line(&plain.stderr)
+                )),
+                (false, true) => Some(format!(
+                    "{}: dprintx (profile {profile}) failed: {}\n",
+                    file.display(),
+                    first_line(&routed.stderr)
+                )),
+                (false, false) => {
+                    warnings::warn(
+                        "parity",
+                        format!(
+                            "not comparing {}: dprint fails either way: {}",
+                            file.display(),
+                            first_line(&plain.stderr)
+                        ),
+                    );
+                    continue;
+                }
+            };
+            compared += 1;
+            if let Some(report) = report {
+                different.push((file.clone(), profile.clone(), report));
+            }
+        }
+
+        let differ = different.len();
+        eprintln!(
+            "dprintx parity: {compared} of {total} file(s) compared, {} same, {differ} different",
+            compared - differ
+        );
+        self.output_diff(different, false, config)?;
+        Ok(differ)
+    }
+
     /// Run a subcommand (fmt/check) for all profiles.
-    /// Files are grouped by effective config (merged local + profile, or just profile).
     /// If `dir_filter` is set, only files under those directories are included.
     fn run_all(
         &self,
@@ -339,8 +937,65 @@ impl DprintRunner {
         config: &DprintxConfig,
         dir_filter: Option<&[PathBuf]>,
     ) -> Result<()> {
-        let mut failed = false;
+        // Hold all merged config guards alive until all dprint commands finish.
+        let (effective_groups, _guards) = self.collect_all(matcher, config, dir_filter)?;
+        self.ensure_files(
+            subcmd,
+            effective_groups.iter().map(|(_, _, f)| f.len()).sum(),
+        );
 
+        // Run dprint once per effective config group.
+        let mut failed = Vec::new();
+        let mut unverified = Vec::new();
+        for (effective_config, profile_name, files) in &effective_groups {
+            if files.is_empty() {
+                continue;
+            }
+
+            let mut cmd = self.dprint_command(subcmd, effective_config);
+            for f in files {
+                cmd.arg(f);
+            }
+
+            let before = match subcmd {
+                "fmt" => self.mtimes(files),
+                _ => Vec::new(),
+            };
+            let status = self
+                .run_recorded(&mut cmd, subcmd, profile_name, files.len())
+                .spawn_context(&self.dprint_bin, || {
+                    format!("{subcmd} --config {}", effective_config.display())
+                })?;
+            self.count_changed(files, &before);
+
+            if !status.success() {
+                failed.push(profile_name.as_str());
+            } else if subcmd == "fmt" {
+                unverified.extend(self.unverified(effective_config, files)?);
+            }
+        }
+
+        let fail = match subcmd {
+            "check" => check_fails(config, failed),
+            _ => !failed.is_empty(),
+        };
+        if fail {
+            self.exit(1);
+        }
+        self.report_unverified(&unverified);
+
+        Ok(())
+    }
+
+    /// Every file of every profile (from dprint's `output-file-paths`),
+    /// grouped by effective config (merged local + profile, or just profile).
+    /// If `dir_filter` is set, only files under those directories are included.
+    fn collect_all(
+        &self,
+        matcher: &ProfileMatcher,
+        config: &DprintxConfig,
+        dir_filter: Option<&[PathBuf]>,
+    ) -> Result<(Vec<FileGroup<PathBuf>>, Vec<config::TempConfig>)> {
         // Collect unique profile config paths in order.
         let mut seen = std::collections::HashSet::new();
         let mut profile_configs: Vec<(String, std::path::PathBuf)> = Vec::new();
@@ -354,10 +1009,11 @@ impl DprintRunner {
             }
         }
 
-        // Hold all merged config guards alive until all dprint commands finish.
-        let mut _guards: Vec<config::TempConfig> = Vec::new();
-        let mut effective_groups: std::collections::HashMap<PathBuf, Vec<String>> =
-            std::collections::HashMap::new();
+        let mut guards: Vec<config::TempConfig> = Vec::new();
+        // Effective config → (profile name, files).
+        let mut effective_groups: HashMap<PathBuf, (String, Vec<PathBuf>)> = HashMap::new();
+        // Files already grouped, so `roots` overlapping profiles add nothing twice.
+        let mut known = std::collections::HashSet::new();
 
         for (profile_name, profile_config) in &profile_configs {
             // Get file list from dprint for this profile.
@@ -365,149 +1021,218 @@ impl DprintRunner {
                 .args(["output-file-paths", "--config"])
                 .arg(profile_config)
                 .output()
-                .with_context(|| {
-                    format!(
-                        "getting file paths for profile {profile_name}: {}",
-                        profile_config.display()
-                    )
+                .spawn_context(&self.dprint_bin, || {
+                    format!("output-file-paths --config {}", profile_config.display())
                 })?;
 
             if !output.status.success() {
-                eprintln!("dprintx: warning: output-file-paths failed for profile {profile_name}");
+                warnings::warn(
+                    "output-file-paths",
+                    format!("output-file-paths failed for profile {profile_name}"),
+                );
                 continue;
             }
 
-            let file_list = String::from_utf8_lossy(&output.stdout);
-            for line in file_list.lines() {
-                // Filter by directory prefixes if specified.
-                if let Some(dirs) = dir_filter {
-                    let file_path = std::path::Path::new(line);
-                    if !dirs.iter().any(|d| file_path.starts_with(d)) {
-                        continue;
-                    }
-                }
+            // Filter by directory prefixes if specified.
+            let files: Vec<PathBuf> = path_lines(&output.stdout)
+                .into_iter()
+                .filter(|f| dir_filter.is_none_or(|dirs| dirs.iter().any(|d| f.starts_with(d))))
+                .collect();
+            let resolved = matcher.resolve_configs(&files, config);
 
+            for (file_path, resolved) in files.into_iter().zip(resolved) {
                 // Only include files that match this profile.
-                let resolved = matcher.resolve_config(std::path::Path::new(line), config);
                 match resolved {
                     Ok(Some(ProfileResolution::Config(ref p))) if p == profile_config => {}
                     _ => continue,
                 }
 
-                // Resolve effective config (merged or profile).
-                let file_path = std::path::Path::new(line);
-                let effective = if let Some(parent) = file_path.parent() {
-                    match config::build_merged_config(parent, profile_config)? {
-                        Some(tc) => {
-                            let p = tc.path().to_path_buf();
-                            _guards.push(tc);
-                            p
-                        }
-                        None => profile_config.clone(),
-                    }
-                } else {
-                    profile_config.clone()
+                if exceeds_max_file_size(&file_path, config.max_file_size_for(profile_config)) {
+                    continue;
+                }
+
+                let Some(effective) =
+                    effective_config(&file_path, profile_config, config, &mut guards)?
+                else {
+                    continue;
                 };
+                known.insert(file_path.clone());
                 effective_groups
                     .entry(effective)
-                    .or_default()
-                    .push(line.to_string());
+                    .or_insert_with(|| (profile_name.clone(), Vec::new()))
+                    .1
+                    .push(file_path);
             }
         }
 
-        // Run dprint once per effective config group.
-        for (effective_config, files) in &effective_groups {
-            if files.is_empty() {
-                continue;
-            }
-
-            let mut cmd = Command::new(&self.dprint_bin);
-            cmd.arg(subcmd).arg("--config").arg(effective_config);
-            for f in files {
-                cmd.arg(f);
-            }
+        self.collect_roots(
+            matcher,
+            config,
+            dir_filter,
+            &mut known,
+            &mut effective_groups,
+            &mut guards,
+        )?;
 
-            let status = cmd.status().with_context(|| {
-                format!(
-                    "running dprint {subcmd} --config {}",
-                    effective_config.display()
-                )
-            })?;
+        Ok((in_order(effective_groups), guards))
+    }
 
-            if !status.success() {
-                failed = true;
+    /// Add the files under the configured `roots` that are not `known`
+    /// (enumerated by dprint already) to `groups`, routed by the matcher
+    /// like explicit file arguments. If `dir_filter` is set, only files
+    /// under those directories are included.
+    fn collect_roots(
+        &self,
+        matcher: &ProfileMatcher,
+        config: &DprintxConfig,
+        dir_filter: Option<&[PathBuf]>,
+        known: &mut std::collections::HashSet<PathBuf>,
+        groups: &mut HashMap<PathBuf, (String, Vec<PathBuf>)>,
+        guards: &mut Vec<config::TempConfig>,
+    ) -> Result<()> {
+        for root in config.root_paths() {
+            // A missing root (an unmounted share, a removed checkout) is
+            // not worth failing the run over.
+            let files = match fsutil::walk_files(&fsutil::canonical(&root), false) {
+                Ok(files) => files,
+                Err(e) => {
+                    warnings::warn("roots", format!("skipping root: {e:#}"));
+                    continue;
+                }
+            };
+            let files: Vec<PathBuf> = files
+                .into_iter()
+                .filter(|f| dir_filter.is_none_or(|dirs| dirs.iter().any(|d| f.starts_with(d))))
+                .filter(|f| known.insert(f.clone()))
+                .collect();
+            let resolved = matcher.resolve_configs(&files, config);
+            for (file_path, resolved) in files.into_iter().zip(resolved) {
+                let Ok(Some(ProfileResolution::Config(profile_config))) = resolved else {
+                    continue;
+                };
+                if exceeds_max_file_size(&file_path, config.max_file_size_for(&profile_config)) {
+                    continue;
+                }
+                let Some(effective) =
+                    effective_config(&file_path, &profile_config, config, guards)?
+                else {
+                    continue;
+                };
+                groups
+                    .entry(effective)
+                    .or_insert_with(|| (config.profile_label(&profile_config), Vec::new()))
+                    .1
+                    .push(file_path);
             }
         }
-
-        if failed {
-            std::process::exit(1);
-        }
-
         Ok(())
     }
 
     /// Check explicit files, grouped by effective config (profile or merged).
-    /// If diff_pager is configured, produces unified diff output.
+    /// If diff_pager is configured, produces diff output.
     pub fn check_files(
         &self,
-        files: &[String],
+        files: &[PathBuf],
         matcher: &ProfileMatcher,
         config: &DprintxConfig,
     ) -> Result<()> {
-        if config.diff_pager.is_some() {
+        let (groups, _guards) = group_files(files, matcher, config)?;
+        if self.json {
+            return self.check_json(&groups, config);
+        }
+        if config.diffs_enabled() {
             return self.check_diff_files(files, matcher, config);
         }
 
-        let mut _guards: Vec<config::TempConfig> = Vec::new();
-        let mut groups: std::collections::HashMap<PathBuf, Vec<&str>> =
-            std::collections::HashMap::new();
-
-        for file in files {
-            let abs_path = std::fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file));
-            let resolution = matcher
-                .resolve_config(&abs_path, config)
-                .with_context(|| format!("resolving config for {file}"))?;
-            if let Some(ProfileResolution::Config(profile_config)) = resolution {
-                let effective = if let Some(parent) = abs_path.parent() {
-                    match config::build_merged_config(parent, &profile_config)? {
-                        Some(tc) => {
-                            let p = tc.path().to_path_buf();
-                            _guards.push(tc);
-                            p
-                        }
-                        None => profile_config,
-                    }
-                } else {
-                    profile_config
-                };
-                groups.entry(effective).or_default().push(file);
-            }
-        }
+        self.ensure_files("check", groups.iter().map(|(_, _, f)| f.len()).sum());
 
-        let mut failed = false;
-        for (config_path, group_files) in &groups {
-            let mut cmd = Command::new(&self.dprint_bin);
-            cmd.arg("check").arg("--config").arg(config_path);
+        let mut failed = Vec::new();
+        for (config_path, profile, group_files) in &groups {
+            let mut cmd = self.dprint_command("check", config_path);
             for f in group_files {
                 cmd.arg(f);
             }
 
-            let status = cmd.status().with_context(|| {
-                format!("running dprint check --config {}", config_path.display())
-            })?;
+            let status = self
+                .run_recorded(&mut cmd, "check", profile, group_files.len())
+                .spawn_context(&self.dprint_bin, || {
+                    format!("check --config {}", config_path.display())
+                })?;
 
             if !status.success() {
-                failed = true;
+                failed.push(profile.as_str());
             }
         }
 
-        if failed {
-            std::process::exit(1);
+        if check_fails(config, failed) {
+            self.exit(1);
         }
 
         Ok(())
     }
 
+    /// Check `groups` and print one JSON report for all their files: path,
+    /// profile, effective config, severity and whether it is formatted, in
+    /// path order. Exits 1 if any file is not formatted, like `check`, unless
+    /// all of them belong to warning-level profiles.
+    fn check_json<T: AsRef<Path>>(
+        &self,
+        groups: &[FileGroup<T>],
+        config: &DprintxConfig,
+    ) -> Result<()> {
+        self.ensure_files("check", groups.iter().map(|(_, _, f)| f.len()).sum());
+
+        let mut entries: Vec<(PathBuf, serde_json::Value)> = Vec::new();
+        for (effective_config, profile, files) in groups {
+            let paths: Vec<&Path> = files.iter().map(AsRef::as_ref).collect();
+            // dprint found nothing to check (its own excludes): not reported.
+            let Some(different) = self.list_different(effective_config, &paths)? else {
+                continue;
+            };
+            let different: Vec<PathBuf> = different.iter().map(|f| comparable(f)).collect();
+            for file in paths {
+                let formatted = !different.contains(&comparable(file));
+                entries.push((
+                    file.to_path_buf(),
+                    serde_json::json!({
+                        "path": file.display().to_string(),
+                        "profile": profile,
+                        "config": effective_config.display().to_string(),
+                        "merged": config.profile_label(effective_config) != *profile,
+                        "severity": if config.warns_only(profile) { "warning" } else { "error" },
+                        "formatted": formatted,
+                    }),
+                ));
+            }
+        }
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let unformatted: Vec<String> = entries
+            .iter()
+            .filter(|(_, e)| e["formatted"] == false)
+            .filter_map(|(_, e)| e["profile"].as_str().map(String::from))
+            .collect();
+        let fail = check_fails(config, unformatted.iter().map(String::as_str));
+        // Resolved as canonical paths: shown relative like the checked files.
+        let cwd = std::env::current_dir()
+            .map(|dir| fsutil::canonical(&dir))
+            .unwrap_or_default();
+        let disabled: Vec<String> = disabled::take()
+            .iter()
+            .map(|f| f.strip_prefix(&cwd).unwrap_or(f).display().to_string())
+            .collect();
+        let report = serde_json::json!({
+            "files": entries.into_iter().map(|(_, e)| e).collect::<Vec<_>>(),
+            "unformatted": unformatted.len(),
+            "disabled": disabled,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        if fail {
+            self.exit(1);
+        }
+        Ok(())
+    }
+
     // ---- diff_pager support ----
 
     /// Check all files with unified diff output.
@@ -518,7 +1243,7 @@ impl DprintRunner {
         config: &DprintxConfig,
         dir_filter: Option<&[PathBuf]>,
     ) -> Result<()> {
-        let mut all_diff = String::new();
+        let mut diffs: Vec<(PathBuf, String, String)> = Vec::new();
         let mut _guards: Vec<config::TempConfig> = Vec::new();
 
         let mut seen = std::collections::HashSet::new();
@@ -532,203 +1257,770 @@ impl DprintRunner {
             }
         }
 
+        // list-different only names changed files; "no files" is dprint's verdict.
+        let mut found_files = false;
         for profile_config in &profile_configs {
             // Get changed files for this profile.
-            let changed = self.list_different(profile_config)?;
-            for file in &changed {
-                // Filter by directory prefixes if specified.
-                if let Some(dirs) = dir_filter {
-                    let file_path = std::path::Path::new(file.as_str());
-                    if !dirs.iter().any(|d| file_path.starts_with(d)) {
-                        continue;
-                    }
-                }
-
+            let no_files: &[&Path] = &[];
+            let Some(changed) = self.list_different(profile_config, no_files)? else {
+                continue;
+            };
+            found_files = true;
+            // Filter by directory prefixes if specified.
+            let changed: Vec<PathBuf> = changed
+                .into_iter()
+                .filter(|f| dir_filter.is_none_or(|dirs| dirs.iter().any(|d| f.starts_with(d))))
+                .collect();
+            let resolved = matcher.resolve_configs(&changed, config);
+            for (file, resolved) in changed.iter().zip(resolved) {
                 // Filter: only files that belong to this profile.
-                let resolved = matcher.resolve_config(std::path::Path::new(file), config);
                 match resolved {
                     Ok(Some(ProfileResolution::Config(ref p))) if p == profile_config => {}
                     _ => continue,
                 }
 
+                let file_path = file.as_path();
+                if exceeds_max_file_size(file_path, config.max_file_size_for(profile_config)) {
+                    continue;
+                }
+
                 // Resolve effective config (merged or profile).
-                let file_path = std::path::Path::new(file.as_str());
                 let effective = if let Some(parent) = file_path.parent() {
-                    match config::build_merged_config(parent, profile_config)? {
-                        Some(tc) => {
+                    match config::merge_local_config(
+                        parent,
+                        profile_config,
+                        config.merge_options(profile_config),
+                    )? {
+                        Merge::Merged(tc) => {
                             let p = tc.path().to_path_buf();
                             _guards.push(tc);
                             p
                         }
-                        None => profile_config.clone(),
+                        Merge::Profile => profile_config.clone(),
+                        Merge::Skip => continue,
                     }
                 } else {
                     profile_config.clone()
                 };
 
-                if let Some(diff) = self.unified_diff_for_file(file, &effective)? {
-                    all_diff.push_str(&diff);
+                let profile = config.profile_label(profile_config);
+                if let Some(diff) =
+                    self.unified_diff_for_file(file, &effective, &profile, config)?
+                {
+                    diffs.push((file.clone(), profile, diff));
                 }
             }
         }
 
-        self.output_diff(&all_diff, config)
+        // Files under `roots`: each group checked as a whole, skipping
+        // files diffed above.
+        let mut root_groups = HashMap::new();
+        let mut known = diffs.iter().map(|(file, _, _)| file.clone()).collect();
+        self.collect_roots(
+            matcher,
+            config,
+            dir_filter,
+            &mut known,
+            &mut root_groups,
+            &mut _guards,
+        )?;
+        for (effective, profile, files) in in_order(root_groups) {
+            let Some(changed) = self.list_different(&effective, &files)? else {
+                continue;
+            };
+            found_files = true;
+            for file in &changed {
+                if let Some(diff) =
+                    self.unified_diff_for_file(file, &effective, &profile, config)?
+                {
+                    diffs.push((file.clone(), profile.clone(), diff));
+                }
+            }
+        }
+
+        self.ensure_files("check", usize::from(found_files));
+        let fail = check_fails(config, diffs.iter().map(|(_, profile, _)| profile.as_str()));
+        self.output_diff(diffs, fail, config)
     }
 
     /// Check explicit files with unified diff output.
     fn check_diff_files(
         &self,
-        files: &[String],
+        files: &[PathBuf],
         matcher: &ProfileMatcher,
         config: &DprintxConfig,
     ) -> Result<()> {
-        let mut all_diff = String::new();
+        let mut diffs: Vec<(PathBuf, String, String)> = Vec::new();
         let mut _guards: Vec<config::TempConfig> = Vec::new();
+        let mut matched = 0;
 
         for file in files {
-            let abs_path = std::fs::canonicalize(file).unwrap_or_else(|_| PathBuf::from(file));
+            let abs_path = fsutil::canonical(file);
             let resolution = matcher
                 .resolve_config(&abs_path, config)
-                .with_context(|| format!("resolving config for {file}"))?;
+                .with_context(|| format!("resolving config for {}", file.display()))?;
 
             let Some(ProfileResolution::Config(profile_config)) = resolution else {
                 continue; // No profile matched or ignore — skip.
             };
+            if exceeds_max_file_size(&abs_path, config.max_file_size_for(&profile_config)) {
+                continue;
+            }
+            let profile = config.profile_label(&profile_config);
 
             // Resolve effective config (merged or profile).
             let effective = if let Some(parent) = abs_path.parent() {
-                match config::build_merged_config(parent, &profile_config)? {
-                    Some(tc) => {
+                match config::merge_local_config(
+                    parent,
+                    &profile_config,
+                    config.merge_options(&profile_config),
+                )? {
+                    Merge::Merged(tc) => {
                         let p = tc.path().to_path_buf();
                         _guards.push(tc);
                         p
                     }
-                    None => profile_config,
+                    Merge::Profile => profile_config,
+                    Merge::Skip => continue,
                 }
             } else {
                 profile_config
             };
 
-            if let Some(diff) = self.unified_diff_for_file(file, &effective)? {
-                all_diff.push_str(&diff);
+            matched += 1;
+            if let Some(diff) = self.unified_diff_for_file(file, &effective, &profile, config)? {
+                diffs.push((file.clone(), profile, diff));
             }
         }
 
-        self.output_diff(&all_diff, config)
+        self.ensure_files("check", matched);
+        let fail = check_fails(config, diffs.iter().map(|(_, profile, _)| profile.as_str()));
+        self.output_diff(diffs, fail, config)
     }
 
-    /// Get list of files that differ from formatted output.
-    fn list_different(&self, config_path: &PathBuf) -> Result<Vec<String>> {
-        let output = Command::new(&self.dprint_bin)
-            .args(["check", "--list-different", "--config"])
+    /// Get list of files that differ from formatted output: all files of
+    /// the config, or just `files` if given.
+    /// None if dprint found no files at all for the config.
+    fn list_different<S: AsRef<OsStr>>(
+        &self,
+        config_path: &PathBuf,
+        files: &[S],
+    ) -> Result<Option<Vec<PathBuf>>> {
+        let mut cmd = Command::new(&self.dprint_bin);
+        cmd.args(["check", "--list-different", "--config"])
             .arg(config_path)
-            .output()
-            .with_context(|| {
-                format!(
-                    "running dprint check --list-different --config {}",
-                    config_path.display()
-                )
-            })?;
+            .args(files);
+        self.snapshot(&cmd);
+        let output = cmd.output().spawn_context(&self.dprint_bin, || {
+            format!("check --list-different --config {}", config_path.display())
+        })?;
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        Ok(stdout.lines().map(String::from).collect())
+        if output.status.code() == Some(NO_FILES_EXIT_CODE) {
+            return Ok(None);
+        }
+        Ok(Some(path_lines(&output.stdout)))
     }
 
-    /// Generate unified diff for a single file.
-    /// Returns None if file is already formatted.
-    fn unified_diff_for_file(&self, file: &str, config_path: &PathBuf) -> Result<Option<String>> {
-        // Read original.
-        let original = std::fs::read_to_string(file).with_context(|| format!("reading {file}"))?;
-
-        // Format via dprint.
-        let mut child = Command::new(&self.dprint_bin)
-            .args(["fmt", "--stdin", file, "--config"])
-            .arg(config_path)
+    /// `dprint fmt --stdin <file>` of `text`, with `config_path`, or else
+    /// with the config dprint discovers from the file's directory.
+    fn format_text(&self, file: &Path, text: &[u8], config_path: Option<&Path>) -> Result<Output> {
+        let mut cmd = Command::new(&self.dprint_bin);
+        cmd.args(["fmt", "--stdin"]).arg(file);
+        match config_path {
+            Some(config_path) => cmd.arg("--config").arg(config_path),
+            None => cmd.current_dir(file.parent().unwrap_or(Path::new("/"))),
+        };
+        self.snapshot(&cmd);
+        let mut child = cmd
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::null())
+            .stderr(Stdio::piped())
             .spawn()
-            .with_context(|| format!("spawning dprint fmt --stdin {file}"))?;
+            .spawn_context(&self.dprint_bin, || {
+                format!("fmt --stdin {}", file.display())
+            })?;
+        // Written from another thread: dprint may fill stdout before it
+        // reads all of stdin.
+        let mut stdin = child.stdin.take().context("dprint stdin")?;
+        let text = text.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&text));
+        let output = child.wait_with_output().context("waiting for dprint")?;
+        // dprint may exit without reading its input, e.g. without a config.
+        let _ = writer.join();
+        Ok(output)
+    }
 
-        if let Some(ref mut stdin) = child.stdin {
-            stdin.write_all(original.as_bytes())?;
+    /// Generate the diff for a single file.
+    /// Returns None if file is already formatted.
+    fn unified_diff_for_file(
+        &self,
+        file: &Path,
+        config_path: &PathBuf,
+        profile: &str,
+        config: &DprintxConfig,
+    ) -> Result<Option<String>> {
+        // Read original.
+        let original =
+            std::fs::read(file).with_context(|| format!("reading {}", file.display()))?;
+        self.unified_diff(file, &original, config_path, profile, config)
+    }
+
+    /// Diff between `original` (named `file`) and its formatted form, in the
+    /// format of `profile`'s diff settings. Content that is not UTF-8 is
+    /// binary to the diff: only a changed file is reported, like `diff` does.
+    fn unified_diff(
+        &self,
+        file: &Path,
+        original: &[u8],
+        config_path: &PathBuf,
+        profile: &str,
+        config: &DprintxConfig,
+    ) -> Result<Option<String>> {
+        let t0 = Instant::now();
+        let mut cmd = Command::new(&self.dprint_bin);
+        cmd.args(["fmt", "--stdin"])
+            .arg(file)
+            .arg("--config")
+            .arg(config_path);
+        self.snapshot(&cmd);
+        let text = std::str::from_utf8(original).ok();
+        let key = self
+            .cache
+            .as_ref()
+            .and_then(|c| c.key(file, original, config_path));
+        let cached = key.as_ref().and_then(|k| self.cache.as_ref()?.get(k));
+        let hit = cached.is_some();
+        let warm = || {
+            let formatted = self.format_warm(file, text?, config_path)?;
+            Some(formatted.into_bytes())
+        };
+        let (formatted, success) = match cached.or_else(warm) {
+            Some(formatted) => (formatted, true),
+            None => {
+                let mut child = cmd
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .spawn_context(&self.dprint_bin, || {
+                        format!("fmt --stdin {}", file.display())
+                    })?;
+
+                if let Some(ref mut stdin) = child.stdin {
+                    stdin.write_all(original)?;
+                }
+                drop(child.stdin.take());
+
+                let output = child.wait_with_output()?;
+                (output.stdout, output.status.success())
+            }
+        };
+        self.record("check", profile, 1, t0.elapsed(), success);
+        if let (Some(cache), Some(key)) = (&self.cache, &key)
+            && success
+            && !hit
+        {
+            cache.put(key, &formatted);
         }
-        drop(child.stdin.take());
 
-        let output = child.wait_with_output()?;
-        let formatted = String::from_utf8_lossy(&output.stdout);
+        let label = file.as_os_str();
+        diff_texts(original, &formatted, [label, label], profile, config)
+    }
 
-        if original == formatted.as_ref() {
-            return Ok(None);
+    /// `text` of `file` formatted by a warm editor-service backend, or None
+    /// if backends are off or could not format it.
+    fn format_warm(&self, file: &Path, text: &str, config_path: &Path) -> Option<String> {
+        let path = std::path::absolute(file).ok()?;
+        self.backends
+            .as_ref()?
+            .borrow_mut()
+            .format(config_path, &path, text)
+    }
+
+    /// Output collected `(file, profile, diff)`s in file path order: on a
+    /// TTY each profile's through its `diff_pager`, every pager run once,
+    /// otherwise raw. Exits 1 afterwards if `fail` (some diff is from an
+    /// error-level profile).
+    fn output_diff(
+        &self,
+        mut diffs: Vec<(PathBuf, String, String)>,
+        fail: bool,
+        config: &DprintxConfig,
+    ) -> Result<()> {
+        if diffs.is_empty() {
+            return Ok(());
+        }
+        diffs.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Diffs by pager, in the order of each pager's first file.
+        let mut paged: Vec<(Option<String>, String)> = Vec::new();
+        let tty = io::stdout().is_terminal();
+        for (_, profile, diff) in diffs {
+            let pager = tty.then(|| config.diff_settings(&profile).pager).flatten();
+            match paged.iter_mut().find(|(p, _)| *p == pager) {
+                Some((_, text)) => text.push_str(&diff),
+                None => paged.push((pager, diff)),
+            }
+        }
+        for (pager, diff) in paged {
+            match pager {
+                Some(pager_cmd) => page_diff(&pager_cmd, &diff)?,
+                // Not a TTY or no pager: raw diff to stdout.
+                None => io::stdout().write_all(diff.as_bytes())?,
+            }
         }
 
-        // Build unified diff via system diff.
-        let label = file.to_string();
+        if fail {
+            self.exit(1);
+        }
 
-        let tmp_dir = std::env::temp_dir();
-        let orig_path = tmp_dir.join("dprintx-orig");
-        let fmt_path = tmp_dir.join("dprintx-fmt");
-        std::fs::write(&orig_path, &original)?;
-        std::fs::write(&fmt_path, formatted.as_bytes())?;
+        Ok(())
+    }
+}
 
-        let diff_out = Command::new("diff")
-            .args(["-u", "--label", &label, "--label", &label])
-            .arg(&orig_path)
-            .arg(&fmt_path)
-            .output()
-            .context("running diff")?;
+/// Diff from `a` to `b`, labeled `labels`, in the format of `profile`'s diff
+/// settings; None if they are the same. Content that is not UTF-8 is binary
+/// to the diff: only that it changed is reported, like `diff` does.
+fn diff_texts(
+    a: &[u8],
+    b: &[u8],
+    labels: [&OsStr; 2],
+    profile: &str,
+    config: &DprintxConfig,
+) -> Result<Option<String>> {
+    if a == b {
+        return Ok(None);
+    }
+    if std::str::from_utf8(a).is_err() || std::str::from_utf8(b).is_err() {
+        return Ok(Some(format!(
+            "Binary file {} differs\n",
+            labels[0].to_string_lossy()
+        )));
+    }
 
-        let _ = std::fs::remove_file(&orig_path);
-        let _ = std::fs::remove_file(&fmt_path);
+    // Build the diff via system diff.
+    let settings = config.diff_settings(profile);
+    let format = match settings.format {
+        DiffFormat::Unified => format!("-U{}", settings.context),
+        DiffFormat::Context => format!("-C{}", settings.context),
+    };
+
+    let tmp_dir = std::env::temp_dir();
+    let orig_path = tmp_dir.join("dprintx-orig");
+    let fmt_path = tmp_dir.join("dprintx-fmt");
+    std::fs::write(&orig_path, a)?;
+    std::fs::write(&fmt_path, b)?;
+
+    let diff_out = Command::new("diff")
+        .arg(&format)
+        .args([OsStr::new("--label"), labels[0]])
+        .args([OsStr::new("--label"), labels[1]])
+        .arg(&orig_path)
+        .arg(&fmt_path)
+        .output()
+        .context("running diff")?;
+
+    let _ = std::fs::remove_file(&orig_path);
+    let _ = std::fs::remove_file(&fmt_path);
+
+    let diff_text = String::from_utf8_lossy(&diff_out.stdout);
+    if diff_text.is_empty() {
+        return Ok(None);
+    }
 
-        let diff_text = String::from_utf8_lossy(&diff_out.stdout);
-        if diff_text.is_empty() {
-            return Ok(None);
-        }
+    Ok(Some(diff_text.into_owned()))
+}
+
+/// Show `diff` through the pager command `pager_cmd`; raw if it is empty.
+fn page_diff(pager_cmd: &str, diff: &str) -> Result<()> {
+    let parts: Vec<&str> = pager_cmd.split_whitespace().collect();
+    let Some((cmd, args)) = parts.split_first() else {
+        io::stdout().write_all(diff.as_bytes())?;
+        return Ok(());
+    };
+    let mut command = Command::new(cmd);
+    command.args(args);
+    if is_delta(cmd) {
+        configure_delta(&mut command, args, diff.lines().count());
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("spawning pager: {pager_cmd}"))?;
 
-        Ok(Some(diff_text.into_owned()))
+    if let Some(ref mut stdin) = child.stdin {
+        let _ = stdin.write_all(diff.as_bytes());
     }
+    drop(child.stdin.take());
 
-    /// Output collected diff: through pager if TTY, raw if pipe.
-    fn output_diff(&self, diff: &str, config: &DprintxConfig) -> Result<()> {
-        if diff.is_empty() {
-            return Ok(());
+    let _ = child.wait()?;
+    Ok(())
+}
+
+/// Whether `check` fails for unformatted files of `profiles`. Those of
+/// warning-level profiles are only reported, once per profile.
+pub(crate) fn check_fails<'a>(
+    config: &DprintxConfig,
+    profiles: impl IntoIterator<Item = &'a str>,
+) -> bool {
+    let mut fail = false;
+    let mut warned = std::collections::BTreeSet::new();
+    for profile in profiles {
+        if config.warns_only(profile) {
+            warned.insert(profile);
+        } else {
+            fail = true;
         }
+    }
+    for profile in warned {
+        warnings::warn(
+            "severity",
+            format!("profile {profile} has unformatted files (severity: warning, not failing)"),
+        );
+    }
+    fail
+}
 
-        let has_diff = !diff.is_empty();
+/// Append `path: a -> b` lines for every setting that differs between two
+/// resolved configs, descending into objects. Arrays compare as a whole.
+fn diff_settings(path: &str, a: &serde_json::Value, b: &serde_json::Value, out: &mut Vec<String>) {
+    match (a, b) {
+        (serde_json::Value::Object(ma), serde_json::Value::Object(mb)) => {
+            let mut keys: Vec<&String> = ma.keys().collect();
+            keys.extend(mb.keys().filter(|k| !ma.contains_key(*k)));
+            for key in keys {
+                let child = format!("{path}.{key}");
+                match (ma.get(key), mb.get(key)) {
+                    (Some(va), Some(vb)) => diff_settings(&child, va, vb, out),
+                    (Some(va), None) => out.push(format!("{child}: {va} -> (unset)")),
+                    (None, Some(vb)) => out.push(format!("{child}: (unset) -> {vb}")),
+                    (None, None) => {}
+                }
+            }
+        }
+        _ if a != b => out.push(format!("{path}: {a} -> {b}")),
+        _ => {}
+    }
+}
 
-        if io::stdout().is_terminal() {
-            // TTY: pipe through diff_pager.
-            if let Some(ref pager_cmd) = config.diff_pager {
-                let parts: Vec<&str> = pager_cmd.split_whitespace().collect();
-                if let Some((cmd, args)) = parts.split_first() {
-                    let mut child = Command::new(cmd)
-                        .args(args)
-                        .stdin(Stdio::piped())
-                        .spawn()
-                        .with_context(|| format!("spawning pager: {pager_cmd}"))?;
+fn is_delta(cmd: &str) -> bool {
+    std::path::Path::new(cmd)
+        .file_name()
+        .is_some_and(|name| name == "delta")
+}
 
-                    if let Some(ref mut stdin) = child.stdin {
-                        let _ = stdin.write_all(diff.as_bytes());
-                    }
-                    drop(child.stdin.take());
+/// Adjust a `delta` pager invocation. delta reads a pipe, so under tmux and
+/// some terminals it guesses the width wrong and mis-wraps (worst with
+/// side-by-side from `DELTA_FEATURES`): pass the real terminal size via
+/// `COLUMNS`/`LINES` and `--width`. Page only diffs taller than the terminal,
+/// so short diffs don't flash through `less`. User-given flags win.
+fn configure_delta(command: &mut Command, args: &[&str], diff_lines: usize) {
+    let has_flag = |long: &str, short: Option<&str>| {
+        args.iter()
+            .any(|a| *a == long || a.starts_with(&format!("{long}=")) || Some(*a) == short)
+    };
+    let size = terminal_size();
+
+    if let Some((rows, cols)) = size {
+        command
+            .env("COLUMNS", cols.to_string())
+            .env("LINES", rows.to_string());
+        if !has_flag("--width", Some("-w")) {
+            command.arg(format!("--width={cols}"));
+        }
+    }
+    if !has_flag("--paging", None) {
+        let taller = size.is_none_or(|(rows, _)| diff_lines >= rows as usize);
+        command.arg(if taller {
+            "--paging=always"
+        } else {
+            "--paging=never"
+        });
+    }
+}
 
-                    let _ = child.wait()?;
-                    if has_diff {
-                        std::process::exit(1);
-                    }
-                    return Ok(());
+/// Terminal (rows, columns) from `stty size` on the controlling terminal,
+/// falling back to `LINES`/`COLUMNS`.
+fn terminal_size() -> Option<(u16, u16)> {
+    let from_stty = std::fs::File::open("/dev/tty").ok().and_then(|tty| {
+        let output = Command::new("stty")
+            .arg("size")
+            .stdin(tty)
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        let text = String::from_utf8_lossy(&output.stdout);
+        let mut parts = text.split_whitespace().map(|p| p.parse::<u16>().ok());
+        Some((parts.next()??, parts.next()??))
+    });
+    from_stty.or_else(|| {
+        let env = |key: &str| std::env::var(key).ok()?.parse::<u16>().ok();
+        Some((env("LINES")?, env("COLUMNS")?))
+    })
+}
+
+/// Explicit files grouped by effective config (merged local + profile, or
+/// just the profile), with profile names. Unmatched, ignored and oversized
+/// files are left out. The returned guards keep merged configs on disk and
+/// must outlive the dprint runs.
+pub(crate) fn group_files<'a>(
+    files: &'a [PathBuf],
+    matcher: &ProfileMatcher,
+    config: &DprintxConfig,
+) -> Result<(Vec<FileGroup<&'a Path>>, Vec<config::TempConfig>)> {
+    let mut guards: Vec<config::TempConfig> = Vec::new();
+    let groups = group_files_with(files, matcher, config, |parent, profile_config| {
+        Ok(
+            match config::merge_local_config(
+                parent,
+                profile_config,
+                config.merge_options(profile_config),
+            )? {
+                Merge::Merged(tc) => {
+                    let p = tc.path().to_path_buf();
+                    guards.push(tc);
+                    Some(p)
                 }
-            }
+                Merge::Profile => Some(profile_config.to_path_buf()),
+                Merge::Skip => None,
+            },
+        )
+    })?;
+    Ok((groups, guards))
+}
+
+/// [`group_files`] with the effective config of a file in directory
+/// `parent` chosen by `merge(parent, profile_config)`; None skips the file.
+/// The caller owns whatever temp configs `merge` creates.
+pub(crate) fn group_files_with<'a>(
+    files: &'a [PathBuf],
+    matcher: &ProfileMatcher,
+    config: &DprintxConfig,
+    mut merge: impl FnMut(&Path, &Path) -> Result<Option<PathBuf>>,
+) -> Result<Vec<FileGroup<&'a Path>>> {
+    let mut groups: HashMap<PathBuf, (String, Vec<&Path>)> = HashMap::new();
+
+    let abs_paths: Vec<PathBuf> = files.iter().map(fsutil::canonical).collect();
+    let resolved = matcher.resolve_configs(&abs_paths, config);
+    for ((file, abs_path), resolution) in files.iter().zip(abs_paths).zip(resolved) {
+        let resolution =
+            resolution.with_context(|| format!("resolving config for {}", file.display()))?;
+        let Some(ProfileResolution::Config(profile_config)) = resolution else {
+            continue;
+        };
+        if exceeds_max_file_size(&abs_path, config.max_file_size_for(&profile_config)) {
+            continue;
         }
+        let profile = config.profile_label(&profile_config);
+        let effective = match abs_path.parent() {
+            Some(parent) => match merge(parent, &profile_config)? {
+                Some(effective) => effective,
+                None => continue,
+            },
+            None => profile_config,
+        };
+        groups
+            .entry(effective)
+            .or_insert_with(|| (profile, Vec::new()))
+            .1
+            .push(file);
+    }
+    Ok(in_order(groups))
+}
 
-        // Not a TTY or no pager: raw unified diff to stdout.
-        io::stdout().write_all(diff.as_bytes())?;
+/// At most `n` of `items`, spread evenly over them, so a sample of a tree
+/// touches all of it and the same sample is taken again.
+fn spread_sample<T>(items: Vec<T>, n: usize) -> Vec<T> {
+    let len = items.len();
+    if len <= n {
+        return items;
+    }
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| (i * n / len) != ((i + 1) * n / len))
+        .map(|(_, item)| item)
+        .collect()
+}
+
+/// First line of dprint's stderr, for a one-line report.
+fn first_line(stderr: &[u8]) -> String {
+    let text = String::from_utf8_lossy(stderr);
+    text.lines()
+        .find(|l| !l.trim().is_empty())
+        .unwrap_or("(no message)")
+        .trim()
+        .to_string()
+}
 
-        if has_diff {
-            std::process::exit(1);
+/// Files sharing an effective config: (effective config, profile name, files).
+pub(crate) type FileGroup<T> = (PathBuf, String, Vec<T>);
+
+/// Groups in a reproducible order, independent of hashing and locale:
+/// files sorted by path within each group, groups by their first file.
+/// Effective configs may be temp files with random names, so they cannot
+/// order the groups themselves.
+fn in_order<T: AsRef<Path>>(groups: HashMap<PathBuf, (String, Vec<T>)>) -> Vec<FileGroup<T>> {
+    let mut groups: Vec<FileGroup<T>> = groups
+        .into_iter()
+        .map(|(config, (profile, mut files))| {
+            files.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+            (config, profile, files)
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        let first = |g: &FileGroup<T>| g.2.first().map(|f| f.as_ref().to_path_buf());
+        first(a).cmp(&first(b)).then_with(|| a.1.cmp(&b.1))
+    });
+    groups
+}
+
+/// `path` in a form comparable with dprint's own spelling of it.
+fn comparable(path: &Path) -> PathBuf {
+    if path.exists() {
+        return fsutil::canonical(path);
+    }
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The paths dprint printed one per line, taken as bytes: a file name need
+/// not be UTF-8.
+fn path_lines(stdout: &[u8]) -> Vec<PathBuf> {
+    stdout
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+        .map(fsutil::path_from_bytes)
+        .collect()
+}
+
+/// Resolve the profile config for stdin content named `filename`, plus the
+/// merged config guard if a local config applies. None if the content is not
+/// formatted: no profile, an ignore profile, or a skipped local config.
+pub(crate) fn resolve_stdin(
+    filename: &Path,
+    matcher: &ProfileMatcher,
+    config: &DprintxConfig,
+) -> Result<Option<(PathBuf, Option<config::TempConfig>)>> {
+    let abs_path = fsutil::canonical(filename);
+
+    let resolution = matcher
+        .resolve_config(&abs_path, config)
+        .with_context(|| format!("resolving config for {}", filename.display()))?;
+    let Some(ProfileResolution::Config(profile_config)) = resolution else {
+        return Ok(None);
+    };
+
+    // Try to build a merged config (local dprint.json + profile extends).
+    let merged = match abs_path.parent() {
+        Some(parent) => {
+            match config::merge_local_config(
+                parent,
+                &profile_config,
+                config.merge_options(&profile_config),
+            )? {
+                Merge::Merged(tc) => Some(tc),
+                Merge::Profile => None,
+                Merge::Skip => return Ok(None),
+            }
         }
+        None => None,
+    };
+    Ok(Some((profile_config, merged)))
+}
 
-        Ok(())
+/// Read `stdin` up to `limit` bytes. Returns the bytes read and whether the
+/// limit was exceeded (with a warning); the rest of stdin is left unread.
+fn read_stdin(
+    stdin: &mut impl Read,
+    filename: &Path,
+    limit: Option<u64>,
+) -> Result<(Vec<u8>, bool)> {
+    let mut input = Vec::new();
+    stdin
+        .take(limit.map_or(u64::MAX, |l| l + 1))
+        .read_to_end(&mut input)
+        .context("reading stdin")?;
+    if let Some(limit) = limit
+        && input.len() as u64 > limit
+    {
+        warnings::warn(
+            "file-size",
+            format!(
+                "not formatting {}: stdin exceeds max_file_size ({limit})",
+                filename.display()
+            ),
+        );
+        return Ok((input, true));
+    }
+    Ok((input, false))
+}
+
+/// Effective config for `file`: its profile config merged with the local
+/// dprint config above it (the temp file kept alive in `guards`), or the
+/// profile config itself. None if the local config says to skip the file.
+fn effective_config(
+    file: &Path,
+    profile_config: &Path,
+    config: &DprintxConfig,
+    guards: &mut Vec<config::TempConfig>,
+) -> Result<Option<PathBuf>> {
+    let Some(parent) = file.parent() else {
+        return Ok(Some(profile_config.to_path_buf()));
+    };
+    Ok(
+        match config::merge_local_config(
+            parent,
+            profile_config,
+            config.merge_options(profile_config),
+        )? {
+            Merge::Merged(tc) => {
+                let p = tc.path().to_path_buf();
+                guards.push(tc);
+                Some(p)
+            }
+            Merge::Profile => Some(profile_config.to_path_buf()),
+            Merge::Skip => None,
+        },
+    )
+}
+
+/// True (with a warning) if `path` is larger than `limit` bytes.
+fn exceeds_max_file_size(path: &std::path::Path, limit: Option<u64>) -> bool {
+    let Some(limit) = limit else {
+        return false;
+    };
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.len() > limit => {
+            warnings::warn(
+                "file-size",
+                format!(
+                    "skipping {}: {} bytes exceeds max_file_size ({limit})",
+                    path.display(),
+                    meta.len()
+                ),
+            );
+            true
+        }
+        _ => false,
     }
 }
+
+/// Where `shell` loads completions for `dprintx` from on its own: the
+/// conventional per-user directory (`~/.zsh/completions`, which must be in
+/// `fpath`; bash-completion's and fish's XDG directories).
+fn completions_path(shell: &str) -> Result<PathBuf> {
+    let home = dirs::home_dir().context("cannot determine home directory")?;
+    let xdg = |var: &str, default: &str| {
+        std::env::var_os(var)
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .unwrap_or_else(|| home.join(default))
+    };
+    Ok(match shell {
+        "zsh" => home.join(".zsh/completions/_dprintx"),
+        "bash" => xdg("XDG_DATA_HOME", ".local/share").join("bash-completion/completions/dprintx"),
+        "fish" => xdg("XDG_CONFIG_HOME", ".config").join("fish/completions/dprintx.fish"),
+        _ => bail!("cannot install completions for {shell}: supported are zsh, bash and fish"),
+    })
+}
@@ -0,0 +1,114 @@
+//! Output of `fmt --stdin`. Editors replace the buffer with whatever a
+//! stdin run writes to stdout, so the content is the only thing that may
+//! reach it: warnings, traces and dprint's own messages go to stderr.
+
+use std::io::{self, Read, Write};
+use std::process::Output;
+
+/// The two streams of a `fmt --stdin` run: `content` gets the formatted
+/// (or unchanged) text and nothing else, `diagnostics` everything else.
+pub(crate) struct StdinOutput<C: Write, D: Write> {
+    content: C,
+    diagnostics: D,
+}
+
+impl StdinOutput<io::Stdout, io::Stderr> {
+    /// The process's stdout and stderr.
+    pub(crate) fn stdio() -> Self {
+        Self::new(io::stdout(), io::stderr())
+    }
+}
+
+impl<C: Write, D: Write> StdinOutput<C, D> {
+    pub(crate) fn new(content: C, diagnostics: D) -> Self {
+        Self {
+            content,
+            diagnostics,
+        }
+    }
+
+    /// Write (part of) the content.
+    pub(crate) fn content(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.content.write_all(bytes)
+    }
+
+    /// Stream the rest of `input` as content, e.g. oversized stdin.
+    pub(crate) fn content_from(&mut self, input: &mut impl Read) -> io::Result<u64> {
+        io::copy(input, &mut self.content)
+    }
+
+    /// Forward a finished `dprint fmt --stdin`. Its stdout is content only
+    /// if dprint succeeded: a failed run's stdout is a partial result or a
+    /// message, and goes to diagnostics after its stderr.
+    pub(crate) fn dprint(&mut self, output: &Output) -> io::Result<()> {
+        self.diagnostics.write_all(&output.stderr)?;
+        if output.status.success() {
+            self.content.write_all(&output.stdout)
+        } else {
+            self.diagnostics.write_all(&output.stdout)
+        }
+    }
+
+    /// Flush both streams and hand them back.
+    pub(crate) fn finish(mut self) -> io::Result<(C, D)> {
+        self.content.flush()?;
+        self.diagnostics.flush()?;
+        Ok((self.content, self.diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    fn output(code: i32, stdout: &str, stderr: &str) -> Output {
+        Output {
+            // A wait status: the exit code in the second byte.
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.into(),
+            stderr: stderr.into(),
+        }
+    }
+
+    fn run(f: impl FnOnce(&mut StdinOutput<Vec<u8>, Vec<u8>>)) -> (String, String) {
+        let mut sink = StdinOutput::new(Vec::new(), Vec::new());
+        f(&mut sink);
+        let (content, diagnostics) = sink.finish().unwrap();
+        (
+            String::from_utf8(content).unwrap(),
+            String::from_utf8(diagnostics).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_dprint_success_is_content() {
+        let (content, diagnostics) = run(|sink| {
+            sink.dprint(&output(0, "let a = 1;\n", "Compiling wasm module...\n"))
+                .unwrap()
+        });
+        assert_eq!(content, "let a = 1;\n");
+        assert_eq!(diagnostics, "Compiling wasm module...\n");
+    }
+
+    #[test]
+    fn test_dprint_failure_is_diagnostics() {
+        let (content, diagnostics) = run(|sink| {
+            sink.dprint(&output(1, "partial", "Error formatting a.ts\n"))
+                .unwrap()
+        });
+        assert_eq!(content, "");
+        assert_eq!(diagnostics, "Error formatting a.ts\npartial");
+    }
+
+    #[test]
+    fn test_content_from_streams_input() {
+        let (content, diagnostics) = run(|sink| {
+            sink.content(b"head ").unwrap();
+            sink.content_from(&mut "rest".as_bytes()).unwrap();
+        });
+        assert_eq!(content, "head rest");
+        assert_eq!(diagnostics, "");
+    }
+}
@@ -0,0 +1,465 @@
+//! Test support (`test-support` feature): a scriptable fake dprint, for
+//! end-to-end tests of grouping, merging and routing without a dprint
+//! install. The `fake-dprint` binary runs [`fake_dprint_main`], both as the
+//! CLI (`fmt`, `check`, `output-file-paths`, ...) and as `dprint lsp`;
+//! [`FakeDprint`] sets it up in a directory of its own, next to the
+//! [`Script`] it follows and the log of [`Call`]s it got.
+//!
+//! Hand [`FakeDprint::path`] to [`DprintRunner::with_dprint_bin`] or
+//! [`LspProxy::new`], and drive the proxy with [`LspProxy::run_on`].
+//!
+//! [`DprintRunner::with_dprint_bin`]: crate::runner::DprintRunner::with_dprint_bin
+//! [`LspProxy::new`]: crate::lsp::LspProxy::new
+//! [`LspProxy::run_on`]: crate::lsp::LspProxy::run_on
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const SCRIPT: &str = "script.json";
+const CALLS: &str = "calls.jsonl";
+
+/// What the fake dprint does.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Script {
+    /// Formatting: each `(from, to)` is replaced in the text, in order. A
+    /// file that changes is reformatted by `fmt` and fails `check`.
+    pub rewrite: Vec<(String, String)>,
+    /// Configs dprint fails to load: every command with one of them fails.
+    pub broken: Vec<PathBuf>,
+    /// `capabilities` of the `initialize` result of `dprint lsp`; default
+    /// whole-document formatting only.
+    pub capabilities: Option<serde_json::Value>,
+    /// How long `fmt --stdin` takes, in milliseconds.
+    pub stdin_delay_ms: u64,
+}
+
+impl Script {
+    fn apply(&self, text: &str) -> String {
+        self.rewrite
+            .iter()
+            .fold(text.to_string(), |text, (from, to)| text.replace(from, to))
+    }
+}
+
+/// One invocation of the fake dprint, or one message a fake `dprint lsp`
+/// received.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Call {
+    /// The subcommand: `fmt`, `check`, `lsp`, ...
+    pub command: String,
+    /// The `--config` argument.
+    pub config: Option<PathBuf>,
+    /// File arguments, the `--stdin` file name (lossily as UTF-8), or the
+    /// document URI of an LSP message (the `rootUri` of `initialize`).
+    pub files: Vec<String>,
+    /// Method of an LSP message.
+    pub method: Option<String>,
+}
+
+/// The `fake-dprint` binary, set up as `<dir>/dprint`.
+pub struct FakeDprint {
+    dir: PathBuf,
+}
+
+impl FakeDprint {
+    /// Link `bin` (the `fake-dprint` binary, `env!("CARGO_BIN_EXE_fake-dprint")`
+    /// in integration tests) into `dir`, which is created, and script it.
+    pub fn new(bin: &Path, dir: &Path, script: &Script) -> Result<Self> {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        let fake = Self {
+            dir: dir.to_path_buf(),
+        };
+        let _ = std::fs::remove_file(fake.path());
+        std::os::unix::fs::symlink(bin, fake.path())
+            .with_context(|| format!("linking {}", bin.display()))?;
+        fake.script(script)?;
+        fake.clear_calls();
+        Ok(fake)
+    }
+
+    /// The fake dprint binary.
+    pub fn path(&self) -> PathBuf {
+        self.dir.join("dprint")
+    }
+
+    /// Replace the script; running backends pick it up with their next message.
+    pub fn script(&self, script: &Script) -> Result<()> {
+        std::fs::write(self.dir.join(SCRIPT), serde_json::to_string(script)?)
+            .context("writing fake dprint script")
+    }
+
+    /// Calls so far, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        std::fs::read_to_string(self.dir.join(CALLS))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+
+    pub fn clear_calls(&self) {
+        let _ = std::fs::remove_file(self.dir.join(CALLS));
+    }
+}
+
+/// A writer whose bytes stay readable after it is handed away, for the
+/// output of [`LspProxy::run_on`](crate::lsp::LspProxy::run_on).
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `msg` framed as an LSP message.
+pub fn lsp_frame(msg: &serde_json::Value) -> Vec<u8> {
+    let body = msg.to_string();
+    let mut frame = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+    frame.extend_from_slice(body.as_bytes());
+    frame
+}
+
+/// The LSP messages in `bytes`.
+pub fn lsp_messages(bytes: &[u8]) -> Result<Vec<serde_json::Value>> {
+    let mut reader = bytes;
+    let mut messages = Vec::new();
+    while !reader.is_empty() {
+        messages.push(lsp_message(&mut reader)?);
+    }
+    Ok(messages)
+}
+
+/// The next LSP message from `reader`, e.g. a connection to the proxy.
+pub fn lsp_message(reader: &mut impl io::BufRead) -> Result<serde_json::Value> {
+    let body = crate::lsp::read_lsp_message(reader, usize::MAX)?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Entry point of the `fake-dprint` binary. The script and the call log
+/// live next to the path it was started as.
+pub fn fake_dprint_main() -> ! {
+    let mut args = std::env::args_os();
+    let dir = args
+        .next()
+        .map(PathBuf::from)
+        .and_then(|argv0| argv0.parent().map(Path::to_path_buf))
+        .unwrap_or_default();
+    let args: Vec<OsString> = args.collect();
+    let code = match fake_dprint(&dir, &args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("fake dprint: {e:#}");
+            1
+        }
+    };
+    std::process::exit(code)
+}
+
+fn fake_dprint(dir: &Path, args: &[OsString]) -> Result<i32> {
+    let script: Script = match std::fs::read_to_string(dir.join(SCRIPT)) {
+        Ok(content) => serde_json::from_str(&content).context("reading fake dprint script")?,
+        Err(_) => Script::default(),
+    };
+    let Some(command) = args.first().map(|c| c.to_string_lossy().into_owned()) else {
+        bail!("no command");
+    };
+    if command == "--version" || command == "-V" {
+        println!("dprint 0.0.0-fake");
+        return Ok(0);
+    }
+
+    let mut config = None;
+    let mut stdin_file = None;
+    let mut files = Vec::new();
+    let mut flags = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.to_str().unwrap_or_default() {
+            "--config" | "-c" => config = rest.next().map(PathBuf::from),
+            "--stdin" => stdin_file = rest.next().map(PathBuf::from),
+            flag if flag.starts_with('-') => flags.push(flag),
+            _ => files.push(PathBuf::from(arg)),
+        }
+    }
+    let call = Call {
+        command: command.clone(),
+        config: config.clone(),
+        files: stdin_file
+            .iter()
+            .chain(&files)
+            .map(|f| f.to_string_lossy().into_owned())
+            .collect(),
+        method: None,
+    };
+    if command != "lsp" {
+        log_call(dir, &call);
+    }
+    if let Some(config) = &config
+        && script.broken.contains(config)
+    {
+        eprintln!("Error resolving plugin https://plugins.dprint.dev/broken.wasm: 404");
+        return Ok(1);
+    }
+
+    match command.as_str() {
+        "output-resolved-config" => {
+            let content = config
+                .and_then(|c| std::fs::read_to_string(c).ok())
+                .unwrap_or_else(|| "{}".to_string());
+            print!("{content}");
+            Ok(0)
+        }
+        "output-file-paths" => {
+            let root = match &config {
+                Some(config) => config.parent().unwrap_or(Path::new(".")).to_path_buf(),
+                None => std::env::current_dir()?,
+            };
+            let mut found = Vec::new();
+            list_files(&root, &mut found);
+            found.retain(|f| Some(f) != config.as_ref());
+            found.sort();
+            let mut stdout = io::stdout().lock();
+            for file in found {
+                stdout.write_all(file.as_os_str().as_bytes())?;
+                stdout.write_all(b"\n")?;
+            }
+            Ok(0)
+        }
+        "fmt" if stdin_file.is_some() => {
+            // Like dprint, look for a config from the working directory up.
+            if config.is_none() && discovered_config()?.is_none() {
+                eprintln!("No config file found.");
+                return Ok(11);
+            }
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            std::thread::sleep(std::time::Duration::from_millis(script.stdin_delay_ms));
+            print!("{}", script.apply(&text));
+            Ok(0)
+        }
+        "fmt" | "check" => {
+            let mut different = Vec::new();
+            for file in &files {
+                let text =
+                    std::fs::read_to_string(file).with_context(|| file.display().to_string())?;
+                let formatted = script.apply(&text);
+                if formatted == text {
+                    continue;
+                }
+                if command == "fmt" {
+                    std::fs::write(file, formatted)?;
+                } else {
+                    different.push(file);
+                }
+            }
+            let list = flags.contains(&"--list-different");
+            let mut stdout = io::stdout().lock();
+            for file in &different {
+                if list {
+                    stdout.write_all(file.as_os_str().as_bytes())?;
+                } else {
+                    write!(stdout, "from {}:\n  would be reformatted", file.display())?;
+                }
+                stdout.write_all(b"\n")?;
+            }
+            Ok(i32::from(!different.is_empty() && !list))
+        }
+        "lsp" => {
+            fake_lsp(dir, &call)?;
+            Ok(0)
+        }
+        other => bail!("unsupported command {other:?}"),
+    }
+}
+
+/// `dprint.json` or `dprint.jsonc` in the working directory or above it.
+fn discovered_config() -> Result<Option<PathBuf>> {
+    let cwd = std::env::current_dir()?;
+    Ok(cwd
+        .ancestors()
+        .flat_map(|dir| ["dprint.json", "dprint.jsonc"].map(|name| dir.join(name)))
+        .find(|config| config.is_file()))
+}
+
+fn list_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            list_files(&path, out);
+        } else {
+            out.push(path);
+        }
+    }
+}
+
+fn log_call(dir: &Path, call: &Call) {
+    let line = format!("{}\n", serde_json::to_string(call).unwrap_or_default());
+    if let Ok(mut log) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(CALLS))
+    {
+        let _ = log.write_all(line.as_bytes());
+    }
+}
+
+/// `dprint lsp`: whole-document formatting of the documents it was sent,
+/// with the script reread per message.
+fn fake_lsp(dir: &Path, call: &Call) -> Result<()> {
+    let mut reader = BufReader::new(io::stdin().lock());
+    let mut stdout = io::stdout().lock();
+    let mut documents: HashMap<String, String> = HashMap::new();
+    loop {
+        let Ok(body) = crate::lsp::read_lsp_message(&mut reader, usize::MAX) else {
+            return Ok(());
+        };
+        let msg: serde_json::Value = serde_json::from_str(&body)?;
+        let method = msg.get("method").and_then(|m| m.as_str());
+        let uri = msg
+            .pointer("/params/textDocument/uri")
+            .or_else(|| {
+                msg.pointer("/params/rootUri")
+                    .filter(|_| method == Some("initialize"))
+            })
+            .and_then(|u| u.as_str())
+            .map(str::to_string);
+        log_call(
+            dir,
+            &Call {
+                method: method.map(str::to_string),
+                files: uri.iter().cloned().collect(),
+                ..call.clone()
+            },
+        );
+        let script: Script = std::fs::read_to_string(dir.join(SCRIPT))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        let result = match method {
+            Some("initialize") => serde_json::json!({
+                "capabilities": script.capabilities.clone().unwrap_or_else(
+                    || serde_json::json!({ "documentFormattingProvider": true })
+                ),
+            }),
+            Some("textDocument/didOpen") => {
+                let text = msg
+                    .pointer("/params/textDocument/text")
+                    .and_then(|t| t.as_str());
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    documents.insert(uri, text.to_string());
+                }
+                continue;
+            }
+            Some("textDocument/didChange") => {
+                // Full sync: the last change is the whole text.
+                let text = msg
+                    .pointer("/params/contentChanges")
+                    .and_then(|c| c.as_array())
+                    .and_then(|c| c.last())
+                    .and_then(|c| c.get("text"))
+                    .and_then(|t| t.as_str());
+                if let (Some(uri), Some(text)) = (uri, text) {
+                    documents.insert(uri, text.to_string());
+                }
+                continue;
+            }
+            Some("textDocument/didClose") => {
+                if let Some(uri) = uri {
+                    documents.remove(&uri);
+                }
+                continue;
+            }
+            Some("textDocument/formatting" | "textDocument/onTypeFormatting") => {
+                let text = uri.and_then(|u| documents.get(&u));
+                match text {
+                    Some(text) if script.apply(text) != *text => {
+                        serde_json::json!([{
+                            "range": { "start": { "line": 0, "character": 0 }, "end": end_position(text) },
+                            "newText": script.apply(text),
+                        }])
+                    }
+                    _ => serde_json::Value::Null,
+                }
+            }
+            Some("exit") => return Ok(()),
+            _ => serde_json::Value::Null,
+        };
+        // Notifications and responses get no answer.
+        let Some(id) = msg.get("id").filter(|_| method.is_some()) else {
+            continue;
+        };
+        let response = serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": result });
+        stdout.write_all(&lsp_frame(&response))?;
+        stdout.flush()?;
+    }
+}
+
+/// LSP position of the end of `text` (UTF-16 columns).
+fn end_position(text: &str) -> serde_json::Value {
+    let line = text.matches('\n').count();
+    let last = text.rsplit('\n').next().unwrap_or_default();
+    serde_json::json!({ "line": line, "character": last.encode_utf16().count() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_apply() {
+        let script = Script {
+            rewrite: vec![("a=1".into(), "a = 1".into()), ("  ".into(), " ".into())],
+            ..Script::default()
+        };
+        assert_eq!(script.apply("let  a=1"), "let a = 1");
+        assert_eq!(script.apply("let b = 2"), "let b = 2");
+    }
+
+    #[test]
+    fn test_lsp_frames_round_trip() {
+        let msgs = [
+            serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize" }),
+            serde_json::json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ];
+        let bytes: Vec<u8> = msgs.iter().flat_map(lsp_frame).collect();
+        assert_eq!(lsp_messages(&bytes).unwrap(), msgs);
+    }
+
+    #[test]
+    fn test_end_position() {
+        assert_eq!(
+            end_position("ab\nсд"),
+            serde_json::json!({ "line": 1, "character": 2 })
+        );
+        assert_eq!(
+            end_position("ab\n"),
+            serde_json::json!({ "line": 1, "character": 0 })
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::fsutil;
+
+/// File listing trusted directories, one absolute path per line.
+fn trust_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("dprintx").join("trusted"))
+}
+
+/// Directories marked trusted with `dprintx trust`. Empty if none.
+pub fn load() -> Vec<PathBuf> {
+    trust_file()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+        .map(|content| parse(&content))
+        .unwrap_or_default()
+}
+
+/// Mark `dir` (and everything below it) trusted. Returns the canonical path
+/// and whether it was newly added.
+pub fn add(dir: &Path) -> Result<(PathBuf, bool)> {
+    let dir = std::fs::canonicalize(dir).with_context(|| format!("resolving {}", dir.display()))?;
+    if !dir.is_dir() {
+        anyhow::bail!("{} is not a directory", dir.display());
+    }
+    let mut trusted = load();
+    if trusted.contains(&dir) {
+        return Ok((dir, false));
+    }
+    trusted.push(dir.clone());
+
+    let file = trust_file().context("cannot determine config directory")?;
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("creating {}", parent.display()))?;
+    }
+    let content: String = trusted
+        .iter()
+        .map(|d| format!("{}\n", d.display()))
+        .collect();
+    fsutil::write_atomic(&file, content.as_bytes())?;
+    Ok((dir, true))
+}
+
+/// Whether `dir` is one of `trusted` or lies below one.
+pub fn is_trusted(dir: &Path, trusted: &[PathBuf]) -> bool {
+    let dir = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    trusted.iter().any(|t| dir.starts_with(t))
+}
+
+/// Absolute paths from the trust file; blank lines and `#` comments skipped.
+fn parse(content: &str) -> Vec<PathBuf> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trusted_subtree() {
+        let trusted = parse("# mine\n/home/u/src\n\nrelative/ignored\n/opt/work\n");
+        assert_eq!(
+            trusted,
+            vec![PathBuf::from("/home/u/src"), PathBuf::from("/opt/work")]
+        );
+        assert!(is_trusted(Path::new("/home/u/src"), &trusted));
+        assert!(is_trusted(Path::new("/home/u/src/app/web"), &trusted));
+        assert!(!is_trusted(Path::new("/home/u/srcx"), &trusted));
+        assert!(!is_trusted(Path::new("/tmp/clone"), &trusted));
+    }
+}
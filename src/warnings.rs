@@ -0,0 +1,140 @@
+use std::sync::Mutex;
+
+/// A non-fatal problem noticed during a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// Short machine-readable category, e.g. "output-file-paths".
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl Warning {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "event": "warning",
+            "kind": self.kind,
+            "message": self.message,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Emit JSON lines (`--events`) instead of human-readable text.
+    events: bool,
+    /// Print each warning as it happens (long-running LSP proxy).
+    immediate: bool,
+    pending: Vec<Warning>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    events: false,
+    immediate: false,
+    pending: Vec::new(),
+});
+
+/// Switch output to JSON lines on stderr.
+pub fn set_events(events: bool) {
+    STATE.lock().unwrap().events = events;
+}
+
+/// Print warnings as they are reported instead of collecting them.
+pub fn set_immediate(immediate: bool) {
+    STATE.lock().unwrap().immediate = immediate;
+}
+
+/// Report a warning. Collected until [`flush`] unless in immediate mode.
+pub fn warn(kind: &'static str, message: impl Into<String>) {
+    let warning = Warning {
+        kind,
+        message: message.into(),
+    };
+    let mut state = STATE.lock().unwrap();
+    if state.immediate {
+        eprintln!("{}", render(&warning, state.events));
+    } else {
+        state.pending.push(warning);
+    }
+}
+
+/// Print collected warnings to stderr, once, and clear them.
+pub fn flush() {
+    let mut state = STATE.lock().unwrap();
+    let pending = std::mem::take(&mut state.pending);
+    for warning in dedup(pending) {
+        eprintln!("{}", render(&warning, state.events));
+    }
+}
+
+/// Take collected warnings, rendered as [`flush`] would print them. Used by
+/// the daemon to hand a request's warnings to the attached client.
+pub fn take_rendered() -> Vec<String> {
+    let mut state = STATE.lock().unwrap();
+    let pending = std::mem::take(&mut state.pending);
+    dedup(pending)
+        .iter()
+        .map(|warning| render(warning, state.events))
+        .collect()
+}
+
+/// Flushes collected warnings when dropped, so they are printed even when
+/// `main` returns early with an error.
+pub struct FlushGuard;
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        flush();
+    }
+}
+
+fn render(warning: &Warning, events: bool) -> String {
+    if events {
+        warning.to_json().to_string()
+    } else {
+        format!("dprintx: warning: {}", warning.message)
+    }
+}
+
+/// Drop repeated warnings, keeping first-seen order.
+fn dedup(warnings: Vec<Warning>) -> Vec<Warning> {
+    let mut out: Vec<Warning> = Vec::with_capacity(warnings.len());
+    for warning in warnings {
+        if !out.contains(&warning) {
+            out.push(warning);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warning(kind: &'static str, message: &str) -> Warning {
+        Warning {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let w = warning(
+            "output-file-paths",
+            "output-file-paths failed for profile x",
+        );
+        assert_eq!(
+            render(&w, false),
+            "dprintx: warning: output-file-paths failed for profile x"
+        );
+        let json: serde_json::Value = serde_json::from_str(&render(&w, true)).unwrap();
+        assert_eq!(json["event"], "warning");
+        assert_eq!(json["kind"], "output-file-paths");
+    }
+
+    #[test]
+    fn test_dedup_keeps_order() {
+        let warnings = vec![warning("a", "1"), warning("b", "2"), warning("a", "1")];
+        assert_eq!(dedup(warnings), vec![warning("a", "1"), warning("b", "2")]);
+    }
+}
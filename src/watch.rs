@@ -0,0 +1,179 @@
+//! Config file watching for the resident modes (`dprintx daemon`,
+//! `dprintx serve`), so edits to dprint configs reach warm state without a
+//! restart. Built on inotify; elsewhere [`ConfigWatcher::new`] fails and
+//! callers rely on their per-request mtime checks.
+
+use anyhow::{Context, Result, bail};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Watches config files through their directories, so editors that save
+/// by writing a new file and renaming it over the old one are seen too.
+pub(crate) struct ConfigWatcher {
+    inotify: File,
+    /// Watch descriptor → watched directory.
+    dirs: Mutex<HashMap<i32, PathBuf>>,
+    /// Watched file → its mtime when last reported (or first watched).
+    files: Mutex<HashMap<PathBuf, Option<SystemTime>>>,
+}
+
+impl ConfigWatcher {
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new() -> Result<Self> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        // SAFETY: plain syscall; a non-negative result is a new fd we own.
+        let fd = unsafe { sys::inotify_init1(sys::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("inotify_init1");
+        }
+        // SAFETY: `fd` was just returned by inotify_init1 and is not shared.
+        let inotify = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        Ok(Self {
+            inotify,
+            dirs: Mutex::new(HashMap::new()),
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn new() -> Result<Self> {
+        bail!("config watching needs inotify (Linux only)")
+    }
+
+    /// Report changes of `file` from [`Self::wait`]. Watching a file twice
+    /// is a no-op; a file that does not exist yet is reported once created.
+    pub(crate) fn watch(&self, file: &Path) -> Result<()> {
+        let mut files = self.files.lock().unwrap();
+        if files.contains_key(file) {
+            return Ok(());
+        }
+        let dir = file.parent().context("config file without directory")?;
+        let mut dirs = self.dirs.lock().unwrap();
+        if !dirs.values().any(|d| d == dir) {
+            let wd = self.add_watch(dir)?;
+            dirs.insert(wd, dir.to_path_buf());
+        }
+        files.insert(file.to_path_buf(), mtime(file));
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn add_watch(&self, dir: &Path) -> Result<i32> {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = std::ffi::CString::new(dir.as_os_str().as_bytes())?;
+        let mask = sys::IN_CLOSE_WRITE | sys::IN_MOVED_TO | sys::IN_MOVED_FROM | sys::IN_DELETE;
+        // SAFETY: `path` is NUL-terminated and outlives the call.
+        let wd = unsafe { sys::inotify_add_watch(self.inotify.as_raw_fd(), path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("watching {}", dir.display()));
+        }
+        Ok(wd)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn add_watch(&self, _dir: &Path) -> Result<i32> {
+        bail!("config watching needs inotify (Linux only)")
+    }
+
+    /// Block until watched files change and return them. Events that leave
+    /// a file's mtime as last reported (a second event for the same save,
+    /// a sibling file in the directory) are swallowed.
+    pub(crate) fn wait(&self) -> Result<Vec<PathBuf>> {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = std::io::Read::read(&mut &self.inotify, &mut buf).context("reading inotify")?;
+            if n == 0 {
+                bail!("inotify closed");
+            }
+            let touched = self.touched(&buf[..n]);
+            let mut files = self.files.lock().unwrap();
+            let mut changed = Vec::new();
+            for file in touched {
+                if let Some(seen) = files.get_mut(&file) {
+                    let now = mtime(&file);
+                    if *seen != now {
+                        *seen = now;
+                        changed.push(file);
+                    }
+                }
+            }
+            if !changed.is_empty() {
+                changed.sort();
+                return Ok(changed);
+            }
+        }
+    }
+
+    /// Paths named by the `inotify_event`s in `events`.
+    fn touched(&self, mut events: &[u8]) -> HashSet<PathBuf> {
+        let dirs = self.dirs.lock().unwrap();
+        let mut touched = HashSet::new();
+        // struct inotify_event { int wd; u32 mask, cookie, len; char name[len]; }
+        while events.len() >= 16 {
+            let wd = i32::from_ne_bytes(events[0..4].try_into().unwrap());
+            let len = u32::from_ne_bytes(events[12..16].try_into().unwrap()) as usize;
+            let Some(name) = events.get(16..16 + len) else {
+                break;
+            };
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(len)];
+            if let Some(dir) = dirs.get(&wd) {
+                touched.insert(dir.join(String::from_utf8_lossy(name).as_ref()));
+            }
+            events = &events[16 + len..];
+        }
+        touched
+    }
+}
+
+fn mtime(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{c_char, c_int};
+
+    pub const IN_CLOEXEC: c_int = 0o2000000;
+    pub const IN_CLOSE_WRITE: u32 = 0x0000_0008;
+    pub const IN_MOVED_FROM: u32 = 0x0000_0040;
+    pub const IN_MOVED_TO: u32 = 0x0000_0080;
+    pub const IN_DELETE: u32 = 0x0000_0200;
+
+    unsafe extern "C" {
+        pub fn inotify_init1(flags: c_int) -> c_int;
+        pub fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wait_reports_changed_files() {
+        let dir = std::env::temp_dir().join(format!("dprintx-test-watch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("dprint.json");
+        std::fs::write(&config, "{}").unwrap();
+
+        let watcher = ConfigWatcher::new().unwrap();
+        watcher.watch(&config).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        // An unwatched sibling first: only the config is reported.
+        std::fs::write(dir.join("other.json"), "{}").unwrap();
+        let tmp = dir.join("dprint.json.tmp");
+        std::fs::write(&tmp, r#"{"lineWidth": 100}"#).unwrap();
+        std::fs::rename(&tmp, &config).unwrap();
+        assert_eq!(watcher.wait().unwrap(), vec![config]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
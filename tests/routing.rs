@@ -0,0 +1,1025 @@
+//! End-to-end routing through the fake dprint (`--features test-support`):
+//! files grouped per profile and local config, documents routed to
+//! per-profile `dprint lsp` backends.
+
+use dprintx::cli::Cli;
+use dprintx::config::{DprintxConfig, LspRoot};
+use dprintx::daemon::{self, Attached};
+use dprintx::lsp::LspProxy;
+use dprintx::matcher::ProfileMatcher;
+use dprintx::runner::DprintRunner;
+use dprintx::testing::{
+    Call, FakeDprint, Script, SharedBuffer, lsp_frame, lsp_message, lsp_messages,
+};
+use serde_json::json;
+use std::ffi::OsStr;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+struct Setup {
+    dir: PathBuf,
+    fake: FakeDprint,
+    config: DprintxConfig,
+    matcher: ProfileMatcher,
+}
+
+impl Setup {
+    /// Profiles `a` and `b` in a fresh directory: Markdown goes to `b`,
+    /// everything else to `a`. The fake spaces out `=`.
+    fn new(name: &str, script: Script) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("dprintx-routing-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let fake = FakeDprint::new(
+            Path::new(env!("CARGO_BIN_EXE_fake-dprint")),
+            &dir.join("bin"),
+            &Script {
+                rewrite: vec![("=".into(), " = ".into())],
+                ..script
+            },
+        )
+        .unwrap();
+        for profile in ["a", "b"] {
+            write(
+                &dir.join(format!("p{profile}.json")),
+                r#"{ "lineWidth": 80 }"#,
+            );
+        }
+        let config = DprintxConfig::parse(
+            &json!({
+                "dprint": fake.path(),
+                "profiles": { "a": dir.join("pa.json"), "b": dir.join("pb.json") },
+                "match": { "**/*.md": "b", "**": "a" },
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let matcher = ProfileMatcher::from_config(&config).unwrap();
+        Self {
+            dir,
+            fake,
+            config,
+            matcher,
+        }
+    }
+
+    fn profile(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("p{name}.json"))
+    }
+
+    /// Create `rel` with `text`; returns its path.
+    fn file(&self, rel: &str, text: &str) -> String {
+        let path = self.dir.join(rel);
+        write(&path, text);
+        path.display().to_string()
+    }
+
+    fn runner(&self) -> DprintRunner {
+        DprintRunner::new(&self.config).with_dprint_bin(self.fake.path())
+    }
+
+    /// Run an LSP session with `messages` from the editor, then `shutdown`
+    /// and `exit`; returns what the editor got.
+    fn lsp(&self, messages: &[serde_json::Value]) -> Vec<serde_json::Value> {
+        let matcher = ProfileMatcher::from_config(&self.config).unwrap();
+        self.lsp_on(
+            LspProxy::new(self.fake.path(), matcher, self.config.clone()),
+            messages,
+        )
+    }
+
+    /// [`Setup::lsp`] through `proxy`.
+    fn lsp_on(&self, proxy: LspProxy, messages: &[serde_json::Value]) -> Vec<serde_json::Value> {
+        let mut input: Vec<u8> = [
+            json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": { "rootUri": uri(&self.dir) } }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        ]
+        .iter()
+        .chain(messages)
+        .flat_map(lsp_frame)
+        .collect();
+        input.extend(lsp_frame(
+            &json!({ "jsonrpc": "2.0", "id": 999, "method": "shutdown" }),
+        ));
+        input.extend(lsp_frame(&json!({ "jsonrpc": "2.0", "method": "exit" })));
+        let output = SharedBuffer::default();
+        proxy.run_on(input.as_slice(), output.clone()).unwrap();
+        lsp_messages(&output.contents()).unwrap()
+    }
+
+    /// Calls a backend for `config` got for `method`, as document URIs.
+    fn lsp_calls(&self, method: &str) -> Vec<(PathBuf, String)> {
+        self.fake
+            .calls()
+            .into_iter()
+            .filter(|c| c.command == "lsp" && c.method.as_deref() == Some(method))
+            .map(|c| (c.config.unwrap(), c.files.concat()))
+            .collect()
+    }
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn write(path: &Path, text: &str) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, text).unwrap();
+}
+
+fn uri(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
+
+fn did_open(path: &str, language_id: &str, text: &str) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": { "textDocument": {
+            "uri": uri(Path::new(path)), "languageId": language_id, "version": 1, "text": text,
+        } },
+    })
+}
+
+fn formatting(id: u64, path: &str) -> serde_json::Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "textDocument/formatting",
+        "params": {
+            "textDocument": { "uri": uri(Path::new(path)) },
+            "options": { "tabSize": 2, "insertSpaces": true },
+        },
+    })
+}
+
+fn response(messages: &[serde_json::Value], id: u64) -> &serde_json::Value {
+    messages
+        .iter()
+        .find(|m| m.get("id") == Some(&json!(id)) && m.get("method").is_none())
+        .unwrap_or_else(|| panic!("no response {id} in {messages:?}"))
+}
+
+/// `(config, sorted files)` of each `command` call.
+fn runs(calls: Vec<Call>, command: &str) -> Vec<(PathBuf, Vec<String>)> {
+    calls
+        .into_iter()
+        .filter(|c| c.command == command)
+        .map(|mut c| {
+            c.files.sort();
+            (c.config.unwrap(), c.files)
+        })
+        .collect()
+}
+
+#[test]
+fn fmt_files_runs_once_per_profile() {
+    let s = Setup::new("fmt-profiles", Script::default());
+    let a = s.file("src/a.ts", "let a=1\n");
+    let b = s.file("src/b.ts", "let b=2\n");
+    let x = s.file("docs/x.md", "x=3\n");
+
+    s.runner()
+        .fmt_files(
+            &[a.clone(), x.clone(), b.clone()].map(PathBuf::from),
+            &s.matcher,
+            &s.config,
+        )
+        .unwrap();
+
+    let mut fmt = runs(s.fake.calls(), "fmt");
+    fmt.sort();
+    assert_eq!(
+        fmt,
+        vec![
+            (s.profile("a"), vec![a.clone(), b]),
+            (s.profile("b"), vec![x])
+        ]
+    );
+    assert_eq!(std::fs::read_to_string(a).unwrap(), "let a = 1\n");
+}
+
+#[test]
+fn fmt_files_skips_disabled_files() {
+    let s = Setup::new("fmt-disabled", Script::default());
+    let a = s.file("src/a.ts", "let a=1\n");
+    let off = s.file("src/off.ts", "// dprintx-disable-file\nlet b=2\n");
+
+    s.runner()
+        .fmt_files(
+            &[a.clone(), off.clone()].map(PathBuf::from),
+            &s.matcher,
+            &s.config,
+        )
+        .unwrap();
+
+    assert_eq!(runs(s.fake.calls(), "fmt"), vec![(s.profile("a"), vec![a])]);
+    assert_eq!(
+        std::fs::read_to_string(off).unwrap(),
+        "// dprintx-disable-file\nlet b=2\n"
+    );
+}
+
+#[test]
+fn fmt_files_merges_local_config() {
+    let s = Setup::new("fmt-local", Script::default());
+    let a = s.file("src/a.ts", "let a=1\n");
+    s.file("proj/dprint.json", r#"{ "indentWidth": 8 }"#);
+    let c = s.file("proj/c.ts", "let c=1\n");
+    let d = s.file("proj/d.ts", "let d=1\n");
+
+    s.runner()
+        .fmt_files(
+            &[a.clone(), c.clone(), d.clone()].map(PathBuf::from),
+            &s.matcher,
+            &s.config,
+        )
+        .unwrap();
+
+    // Local files run under merged configs, deleted afterwards.
+    let fmt = runs(s.fake.calls(), "fmt");
+    assert!(fmt.contains(&(s.profile("a"), vec![a])), "{fmt:?}");
+    let merged: Vec<_> = fmt
+        .iter()
+        .filter(|(config, _)| *config != s.profile("a"))
+        .collect();
+    assert_eq!(
+        merged
+            .iter()
+            .flat_map(|(_, files)| files.clone())
+            .collect::<Vec<_>>(),
+        vec![c.clone(), d]
+    );
+    assert!(merged.iter().all(|(config, _)| !config.exists()));
+    assert_eq!(std::fs::read_to_string(c).unwrap(), "let c = 1\n");
+}
+
+#[test]
+fn local_configs_are_ignored_without_merge_local() {
+    let mut s = Setup::new("no-merge", Script::default());
+    s.config.merge_local = false;
+    s.file("proj/dprint.json", r#"{ "indentWidth": 8 }"#);
+    let c = s.file("proj/c.ts", "let c=1\n");
+
+    s.runner()
+        .fmt_files(&[PathBuf::from(&c)], &s.matcher, &s.config)
+        .unwrap();
+    assert_eq!(
+        runs(s.fake.calls(), "fmt"),
+        [(s.profile("a"), vec![c.clone()])]
+    );
+
+    s.lsp(&[did_open(&c, "typescript", "let c=1"), formatting(1, &c)]);
+    let formatted: Vec<PathBuf> = s
+        .lsp_calls("textDocument/formatting")
+        .into_iter()
+        .map(|c| c.0)
+        .collect();
+    assert_eq!(formatted, [s.profile("a")]);
+}
+
+#[test]
+fn fmt_files_passes_non_utf8_paths() {
+    let s = Setup::new("fmt-non-utf8", Script::default());
+    let a = s.dir.join(OsStr::from_bytes(b"src/caf\xe9.ts"));
+    write(&a, "let a=1\n");
+
+    s.runner()
+        .fmt_files(std::slice::from_ref(&a), &s.matcher, &s.config)
+        .unwrap();
+
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "let a = 1\n");
+}
+
+#[test]
+fn attached_check_passes_for_warning_level_profiles() {
+    let s = Setup::new("attach-severity", Script::default());
+    let a = s.file("src/a.ts", "let a=1");
+    let b = s.file("src/b.md", "x=1");
+    let config_path = s.dir.join("dprintx.jsonc");
+    write(
+        &config_path,
+        &json!({
+            "dprint": s.fake.path(),
+            "profiles": {
+                "a": { "config": s.profile("a"), "severity": "warning" },
+                "b": s.profile("b"),
+            },
+            "match": { "**/*.md": "b", "**": "a" },
+        })
+        .to_string(),
+    );
+    let socket = s.dir.join("daemon.sock");
+    {
+        let (config_path, socket) = (config_path.clone(), socket.clone());
+        std::thread::spawn(move || daemon::serve(&config_path, Some(socket)));
+    }
+    for _ in 0..100 {
+        if socket.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    let check = |file: &str| {
+        let args: Vec<_> = ["--config", &config_path.display().to_string(), "check"]
+            .iter()
+            .chain(&["--attach", file])
+            .map(Into::into)
+            .collect();
+        match daemon::attach(&Cli::parse_from(&args), Some(socket.clone())).unwrap() {
+            Attached::Exit(code) => code,
+            Attached::Local(_) => panic!("check {file} not attached"),
+        }
+    };
+
+    // Both are unformatted; only `b` is an error-level profile.
+    assert_eq!(check(&a), 0);
+    assert_eq!(check(&b), 1);
+    assert_eq!(runs(s.fake.calls(), "check").len(), 2);
+}
+
+#[test]
+fn staged_commands_refuse_partially_staged_files() {
+    let s = Setup::new("staged", Script::default());
+    let config_path = s.dir.join("dprintx.jsonc");
+    write(
+        &config_path,
+        &json!({
+            "dprint": s.fake.path(),
+            "profiles": { "a": s.profile("a"), "b": s.profile("b") },
+            "match": { "**/*.md": "b", "**": "a" },
+        })
+        .to_string(),
+    );
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&s.dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?}");
+    };
+    let dprintx = |command: &str| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_dprintx"))
+            .arg("--config")
+            .arg(&config_path)
+            .args([command, "--staged"])
+            .current_dir(&s.dir)
+            .output()
+            .unwrap()
+    };
+    git(&["init", "-q"]);
+    s.file("a.ts", "let a=1\n");
+    s.file("b.ts", "let b=1\n");
+    git(&["add", "a.ts", "b.ts"]);
+    // Staged, then modified again: the working tree is not what is staged.
+    s.file("b.ts", "let b=2\n");
+
+    for command in ["check", "fmt"] {
+        let output = dprintx(command);
+        assert!(!output.status.success(), "{command}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("unstaged changes in"),
+            "{command}: {stderr}"
+        );
+        assert!(stderr.contains("b.ts"), "{command}: {stderr}");
+    }
+    assert!(s.fake.calls().is_empty());
+
+    git(&["add", "b.ts"]);
+    let output = dprintx("check");
+    assert_eq!(runs(s.fake.calls(), "check").len(), 1);
+    // Both files are unformatted.
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn parity_compares_with_plain_dprint() {
+    let s = Setup::new("parity", Script::default());
+    s.file("one/dprint.json", "{}");
+    let one = s.file("one/a.ts", "let a=1\n");
+    let two = s.file("two/b.ts", "let b=2\n");
+    let files = [one.clone(), two.clone()].map(PathBuf::from);
+
+    // Only plain dprint fails, for want of a config next to two/b.ts.
+    let different = s
+        .runner()
+        .parity(&files, &[], 100, &s.matcher, &s.config)
+        .unwrap();
+    assert_eq!(different, 1);
+    let plain: Vec<_> = s
+        .fake
+        .calls()
+        .into_iter()
+        .filter(|c| c.command == "fmt" && c.config.is_none())
+        .flat_map(|c| c.files)
+        .collect();
+    assert_eq!(plain, vec![one, two.clone()]);
+
+    // A sample of one takes the last file.
+    s.fake.clear_calls();
+    s.runner()
+        .parity(&files, &[], 1, &s.matcher, &s.config)
+        .unwrap();
+    assert!(s.fake.calls().iter().all(|c| c.files == [two.clone()]));
+}
+
+#[test]
+fn lsp_routes_documents_by_profile() {
+    let s = Setup::new("lsp-profiles", Script::default());
+    let a = s.file("src/a.ts", "");
+    let x = s.file("docs/x.md", "");
+
+    let messages = s.lsp(&[
+        did_open(&a, "typescript", "let a=1"),
+        did_open(&x, "markdown", "x=3"),
+        formatting(1, &a),
+        formatting(2, &x),
+    ]);
+
+    assert_eq!(response(&messages, 1)["result"][0]["newText"], "let a = 1");
+    assert_eq!(response(&messages, 2)["result"][0]["newText"], "x = 3");
+    let mut formatted = s.lsp_calls("textDocument/formatting");
+    formatted.sort();
+    assert_eq!(
+        formatted,
+        vec![
+            (s.profile("a"), uri(Path::new(&a))),
+            (s.profile("b"), uri(Path::new(&x)))
+        ]
+    );
+    assert!(response(&messages, 999)["result"].is_null());
+}
+
+#[test]
+fn lsp_serves_a_tcp_client() {
+    let s = Setup::new("lsp-tcp", Script::default());
+    let a = s.file("src/a.ts", "");
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let input = [
+        json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": { "rootUri": uri(&s.dir) } }),
+        json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+        did_open(&a, "typescript", "let a=1"),
+        formatting(1, &a),
+        json!({ "jsonrpc": "2.0", "id": 999, "method": "shutdown" }),
+        json!({ "jsonrpc": "2.0", "method": "exit" }),
+    ];
+    let client = std::thread::spawn(move || {
+        let mut client = std::net::TcpStream::connect(addr).unwrap();
+        for message in &input {
+            client.write_all(&lsp_frame(message)).unwrap();
+        }
+        let mut reader = std::io::BufReader::new(client);
+        let mut messages: Vec<serde_json::Value> = Vec::new();
+        while !messages.iter().any(|m| m["id"] == 999) {
+            messages.push(lsp_message(&mut reader).unwrap());
+        }
+        messages
+    });
+
+    let matcher = ProfileMatcher::from_config(&s.config).unwrap();
+    LspProxy::new(s.fake.path(), matcher, s.config.clone())
+        .run_listener(listener)
+        .unwrap();
+    let messages = client.join().unwrap();
+    assert_eq!(response(&messages, 1)["result"][0]["newText"], "let a = 1");
+}
+
+#[test]
+fn lsp_routes_cancel_to_the_backend_of_the_request() {
+    let s = Setup::new("lsp-cancel", Script::default());
+    let a = s.file("src/a.ts", "");
+    let x = s.file("docs/x.md", "");
+    let cancel =
+        |id: i64| json!({ "jsonrpc": "2.0", "method": "$/cancelRequest", "params": { "id": id } });
+
+    let messages = s.lsp(&[
+        did_open(&a, "typescript", "let a=1"),
+        did_open(&x, "markdown", "x=3"),
+        formatting(1, &a),
+        cancel(1),
+        cancel(2),
+    ]);
+
+    // Request 1 may be answered before its cancellation arrives; request 2
+    // was never sent. Neither concerns the markdown backend.
+    assert_eq!(response(&messages, 1)["result"][0]["newText"], "let a = 1");
+    let cancelled = s.lsp_calls("$/cancelRequest");
+    assert!(cancelled.len() <= 1, "{cancelled:?}");
+    assert!(
+        cancelled
+            .iter()
+            .all(|(config, _)| *config == s.profile("a"))
+    );
+}
+
+#[test]
+fn lsp_logs_routing_records() {
+    let mut s = Setup::new("lsp-log", Script::default());
+    let log = s.dir.join("lsp.log");
+    s.config.lsp_log = Some(log.display().to_string());
+    let a = s.file("src/a.ts", "");
+
+    let messages = s.lsp(&[did_open(&a, "typescript", "let a=1"), formatting(1, &a)]);
+
+    assert_eq!(response(&messages, 1)["result"][0]["newText"], "let a = 1");
+    let records: Vec<serde_json::Value> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let routed: Vec<_> = records.iter().filter(|r| r["event"] == "route").collect();
+    assert_eq!(routed.len(), 2, "{records:?}");
+    assert_eq!(routed[1]["method"], "textDocument/formatting");
+    assert_eq!(routed[1]["id"], 1);
+    assert_eq!(routed[1]["uri"], uri(Path::new(&a)));
+    assert_eq!(routed[1]["rule"], "match ** -> a");
+    assert_eq!(routed[1]["profile"], "a");
+    assert_eq!(routed[1]["backend"], s.profile("a").display().to_string());
+    let answered = records.iter().find(|r| r["event"] == "response").unwrap();
+    assert_eq!(answered["id"], 1);
+    assert_eq!(answered["timed_out"], false);
+    assert!(answered["latency_ms"].is_f64());
+    assert!(answered["time"].as_str().unwrap().ends_with('Z'));
+}
+
+#[test]
+fn lsp_shares_backend_of_identical_local_configs() {
+    let s = Setup::new("lsp-local", Script::default());
+    s.file("one/dprint.json", r#"{ "indentWidth": 8 }"#);
+    s.file("two/dprint.json", r#"{ "indentWidth": 8 }"#);
+    let one = s.file("one/a.ts", "");
+    let two = s.file("two/b.ts", "");
+
+    let messages = s.lsp(&[
+        did_open(&one, "typescript", "let a=1"),
+        did_open(&two, "typescript", "let b=1"),
+        formatting(1, &one),
+        formatting(2, &two),
+    ]);
+
+    let opened = s.lsp_calls("textDocument/didOpen");
+    assert_eq!(opened.len(), 2, "{opened:?}");
+    assert_eq!(opened[0].0, opened[1].0);
+    assert_ne!(opened[0].0, s.profile("a"));
+    assert_eq!(response(&messages, 2)["result"][0]["newText"], "let b = 1");
+}
+
+#[test]
+fn lsp_formats_with_editor_options_when_honored() {
+    let s = Setup::new("lsp-editor-options", Script::default());
+    let a = s.file("src/a.ts", "");
+    let mut config = s.config.clone();
+    config.lsp_honor_editor_options = true;
+    let matcher = ProfileMatcher::from_config(&config).unwrap();
+
+    let messages = s.lsp_on(
+        LspProxy::new(s.fake.path(), matcher, config),
+        &[
+            did_open(&a, "typescript", "let a=1"),
+            formatting(1, &a),
+            formatting(2, &a),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didClose",
+                "params": { "textDocument": { "uri": uri(Path::new(&a)) } },
+            }),
+        ],
+    );
+
+    assert_eq!(response(&messages, 2)["result"][0]["newText"], "let a = 1");
+    // The document moves to one backend with the editor's options on top of
+    // profile a, and stays there.
+    let formatted = s.lsp_calls("textDocument/formatting");
+    assert_eq!(formatted.len(), 2, "{formatted:?}");
+    let overlay = &formatted[0].0;
+    assert_eq!(formatted[1].0, *overlay);
+    assert!(
+        overlay
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("editor")
+    );
+    let opened: Vec<PathBuf> = s
+        .lsp_calls("textDocument/didOpen")
+        .into_iter()
+        .map(|c| c.0)
+        .collect();
+    assert_eq!(opened, [s.profile("a"), overlay.clone()]);
+    // Both backends get the didClose at once and log it in either order.
+    let mut closed: Vec<PathBuf> = s
+        .lsp_calls("textDocument/didClose")
+        .into_iter()
+        .map(|c| c.0)
+        .collect();
+    closed.sort();
+    let mut expected = [s.profile("a"), overlay.clone()];
+    expected.sort();
+    assert_eq!(closed, expected);
+    assert!(!overlay.exists());
+}
+
+#[test]
+fn lsp_exit_stops_every_backend_and_removes_merged_configs() {
+    let s = Setup::new("lsp-exit", Script::default());
+    s.file("proj/dprint.json", r#"{ "indentWidth": 8 }"#);
+    let c = s.file("proj/c.ts", "");
+
+    let messages = s.lsp(&[did_open(&c, "typescript", "let c=1"), formatting(1, &c)]);
+
+    assert_eq!(response(&messages, 1)["result"][0]["newText"], "let c = 1");
+    let mut spawned: Vec<PathBuf> = s
+        .fake
+        .calls()
+        .into_iter()
+        .filter(|c| c.method.as_deref() == Some("initialize"))
+        .filter_map(|c| c.config)
+        .collect();
+    spawned.sort();
+    // Both profiles and the merged config, each asked to shut down and exit.
+    assert_eq!(spawned.len(), 3, "{spawned:?}");
+    for method in ["shutdown", "exit"] {
+        let mut stopped: Vec<PathBuf> = s.lsp_calls(method).into_iter().map(|c| c.0).collect();
+        stopped.sort();
+        assert_eq!(stopped, spawned, "{method}");
+    }
+    assert!(spawned.iter().any(|config| !config.exists()));
+}
+
+#[test]
+fn lsp_publishes_diagnostics_for_unformatted_documents() {
+    let mut s = Setup::new("lsp-diagnostics", Script::default());
+    s.config.lsp_diagnostics = true;
+    let a = s.file("src/a.ts", "");
+    let x = s.file("docs/x.md", "");
+
+    let messages = s.lsp(&[
+        did_open(&a, "typescript", "let a=1\n"),
+        did_open(&x, "markdown", "# x\n"),
+        formatting(1, &a),
+    ]);
+
+    let diagnostics = |path: &str| -> Vec<serde_json::Value> {
+        messages
+            .iter()
+            .filter(|m| {
+                m["method"] == "textDocument/publishDiagnostics"
+                    && m["params"]["uri"] == uri(Path::new(path))
+            })
+            .map(|m| m["params"]["diagnostics"].clone())
+            .collect()
+    };
+    let published = diagnostics(&a);
+    assert_eq!(published.len(), 1, "{messages:?}");
+    assert_eq!(
+        published[0][0]["message"],
+        "file is not formatted per profile a"
+    );
+    assert_eq!(published[0][0]["range"]["start"]["line"], 0);
+    assert_eq!(diagnostics(&x), vec![json!([])]);
+    // Checks do not hold up the editor's own requests.
+    assert_eq!(
+        response(&messages, 1)["result"][0]["newText"],
+        "let a = 1\n"
+    );
+}
+
+#[test]
+fn lsp_reload_config_command_reroutes_documents() {
+    let s = Setup::new("lsp-reload", Script::default());
+    let a = s.file("src/a.ts", "");
+    // On disk, everything routes to profile b already.
+    let config_path = s.dir.join("dprintx.jsonc");
+    write(
+        &config_path,
+        &json!({
+            "dprint": s.fake.path(),
+            "profiles": { "a": s.profile("a"), "b": s.profile("b") },
+            "match": { "**": "b" },
+        })
+        .to_string(),
+    );
+    let matcher = ProfileMatcher::from_config(&s.config).unwrap();
+    let reload_path = config_path.clone();
+    let proxy = LspProxy::new(s.fake.path(), matcher, s.config.clone()).with_config_reload(
+        config_path,
+        Box::new(move || DprintxConfig::load(&reload_path)),
+    );
+
+    let messages = s.lsp_on(
+        proxy,
+        &[
+            did_open(&a, "typescript", "let a=1"),
+            formatting(1, &a),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "workspace/executeCommand",
+                "params": { "command": "dprintx.reloadConfig" },
+            }),
+            formatting(3, &a),
+        ],
+    );
+
+    assert_eq!(
+        response(&messages, 2)["result"]["profiles"],
+        json!(["a", "b"])
+    );
+    assert_eq!(response(&messages, 3)["result"][0]["newText"], "let a = 1");
+    let formatted: Vec<PathBuf> = s
+        .lsp_calls("textDocument/formatting")
+        .into_iter()
+        .map(|(config, _)| config)
+        .collect();
+    assert_eq!(formatted, vec![s.profile("a"), s.profile("b")]);
+}
+
+#[test]
+fn lsp_roots_backends_in_their_workspace_folder() {
+    let mut s = Setup::new("lsp-folders", Script::default());
+    s.config.lsp_root = LspRoot::Client;
+    s.file("lib/dprint.json", r#"{ "indentWidth": 8 }"#);
+    let a = s.file("app/a.ts", "");
+    let b = s.file("lib/b.ts", "");
+    let lib = json!({ "uri": uri(&s.dir.join("lib")), "name": "lib" });
+
+    let messages = s.lsp(&[
+        json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/didChangeWorkspaceFolders",
+            "params": { "event": { "added": [lib], "removed": [] } },
+        }),
+        did_open(&a, "typescript", "let a=1"),
+        did_open(&b, "typescript", "let b=1"),
+        formatting(1, &b),
+    ]);
+
+    // Profile backends keep the editor's root; the one spawned for the
+    // lib folder's local config is rooted there.
+    assert_eq!(response(&messages, 1)["result"][0]["newText"], "let b = 1");
+    let initialized = s.lsp_calls("initialize");
+    let root = |config: &Path| {
+        initialized
+            .iter()
+            .find(|(c, _)| c == config)
+            .map(|(_, root)| root.clone())
+    };
+    assert_eq!(root(&s.profile("a")), Some(uri(&s.dir)));
+    let (merged, _) = s
+        .lsp_calls("textDocument/didOpen")
+        .into_iter()
+        .find(|(_, doc)| *doc == uri(Path::new(&b)))
+        .unwrap();
+    assert_ne!(merged, s.profile("a"));
+    assert_eq!(root(&merged), Some(uri(&s.dir.join("lib"))));
+    assert_eq!(
+        s.lsp_calls("workspace/didChangeWorkspaceFolders").len(),
+        2,
+        "one per profile backend"
+    );
+}
+
+#[test]
+fn lsp_formats_untitled_buffers_by_language() {
+    let s = Setup::new("lsp-untitled", Script::default());
+    let mut config = s.config.clone();
+    config.lsp_untitled_profile = Some("a".into());
+    config.match_language = Some(json!({ "markdown": "b" }).as_object().unwrap().clone());
+    let matcher = ProfileMatcher::from_config(&config).unwrap();
+    let open = |uri: &str, language_id: &str, text: &str| {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": {
+                "uri": uri, "languageId": language_id, "version": 1, "text": text,
+            } },
+        })
+    };
+    let format = |id: u64, uri: &str| {
+        let mut request = formatting(id, "");
+        request["params"]["textDocument"]["uri"] = uri.into();
+        request
+    };
+
+    let messages = s.lsp_on(
+        LspProxy::new(s.fake.path(), matcher, config),
+        &[
+            open("untitled:Untitled-1", "typescript", "let a=1"),
+            open("untitled:Untitled-2", "markdown", "x=3"),
+            format(1, "untitled:Untitled-1"),
+            format(2, "untitled:Untitled-2"),
+        ],
+    );
+
+    assert_eq!(response(&messages, 1)["result"][0]["newText"], "let a = 1");
+    assert_eq!(response(&messages, 2)["result"][0]["newText"], "x = 3");
+    // Formatted side by side: no order across the two.
+    let mut formatted = runs(s.fake.calls(), "fmt");
+    formatted.sort();
+    assert_eq!(
+        formatted,
+        [
+            (s.profile("a"), vec!["untitled.ts".to_string()]),
+            (s.profile("b"), vec!["untitled.md".to_string()]),
+        ]
+    );
+    // Backends never hear of them.
+    assert!(s.lsp_calls("textDocument/didOpen").is_empty());
+}
+
+#[test]
+fn lsp_serves_files_while_formatting_untitled_buffers() {
+    let s = Setup::new(
+        "lsp-untitled-slow",
+        Script {
+            stdin_delay_ms: 500,
+            ..Script::default()
+        },
+    );
+    let a = s.file("src/a.ts", "");
+    let mut config = s.config.clone();
+    config.lsp_untitled_profile = Some("a".into());
+    let matcher = ProfileMatcher::from_config(&config).unwrap();
+    let mut untitled = did_open("", "typescript", "let u=1");
+    untitled["params"]["textDocument"]["uri"] = "untitled:Untitled-1".into();
+    let mut format_untitled = formatting(1, "");
+    format_untitled["params"]["textDocument"]["uri"] = "untitled:Untitled-1".into();
+
+    let messages = s.lsp_on(
+        LspProxy::new(s.fake.path(), matcher, config),
+        &[
+            untitled,
+            did_open(&a, "typescript", "let a=1"),
+            format_untitled,
+            formatting(2, &a),
+        ],
+    );
+
+    let position = |id: u64| {
+        messages
+            .iter()
+            .position(|m| m.get("id") == Some(&json!(id)) && m.get("method").is_none())
+            .unwrap()
+    };
+    assert_eq!(response(&messages, 1)["result"][0]["newText"], "let u = 1");
+    assert_eq!(response(&messages, 2)["result"][0]["newText"], "let a = 1");
+    assert!(position(2) < position(1), "{messages:?}");
+}
+
+#[test]
+fn lsp_answers_ignored_documents_with_no_edits() {
+    let s = Setup::new("lsp-ignored", Script::default());
+    let a = s.file("src/a.ts", "");
+    let b = s.file("src/b.ts", "");
+
+    let messages = s.lsp(&[
+        did_open(&a, "typescript", "// dprintx-disable-file\nlet a=1"),
+        did_open(&b, "typescript", "// dprintx-disable-file\nlet b=1"),
+        formatting(1, &a),
+        formatting(2, &a),
+        formatting(3, &b),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": uri(Path::new(&a)), "version": 2 },
+                "contentChanges": [{ "text": "let a=1" }],
+            },
+        }),
+        formatting(4, &a),
+        json!({ "jsonrpc": "2.0", "id": 5, "method": "dprintx/status" }),
+    ]);
+
+    assert_eq!(response(&messages, 1)["result"], json!([]));
+    assert_eq!(response(&messages, 2)["result"], json!([]));
+    assert_eq!(response(&messages, 3)["result"], json!([]));
+    // Editing the comment away routes the document again.
+    assert_eq!(response(&messages, 4)["result"][0]["newText"], "let a = 1");
+    // Each document is matched when opened or edited, not per request.
+    assert_eq!(
+        response(&messages, 5)["result"]["resolutions"],
+        json!({ "matched": 3, "cached": 4 })
+    );
+    // Only the edited document reached a backend, and only once edited.
+    let formatted: Vec<String> = s
+        .lsp_calls("textDocument/formatting")
+        .into_iter()
+        .map(|c| c.1)
+        .collect();
+    assert_eq!(formatted, [uri(Path::new(&a))]);
+    let b_uri = uri(Path::new(&b));
+    assert!(s.fake.calls().iter().all(|c| !c.files.contains(&b_uri)));
+}
+
+#[test]
+fn lsp_resolves_documents_again_after_settings_change() {
+    let s = Setup::new("lsp-resolutions", Script::default());
+    let a = s.file("src/a.ts", "");
+
+    let messages = s.lsp(&[
+        did_open(&a, "typescript", "let a=1"),
+        formatting(1, &a),
+        formatting(2, &a),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/didChangeConfiguration",
+            "params": { "settings": { "dprintx": { "profiles": { "a": s.profile("b") } } } },
+        }),
+        formatting(3, &a),
+        json!({ "jsonrpc": "2.0", "id": 4, "method": "dprintx/status" }),
+    ]);
+
+    // Matched on didOpen and again after the settings change; the two
+    // requests in between reuse the first resolution.
+    assert_eq!(
+        response(&messages, 4)["result"]["resolutions"],
+        json!({ "matched": 2, "cached": 2 })
+    );
+
+    // Each backend appends to the call log itself: no order across backends.
+    let mut formatted: Vec<PathBuf> = s
+        .lsp_calls("textDocument/formatting")
+        .into_iter()
+        .map(|c| c.0)
+        .collect();
+    formatted.sort();
+    assert_eq!(formatted, [s.profile("a"), s.profile("a"), s.profile("b")]);
+}
+
+#[test]
+fn lsp_formats_on_type_through_backends_that_registered_the_character() {
+    let s = Setup::new(
+        "lsp-on-type",
+        Script {
+            capabilities: Some(json!({
+                "documentFormattingProvider": true,
+                "documentOnTypeFormattingProvider": { "firstTriggerCharacter": "}" },
+            })),
+            ..Script::default()
+        },
+    );
+    let a = s.file("src/a.ts", "");
+    let on_type = |id: u64, ch: &str| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "textDocument/onTypeFormatting",
+            "params": {
+                "textDocument": { "uri": uri(Path::new(&a)) },
+                "position": { "line": 0, "character": 7 },
+                "ch": ch,
+                "options": { "tabSize": 2, "insertSpaces": true },
+            },
+        })
+    };
+
+    let messages = s.lsp(&[
+        did_open(&a, "typescript", "let a=1"),
+        on_type(1, "}"),
+        on_type(2, ";"),
+    ]);
+
+    assert_eq!(
+        response(&messages, 0)["result"]["capabilities"]["documentOnTypeFormattingProvider"],
+        json!({ "firstTriggerCharacter": "}" })
+    );
+    assert_eq!(response(&messages, 1)["result"][0]["newText"], "let a = 1");
+    assert!(response(&messages, 2)["result"].is_null());
+    assert_eq!(s.lsp_calls("textDocument/onTypeFormatting").len(), 1);
+}
+
+#[test]
+fn lsp_answers_for_unhealthy_profile() {
+    let s = Setup::new("lsp-unhealthy", Script::default());
+    let broken = Script {
+        rewrite: vec![("=".into(), " = ".into())],
+        broken: vec![s.profile("b")],
+        ..Script::default()
+    };
+    s.fake.script(&broken).unwrap();
+    let x = s.file("docs/x.md", "");
+
+    let messages = s.lsp(&[did_open(&x, "markdown", "x=3"), formatting(1, &x)]);
+
+    assert!(response(&messages, 1)["result"].is_null());
+    assert!(messages.iter().any(|m| {
+        m["method"] == "window/showMessage"
+            && m["params"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("unhealthy")
+    }));
+    assert!(
+        s.lsp_calls("initialize")
+            .iter()
+            .all(|(config, _)| *config != s.profile("b"))
+    );
+}